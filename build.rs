@@ -0,0 +1,109 @@
+use std::{
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Crates whose exact version decides how transactions and blocks are validated, reported by the `build_info` RPC.
+const CONSENSUS_CRATES: &[&str] = &[
+    "melstructs",
+    "melvm",
+    "melprot",
+    "melbootstrap",
+    "tmelcrypt",
+    "stdcode",
+    "melwalletd-prot",
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-env-changed=MELWALLETD_GIT_COMMIT");
+
+    println!(
+        "cargo:rustc-env=MELWALLETD_GIT_COMMIT={}",
+        git_commit().unwrap_or_default()
+    );
+    // reproducible builds pin the timestamp through SOURCE_DATE_EPOCH
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            .to_string()
+    });
+    println!("cargo:rustc-env=MELWALLETD_BUILD_TIMESTAMP={timestamp}");
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let rustc_version = command_output(Command::new(rustc).arg("--version")).unwrap_or_default();
+    println!("cargo:rustc-env=MELWALLETD_RUSTC_VERSION={rustc_version}");
+    for var in ["TARGET", "PROFILE"] {
+        println!(
+            "cargo:rustc-env=MELWALLETD_{var}={}",
+            std::env::var(var).unwrap_or_default()
+        );
+    }
+
+    let features: Vec<String> = std::env::vars()
+        .filter_map(|(key, _)| {
+            let feature = key.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_ascii_lowercase().replace('_', "-"))
+        })
+        .collect();
+    println!("cargo:rustc-env=MELWALLETD_FEATURES={}", features.join(","));
+
+    let lock = std::fs::read_to_string("Cargo.lock").unwrap_or_default();
+    println!(
+        "cargo:rustc-env=MELWALLETD_CONSENSUS_CRATES={}",
+        locked_versions(&lock).join(",")
+    );
+}
+
+/// The commit being built, marked `-dirty` if the tree has uncommitted changes. Builds outside a git checkout, such as from a source tarball, can pass the commit in `MELWALLETD_GIT_COMMIT`.
+fn git_commit() -> Option<String> {
+    if let Ok(commit) = std::env::var("MELWALLETD_GIT_COMMIT") {
+        return Some(commit);
+    }
+    let head = Path::new(".git/HEAD");
+    if head.exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        let contents = std::fs::read_to_string(head).ok()?;
+        if let Some(reference) = contents.trim().strip_prefix("ref: ") {
+            println!("cargo:rerun-if-changed=.git/{reference}");
+        }
+        println!("cargo:rerun-if-changed=.git/index");
+    }
+    let commit = command_output(Command::new("git").args(["rev-parse", "HEAD"]))?;
+    let dirty = command_output(Command::new("git").args(["status", "--porcelain"]))
+        .is_some_and(|status| !status.is_empty());
+    Some(if dirty {
+        format!("{commit}-dirty")
+    } else {
+        commit
+    })
+}
+
+fn command_output(command: &mut Command) -> Option<String> {
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// `name=version` for every consensus-critical crate in the lockfile.
+fn locked_versions(lock: &str) -> Vec<String> {
+    let mut versions = vec![];
+    let mut name = None;
+    for line in lock.lines() {
+        if let Some(value) = line.strip_prefix("name = ") {
+            name = Some(value.trim_matches('"'));
+        } else if let Some(value) = line.strip_prefix("version = ") {
+            if let Some(name) = name.take().filter(|n| CONSENSUS_CRATES.contains(n)) {
+                versions.push(format!("{name}={}", value.trim_matches('"')));
+            }
+        }
+    }
+    versions
+}
@@ -0,0 +1,129 @@
+use chrono::NaiveDateTime;
+use melstructs::TxHash;
+
+/// One transaction of a wallet's history in a single denomination, for exporting to accounting software.
+#[derive(Clone, Debug)]
+pub struct ActivityLine {
+    pub txhash: TxHash,
+    /// UNIX timestamp of the block confirming the transaction
+    pub time: u64,
+    /// Net change of the balance, in display units, with a leading `-` if negative
+    pub amount: String,
+    pub payee: String,
+}
+
+fn format_time(time: u64, format: &str) -> String {
+    NaiveDateTime::from_timestamp_opt(time as i64, 0)
+        .map(|time| time.format(format).to_string())
+        .unwrap_or_default()
+}
+
+/// Escapes text for an XML element.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Renders activity as a QIF bank account, the format Quicken and GnuCash import.
+pub fn qif(lines: &[ActivityLine]) -> String {
+    let mut qif = String::from("!Type:Bank\n");
+    for line in lines {
+        // QIF fields end at the line break, so one in the payee would start a bogus field
+        let payee = line.payee.replace(['\r', '\n'], " ");
+        qif.push_str(&format!(
+            "D{}\nT{}\nP{payee}\nM{}\n^\n",
+            format_time(line.time, "%m/%d/%Y"),
+            line.amount,
+            line.txhash
+        ));
+    }
+    qif
+}
+
+/// Renders activity as an OFX 2.2 bank statement of the account `account_id`, ending with the ledger balance `balance` as of `now`. Tokens have no ISO 4217 code, so amounts are in the "no currency" code XXX.
+pub fn ofx(account_id: &str, lines: &[ActivityLine], balance: &str, now: u64) -> String {
+    const OFX_TIME: &str = "%Y%m%d%H%M%S";
+    let now = format_time(now, OFX_TIME);
+    let start = lines
+        .iter()
+        .map(|line| line.time)
+        .min()
+        .map(|time| format_time(time, OFX_TIME))
+        .unwrap_or_else(|| now.clone());
+    let mut transactions = String::new();
+    for line in lines {
+        let kind = if line.amount.starts_with('-') {
+            "DEBIT"
+        } else {
+            "CREDIT"
+        };
+        transactions.push_str(&format!(
+            "<STMTTRN><TRNTYPE>{kind}</TRNTYPE><DTPOSTED>{}</DTPOSTED><TRNAMT>{}</TRNAMT><FITID>{}</FITID><NAME>{}</NAME><MEMO>{}</MEMO></STMTTRN>\n",
+            format_time(line.time, OFX_TIME),
+            line.amount,
+            line.txhash,
+            escape(&line.payee),
+            line.txhash
+        ));
+    }
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>
+<OFX>
+<SIGNONMSGSRSV1><SONRS><STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS><DTSERVER>{now}</DTSERVER><LANGUAGE>ENG</LANGUAGE></SONRS></SIGNONMSGSRSV1>
+<BANKMSGSRSV1><STMTTRNRS><TRNUID>0</TRNUID><STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>
+<STMTRS><CURDEF>XXX</CURDEF><BANKACCTFROM><BANKID>MEL</BANKID><ACCTID>{}</ACCTID><ACCTTYPE>CHECKING</ACCTTYPE></BANKACCTFROM>
+<BANKTRANLIST><DTSTART>{start}</DTSTART><DTEND>{now}</DTEND>
+{transactions}</BANKTRANLIST>
+<LEDGERBAL><BALAMT>{balance}</BALAMT><DTASOF>{now}</DTASOF></LEDGERBAL></STMTRS></STMTTRNRS></BANKMSGSRSV1>
+</OFX>
+"#,
+        escape(account_id)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use tmelcrypt::HashVal;
+
+    use super::*;
+
+    fn lines() -> Vec<ActivityLine> {
+        vec![
+            ActivityLine {
+                txhash: HashVal([1; 32]).into(),
+                time: 1_700_000_000,
+                amount: "12.5".into(),
+                payee: "Received".into(),
+            },
+            ActivityLine {
+                txhash: HashVal([2; 32]).into(),
+                time: 1_700_086_400,
+                amount: "-2.000001".into(),
+                payee: "Sent <to> savings & co".into(),
+            },
+        ]
+    }
+
+    #[test]
+    fn qif_entries() {
+        let qif = qif(&lines());
+        assert!(qif.starts_with("!Type:Bank\nD11/14/2023\nT12.5\nPReceived\n"));
+        assert!(qif.contains("D11/15/2023\nT-2.000001\nPSent <to> savings & co\n"));
+        assert_eq!(qif.matches("\n^\n").count(), 2);
+    }
+
+    #[test]
+    fn ofx_statement() {
+        let ofx = ofx("main:MEL", &lines(), "10.499999", 1_700_100_000);
+        assert!(ofx.contains("<ACCTID>main:MEL</ACCTID>"));
+        assert!(ofx.contains(
+            "<TRNTYPE>CREDIT</TRNTYPE><DTPOSTED>20231114221320</DTPOSTED><TRNAMT>12.5</TRNAMT>"
+        ));
+        assert!(ofx.contains("<TRNTYPE>DEBIT</TRNTYPE>"));
+        assert!(ofx.contains("<NAME>Sent &lt;to&gt; savings &amp; co</NAME>"));
+        assert!(ofx.contains("<DTSTART>20231114221320</DTSTART>"));
+        assert!(ofx.contains("<BALAMT>10.499999</BALAMT>"));
+    }
+}
@@ -0,0 +1,162 @@
+use std::convert::TryInto;
+
+use melstructs::Address;
+use tmelcrypt::HashVal;
+
+use crate::protocol::types::AddressForms;
+
+/// Human-readable prefix of bech32-encoded addresses.
+pub const BECH32_HRP: &str = "mel";
+
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Parses an address in any of the accepted encodings: the standard `t...` form (case-insensitive, dashes ignored, checksum checked), 64 hex digits with an optional `0x` prefix, or bech32 with the [BECH32_HRP] prefix.
+pub fn parse_address(s: &str) -> Option<Address> {
+    let s = s.trim();
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    if hex.len() == 64 {
+        if let Ok(bytes) = hex::decode(hex) {
+            return Some(HashVal(bytes.try_into().ok()?).into());
+        }
+    }
+    if let Some(address) = bech32_decode(s) {
+        return Some(address);
+    }
+    let standard = s.replace('-', "").to_ascii_lowercase();
+    let address: Address = standard.parse().ok()?;
+    // the standard parser doesn't check the checksum digit
+    (address.to_string() == standard).then_some(address)
+}
+
+/// Every encoding of an address.
+pub fn address_forms(address: Address) -> AddressForms {
+    let canonical = address.to_string();
+    let display = canonical
+        .to_ascii_uppercase()
+        .as_bytes()
+        .chunks(6)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect::<Vec<_>>()
+        .join("-");
+    AddressForms {
+        canonical,
+        display,
+        hex: hex::encode(address.0 .0),
+        bech32: bech32_encode(address),
+    }
+}
+
+fn bech32_polymod(values: impl Iterator<Item = u8>) -> u32 {
+    const GEN: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut chk = 1u32;
+    for value in values {
+        let top = chk >> 25;
+        chk = (chk & 0x1ffffff) << 5 ^ value as u32;
+        for (i, gen) in GEN.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= gen;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let bytes = hrp.as_bytes();
+    bytes
+        .iter()
+        .map(|b| b >> 5)
+        .chain(std::iter::once(0))
+        .chain(bytes.iter().map(|b| b & 31))
+        .collect()
+}
+
+fn bech32_encode_data(hrp: &str, data: &[u8]) -> String {
+    let polymod = bech32_polymod(
+        hrp_expand(hrp)
+            .into_iter()
+            .chain(data.iter().copied())
+            .chain([0u8; 6]),
+    ) ^ 1;
+    let checksum = (0..6).map(|i| ((polymod >> (5 * (5 - i))) & 31) as u8);
+    let mut out = format!("{hrp}1");
+    out.extend(
+        data.iter()
+            .copied()
+            .chain(checksum)
+            .map(|d| BECH32_CHARSET[d as usize] as char),
+    );
+    out
+}
+
+fn bech32_encode(address: Address) -> String {
+    // regroup the 256 bits into 5-bit groups, padding the last one with zeros
+    let mut data = vec![];
+    let (mut acc, mut bits) = (0u32, 0u32);
+    for byte in address.0 .0 {
+        acc = (acc << 8 | byte as u32) & 0xfff;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            data.push(((acc >> bits) & 31) as u8);
+        }
+    }
+    if bits > 0 {
+        data.push(((acc << (5 - bits)) & 31) as u8);
+    }
+    bech32_encode_data(BECH32_HRP, &data)
+}
+
+fn bech32_decode(s: &str) -> Option<Address> {
+    let s = s.to_ascii_lowercase();
+    let (hrp, data) = s.rsplit_once('1')?;
+    if hrp != BECH32_HRP || data.len() < 6 {
+        return None;
+    }
+    let data: Vec<u8> = data
+        .bytes()
+        .map(|c| BECH32_CHARSET.iter().position(|&x| x == c).map(|p| p as u8))
+        .collect::<Option<_>>()?;
+    if bech32_polymod(hrp_expand(hrp).into_iter().chain(data.iter().copied())) != 1 {
+        return None;
+    }
+    let mut bytes = vec![];
+    let (mut acc, mut bits) = (0u32, 0u32);
+    for group in &data[..data.len() - 6] {
+        acc = (acc << 5 | *group as u32) & 0xfff;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    // leftover bits must be zero padding
+    if bits >= 5 || acc & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(HashVal(bytes.try_into().ok()?).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        // from BIP-173
+        assert_eq!(bech32_encode_data("a", &[]), "a12uel5l");
+
+        let address: Address = tmelcrypt::hash_single(b"hello").into();
+        let forms = address_forms(address);
+        for form in [&forms.canonical, &forms.display, &forms.hex, &forms.bech32] {
+            assert_eq!(parse_address(form), Some(address), "{form}");
+        }
+        assert_eq!(parse_address(&format!("0x{}", forms.hex)), Some(address));
+        let mut typo = forms.canonical.clone();
+        typo.replace_range(1..2, if &typo[1..2] == "0" { "1" } else { "0" });
+        assert_eq!(parse_address(&typo), None);
+        let mut bad_bech32 = forms.bech32.clone();
+        bad_bech32.pop();
+        assert_eq!(parse_address(&bad_bech32), None);
+    }
+}
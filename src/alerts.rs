@@ -0,0 +1,77 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use melstructs::{BlockHeight, CoinValue, Denom};
+
+use crate::{
+    database::Database,
+    invoice::fire_webhook,
+    protocol::types::{AlertKind, WalletAlert},
+};
+
+/// Checks every wallet with alert thresholds against them after a sync to `height`, raising an alert for each threshold newly crossed.
+pub async fn check_alerts(database: &Database, height: BlockHeight) -> anyhow::Result<()> {
+    for name in database.alerted_wallets().await? {
+        let wallet = if let Some(wallet) = database.get_wallet(&name).await {
+            wallet
+        } else {
+            continue;
+        };
+        let (thresholds, was_low) = if let Some(t) = database.alert_thresholds(&name).await? {
+            t
+        } else {
+            continue;
+        };
+        let mut alerts = vec![];
+        if let Some(threshold) = thresholds.min_balance {
+            let balance = wallet
+                .get_balances()
+                .await
+                .get(&Denom::Mel)
+                .copied()
+                .unwrap_or_default();
+            let is_low = balance < threshold;
+            // alert only on dropping below the threshold, not on every sync while below it
+            if is_low && !was_low {
+                alerts.push(AlertKind::LowBalance { balance, threshold });
+            }
+            if is_low != was_low {
+                database.set_low_balance(&name, is_low).await?;
+            }
+        }
+        if let Some(threshold) = thresholds.max_outgoing {
+            for txhash in wallet.confirmed_spenders().await? {
+                // transactions not cached yet are checked once a later sync caches them
+                let txn = match wallet.get_cached_transaction(txhash).await {
+                    Some(txn) => txn,
+                    None => continue,
+                };
+                if database.check_once(&name, txhash).await? {
+                    continue;
+                }
+                let amount = CoinValue(wallet.outgoing_value(&txn));
+                if amount > threshold {
+                    alerts.push(AlertKind::LargeOutgoing {
+                        txhash,
+                        amount,
+                        threshold,
+                    });
+                }
+            }
+        }
+        for kind in alerts {
+            let alert = WalletAlert {
+                wallet: name.clone(),
+                height,
+                time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                kind,
+            };
+            log::warn!("alert for {name}: {:?}", alert.kind);
+            database.record_alert(&alert).await?;
+            if let Some(url) = thresholds.alert_webhook.clone() {
+                let label = format!("alert for {name}");
+                smolscale::spawn(fire_webhook(url, label, alert)).detach();
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,193 @@
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use melstructs::{Denom, Transaction};
+
+use crate::{
+    database::Wallet,
+    invoice::fire_webhook,
+    protocol::types::{AnomalyPolicy, HeldSend, HoldKind},
+    state::AppState,
+};
+
+/// A transaction sent by a wallet, as far as anomaly detection is concerned.
+#[derive(Clone, Debug)]
+pub struct SendActivity {
+    /// UNIX timestamp of when the transaction was sent
+    pub time: u64,
+    /// What was sent to addresses other than the wallet's own, by denomination
+    pub values: BTreeMap<Denom, u128>,
+}
+
+/// Checks whether sending a transaction with the given outgoing values, by denomination, now would be anomalous for a wallet with the given send history. Returns why, if so.
+///
+/// The baseline is the busiest `window_secs`-long window of the `baseline_secs` before the current window. A send is anomalous if it makes the current window's send count, or its volume of any denomination, exceed the baseline's by more than `multiplier` times. Wallets with fewer than `min_baseline_sends` sends in the baseline period have no baseline yet, so nothing they send is anomalous.
+pub fn check_send(
+    policy: &AnomalyPolicy,
+    history: &[SendActivity],
+    now: u64,
+    values: &BTreeMap<Denom, u128>,
+) -> Option<String> {
+    let window = policy.window_secs.max(1);
+    let window_start = now.saturating_sub(window);
+    let baseline_start = window_start.saturating_sub(policy.baseline_secs);
+    let baseline: Vec<&SendActivity> = history
+        .iter()
+        .filter(|s| s.time > baseline_start && s.time <= window_start)
+        .collect();
+    if baseline.len() < policy.min_baseline_sends.max(1) {
+        return None;
+    }
+
+    // bucket the baseline into windows that end where the current window starts
+    let mut buckets: Vec<(usize, BTreeMap<Denom, u128>)> =
+        vec![Default::default(); (policy.baseline_secs / window).max(1) as usize + 1];
+    for send in baseline {
+        let bucket = ((window_start - send.time) / window) as usize;
+        if let Some((count, volumes)) = buckets.get_mut(bucket) {
+            *count += 1;
+            for (denom, value) in send.values.iter() {
+                *volumes.entry(*denom).or_default() += value;
+            }
+        }
+    }
+    let peak_count = buckets.iter().map(|b| b.0).max().unwrap_or_default();
+
+    let mut count = 1usize;
+    let mut volumes = values.clone();
+    for send in history.iter().filter(|s| s.time > window_start) {
+        count += 1;
+        for (denom, value) in send.values.iter() {
+            *volumes.entry(*denom).or_default() += value;
+        }
+    }
+    if count as f64 > peak_count as f64 * policy.multiplier {
+        return Some(format!(
+            "{count} sends in the last {window} seconds, against at most {peak_count} per {window} seconds before"
+        ));
+    }
+    // volumes are compared per denomination, so a burst of one token can't hide behind the usual volume of another
+    for (denom, volume) in volumes {
+        let peak_volume = buckets
+            .iter()
+            .map(|b| b.1.get(&denom).copied().unwrap_or_default())
+            .max()
+            .unwrap_or_default();
+        if volume as f64 > peak_volume as f64 * policy.multiplier {
+            return Some(format!(
+                "{volume} {denom} units sent in the last {window} seconds, against at most {peak_volume} per {window} seconds before"
+            ));
+        }
+    }
+    None
+}
+
+impl AppState {
+    /// Holds back a transaction that a wallet is about to send, if it is anomalous under the wallet's policy, and alerts about it. Returns the held transaction, if so.
+    pub async fn hold_if_anomalous(
+        &self,
+        name: &str,
+        wallet: &Wallet,
+        tx: &Transaction,
+    ) -> anyhow::Result<Option<HeldSend>> {
+        let policy = match self.database.anomaly_policy(name).await? {
+            Some(policy) => policy,
+            None => return Ok(None),
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let history = wallet
+            .send_activity(now.saturating_sub(policy.window_secs + policy.baseline_secs))
+            .await?;
+        let reason = match check_send(&policy, &history, now, &wallet.outgoing_values(tx)) {
+            Some(reason) => reason,
+            None => return Ok(None),
+        };
+        let held = self
+            .database
+            .hold_send(name, tx, HoldKind::Anomaly, &reason)
+            .await?;
+        log::warn!(
+            "held anomalous transaction {} of {name}: {reason}",
+            held.txhash
+        );
+        if let Some(url) = policy.alert_webhook {
+            let label = format!("held transaction {}", held.txhash);
+            smolscale::spawn(fire_webhook(url, label, held.clone())).detach();
+        }
+        Ok(Some(held))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mel(value: u128) -> BTreeMap<Denom, u128> {
+        std::iter::once((Denom::Mel, value)).collect()
+    }
+
+    fn policy() -> AnomalyPolicy {
+        AnomalyPolicy {
+            window_secs: 3600,
+            baseline_secs: 86400,
+            multiplier: 3.0,
+            min_baseline_sends: 5,
+            alert_webhook: None,
+        }
+    }
+
+    #[test]
+    fn detects_bursts() {
+        let policy = policy();
+        let now = 1_000_000;
+        // one send of 100 every two hours for the past day
+        let mut history: Vec<SendActivity> = (1..12)
+            .map(|i| SendActivity {
+                time: now - 7200 * i,
+                values: mel(100),
+            })
+            .collect();
+        assert!(check_send(&policy, &history, now, &mel(100)).is_none());
+        assert!(check_send(&policy, &history, now, &mel(301)).is_some());
+        assert!(check_send(&policy, &[], now, &mel(u128::MAX / 2)).is_none());
+
+        history.extend((0..2).map(|i| SendActivity {
+            time: now - 60 * i,
+            values: mel(1),
+        }));
+        assert!(check_send(&policy, &history, now, &mel(1)).is_none());
+        history.push(SendActivity {
+            time: now - 10,
+            values: mel(1),
+        });
+        assert!(check_send(&policy, &history, now, &mel(1)).is_some());
+    }
+
+    #[test]
+    fn volume_is_per_denom() {
+        let policy = policy();
+        let now = 1_000_000;
+        // a wallet that only ever sent small amounts of MEL
+        let history: Vec<SendActivity> = (1..12)
+            .map(|i| SendActivity {
+                time: now - 7200 * i,
+                values: mel(100),
+            })
+            .collect();
+        let sym = std::iter::once((Denom::Sym, 1_000_000_000)).collect();
+        assert!(check_send(&policy, &history, now, &sym).is_some());
+
+        // and one that usually sends SYM too
+        let history: Vec<SendActivity> = (1..12)
+            .map(|i| SendActivity {
+                time: now - 7200 * i,
+                values: BTreeMap::from([(Denom::Mel, 100), (Denom::Sym, 1_000_000_000)]),
+            })
+            .collect();
+        assert!(check_send(&policy, &history, now, &sym).is_none());
+        let sym = std::iter::once((Denom::Sym, 3_000_000_001)).collect();
+        assert!(check_send(&policy, &history, now, &sym).is_some());
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use melstructs::NetID;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cli::Config,
+    database::{inspect_snapshot, Database},
+    protocol::types::{BackupError, BackupInfo},
+    secrets::{argon2id_key, entry_dir, SecretStore, MASTER_KEY_FILE, MEM_COST, TIME_COST},
+    state::AppState,
+};
+
+mod s3;
+
+pub use s3::S3Config;
+
+/// Extension of backup object names.
+const BACKUP_EXTENSION: &str = "mwbak";
+
+/// Directory, within the wallet directory, where a restored backup waits for the daemon to restart.
+const RESTORE_DIR: &str = "pending-restore";
+
+/// Suffix of files set aside when a backup is restored over them.
+const SET_ASIDE_SUFFIX: &str = ".before-restore";
+
+/// Configuration of encrypted backups. Backups are only taken if this is present in the config file.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct BackupConfig {
+    /// Bucket that backups are uploaded to
+    pub s3: S3Config,
+    /// Passphrase that backups are encrypted with. Backups cannot be restored without it!
+    pub passphrase: String,
+    /// Seconds between automatic backups
+    #[serde(default = "default_interval")]
+    pub interval_secs: u64,
+}
+
+fn default_interval() -> u64 {
+    6 * 60 * 60
+}
+
+/// Somewhere backups can be kept. Backups are already encrypted when they reach the driver, so drivers don't need to trust the storage they use.
+#[async_trait]
+pub trait BackupDriver: Send + Sync + 'static {
+    /// Stores a backup under a name, replacing any backup of the same name.
+    async fn put(&self, name: &str, data: Vec<u8>) -> anyhow::Result<()>;
+
+    /// Retrieves a backup by name.
+    async fn get(&self, name: &str) -> anyhow::Result<Vec<u8>>;
+
+    /// Lists the names of all stored objects.
+    async fn list(&self) -> anyhow::Result<Vec<String>>;
+}
+
+/// Creates the driver for the configured storage.
+pub fn backup_driver(config: &BackupConfig) -> Arc<dyn BackupDriver> {
+    Arc::new(s3::S3Driver::new(config.s3.clone()))
+}
+
+/// Everything needed to bring a daemon back: its secrets, and a snapshot of its database.
+#[derive(Serialize, Deserialize)]
+struct BackupBundle {
+    created: u64,
+    network: NetID,
+    /// The secret store, in its on-disk format. Password-protected keys stay encrypted under their own passwords.
+    secrets: Vec<u8>,
+    /// The main database file
+    database: Vec<u8>,
+    /// Per-wallet database files, by file name, if wallets are stored separately
+    wallet_files: BTreeMap<String, Vec<u8>>,
+}
+
+/// A bundle encrypted with a key derived from the backup passphrase, in the same way as [crate::secrets::EncryptedSK].
+#[derive(Serialize, Deserialize)]
+struct SealedBundle {
+    argon2id_salt: Vec<u8>,
+    argon2id_mem_cost: u32,
+    argon2id_time_cost: u32,
+    cp20p1350_ciphertext: Vec<u8>,
+}
+
+impl BackupBundle {
+    fn seal(&self, passphrase: &str) -> Vec<u8> {
+        let plain = stdcode::serialize(self).expect("cannot serialize backup");
+        let mut salt = [0u8; 16];
+        getrandom::getrandom(&mut salt).unwrap();
+        let key = argon2id_key(passphrase, &salt, MEM_COST, TIME_COST);
+        let aead = crypto_api_chachapoly::ChachaPolyIetf::aead_cipher();
+        let mut ciphertext = vec![0u8; plain.len() + 16];
+        aead.seal_to(&mut ciphertext, &plain, &[], &key, &[0; 12])
+            .expect("seal failed");
+        stdcode::serialize(&SealedBundle {
+            argon2id_salt: salt.to_vec(),
+            argon2id_mem_cost: MEM_COST,
+            argon2id_time_cost: TIME_COST,
+            cp20p1350_ciphertext: ciphertext,
+        })
+        .expect("cannot serialize backup")
+    }
+
+    fn open(sealed: &[u8], passphrase: &str) -> Result<Self, BackupError> {
+        let sealed: SealedBundle =
+            stdcode::deserialize(sealed).map_err(|e| BackupError::Corrupt(e.to_string()))?;
+        if sealed.cp20p1350_ciphertext.len() < 16 {
+            return Err(BackupError::Corrupt("truncated".into()));
+        }
+        let key = argon2id_key(
+            passphrase,
+            &sealed.argon2id_salt,
+            sealed.argon2id_mem_cost,
+            sealed.argon2id_time_cost,
+        );
+        let aead = crypto_api_chachapoly::ChachaPolyIetf::aead_cipher();
+        let mut plain = vec![0u8; sealed.cp20p1350_ciphertext.len() - 16];
+        aead.open_to(
+            &mut plain,
+            &sealed.cp20p1350_ciphertext,
+            &[],
+            &key,
+            &[0; 12],
+        )
+        .map_err(|_| BackupError::Corrupt("cannot decrypt".into()))?;
+        stdcode::deserialize(&plain).map_err(|e| BackupError::Corrupt(e.to_string()))
+    }
+
+    /// Checks the integrity of every database file in the bundle, returning the names of the wallets it contains.
+    fn inspect(&self, scratch: &Path) -> Result<Vec<String>, BackupError> {
+        serde_json::from_slice::<serde_json::Value>(&self.secrets)
+            .map_err(|e| BackupError::Corrupt(format!("bad secrets: {e}")))?;
+        for (name, contents) in self.wallet_files.iter() {
+            inspect_snapshot(contents, scratch)
+                .map_err(|e| BackupError::Corrupt(format!("bad wallet file {name}: {e}")))?;
+        }
+        inspect_snapshot(&self.database, scratch)
+            .map_err(|e| BackupError::Corrupt(format!("bad database: {e}")))
+    }
+}
+
+fn backup_name(network: NetID, created: u64) -> String {
+    format!("{network:?}-{created}.{BACKUP_EXTENSION}").to_ascii_lowercase()
+}
+
+/// Takes a backup and uploads it.
+async fn upload_backup(
+    database: &Database,
+    secrets: &SecretStore,
+    driver: &dyn BackupDriver,
+    config: &Config,
+) -> Result<BackupInfo, BackupError> {
+    let backup = config.backup.as_ref().ok_or(BackupError::NotConfigured)?;
+    let created = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let (db_contents, wallet_files) = database
+        .snapshot(&config.wallet_dir)
+        .await
+        .map_err(|e| BackupError::Other(e.to_string()))?;
+    let bundle = BackupBundle {
+        created,
+        network: config.network,
+        secrets: secrets.export().ok_or_else(|| {
+            BackupError::Other(
+                "secrets are sealed until unlocked with the master passphrase".into(),
+            )
+        })?,
+        database: db_contents,
+        wallet_files,
+    };
+    let wallets = bundle.inspect(&config.wallet_dir)?;
+    let passphrase = backup.passphrase.clone();
+    let sealed = smol::unblock(move || bundle.seal(&passphrase)).await;
+    let name = backup_name(config.network, created);
+    let size = sealed.len() as u64;
+    driver
+        .put(&name, sealed)
+        .await
+        .map_err(|e| BackupError::Storage(e.to_string()))?;
+    log::info!("uploaded backup {name} ({size} bytes)");
+    Ok(BackupInfo {
+        name,
+        created,
+        size,
+        network: config.network,
+        wallets,
+    })
+}
+
+/// Periodically uploads a backup. Only spawned if backups are configured.
+pub async fn backup_task(
+    database: Arc<Database>,
+    secrets: Arc<SecretStore>,
+    driver: Arc<dyn BackupDriver>,
+    config: Arc<Config>,
+) {
+    let interval = config
+        .backup
+        .as_ref()
+        .map(|b| b.interval_secs)
+        .unwrap_or_else(default_interval);
+    let mut pacer = smol::Timer::interval(Duration::from_secs(interval.max(60)));
+    loop {
+        (&mut pacer).await;
+        if let Err(err) = upload_backup(&database, &secrets, driver.as_ref(), &config).await {
+            log::warn!("automatic backup failed: {:?}", err);
+        }
+    }
+}
+
+impl AppState {
+    fn backup_config(&self) -> Result<(&BackupConfig, &dyn BackupDriver), BackupError> {
+        match (self.config.backup.as_ref(), self.backups.as_ref()) {
+            (Some(config), Some(driver)) => Ok((config, driver.as_ref())),
+            _ => Err(BackupError::NotConfigured),
+        }
+    }
+
+    /// Takes a backup right away.
+    pub async fn backup_now(&self) -> Result<BackupInfo, BackupError> {
+        let (_, driver) = self.backup_config()?;
+        upload_backup(&self.database, &self.secrets, driver, &self.config).await
+    }
+
+    /// Lists the backups of this daemon's network in storage, oldest first.
+    pub async fn list_backups(&self) -> Result<Vec<String>, BackupError> {
+        let (_, driver) = self.backup_config()?;
+        let prefix = backup_name(self.network, 0);
+        let prefix = prefix.trim_end_matches(&format!("0.{BACKUP_EXTENSION}"));
+        let mut names: Vec<(u64, String)> = driver
+            .list()
+            .await
+            .map_err(|e| BackupError::Storage(e.to_string()))?
+            .into_iter()
+            .filter_map(|name| {
+                let created = name
+                    .strip_prefix(prefix)?
+                    .strip_suffix(&format!(".{BACKUP_EXTENSION}"))?
+                    .parse()
+                    .ok()?;
+                Some((created, name))
+            })
+            .collect();
+        names.sort_unstable();
+        Ok(names.into_iter().map(|(_, name)| name).collect())
+    }
+
+    /// Downloads and decrypts a backup, checking that it belongs to this daemon's network.
+    async fn fetch_backup(&self, name: &str) -> Result<(BackupBundle, BackupInfo), BackupError> {
+        let (config, driver) = self.backup_config()?;
+        let sealed = driver
+            .get(name)
+            .await
+            .map_err(|e| BackupError::Storage(e.to_string()))?;
+        let size = sealed.len() as u64;
+        let bundle = smol::unblock({
+            let passphrase = config.passphrase.clone();
+            move || BackupBundle::open(&sealed, &passphrase)
+        })
+        .await?;
+        if bundle.network != self.network {
+            return Err(BackupError::WrongNetwork(bundle.network));
+        }
+        let wallets = bundle.inspect(&self.config.wallet_dir)?;
+        let info = BackupInfo {
+            name: name.to_owned(),
+            created: bundle.created,
+            size,
+            network: bundle.network,
+            wallets,
+        };
+        Ok((bundle, info))
+    }
+
+    /// Checks that a backup can be decrypted and restored, without restoring it.
+    pub async fn verify_backup(&self, name: &str) -> Result<BackupInfo, BackupError> {
+        Ok(self.fetch_backup(name).await?.1)
+    }
+
+    /// Stages a backup to replace the current database and secrets the next time the daemon starts.
+    pub async fn restore_backup(&self, name: &str) -> Result<BackupInfo, BackupError> {
+        let (bundle, info) = self.fetch_backup(name).await?;
+        stage_restore(&bundle, &self.config.wallet_dir, &self.secrets)
+            .map_err(|e| BackupError::Other(e.to_string()))?;
+        log::warn!("staged backup {name} for restoring; restart the daemon to restore it");
+        Ok(info)
+    }
+}
+
+/// Writes out a bundle, to be swapped in by [apply_staged_restore]. The bundle is written to a temporary directory first, so that a half-written bundle is never restored. Its secrets are written as a secrets directory protected like the current one, so that a master passphrase stays set and the secrets never touch the disk unencrypted.
+fn stage_restore(
+    bundle: &BackupBundle,
+    wallet_dir: &Path,
+    secrets: &SecretStore,
+) -> anyhow::Result<()> {
+    let staging = wallet_dir.join(format!("{RESTORE_DIR}.tmp"));
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::create_dir_all(&staging)?;
+    secrets.write_restored(&bundle.secrets, &staging.join("secrets.d"))?;
+    std::fs::write(staging.join("wallets.db"), &bundle.database)?;
+    if !bundle.wallet_files.is_empty() {
+        std::fs::create_dir(staging.join("wallets"))?;
+        for (name, contents) in bundle.wallet_files.iter() {
+            std::fs::write(staging.join("wallets").join(name), contents)?;
+        }
+    }
+    let staged = wallet_dir.join(RESTORE_DIR);
+    let _ = std::fs::remove_dir_all(&staged);
+    std::fs::rename(&staging, &staged)?;
+    Ok(())
+}
+
+/// Swaps a restored backup in for the database and secrets, if one was staged. Must be called before either is opened. Whatever is replaced is set aside rather than deleted.
+pub fn apply_staged_restore(
+    wallet_dir: &Path,
+    db_path: &Path,
+    secrets_path: &Path,
+) -> anyhow::Result<bool> {
+    let staged = wallet_dir.join(RESTORE_DIR);
+    if !staged.exists() {
+        return Ok(false);
+    }
+    let split_dir = db_path.with_extension("d");
+    for suffix in ["", "-wal", "-shm"].iter() {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(suffix);
+        set_aside(&PathBuf::from(path))?;
+    }
+    set_aside(secrets_path)?;
+    set_aside(&entry_dir(secrets_path))?;
+    set_aside(&split_dir)?;
+    std::fs::rename(staged.join("wallets.db"), db_path)?;
+    if staged.join("secrets.d").exists() {
+        std::fs::rename(staged.join("secrets.d"), entry_dir(secrets_path))?;
+    } else {
+        // staged by an older version as a plaintext secrets file, which the store moves into its directory once opened; keep the master key, so that it does so under the same passphrase
+        let master_key = entry_dir(secrets_path).join(MASTER_KEY_FILE);
+        let set_aside_master_key =
+            with_suffix(&entry_dir(secrets_path), SET_ASIDE_SUFFIX).join(MASTER_KEY_FILE);
+        if set_aside_master_key.exists() {
+            std::fs::create_dir_all(entry_dir(secrets_path))?;
+            std::fs::copy(set_aside_master_key, master_key)?;
+        }
+        std::fs::rename(staged.join("secrets.json"), secrets_path)?;
+    }
+    if staged.join("wallets").exists() {
+        std::fs::rename(staged.join("wallets"), &split_dir)?;
+    }
+    std::fs::remove_dir_all(&staged)?;
+    log::warn!("restored backup into {:?}", wallet_dir);
+    Ok(true)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+fn set_aside(path: &Path) -> anyhow::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let aside = with_suffix(path, SET_ASIDE_SUFFIX);
+    if aside.is_dir() {
+        std::fs::remove_dir_all(&aside)?;
+    } else if aside.exists() {
+        std::fs::remove_file(&aside)?;
+    }
+    std::fs::rename(path, &aside)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tmelcrypt::Ed25519SK;
+
+    use super::*;
+    use crate::secrets::PersistentSecret;
+
+    #[test]
+    fn seal_open() {
+        let bundle = BackupBundle {
+            created: 1234,
+            network: NetID::Testnet,
+            secrets: b"{}".to_vec(),
+            database: vec![1, 2, 3],
+            wallet_files: Default::default(),
+        };
+        let sealed = bundle.seal("correct horse");
+        let opened = BackupBundle::open(&sealed, "correct horse").unwrap();
+        assert_eq!(opened.created, 1234);
+        assert_eq!(opened.database, vec![1, 2, 3]);
+        assert!(BackupBundle::open(&sealed, "wrong horse").is_err());
+        assert_eq!(backup_name(NetID::Testnet, 1234), "testnet-1234.mwbak");
+    }
+
+    #[test]
+    fn restore_keeps_master_passphrase() {
+        let dir = std::env::temp_dir().join(format!("melwalletd-restore-{}", fastrand::u64(..)));
+        std::fs::create_dir_all(&dir).unwrap();
+        let secrets_path = dir.join("secrets.json");
+        let secrets = SecretStore::open(&secrets_path).unwrap();
+        let sk = Ed25519SK::generate();
+        secrets
+            .store("alice".into(), PersistentSecret::Plaintext(sk))
+            .unwrap();
+        secrets
+            .set_master_passphrase(None, Some("hunter2"))
+            .unwrap();
+        let bundle = BackupBundle {
+            created: 1234,
+            network: NetID::Testnet,
+            secrets: secrets.export().unwrap(),
+            database: vec![],
+            wallet_files: Default::default(),
+        };
+
+        stage_restore(&bundle, &dir, &secrets).unwrap();
+        drop(secrets);
+        assert!(apply_staged_restore(&dir, &dir.join("wallets.db"), &secrets_path).unwrap());
+        // nothing unencrypted was written
+        assert!(!secrets_path.exists());
+        let on_disk = std::fs::read_to_string(entry_dir(&secrets_path).join("alice.json")).unwrap();
+        assert!(!on_disk.contains(&hex::encode(&sk.0[..32])));
+
+        let secrets = SecretStore::open(&secrets_path).unwrap();
+        assert!(secrets.is_sealed());
+        secrets.unseal("hunter2").unwrap();
+        assert_eq!(secrets.load("alice"), Some(PersistentSecret::Plaintext(sk)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
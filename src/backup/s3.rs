@@ -0,0 +1,185 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use hmac_sha256::{Hash, HMAC};
+use http_types::{Body, Method, Request, Url};
+use serde::{Deserialize, Serialize};
+
+use super::BackupDriver;
+
+/// Where an S3-compatible bucket is, and how to authenticate to it.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct S3Config {
+    /// Base URL of the object storage service, such as `https://s3.us-east-1.amazonaws.com`. Buckets are always addressed path-style, which every S3-compatible service supports.
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Prefix of every object name, such as `melwalletd/`
+    #[serde(default)]
+    pub prefix: String,
+}
+
+fn default_region() -> String {
+    "us-east-1".into()
+}
+
+/// Stores backups in an S3-compatible bucket, signing requests with AWS signature version 4.
+pub struct S3Driver {
+    config: S3Config,
+}
+
+impl S3Driver {
+    pub fn new(config: S3Config) -> Self {
+        Self { config }
+    }
+
+    /// Sends a signed request for an object (or, if `key` is None, for the bucket itself), returning the response body.
+    async fn request(
+        &self,
+        method: Method,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut path = format!("/{}", uri_encode(&self.config.bucket, false));
+        if let Some(key) = key {
+            path.push('/');
+            path.push_str(&uri_encode(&format!("{}{key}", self.config.prefix), true));
+        }
+        let mut query: Vec<String> = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", uri_encode(k, false), uri_encode(v, false)))
+            .collect();
+        query.sort();
+        let query = query.join("&");
+        let mut url = Url::parse(self.config.endpoint.trim_end_matches('/'))?;
+        url.set_path(&path);
+        url.set_query(Some(&query).filter(|q| !q.is_empty()).map(|q| q.as_str()));
+        let host = url.host_str().context("storage endpoint has no host")?;
+        let port = url.port_or_known_default().unwrap_or(443);
+        let host_header = match url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_owned(),
+        };
+
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex::encode(Hash::hash(&body));
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{method}\n{path}\n{query}\nhost:{host_header}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}"
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Hash::hash(canonical_request.as_bytes()))
+        );
+        let signing_key = [self.config.region.as_str(), "s3", "aws4_request"]
+            .iter()
+            .fold(
+                HMAC::mac(&date, format!("AWS4{}", self.config.secret_access_key)),
+                |key, part| HMAC::mac(part, key),
+            );
+        let signature = hex::encode(HMAC::mac(&string_to_sign, signing_key));
+
+        let mut req = Request::new(method, url.clone());
+        req.insert_header("host", host_header.as_str());
+        req.insert_header("x-amz-content-sha256", payload_hash.as_str());
+        req.insert_header("x-amz-date", amz_date.as_str());
+        req.insert_header(
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                self.config.access_key_id
+            ),
+        );
+        req.set_body(Body::from_bytes(body));
+
+        let stream = smol::net::TcpStream::connect((host, port)).await?;
+        let mut resp = if url.scheme() == "https" {
+            let stream = async_native_tls::connect(host, stream).await?;
+            async_h1::connect(stream, req).await
+        } else {
+            async_h1::connect(stream, req).await
+        }
+        .map_err(|e| e.into_inner())?;
+        let body = resp.body_bytes().await.map_err(|e| e.into_inner())?;
+        anyhow::ensure!(
+            resp.status().is_success(),
+            "storage returned {}: {}",
+            resp.status(),
+            String::from_utf8_lossy(&body)
+        );
+        Ok(body)
+    }
+}
+
+#[async_trait]
+impl BackupDriver for S3Driver {
+    async fn put(&self, name: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        self.request(Method::Put, Some(name), &[], data).await?;
+        Ok(())
+    }
+
+    async fn get(&self, name: &str) -> anyhow::Result<Vec<u8>> {
+        self.request(Method::Get, Some(name), &[], vec![]).await
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<String>> {
+        let mut names = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", self.config.prefix.as_str())];
+            if let Some(token) = token.as_deref() {
+                query.push(("continuation-token", token));
+            }
+            let body = self.request(Method::Get, None, &query, vec![]).await?;
+            let body = String::from_utf8(body).context("listing is not UTF-8")?;
+            names.extend(
+                xml_values(&body, "Key")
+                    .into_iter()
+                    .filter_map(|key| key.strip_prefix(&self.config.prefix).map(xml_unescape)),
+            );
+            token = xml_values(&body, "NextContinuationToken")
+                .first()
+                .map(|token| xml_unescape(token));
+            if token.is_none() {
+                return Ok(names);
+            }
+        }
+    }
+}
+
+/// Percent-encodes a string the way AWS signatures expect, optionally leaving slashes alone.
+fn uri_encode(s: &str, keep_slash: bool) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if keep_slash => "/".into(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Iterates over the contents of every `<tag>` element of an XML document. S3 listings are simple enough not to need a real XML parser.
+fn xml_values<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+    xml.split(open.as_str())
+        .skip(1)
+        .filter_map(|rest| rest.split(close.as_str()).next())
+        .collect()
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
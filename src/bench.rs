@@ -0,0 +1,325 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::Future;
+use melstructs::{
+    Address, BlockHeight, CoinData, CoinDataHeight, CoinID, CoinValue, Denom, NetID, Transaction,
+};
+use melvm::Covenant;
+use nanorpc::{JrpcId, JrpcRequest, RpcService};
+use serde::Serialize;
+use tmelcrypt::{Ed25519SK, HashVal};
+
+use crate::{
+    cli::{BenchArgs, Config},
+    database::Database,
+    node_select::NodeSelection,
+    offline::offline_client,
+    password::PasswordPolicy,
+    protocol::{rpc_service, types::CoinSelection},
+    secrets::SecretStore,
+    signer::Signer,
+    state::AppState,
+};
+
+/// Fee multiplier of the synthetic transactions, around what the network charges.
+const FEE_MULTIPLIER: u128 = 1 << 10;
+
+/// How long one phase of the benchmark took, and how long its operations took.
+#[derive(Serialize, Debug)]
+pub struct PhaseReport {
+    pub name: &'static str,
+    pub operations: usize,
+    pub elapsed_secs: f64,
+    pub per_sec: f64,
+    pub p50_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BenchReport {
+    pub wallets: usize,
+    pub coins_per_wallet: usize,
+    pub concurrency: usize,
+    pub phases: Vec<PhaseReport>,
+}
+
+/// Summarizes the latencies of a phase's operations, which took `elapsed` in all.
+fn summarize(name: &'static str, mut latencies: Vec<Duration>, elapsed: Duration) -> PhaseReport {
+    latencies.sort();
+    let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+    let percentile = |p: usize| {
+        if latencies.is_empty() {
+            return 0.0;
+        }
+        millis(latencies[(latencies.len() * p / 100).min(latencies.len() - 1)])
+    };
+    PhaseReport {
+        name,
+        operations: latencies.len(),
+        elapsed_secs: elapsed.as_secs_f64(),
+        per_sec: latencies.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        p50_ms: percentile(50),
+        p99_ms: percentile(99),
+        max_ms: latencies.last().copied().map(millis).unwrap_or_default(),
+    }
+}
+
+/// Runs `iterations` operations, at most `concurrency` at a time, timing each. The operation is given its index.
+async fn run_phase<F, Fut>(
+    name: &'static str,
+    iterations: usize,
+    concurrency: usize,
+    op: F,
+) -> anyhow::Result<PhaseReport>
+where
+    F: Fn(usize) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = anyhow::Result<()>> + Send,
+{
+    let op = Arc::new(op);
+    let next = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+    let workers = (0..concurrency.max(1)).map(|_| {
+        let op = op.clone();
+        let next = next.clone();
+        smolscale::spawn(async move {
+            let mut latencies = vec![];
+            loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= iterations {
+                    return anyhow::Ok(latencies);
+                }
+                let start = Instant::now();
+                op(i).await?;
+                latencies.push(start.elapsed());
+            }
+        })
+    });
+    let mut latencies = vec![];
+    for worker in futures::future::join_all(workers).await {
+        latencies.extend(worker?);
+    }
+    Ok(summarize(name, latencies, start.elapsed()))
+}
+
+/// Coins of random values and heights at an address.
+fn synthetic_coins(covhash: Address, count: usize) -> BTreeMap<CoinID, CoinDataHeight> {
+    (0..count)
+        .map(|_| {
+            let coin = CoinID {
+                txhash: HashVal::random().into(),
+                index: 0,
+            };
+            let data = CoinDataHeight {
+                coin_data: CoinData {
+                    covhash,
+                    value: CoinValue(fastrand::u128(1_000..1_000_000)),
+                    denom: Denom::Mel,
+                    additional_data: Default::default(),
+                },
+                height: BlockHeight(fastrand::u64(1..100_000)),
+            };
+            (coin, data)
+        })
+        .collect()
+}
+
+/// Directory for the throwaway database, in memory-backed storage where there is some.
+fn scratch_dir() -> PathBuf {
+    let shm = PathBuf::from("/dev/shm");
+    let base = if shm.is_dir() {
+        shm
+    } else {
+        std::env::temp_dir()
+    };
+    base.join(format!(
+        "melwalletd-bench-{}",
+        hex::encode(HashVal::random().0)
+    ))
+}
+
+/// Benchmarks the daemon against a throwaway database of synthetic wallets, without any node: storing synced coins, preparing transactions, and answering RPC calls. Prints a report, as JSON if asked to.
+pub async fn run_bench(args: BenchArgs) -> anyhow::Result<()> {
+    anyhow::ensure!(args.wallets > 0, "need at least one wallet");
+    let dir = scratch_dir();
+    std::fs::create_dir_all(&dir)?;
+    let _cleanup = scopeguard::guard(dir.clone(), |dir| {
+        let _ = std::fs::remove_dir_all(dir);
+    });
+    let mut config = Config::new(
+        dir.clone(),
+        "127.0.0.1:0".parse()?,
+        vec![],
+        "127.0.0.1:0".parse()?,
+        NetID::Testnet,
+        PasswordPolicy::default(),
+        false,
+    );
+    config.offline = true;
+    let database = Database::open(config.db_path(), false, args.concurrency).await?;
+    let secrets = SecretStore::open(&config.secrets_path())?;
+    let nodes = Arc::new(NodeSelection::select(&config, None).await);
+    let state = AppState::new(
+        database,
+        NetID::Testnet,
+        secrets,
+        nodes,
+        offline_client(NetID::Testnet),
+        Arc::new(config),
+    );
+
+    let mut keys = vec![];
+    for i in 0..args.wallets {
+        let sk = Ed25519SK::generate();
+        let name = format!("bench-{i}");
+        state
+            .database
+            .create_wallet(&name, Covenant::std_ed25519_pk_new(sk.to_public()))
+            .await?;
+        keys.push((name, sk));
+    }
+    let keys = Arc::new(keys);
+    let mut phases = vec![];
+
+    let db = state.database.clone();
+    let coins = args.coins;
+    let wallets = keys.clone();
+    phases.push(
+        run_phase("store_coins", args.wallets, args.concurrency, move |i| {
+            let db = db.clone();
+            let name = wallets[i].0.clone();
+            async move {
+                let wallet = db.get_wallet(&name).await.expect("wallet just created");
+                let coins = synthetic_coins(wallet.address(), coins);
+                wallet
+                    .restore_history(BlockHeight(100_000), coins, BTreeMap::new())
+                    .await
+            }
+        })
+        .await?,
+    );
+
+    let db = state.database.clone();
+    let wallets = keys.clone();
+    phases.push(
+        run_phase("prepare_tx", args.iterations, args.concurrency, move |i| {
+            let db = db.clone();
+            let (name, sk) = wallets[i % wallets.len()].clone();
+            async move {
+                let wallet = db.get_wallet(&name).await.expect("wallet just created");
+                let output = CoinData {
+                    covhash: HashVal::random().into(),
+                    value: CoinValue(fastrand::u128(1_000..10_000_000)),
+                    denom: Denom::Mel,
+                    additional_data: Default::default(),
+                };
+                let sign = move |mut tx: Transaction| {
+                    for i in 0..tx.inputs.len() {
+                        tx = sk.sign_tx(tx, i)?;
+                    }
+                    Ok(tx)
+                };
+                wallet
+                    .prepare(
+                        vec![],
+                        vec![output],
+                        FEE_MULTIPLIER,
+                        Arc::new(Box::new(sign)),
+                        vec![],
+                        0,
+                        CoinValue(0),
+                        &BTreeSet::new(),
+                        CoinSelection::Arbitrary,
+                        None,
+                        None,
+                        None,
+                    )
+                    .await?;
+                Ok(())
+            }
+        })
+        .await?,
+    );
+
+    for method in ["wallet_summary", "dump_coins"] {
+        let service = Arc::new(rpc_service(state.clone()));
+        let wallets = keys.clone();
+        phases.push(
+            run_phase(method, args.iterations, args.concurrency, move |i| {
+                let service = service.clone();
+                let request = JrpcRequest {
+                    jsonrpc: "2.0".into(),
+                    method: method.into(),
+                    params: vec![wallets[i % wallets.len()].0.clone().into()],
+                    id: JrpcId::Number(i as i64),
+                };
+                async move {
+                    let response = service.respond_raw(request).await;
+                    if let Some(err) = response.error {
+                        anyhow::bail!("{method} failed: {}", err.message);
+                    }
+                    Ok(())
+                }
+            })
+            .await?,
+        );
+    }
+
+    let report = BenchReport {
+        wallets: args.wallets,
+        coins_per_wallet: args.coins,
+        concurrency: args.concurrency,
+        phases,
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!(
+            "{} wallets of {} coins, {} at a time",
+            report.wallets, report.coins_per_wallet, report.concurrency
+        );
+        println!(
+            "{:<16}{:>8}{:>12}{:>10}{:>10}{:>10}",
+            "phase", "ops", "ops/s", "p50 ms", "p99 ms", "max ms"
+        );
+        for phase in report.phases.iter() {
+            println!(
+                "{:<16}{:>8}{:>12.1}{:>10.2}{:>10.2}{:>10.2}",
+                phase.name,
+                phase.operations,
+                phase.per_sec,
+                phase.p50_ms,
+                phase.p99_ms,
+                phase.max_ms
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let latencies = (1..=100).rev().map(Duration::from_millis).collect();
+        let report = summarize("test", latencies, Duration::from_secs(2));
+        assert_eq!(report.operations, 100);
+        assert_eq!(report.per_sec, 50.0);
+        assert_eq!(report.p50_ms, 51.0);
+        assert_eq!(report.p99_ms, 100.0);
+        assert_eq!(report.max_ms, 100.0);
+
+        let empty = summarize("empty", vec![], Duration::from_secs(1));
+        assert_eq!((empty.operations, empty.p99_ms), (0, 0.0));
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::protocol::types::BuildInfo;
+
+fn non_empty(value: &'static str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Parses the `name=version` list that the build script records.
+fn parse_versions(list: &str) -> BTreeMap<String, String> {
+    list.split(',')
+        .filter_map(|entry| {
+            let (name, version) = entry.split_once('=')?;
+            Some((name.to_string(), version.to_string()))
+        })
+        .collect()
+}
+
+/// How this binary was built, as recorded by the build script.
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        daemon_version: env!("CARGO_PKG_VERSION").into(),
+        git_commit: non_empty(env!("MELWALLETD_GIT_COMMIT")),
+        build_timestamp: env!("MELWALLETD_BUILD_TIMESTAMP").parse().ok(),
+        rustc_version: non_empty(env!("MELWALLETD_RUSTC_VERSION")),
+        target: env!("MELWALLETD_TARGET").into(),
+        profile: env!("MELWALLETD_PROFILE").into(),
+        features: env!("MELWALLETD_FEATURES")
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .map(String::from)
+            .collect::<BTreeSet<_>>(),
+        consensus_crates: parse_versions(env!("MELWALLETD_CONSENSUS_CRATES")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_consensus_crates() {
+        assert_eq!(parse_versions(""), BTreeMap::new());
+        let info = build_info();
+        assert!(info.consensus_crates.contains_key("melstructs"));
+        assert!(info.consensus_crates.contains_key("melvm"));
+        assert!(!info.target.is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use futures::{
+    future::{BoxFuture, Shared},
+    Future, FutureExt,
+};
+use lru::LruCache;
+use melprot::CoinChange;
+use melprot::Snapshot;
+use melstructs::{Address, BlockHeight, CoinDataHeight, CoinID, Transaction, TxHash};
+use parking_lot::Mutex;
+
+use crate::throttle::NodeThrottle;
+
+/// Number of results of each kind kept around.
+const CACHE_SIZE: usize = 10_000;
+
+type Pending<V> = Shared<BoxFuture<'static, Result<V, Arc<String>>>>;
+type Cache<K, V> = Arc<Mutex<LruCache<K, Pending<V>>>>;
+
+/// Results fetched from the node, shared by all wallets. When several wallets sync the same heights, each snapshot, coin or transaction is only fetched once, even if the wallets ask for it at the same time.
+#[derive(Clone)]
+pub struct ChainCache {
+    snapshots: Cache<BlockHeight, Snapshot>,
+    coin_changes: Cache<(BlockHeight, Address), Vec<CoinChange>>,
+    transactions: Cache<(BlockHeight, TxHash), Option<Transaction>>,
+    coins: Cache<(BlockHeight, CoinID), Option<CoinDataHeight>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    throttle: NodeThrottle,
+}
+
+impl Default for ChainCache {
+    fn default() -> Self {
+        Self {
+            snapshots: Arc::new(Mutex::new(LruCache::new(CACHE_SIZE))),
+            coin_changes: Arc::new(Mutex::new(LruCache::new(CACHE_SIZE))),
+            transactions: Arc::new(Mutex::new(LruCache::new(CACHE_SIZE))),
+            coins: Arc::new(Mutex::new(LruCache::new(CACHE_SIZE))),
+            hits: Default::default(),
+            misses: Default::default(),
+            throttle: Default::default(),
+        }
+    }
+}
+
+impl ChainCache {
+    /// Gets the snapshot at an older height, like [Snapshot::get_older].
+    pub async fn get_older(
+        &self,
+        snapshot: &Snapshot,
+        height: BlockHeight,
+    ) -> anyhow::Result<Snapshot> {
+        let snapshot = snapshot.clone();
+        let throttle = self.throttle.clone();
+        self.get_or_fetch(&self.snapshots, height, async move {
+            throttle.call(|| snapshot.get_older(height)).await
+        })
+        .await
+    }
+
+    /// Gets the coin changes of an address at the height of the snapshot.
+    pub async fn get_coin_changes(
+        &self,
+        snapshot: &Snapshot,
+        address: Address,
+    ) -> anyhow::Result<Vec<CoinChange>> {
+        let snapshot = snapshot.clone();
+        let throttle = self.throttle.clone();
+        self.get_or_fetch(
+            &self.coin_changes,
+            (snapshot.current_header().height, address),
+            async move { throttle.call(|| snapshot.get_coin_changes(address)).await },
+        )
+        .await
+    }
+
+    /// Gets a transaction included at the height of the snapshot.
+    pub async fn get_transaction(
+        &self,
+        snapshot: &Snapshot,
+        txhash: TxHash,
+    ) -> anyhow::Result<Option<Transaction>> {
+        let snapshot = snapshot.clone();
+        let throttle = self.throttle.clone();
+        self.get_or_fetch(
+            &self.transactions,
+            (snapshot.current_header().height, txhash),
+            async move { throttle.call(|| snapshot.get_transaction(txhash)).await },
+        )
+        .await
+    }
+
+    /// Gets a coin as of the height of the snapshot.
+    pub async fn get_coin(
+        &self,
+        snapshot: &Snapshot,
+        coinid: CoinID,
+    ) -> anyhow::Result<Option<CoinDataHeight>> {
+        let snapshot = snapshot.clone();
+        let throttle = self.throttle.clone();
+        self.get_or_fetch(
+            &self.coins,
+            (snapshot.current_header().height, coinid),
+            async move { throttle.call(|| snapshot.get_coin(coinid)).await },
+        )
+        .await
+    }
+
+    /// Returns the throttle that requests to the node go through.
+    pub fn throttle(&self) -> &NodeThrottle {
+        &self.throttle
+    }
+
+    /// Returns how many lookups were answered from the cache, and how many went to the node.
+    pub fn hit_stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    async fn get_or_fetch<K: Hash + Eq + Copy, V: Clone + Send + Sync + 'static, E: ToString>(
+        &self,
+        cache: &Mutex<LruCache<K, Pending<V>>>,
+        key: K,
+        fetch: impl Future<Output = Result<V, E>> + Send + 'static,
+    ) -> anyhow::Result<V> {
+        let pending = {
+            let mut cache = cache.lock();
+            if let Some(pending) = cache.get(&key) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                pending.clone()
+            } else {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                let pending = fetch
+                    .map(|res| res.map_err(|e| Arc::new(e.to_string())))
+                    .boxed()
+                    .shared();
+                cache.put(key, pending.clone());
+                pending
+            }
+        };
+        match pending.await {
+            Ok(val) => Ok(val),
+            Err(err) => {
+                // don't remember failures, so that the next attempt asks again
+                cache.lock().pop(&key);
+                anyhow::bail!("{err}")
+            }
+        }
+    }
+}
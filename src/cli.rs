@@ -1,15 +1,25 @@
-use std::{convert::TryFrom, fs::File, io::Read, net::SocketAddr, path::PathBuf};
+use std::{
+    collections::BTreeMap, convert::TryFrom, fs::File, io::Read, net::SocketAddr, path::PathBuf,
+    time::Duration,
+};
 
-use clap::{ArgGroup, Parser};
-use melstructs::NetID;
+use anyhow::Context;
+use clap::{ArgGroup, Parser, Subcommand};
+use melstructs::{CoinValue, NetID};
 use serde::*;
 use terminal_size::{terminal_size, Width};
+
+use crate::{
+    backup::BackupConfig, init::DEFAULT_LISTEN, password::PasswordPolicy,
+    protocol::ext::SENDING_METHODS, provision::WalletDecl, units::TokenRegistry, users::UserConfig,
+};
 #[derive(Parser, Clone, Deserialize, Debug)]
 #[clap(
     version,
     group(ArgGroup::new("options")
         .required(true)
         .args(&["wallet-dir", "config"])),
+    subcommand_negates_reqs(true),
     max_term_width(1024),
     term_width(
         if let Some((Width(w), _)) = terminal_size(){
@@ -38,9 +48,45 @@ pub struct Args {
     /// CORS origins allowed to access daemon
     pub allowed_origin: Vec<String>, // TODO: validate as urls
 
+    #[clap(long, default_value = "0", display_order(5))]
+    /// Minimum length of wallet passwords
+    pub min_password_length: usize,
+
+    #[clap(long, default_value = "0", display_order(6))]
+    /// Minimum strength score (0-4) of wallet passwords
+    pub min_password_score: u8,
+
+    #[clap(long, display_order(7))]
+    /// Store each wallet in its own database file, next to the main database
+    pub split_wallet_files: bool,
+
+    #[clap(long, display_order(8))]
+    /// SOCKS5 proxy to reach the full node through, such as Tor's: "socks5://127.0.0.1:9050"
+    pub proxy: Option<String>,
+
+    #[clap(long, display_order(9))]
+    /// Start without connecting to any node. Wallets can still be listed, created and exported, and unsigned transactions prepared from the last block header seen, but nothing syncs or gets sent
+    pub offline: bool,
+
+    #[clap(long, display_order(10))]
+    /// Lowest fee, in MEL, that prepared transactions pay, even if the network would accept less: "0.001"
+    pub min_fee: Option<CoinValue>,
+
+    #[clap(long, display_order(11))]
+    /// Highest fee, in MEL, that prepared transactions may pay unless the request explicitly allows more
+    pub max_fee: Option<CoinValue>,
+
+    #[clap(long, display_order(12))]
+    /// Verify the signatures and covenants of every transaction that spends from or pays to a wallet while syncing, rather than trusting the node, and stop syncing a wallet if any fail
+    pub paranoid: bool,
+
+    #[clap(long, display_order(13))]
+    /// Seconds an RPC call may take before failing with a timeout error, so that calls stuck on an unresponsive node don't hang clients. Methods that send transactions are exempt. Unlimited if unset
+    pub rpc_timeout: Option<u64>,
+
     #[serde(skip_serializing)]
     #[clap(long, display_order(998))]
-    ///
+    /// YAML config file to use instead of command-line arguments
     pub config: Option<String>,
 
     #[serde(skip_serializing)]
@@ -52,23 +98,209 @@ pub struct Args {
     #[clap(long, display_order(998))]
     /// run without starting server
     pub dry_run: bool,
+
+    #[serde(skip_serializing)]
+    #[clap(long, display_order(998))]
+    /// restore send history from the journal of sent transactions, then exit
+    pub rebuild_from_journal: bool,
+
+    #[serde(skip_serializing)]
+    #[clap(long, display_order(998))]
+    /// at startup, remove wallets left without a secret key by a failed creation, if they never received a coin
+    pub repair_wallets: bool,
+
+    #[serde(skip)]
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Set up a new daemon: create the wallet directory, write a config file, and optionally create a first wallet
+    Init(InitArgs),
+    /// Check the wallet directory, secrets, database, node and checkpoint, reporting what is wrong and how to fix it
+    Doctor(DoctorArgs),
+    /// Benchmark storing coins, preparing transactions and answering RPC calls, on a throwaway database of synthetic wallets, without connecting to any node
+    Bench(BenchArgs),
+}
+
+#[derive(Parser, Clone, Debug)]
+#[clap(group(ArgGroup::new("options").required(true).args(&["wallet-dir", "config"])))]
+pub struct DoctorArgs {
+    #[clap(long)]
+    /// Directory of the wallet database
+    pub wallet_dir: Option<PathBuf>,
+
+    #[clap(long, default_value = "mainnet")]
+    /// Network ID: "testnet", "custom02",...
+    pub network: NetID,
+
+    #[clap(long)]
+    /// Address of the full node to check. Required for networks other than "mainnet" and "testnet"
+    pub connect: Option<SocketAddr>,
+
+    #[clap(long)]
+    /// SOCKS5 proxy to reach the full node through
+    pub proxy: Option<String>,
+
+    #[clap(long)]
+    /// YAML config file of the daemon, to check instead of the options above
+    pub config: Option<String>,
+
+    #[clap(long)]
+    /// Print the report as JSON
+    pub json: bool,
+}
+
+impl DoctorArgs {
+    /// The config of the daemon to check.
+    pub fn config(&self) -> anyhow::Result<Config> {
+        if let Some(filename) = self.config.as_ref() {
+            return Config::load(filename);
+        }
+        let network_addr = self
+            .connect
+            .or_else(|| first_bootstrap_route(self.network))
+            .with_context(|| {
+                format!(
+                    "no bootstrap nodes for {:?}; give one with --connect",
+                    self.network
+                )
+            })?;
+        let mut config = Config::new(
+            self.wallet_dir.clone().unwrap(),
+            DEFAULT_LISTEN.parse()?,
+            vec![],
+            network_addr,
+            self.network,
+            PasswordPolicy::default(),
+            false,
+        );
+        config.proxy = self.proxy.clone();
+        Ok(config)
+    }
+}
+
+#[derive(Parser, Clone, Debug)]
+pub struct BenchArgs {
+    #[clap(long, default_value = "4")]
+    /// Number of synthetic wallets
+    pub wallets: usize,
+
+    #[clap(long, default_value = "1000")]
+    /// Number of unspent coins in each wallet
+    pub coins: usize,
+
+    #[clap(long, default_value = "8")]
+    /// Number of operations run at the same time
+    pub concurrency: usize,
+
+    #[clap(long, default_value = "200")]
+    /// Number of transactions prepared, and of calls made to each RPC method
+    pub iterations: usize,
+
+    #[clap(long)]
+    /// Print the report as JSON
+    pub json: bool,
+}
+
+#[derive(Parser, Clone, Debug)]
+pub struct InitArgs {
+    #[clap(long)]
+    /// Directory of the wallet database [default: ~/.melwalletd]
+    pub wallet_dir: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Network ID: "mainnet", "testnet", "custom02",... [default: mainnet]
+    pub network: Option<NetID>,
+
+    #[clap(long)]
+    /// Address of full node on the network. Required for networks other than "mainnet" and "testnet"
+    pub connect: Option<SocketAddr>,
+
+    #[clap(long, short = 'l')]
+    /// melwalletd server address [default: 127.0.0.1:11773]
+    pub listen: Option<SocketAddr>,
+
+    #[clap(long)]
+    /// Where to write the config file [default: melwalletd.yaml in the wallet directory]
+    pub config: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Name of a first wallet to create
+    pub wallet_name: Option<String>,
+
+    #[clap(long, short = 'y')]
+    /// Don't ask anything, using defaults for whatever isn't given. The first wallet's password is then read from the MELWALLETD_PASSWORD environment variable
+    pub non_interactive: bool,
+
+    #[clap(long)]
+    /// Overwrite an existing config file
+    pub force: bool,
 }
 
-#[derive(Deserialize, Debug, Serialize)]
+#[derive(Deserialize, Debug, Serialize, Clone)]
 pub struct Config {
     pub wallet_dir: PathBuf,
     pub listen: SocketAddr,
     pub network_addr: SocketAddr,
     pub allowed_origins: Vec<String>,
     pub network: NetID,
+    #[serde(default)]
+    pub password_policy: PasswordPolicy,
+    #[serde(default)]
+    pub split_wallet_files: bool,
+    #[serde(default)]
+    pub token_registry: TokenRegistry,
+    /// Encrypted backups to object storage. Can only be set in the config file.
+    #[serde(default)]
+    pub backup: Option<BackupConfig>,
+    /// Users of a multi-user daemon, each with an isolated namespace of wallets. If empty, the daemon serves a single namespace to anyone who can reach it. Can only be set in the config file.
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+    /// Read-only database connections, in addition to the read-write ones, that serve read-only queries so that heavy read workloads don't hold up syncing. Can only be set in the config file.
+    #[serde(default)]
+    pub read_connections: usize,
+    /// Advertise the daemon over mDNS, so that GUIs on the local network can find it without entering its address. Can only be set in the config file.
+    #[serde(default)]
+    pub mdns: bool,
+    /// Probe the network's bootstrap nodes at startup and connect to the fastest of those caught up with the chain, falling back to `network_addr` if none answer. Set when no node is given with `--connect`.
+    #[serde(default)]
+    pub auto_select_node: bool,
+    /// SOCKS5 proxy, such as `socks5://127.0.0.1:9050` for Tor, that all connections to full nodes go through, so that the node doesn't learn the daemon's IP address. Webhooks and backups don't go through it.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Run without connecting to any node. Wallets can still be listed, created and exported, and unsigned transactions prepared using the last block header seen while online, but nothing syncs, and methods that need the network fail.
+    #[serde(default)]
+    pub offline: bool,
+    /// Lowest fee that prepared transactions pay. Transactions that would pay less pay this instead.
+    #[serde(default)]
+    pub min_fee: CoinValue,
+    /// Highest fee that prepared transactions may pay, guarding against fee multiplier spikes and fee calculation bugs. Requests can go over it by setting `allow_high_fee`. Unlimited if unset.
+    #[serde(default)]
+    pub max_fee: Option<CoinValue>,
+    /// Verify the transactions that spend from or pay to each wallet while syncing: that spends of the wallet's coins pass their covenants, and that received coins match the transactions that created them. A node that sends transactions failing this is misbehaving, so syncing the wallet stops with an error. Costs a few more requests to the node per transaction.
+    #[serde(default)]
+    pub paranoid: bool,
+    /// Seconds an RPC call may take before failing with a timeout error, freeing up the client rather than leaving it waiting on a node that doesn't answer. Methods that send transactions are exempt, since timing one out could leave a transaction sent but not recorded. Unlimited if unset.
+    #[serde(default)]
+    pub rpc_timeout_secs: Option<u64>,
+    /// Timeouts in seconds of particular methods, overriding `rpc_timeout_secs`, such as a longer one for `wait_for_confirmation`. Can only be set in the config file.
+    #[serde(default)]
+    pub method_timeouts: BTreeMap<String, u64>,
+    /// Wallets that are created at startup if they don't exist, each either watch-only or from an encrypted key file, so that deployments can be provisioned reproducibly. With users, these are wallets of the main namespace. Can only be set in the config file.
+    #[serde(default)]
+    pub wallets: Vec<WalletDecl>,
 }
 impl Config {
-    fn new(
+    pub fn new(
         wallet_dir: PathBuf,
         listen: SocketAddr,
         allowed_origins: Vec<String>,
         network_addr: SocketAddr,
         network: NetID,
+        password_policy: PasswordPolicy,
+        split_wallet_files: bool,
     ) -> Config {
         Config {
             wallet_dir,
@@ -76,23 +308,71 @@ impl Config {
             network_addr,
             allowed_origins,
             network,
+            password_policy,
+            split_wallet_files,
+            token_registry: Default::default(),
+            backup: None,
+            users: vec![],
+            read_connections: 0,
+            mdns: false,
+            auto_select_node: false,
+            proxy: None,
+            offline: false,
+            min_fee: CoinValue(0),
+            max_fee: None,
+            paranoid: false,
+            rpc_timeout_secs: None,
+            method_timeouts: BTreeMap::new(),
+            wallets: vec![],
         }
     }
 }
 
+impl Config {
+    /// Reads a YAML config file.
+    pub fn load(filename: &str) -> anyhow::Result<Self> {
+        let mut config_file = File::open(filename)?;
+        let mut buf: String = "".into();
+        config_file.read_to_string(&mut buf)?;
+        let config: Config = serde_yaml::from_str(&buf)?;
+        Ok(config)
+    }
+
+    /// Path of the main database file.
+    pub fn db_path(&self) -> PathBuf {
+        self.db_path_for(self.network)
+    }
+
+    /// Path of the main database file of the given network, which shares the wallet directory and secrets file with every other network's.
+    pub fn db_path_for(&self, network: NetID) -> PathBuf {
+        let db_name = format!("{:?}-wallets.db", network).to_ascii_lowercase();
+        self.wallet_dir.join(db_name)
+    }
+
+    /// How long calls to an RPC method may take, if limited. Calls of [SENDING_METHODS] never are.
+    pub fn rpc_timeout(&self, method: &str) -> Option<Duration> {
+        if SENDING_METHODS.contains(&method) {
+            return None;
+        }
+        self.method_timeouts
+            .get(method)
+            .copied()
+            .or(self.rpc_timeout_secs)
+            .map(Duration::from_secs)
+    }
+
+    /// Path of the file holding wallet secrets.
+    pub fn secrets_path(&self) -> PathBuf {
+        self.wallet_dir.join(".secrets.json")
+    }
+}
+
 impl TryFrom<Args> for Config {
     type Error = anyhow::Error;
 
     fn try_from(cmd: Args) -> Result<Self, Self::Error> {
         match cmd.config {
-            Some(filename) => {
-                let mut config_file = File::open(filename)?;
-                let mut buf: String = "".into();
-                config_file.read_to_string(&mut buf)?;
-                let config: Config = serde_yaml::from_str(&buf)?;
-
-                anyhow::Ok(config)
-            }
+            Some(filename) => Config::load(&filename),
             None => {
                 let args = cmd;
                 let network = args.network;
@@ -105,19 +385,32 @@ impl TryFrom<Args> for Config {
                             "No bootstrap nodes available for network: {network:?}"
                         )
                     });
-                Ok(Config::new(
+                let mut config = Config::new(
                     args.wallet_dir.unwrap(),
                     args.listen,
                     args.allowed_origin,
                     network_addr,
                     network,
-                ))
+                    PasswordPolicy {
+                        min_length: args.min_password_length,
+                        min_score: args.min_password_score,
+                    },
+                    args.split_wallet_files,
+                );
+                config.auto_select_node = args.connect.is_none();
+                config.proxy = args.proxy;
+                config.offline = args.offline;
+                config.min_fee = args.min_fee.unwrap_or_default();
+                config.max_fee = args.max_fee;
+                config.paranoid = args.paranoid;
+                config.rpc_timeout_secs = args.rpc_timeout;
+                Ok(config)
             }
         }
     }
 }
 
-fn first_bootstrap_route(network: NetID) -> Option<SocketAddr> {
+pub fn first_bootstrap_route(network: NetID) -> Option<SocketAddr> {
     let routes = melbootstrap::bootstrap_routes(network);
     if routes.is_empty() {
         None
@@ -0,0 +1,122 @@
+use melprot::Snapshot;
+use melstructs::{CoinData, CoinID, Denom, Transaction};
+
+use crate::state::AppState;
+
+/// Most coins merged by a single consolidation transaction, so that each stays small and cheap.
+const MAX_CONSOLIDATION_INPUTS: usize = 100;
+
+/// Picks the coins to merge to bring a wallet's `coins` towards `max_utxos`: the smallest ones, including the largest MEL coin if none of them is MEL, to pay the fee with. Returns None if there are few enough coins, or no MEL to pay the fee.
+fn consolidation_batch(
+    mut coins: Vec<(CoinID, CoinData)>,
+    max_utxos: usize,
+) -> Option<Vec<(CoinID, CoinData)>> {
+    if coins.len() <= max_utxos {
+        return None;
+    }
+    // merging n coins of one denomination into one removes n - 1 of them
+    let count = (coins.len() - max_utxos + 1).clamp(2, MAX_CONSOLIDATION_INPUTS);
+    coins.sort_by_key(|(_, data)| data.value);
+    let mut batch: Vec<_> = coins.iter().take(count).cloned().collect();
+    if !batch.iter().any(|(_, data)| data.denom == Denom::Mel) {
+        let mel = coins
+            .iter()
+            .rev()
+            .find(|(_, data)| data.denom == Denom::Mel)?
+            .clone();
+        batch.pop();
+        batch.push(mel);
+    }
+    Some(batch)
+}
+
+impl AppState {
+    /// Consolidates the coins of every unlocked wallet holding more than its consolidation policy allows, while the fee multiplier is at most the policy's threshold.
+    ///
+    /// A wallet gets at most one consolidation transaction at a time, and none while any of its transactions is pending, so that consolidating never competes with the wallet's own payments. Signing goes through [AppState::use_signer], so consolidations count against the wallet's signing limit.
+    pub async fn check_consolidation(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        let header = snapshot.current_header();
+        for (name, policy) in self.database.consolidation_policies().await? {
+            if header.fee_multiplier > policy.max_fee_multiplier || self.get_signer(&name).is_none()
+            {
+                continue;
+            }
+            let wallet = match self.database.get_wallet(&name).await {
+                Some(wallet) => wallet,
+                None => continue,
+            };
+            if !wallet.pending_transactions().await?.is_empty() {
+                continue;
+            }
+            let coins = wallet
+                .get_coin_mapping(true, false)
+                .await
+                .into_iter()
+                .filter(|(_, data)| data.covhash == wallet.address())
+                .collect();
+            let batch = match consolidation_batch(coins, policy.max_utxos) {
+                Some(batch) => batch,
+                None => continue,
+            };
+            let signer = match self.use_signer(&name).await {
+                Some(signer) => signer,
+                None => continue,
+            };
+            let sign = |mut tx: Transaction| {
+                for i in 0..tx.inputs.len() {
+                    tx = signer.sign_tx(tx, i)?;
+                }
+                Ok(tx)
+            };
+            let tx =
+                wallet.prepare_sweep(&batch, wallet.address(), header.fee_multiplier, &sign)?;
+            let txhash = self
+                .broadcast_tx(&wallet, tx)
+                .await
+                .map_err(|err| anyhow::anyhow!("consolidation failed: {err}"))?;
+            log::info!("consolidated {} coins of {name} in {txhash}", batch.len());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use melstructs::CoinValue;
+    use tmelcrypt::HashVal;
+
+    use super::*;
+
+    fn coin(index: u8, value: u128, denom: Denom) -> (CoinID, CoinData) {
+        (
+            CoinID {
+                txhash: HashVal::default().into(),
+                index,
+            },
+            CoinData {
+                covhash: HashVal::default().into(),
+                value: CoinValue(value),
+                denom,
+                additional_data: Default::default(),
+            },
+        )
+    }
+
+    #[test]
+    fn picks_smallest_with_mel() {
+        let coins = vec![
+            coin(0, 500, Denom::Mel),
+            coin(1, 1, Denom::Sym),
+            coin(2, 2, Denom::Sym),
+            coin(3, 3, Denom::Sym),
+            coin(4, 100, Denom::Mel),
+        ];
+        assert!(consolidation_batch(coins.clone(), 5).is_none());
+        // two too many: merge three of them, with the largest MEL coin paying the fee
+        let batch = consolidation_batch(coins.clone(), 3).unwrap();
+        let indexes: Vec<u8> = batch.iter().map(|(id, _)| id.index).collect();
+        assert_eq!(indexes, vec![1, 2, 0]);
+        let no_mel = coins.into_iter().filter(|(_, d)| d.denom == Denom::Sym);
+        assert!(consolidation_batch(no_mel.collect(), 1).is_none());
+    }
+}
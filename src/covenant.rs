@@ -0,0 +1,57 @@
+use melstructs::Transaction;
+use melvm::{Covenant, CovenantEnv};
+
+use crate::{
+    logs::trace_melvm,
+    protocol::{ext::MAX_TRACE_STEPS, types::CovenantEvaluation},
+};
+
+/// Runs a covenant against a transaction, tracing what it does. This blocks for as long as the covenant runs, which its weight bounds.
+pub fn evaluate(
+    covenant: &Covenant,
+    tx: &Transaction,
+    env: Option<CovenantEnv>,
+) -> CovenantEvaluation {
+    let (result, mut trace) = trace_melvm(|| covenant.execute(tx, env));
+    let trace_truncated = trace.len() > MAX_TRACE_STEPS;
+    trace.truncate(MAX_TRACE_STEPS);
+    CovenantEvaluation {
+        passed: result.clone().map(|v| v.into_bool()).unwrap_or(false),
+        result: result.map(|v| format!("{:?}", v)),
+        weight: covenant.weight(),
+        ops: covenant.to_ops().iter().map(|op| op.to_string()).collect(),
+        trace,
+        trace_truncated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use melstructs::{CoinValue, TxKind};
+    use tmelcrypt::Ed25519SK;
+
+    use super::*;
+    use crate::signer::Signer;
+
+    #[test]
+    fn checks_signatures() {
+        let sk = Ed25519SK::generate();
+        let tx = Transaction {
+            kind: TxKind::Normal,
+            inputs: vec![],
+            outputs: vec![],
+            fee: CoinValue(0),
+            covenants: vec![],
+            data: Default::default(),
+            sigs: vec![],
+        };
+        assert!(evaluate(&Covenant::always_true(), &tx, None).passed);
+        // the new-style covenant needs an environment to find its input's signature
+        let covenant = Covenant::std_ed25519_pk_legacy(sk.to_public());
+        let unsigned = evaluate(&covenant, &tx, None);
+        assert!(!unsigned.passed);
+        assert_eq!(unsigned.ops.len(), covenant.to_ops().len());
+        let signed = evaluate(&covenant, &sk.sign_tx(tx, 0).unwrap(), None);
+        assert!(signed.passed);
+    }
+}
@@ -1,13 +1,15 @@
 use std::{
-    collections::{BTreeMap, HashMap},
-    path::Path,
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
     sync::Arc,
-    time::Instant,
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Context;
 
 use binary_search::Direction;
+use dashmap::DashMap;
 
 use futures::{StreamExt, TryStreamExt};
 use melprot::Snapshot;
@@ -17,75 +19,195 @@ use melstructs::{
 };
 use melvm::{covenant_weight_from_bytes, Covenant};
 use parking_lot::Mutex;
-use rusqlite::{params, OptionalExtension};
-use stdcode::StdcodeSerializeExt;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
 
-use self::pool::ConnPool;
+use self::{
+    pool::ConnPool,
+    values::{joined_sum, SqlValue, SPLIT_SUM},
+};
+use crate::{
+    chain_cache::ChainCache,
+    journal::SendJournal,
+    protocol::types::{CoinSelection, DatabaseRepair, InsufficientFunds},
+    secrets::is_wallet_name,
+    throttle::MAX_CONCURRENCY,
+    verify::{verify_received, verify_spend},
+};
 
+mod alerts;
+mod anomaly;
+mod backup;
+mod block_times;
+mod cache;
+mod coldsign;
+mod consolidation;
+mod data_coins;
+mod dca;
+mod display;
+mod escrows;
+mod imported;
+mod inheritance;
+mod invoices;
+mod journal;
+mod labels;
+mod migrations;
+mod orphans;
+mod pending;
+mod plugins;
 mod pool;
+mod repair;
+mod rotation;
+mod search;
+mod settings;
+mod signing_limits;
+mod split;
+mod swap_orders;
+mod sync_errors;
+mod sync_state;
+mod timelocks;
+mod tracked;
+mod trades;
+mod transfers;
+mod values;
+
+pub use backup::inspect_snapshot;
+pub use escrows::EscrowRecord;
+
+/// Most outputs a transaction may have, since coins are identified by a single-byte output index.
+pub const MAX_TX_OUTPUTS: usize = 255;
+
+/// Heaviest transaction that the wallet will prepare, by [Transaction::weight]. Neither melstructs nor melprot define a consensus limit on weight, so this is the daemon's own policy rather than a rule of the network: a few times the weight of a transaction paying [MAX_TX_OUTPUTS] plain outputs, which bounds the fee that a single preparation can spend.
+pub const MAX_TX_WEIGHT: u128 = 1_000_000;
+
+/// Number of read-write connections kept open to the main database file.
+const POOL_SIZE: usize = 8;
+/// Number of read-write connections kept open to the main database file in low-power mode.
+const LOW_POWER_POOL_SIZE: usize = 2;
 
 /// A database that holds wallets.
 #[derive(Clone)]
 pub struct Database {
     pool: ConnPool,
+    /// Directory holding one database file per wallet, if wallets are stored separately. The main database then only holds the list of wallets and the shared transaction cache.
+    split_dir: Option<PathBuf>,
+    wallet_pools: Arc<DashMap<String, ConnPool>>,
+    /// Journal of sent transactions, kept next to the main file
+    journal: Arc<SendJournal>,
+    /// Corrupt files repaired when the database was opened
+    repairs: Arc<Vec<DatabaseRepair>>,
+    /// Read-only connections opened per database file, in addition to the read-write ones
+    read_connections: usize,
 }
 
 impl Database {
-    /// Create a new database
-    pub async fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
-        let pool = ConnPool::open(path)?;
-        // then create the tables
-        let conn = pool.get_conn().await;
-        // *all* known coins, spent and unspent and "virtual" and whatever
-        conn.execute(
-            "create table if not exists coins (coinid primary key, covhash, value, denom, additional_data)",
-            [],
-        )?;
-        conn.execute(
-            "create index if not exists coins_index on coins(covhash)",
-            [],
-        )?;
-        // all confirmed coins
-        conn.execute(
-            "create table if not exists coin_confirmations (coinid primary key, height not null)",
-            [],
-        )?;
-        // all pending coins
-        conn.execute(
-            "create table if not exists pending_coins (coinid primary key, txhash not null)",
-            [],
-        )?;
-        // transactions to the coins that they spend
-        conn.execute(
-            "create table if not exists spends (coinid primary key, txhash not null)",
-            [],
-        )?;
-        // pending spends with expiration block height
-        conn.execute(
-            "create table if not exists pending (txhash primary key, expires not null)",
-            [],
-        )?;
-        // a *cache* of all known transactions
-        conn.execute(
-            "create table if not exists transactions (txhash primary key, txblob not null)",
-            [],
-        )?;
-        // wallets by name
-        conn.execute(
-            "create table if not exists wallet_names (name primary key, covhash not null, covenant not null)",
-            [],
-        )?;
-        // sync records in the past
-        conn.execute(
-            "create table if not exists sync_heights (covhash primary key not null, height not null)",
-            [],
-           )?;
-        Ok(Database { pool })
+    /// Opens a database, creating it if it doesn't exist and migrating its schema if it's out of date. If `split` is set, each wallet is stored in its own file; wallets previously stored in the main file are copied out to their own files.
+    pub async fn open(
+        path: impl AsRef<Path>,
+        split: bool,
+        read_connections: usize,
+    ) -> anyhow::Result<Self> {
+        let split_dir = if split {
+            let dir = path.as_ref().with_extension("d");
+            std::fs::create_dir_all(&dir).context("cannot create wallet file directory")?;
+            Some(dir)
+        } else {
+            None
+        };
+        // check every file for corruption before anything opens it
+        let mut repairs = vec![];
+        repairs.extend(repair::check_and_repair(path.as_ref())?);
+        if let Some(dir) = split_dir.as_ref() {
+            for entry in std::fs::read_dir(dir)? {
+                let file = entry?.path();
+                if file.extension().and_then(|e| e.to_str()) == Some("db") {
+                    repairs.extend(repair::check_and_repair(&file)?);
+                }
+            }
+        }
+        let pool = open_pool(path.as_ref(), POOL_SIZE, read_connections).await?;
+        let journal = SendJournal::open(&path.as_ref().with_extension("journal"))
+            .context("cannot open transaction journal")?;
+        let db = Database {
+            pool,
+            split_dir,
+            wallet_pools: Default::default(),
+            journal: Arc::new(journal),
+            repairs: Arc::new(repairs),
+            read_connections,
+        };
+        if db.split_dir.is_some() {
+            db.split_combined().await?;
+            db.register_wallet_files().await?;
+        }
+        Ok(db)
+    }
+
+    /// Opens the database of another network, which another daemon may have open. Unlike [Database::open], nothing is checked for corruption, repaired or migrated: the database must already exist, with exactly the schema this version expects.
+    pub async fn open_other(path: impl AsRef<Path>, split: bool) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        anyhow::ensure!(
+            path.exists(),
+            "no database at {:?}; start melwalletd on that network first",
+            path
+        );
+        let pool = ConnPool::open(path, 1)?;
+        migrations::check_version(&*pool.get_conn().await)
+            .with_context(|| format!("cannot use the database at {:?}", path))?;
+        let split_dir = if split {
+            let dir = path.with_extension("d");
+            std::fs::create_dir_all(&dir).context("cannot create wallet file directory")?;
+            Some(dir)
+        } else {
+            None
+        };
+        let journal = SendJournal::open(&path.with_extension("journal"))
+            .context("cannot open transaction journal")?;
+        Ok(Database {
+            pool,
+            split_dir,
+            wallet_pools: Default::default(),
+            journal: Arc::new(journal),
+            repairs: Default::default(),
+            read_connections: 0,
+        })
+    }
+
+    /// Shrinks the connection pool of the main file to save memory and file handles in low-power mode, or grows it back.
+    pub async fn set_low_power(&self, low_power: bool) -> anyhow::Result<()> {
+        let size = if low_power {
+            LOW_POWER_POOL_SIZE
+        } else {
+            POOL_SIZE
+        };
+        self.pool.resize(size).await?;
+        Ok(())
+    }
+
+    /// Runs an integrity check on a database file without opening or repairing it, returning every problem found.
+    pub fn integrity_problems(path: &Path) -> anyhow::Result<Vec<String>> {
+        repair::integrity_problems(path)
+    }
+
+    /// Names of the wallets in a database file, read without creating, migrating or repairing it. Returns None if the file can't be read.
+    pub fn wallet_names_in(path: &Path) -> Option<Vec<String>> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).ok()?;
+        let mut stmt = conn.prepare("select name from wallet_names").ok()?;
+        let names = stmt
+            .query_map([], |row| row.get(0))
+            .ok()?
+            .collect::<Result<_, _>>()
+            .ok()?;
+        Some(names)
+    }
+
+    /// Corrupt files that were repaired when the database was opened.
+    pub fn repairs(&self) -> &[DatabaseRepair] {
+        &self.repairs
     }
 
     /// List wallet names.
     pub async fn list_wallets(&self) -> Vec<String> {
-        let conn = self.pool.get_conn().await;
+        let conn = self.pool.get_read_conn().await;
         let mut rows = conn
             .prepare_cached("select name from wallet_names")
             .unwrap();
@@ -95,45 +217,86 @@ impl Database {
 
     /// Gets a wallet by name.
     pub async fn get_wallet(&self, name: &str) -> Option<Wallet> {
-        let conn = self.pool.get_conn().await;
-        let (covhash_string, covenant): (String, Vec<u8>) = conn
-            .query_row(
+        let (covhash_string, covenant): (String, Vec<u8>) = {
+            let conn = self.pool.get_read_conn().await;
+            conn.query_row(
                 "select covhash, covenant from wallet_names where name = $1",
                 [name],
                 |row| Ok((row.get(0)?, row.get(1)?)),
             )
             .optional()
-            .expect("db failed")?;
+            .expect("db failed")?
+        };
         let covhash: Address = covhash_string.parse().expect("malformed covhash in db");
         Some(Wallet {
             name: name.to_string(),
             covhash,
             covenant,
-            pool: self.pool.clone(),
+            pool: self.wallet_pool(name).await.expect("db failed"),
+            cache: self.pool.clone(),
+            journal: self.journal.clone(),
         })
     }
 
+    /// Gets the name of the wallet with the given address, if there is one.
+    pub async fn wallet_with_address(&self, covhash: Address) -> Option<String> {
+        let conn = self.pool.get_read_conn().await;
+        conn.query_row(
+            "select name from wallet_names where covhash = $1",
+            [covhash.to_string()],
+            |row| row.get(0),
+        )
+        .optional()
+        .expect("db failed")
+    }
+
     /// Creates a wallet.
     pub async fn create_wallet(&self, name: &str, covenant: Covenant) -> anyhow::Result<()> {
+        // such names are reserved for other things in the secret store
+        anyhow::ensure!(
+            is_wallet_name(name),
+            "wallet names cannot start with $ or contain #"
+        );
         let covhash = covenant.hash();
         let conn = self.pool.get_conn().await;
         conn.execute(
             "insert into wallet_names values ($1, $2, $3)",
             params![name, covhash.to_string(), covenant.to_bytes().to_vec()],
         )?;
+        drop(conn);
+        self.copy_wallet_record(name).await?;
         Ok(())
     }
 
     /// Retransmit pending transactions
     pub async fn retransmit_pending(&self, snapshot: Snapshot) -> anyhow::Result<()> {
-        let mut conn = self.pool.get_conn().await;
-        let txn = conn.transaction()?;
-        let mut stmt =
-            txn.prepare_cached("select txblob from pending natural join transactions")?;
-        let mut rows = stmt.query(params![]).unwrap();
-        while let Ok(Some(row)) = rows.next() {
-            let blob: Vec<u8> = row.get(0)?;
-            let txn: Transaction = stdcode::deserialize(&blob)?;
+        let mut pending: Vec<(ConnPool, String)> = vec![];
+        for pool in self.all_wallet_pools().await? {
+            let conn = pool.get_conn().await;
+            let mut stmt = conn.prepare_cached("select txhash from pending")?;
+            for txhash in stmt.query_map(params![], |row| row.get(0))? {
+                pending.push((pool.clone(), txhash?));
+            }
+        }
+        let mut found: Vec<(ConnPool, String, Transaction)> = vec![];
+        {
+            let conn = self.pool.get_conn().await;
+            let mut stmt =
+                conn.prepare_cached("select txblob from transactions where txhash = $1")?;
+            for (pool, txhash) in pending {
+                let blob: Option<Vec<u8>> = stmt
+                    .query_row(params![txhash], |row| row.get(0))
+                    .optional()?;
+                if let Some(blob) = blob {
+                    found.push((pool, txhash, stdcode::deserialize(&blob)?));
+                }
+            }
+        }
+        for (pool, txhash, txn) in found {
+            pool.get_conn().await.execute(
+                "update pending set retransmits = retransmits + 1 where txhash = $1",
+                params![txhash],
+            )?;
             log::debug!("retransmit {}", txn.hash_nosigs());
             let snapshot = snapshot.clone();
             smolscale::spawn(async move {
@@ -143,18 +306,71 @@ impl Database {
             })
             .detach();
         }
-        drop(rows);
-        drop(stmt);
         Ok(())
     }
 }
 
+/// Stores coins along with the heights they confirmed at, skipping those already stored. Each statement is prepared once for the whole batch.
+fn insert_coins<'a>(
+    txn: &rusqlite::Transaction,
+    coins: impl Iterator<Item = (&'a CoinID, &'a CoinDataHeight)>,
+) -> anyhow::Result<()> {
+    let mut insert_coin =
+        txn.prepare_cached("insert into coins values ($1, $2, $3, $4, $5) on conflict do nothing")?;
+    let mut insert_confirmation = txn
+        .prepare_cached("insert into coin_confirmations values ($1, $2) on conflict do nothing")?;
+    // coins mostly share a few addresses, so their string forms are worth reusing
+    let mut covhashes: HashMap<Address, String> = HashMap::new();
+    for (coin, cdh) in coins {
+        let coin = coin.to_string();
+        let covhash: &str = covhashes
+            .entry(cdh.coin_data.covhash)
+            .or_insert_with(|| cdh.coin_data.covhash.to_string());
+        insert_coin.execute(params![
+            coin,
+            covhash,
+            SqlValue(cdh.coin_data.value),
+            cdh.coin_data.denom.to_bytes().to_vec(),
+            cdh.coin_data.additional_data.to_vec()
+        ])?;
+        insert_confirmation.execute(params![coin, cdh.height.0])?;
+    }
+    Ok(())
+}
+
+/// Forgets that the given transactions are pending, since they confirmed.
+fn clear_pending(
+    txn: &rusqlite::Transaction,
+    txhashes: impl Iterator<Item = TxHash>,
+) -> anyhow::Result<()> {
+    let mut delete = txn.prepare_cached("delete from pending where txhash = $1")?;
+    for txhash in txhashes.collect::<BTreeSet<_>>() {
+        delete.execute(params![txhash.to_string()])?;
+    }
+    Ok(())
+}
+
+/// Opens a connection pool to a database file, bringing its schema up to date.
+async fn open_pool(path: &Path, size: usize, readers: usize) -> anyhow::Result<ConnPool> {
+    let pool = ConnPool::open(path, size)?;
+    let mut conn = pool.get_conn().await;
+    let mut backup_path = path.as_os_str().to_owned();
+    backup_path.push(".pre-migration.bak");
+    migrations::migrate(&mut conn, Some(Path::new(&backup_path)))?;
+    drop(conn);
+    // readers are opened once the schema is up to date
+    Ok(pool.with_readers(path, readers)?)
+}
+
 /// A wallet within a database
 pub struct Wallet {
     name: String,
     covhash: Address,
     covenant: Vec<u8>,
     pool: ConnPool,
+    /// Pool holding the transaction cache, which is shared by all wallets.
+    cache: ConnPool,
+    journal: Arc<SendJournal>,
 }
 
 impl Wallet {
@@ -163,6 +379,11 @@ impl Wallet {
         self.covhash
     }
 
+    /// Serialized covenant guarding the wallet's coins
+    pub fn covenant(&self) -> &[u8] {
+        &self.covenant
+    }
+
     /// Obtains a transaction, whether cached or not. Must provide a snapshot to retrieve non-cached transactions.
     pub async fn get_transaction(
         &self,
@@ -199,17 +420,13 @@ impl Wallet {
             return Ok(None);
         };
         // now we can actually put it back into the cache so that next time we don't need to do all this.
-        let conn = self.pool.get_conn().await;
-        conn.execute(
-            "insert into transactions values ($1, $2) on conflict do nothing",
-            params![txhash.to_string(), txn.stdcode()],
-        )?;
+        self.cache_transaction(&txn).await?;
         Ok(Some(txn))
     }
 
     /// Obtains a cached transaction.
     pub async fn get_cached_transaction(&self, txhash: TxHash) -> Option<Transaction> {
-        let conn = self.pool.get_conn().await;
+        let conn = self.cache.get_read_conn().await;
         let blob: Vec<u8> = conn
             .query_row(
                 "select txblob from transactions where txhash = $1",
@@ -224,7 +441,7 @@ impl Wallet {
 
     /// Check whether a particular txhash is pending.
     pub async fn is_pending(&self, txhash: TxHash) -> bool {
-        let conn = self.pool.get_conn().await;
+        let conn = self.pool.get_read_conn().await;
         conn.query_row(
             "select txhash from pending where txhash = $1",
             params![txhash.to_string()],
@@ -237,10 +454,33 @@ impl Wallet {
 
     /// Gets the balance by denomination.
     pub async fn get_balances(&self) -> BTreeMap<Denom, CoinValue> {
+        let conn = self.pool.get_read_conn().await;
+        // the same coins as get_coin_mapping(false, false), summed up by SQL
+        let mut stmt = conn
+            .prepare_cached(&format!(
+                r"with balance_coins as (select value, denom from coins where
+                (covhash = $1 or covhash in (select covhash from imported_covenants where name = $2
+                    and coalesce(unlock_height, 0) <= coalesce((select height from sync_heights where covhash = $1), 0)))
+                and (exists (select coinid from coin_confirmations where coin_confirmations.coinid = coins.coinid)
+                     or exists (select coinid from pending_coins where pending_coins.coinid = coins.coinid))
+                and not exists (select txhash from spends where spends.coinid = coins.coinid))
+                select denom, {SPLIT_SUM}, null from balance_coins group by denom
+                union all
+                select denom, null, null, value from balance_coins where typeof(value) = 'text'"
+            ))
+            .unwrap();
+        let mut rows = stmt
+            .query(params![self.covhash.to_string(), self.name])
+            .unwrap();
         let mut toret = BTreeMap::new();
-        log::trace!("calling get_coin_mapping from get_balances");
-        for (_, data) in self.get_coin_mapping(false, false).await {
-            *toret.entry(data.denom).or_default() += data.value;
+        while let Some(row) = rows.next().unwrap() {
+            let denom: Vec<u8> = row.get(0).unwrap();
+            let huge: Option<SqlValue> = row.get(3).unwrap();
+            let value = match huge {
+                Some(huge) => huge.0,
+                None => joined_sum(row.get(1).unwrap(), row.get(2).unwrap()),
+            };
+            *toret.entry(Denom::from_bytes(&denom).unwrap()).or_default() += value;
         }
         toret
     }
@@ -248,7 +488,7 @@ impl Wallet {
     /// Obtains transaction history.
     pub async fn get_transaction_history(&self) -> Vec<(TxHash, Option<BlockHeight>)> {
         // We infer the transaction history through our coin confirmations
-        let conn = self.pool.get_conn().await;
+        let conn = self.pool.get_read_conn().await;
         let mut stmt = conn
             .prepare_cached(
                 r"select coins.coinid, height from 
@@ -284,50 +524,56 @@ impl Wallet {
     ) -> BTreeMap<CoinID, CoinData> {
         let start = Instant::now();
         scopeguard::defer!(log::trace!("get_coin_mapping took {:?}", start.elapsed()));
-        let conn = self.pool.get_conn().await;
+        let conn = self.pool.get_read_conn().await;
         let stmt = match (confirmed, ignore_pending) {
             (true, true) => {
-                r"select coinid, value, denom, additional_data from coins where 
-                covhash = $1
+                r"select coinid, value, denom, additional_data, covhash from coins where 
+                (covhash = $1 or covhash in (select covhash from imported_covenants where name = $2
+                    and coalesce(unlock_height, 0) <= coalesce((select height from sync_heights where covhash = $1), 0)))
                 and exists (select height from coin_confirmations where coin_confirmations.coinid = coins.coinid)
                 and not exists (select txhash from spends where spends.coinid = coins.coinid 
                     and not exists (select txhash from pending where spends.txhash = pending.txhash))"
             }
             (true, false) => {
-                r"select coinid,  value, denom, additional_data from coins where 
-                covhash = $1
+                r"select coinid, value, denom, additional_data, covhash from coins where 
+                (covhash = $1 or covhash in (select covhash from imported_covenants where name = $2
+                    and coalesce(unlock_height, 0) <= coalesce((select height from sync_heights where covhash = $1), 0)))
                 and exists (select height from coin_confirmations where coin_confirmations.coinid = coins.coinid)
                 and not exists (select txhash from spends where spends.coinid = coins.coinid)"
             }
             (false, true) => {
-                r"select coinid,  value, denom, additional_data from coins where 
-                covhash = $1
+                r"select coinid, value, denom, additional_data, covhash from coins where 
+                (covhash = $1 or covhash in (select covhash from imported_covenants where name = $2
+                    and coalesce(unlock_height, 0) <= coalesce((select height from sync_heights where covhash = $1), 0)))
                 and (exists (select coinid from coin_confirmations where coin_confirmations.coinid = coins.coinid)
                     or exists (select coinid from pending_coins where pending_coins.coinid = coins.coinid))
                 and not exists (select txhash from spends where spends.coinid = coins.coinid 
                     and not exists (select txhash from pending where spends.txhash = pending.txhash))"
             }
             (false, false) => {
-                r"select coinid,  value, denom, additional_data from coins where 
-                covhash = $1
+                r"select coinid, value, denom, additional_data, covhash from coins where 
+                (covhash = $1 or covhash in (select covhash from imported_covenants where name = $2
+                    and coalesce(unlock_height, 0) <= coalesce((select height from sync_heights where covhash = $1), 0)))
                 and (exists (select coinid from coin_confirmations where coin_confirmations.coinid = coins.coinid)
                      or exists (select coinid from pending_coins where pending_coins.coinid = coins.coinid))
                 and not exists (select txhash from spends where spends.coinid = coins.coinid)"
             }
         };
         let mut stmt = conn.prepare_cached(stmt).unwrap();
-        let mut rows = stmt.query(params![self.covhash.to_string()]).unwrap();
+        let mut rows = stmt
+            .query(params![self.covhash.to_string(), self.name])
+            .unwrap();
         let mut toret = BTreeMap::new();
         while let Ok(Some(row)) = rows.next() {
             let coinid: String = row.get(0).unwrap();
-            let value: String = row.get(1).unwrap();
+            let value: SqlValue = row.get(1).unwrap();
             let denom: Vec<u8> = row.get(2).unwrap();
             let additional_data: Vec<u8> = row.get(3).unwrap();
-            let value: CoinValue = CoinValue(value.parse().unwrap());
+            let covhash: String = row.get(4).unwrap();
             let denom: Denom = Denom::from_bytes(&denom).unwrap();
             let cdata = CoinData {
-                covhash: self.covhash,
-                value,
+                covhash: covhash.parse().unwrap(),
+                value: value.0,
                 denom,
                 additional_data: additional_data.into(),
             };
@@ -338,7 +584,7 @@ impl Wallet {
     }
 
     #[allow(clippy::too_many_arguments)]
-    /// Prepares transactions
+    /// Prepares transactions. Coins in `exclude` are never picked as inputs, unless explicitly listed in `inputs`. The fee is at least `min_fee`, even if a lower one would do. Change goes to `change_address`, or back to the wallet if None.
     pub async fn prepare(
         &self,
         inputs: Vec<CoinID>,
@@ -347,9 +593,30 @@ impl Wallet {
         sign: Arc<Box<dyn Fn(Transaction) -> anyhow::Result<Transaction> + Send + Sync>>,
         nobalance: Vec<Denom>,
         fee_ballast: usize,
-
-        snap: Snapshot,
+        min_fee: CoinValue,
+        exclude: &BTreeSet<CoinID>,
+        selection: CoinSelection,
+        snap: Option<Snapshot>,
+        sponsor: Option<&Wallet>,
+        change_address: Option<Address>,
     ) -> anyhow::Result<Transaction> {
+        let change_covhash = change_address.unwrap_or(self.covhash);
+        // every balanced denomination may need up to two change outputs, and a sponsor one more
+        let change_slots = outputs
+            .iter()
+            .map(|o| o.denom)
+            .chain(std::iter::once(Denom::Mel))
+            .filter(|d| !nobalance.contains(d))
+            .collect::<BTreeSet<_>>()
+            .len()
+            * 2
+            + sponsor.is_some() as usize;
+        if outputs.len() + change_slots > MAX_TX_OUTPUTS {
+            anyhow::bail!(
+                "too many outputs for one transaction ({} plus up to {change_slots} change outputs, but at most {MAX_TX_OUTPUTS} allowed)",
+                outputs.len()
+            );
+        }
         let mut nobalance = nobalance;
         nobalance.push(Denom::NewCustom);
         let nobalance = nobalance;
@@ -360,13 +627,55 @@ impl Wallet {
                 mandatory_inputs.insert(input, coindata.clone());
             } else {
                 log::warn!("processing out-of-wallet coin {}", input);
+                let snap = snap
+                    .as_ref()
+                    .context("cannot look up out-of-wallet coins while offline")?;
                 let coindata = snap.get_coin(input).await?.context("cannot find coin")?;
                 mandatory_inputs.insert(input, coindata.clone());
             }
         }
         log::trace!("calling get_coin_mapping from prepare");
         let unspent_coins = self.get_coin_mapping(true, false).await;
-        let gen_transaction = |fee| {
+        let mut candidates: Vec<(&CoinID, &CoinData)> = unspent_coins.iter().collect();
+        match selection {
+            CoinSelection::Arbitrary => (),
+            CoinSelection::LargestFirst => candidates.sort_by_key(|(_, data)| Reverse(data.value)),
+            CoinSelection::SmallestFirst => candidates.sort_by_key(|(_, data)| data.value),
+        }
+        let imported = self.imported_covenants().await?;
+        // MEL coins of the sponsor, if any, which pay the fee instead of this wallet
+        let sponsor_coins: Vec<(CoinID, CoinData)> = match sponsor {
+            Some(sponsor) => sponsor
+                .get_coin_mapping(true, false)
+                .await
+                .into_iter()
+                .filter(|(coin, data)| {
+                    data.covhash == sponsor.covhash
+                        && data.denom == Denom::Mel
+                        && !exclude.contains(coin)
+                })
+                .collect(),
+            None => vec![],
+        };
+        // what the wallet could spend of a denomination, for reporting a shortfall
+        let available = |denom: Denom| -> CoinValue {
+            let mandatory = mandatory_inputs
+                .values()
+                .filter(|cdh| cdh.coin_data.denom == denom)
+                .map(|cdh| cdh.coin_data.value);
+            let candidates = candidates
+                .iter()
+                .filter(|(coin, data)| {
+                    data.denom == denom
+                        && !mandatory_inputs.contains_key(*coin)
+                        && !exclude.contains(*coin)
+                        && (data.covhash == self.covhash || imported.contains_key(&data.covhash))
+                })
+                .map(|(_, data)| data.value);
+            mandatory.chain(candidates).sum()
+        };
+        let gen_transaction = |fee: CoinValue| {
+            let fee = fee.max(min_fee);
             log::debug!("trying with a fee of {} MEL", fee);
             let start = Instant::now();
             // find coins that might match
@@ -382,6 +691,13 @@ impl Wallet {
 
             // compute output sum
             let mut output_sum = txn.total_outputs();
+            if sponsor.is_some() {
+                // the sponsor pays the fee
+                let mel = output_sum.remove(&Denom::Mel).unwrap_or_default() - fee;
+                if mel.0 > 0 {
+                    output_sum.insert(Denom::Mel, mel);
+                }
+            }
 
             let mut input_sum: BTreeMap<Denom, CoinValue> = BTreeMap::new();
             // first we add the "mandatory" inputs
@@ -407,24 +723,42 @@ impl Wallet {
 
             log::trace!("after shuffling unspent coins: {:?}", start.elapsed());
 
-            for (coin, data) in unspent_coins.iter() {
+            for (coin, data) in candidates.iter() {
                 // blacklist of coins
-                if mandatory_inputs.contains_key(coin)
+                if mandatory_inputs.contains_key(*coin)
+                    || exclude.contains(*coin)
                     || nobalance.contains(&data.denom)
-                    || data.covhash != self.covhash
+                    || (data.covhash != self.covhash && !imported.contains_key(&data.covhash))
                 {
                     // do not consider it
                     continue;
                 }
                 let existing_val = input_sum.get(&data.denom).cloned().unwrap_or(CoinValue(0));
                 if existing_val < output_sum.get(&data.denom).cloned().unwrap_or(CoinValue(0)) {
-                    txn.inputs.push(*coin);
+                    txn.inputs.push(**coin);
                     input_sum.insert(data.denom, existing_val + data.value);
                 }
             }
 
             log::trace!("after going through unspent coins: {:?}", start.elapsed());
 
+            // imported coins are guarded by covenants other than ours, which must come along
+            let input_covhashes: BTreeSet<Address> = txn
+                .inputs
+                .iter()
+                .filter_map(|coin| {
+                    unspent_coins
+                        .get(coin)
+                        .map(|data| data.covhash)
+                        .or_else(|| mandatory_inputs.get(coin).map(|cdh| cdh.coin_data.covhash))
+                })
+                .collect();
+            for covhash in input_covhashes {
+                if let Some(covenant) = imported.get(&covhash) {
+                    txn.covenants.push(covenant.clone());
+                }
+            }
+
             // create change outputs
             let change = {
                 let mut change = Vec::new();
@@ -442,20 +776,20 @@ impl Wallet {
                                 let first_half = difference / 2;
                                 let second_half = difference - first_half;
                                 change.push(CoinData {
-                                    covhash: self.covhash,
+                                    covhash: change_covhash,
                                     value: first_half,
                                     denom: *cointype,
                                     additional_data: Default::default(),
                                 });
                                 change.push(CoinData {
-                                    covhash: self.covhash,
+                                    covhash: change_covhash,
                                     value: second_half,
                                     denom: *cointype,
                                     additional_data: Default::default(),
                                 })
                             } else {
                                 change.push(CoinData {
-                                    covhash: self.covhash,
+                                    covhash: change_covhash,
                                     value: difference,
                                     denom: *cointype,
                                     additional_data: Default::default(),
@@ -463,15 +797,49 @@ impl Wallet {
                             }
                         }
                     } else {
-                        return Direction::High(Err(anyhow::anyhow!(
-                            "not enough money for denomination {}",
-                            cointype
-                        )));
+                        return Direction::High(Err(InsufficientFunds {
+                            denom: cointype.to_string(),
+                            required: *sum,
+                            available: available(*cointype),
+                            fee_estimate: fee,
+                        }
+                        .into()));
                     }
                 }
                 change
             };
-            txn.outputs.extend(change.into_iter());
+            txn.outputs.extend(change);
+
+            if let Some(sponsor) = sponsor {
+                let mut paid = CoinValue(0);
+                for (coin, data) in sponsor_coins.iter() {
+                    if paid >= fee {
+                        break;
+                    }
+                    txn.inputs.push(*coin);
+                    paid += data.value;
+                }
+                if paid < fee {
+                    return Direction::High(Err(anyhow::Error::new(InsufficientFunds {
+                        denom: Denom::Mel.to_string(),
+                        required: fee,
+                        available: paid,
+                        fee_estimate: fee,
+                    })
+                    .context("not enough MEL in fee sponsor wallet")));
+                }
+                if paid.0 > 0 {
+                    txn.covenants.push(sponsor.covenant.clone().into());
+                }
+                if paid > fee {
+                    txn.outputs.push(CoinData {
+                        covhash: sponsor.covhash,
+                        value: paid - fee,
+                        denom: Denom::Mel,
+                        additional_data: Default::default(),
+                    });
+                }
+            }
 
             log::trace!("before signing: {:?}", start.elapsed());
             log::debug!("candidate with {} inputs", txn.inputs.len());
@@ -503,11 +871,15 @@ impl Wallet {
                 Err(err) => Direction::Low(Err(err)),
             }
         };
-        let max_fee: CoinValue = unspent_coins
-            .values()
-            .filter(|cdh| cdh.denom == Denom::Mel)
-            .map(|d| d.value)
-            .sum();
+        let max_fee: CoinValue = if sponsor.is_some() {
+            sponsor_coins.iter().map(|(_, d)| d.value).sum()
+        } else {
+            unspent_coins
+                .values()
+                .filter(|cdh| cdh.denom == Denom::Mel)
+                .map(|d| d.value)
+                .sum()
+        };
         let max_fee = match gen_transaction(CoinValue(0u128)) {
             Direction::Low(Ok(t)) => {
                 t.base_fee(fee_multiplier, fee_ballast as _, covenant_weight_from_bytes) * 3
@@ -525,20 +897,106 @@ impl Wallet {
             |a| gen_transaction(CoinValue(a)),
         );
         log::debug!("prepared TX with fee {:?}", val.as_ref().map(|v| v.fee));
-        val
+        let txn = val?;
+        let weight = txn.weight(covenant_weight_from_bytes);
+        if weight > MAX_TX_WEIGHT {
+            anyhow::bail!("transaction weight {weight} exceeds the limit of {MAX_TX_WEIGHT}");
+        }
+        Ok(txn)
+    }
+
+    /// Prepares a transaction moving the given coins, all of which must belong to this wallet, to a single destination. The fee is deducted from the MEL being moved.
+    pub fn prepare_sweep(
+        &self,
+        coins: &[(CoinID, CoinData)],
+        destination: Address,
+        fee_multiplier: u128,
+        sign: &(dyn Fn(Transaction) -> anyhow::Result<Transaction> + Send + Sync),
+    ) -> anyhow::Result<Transaction> {
+        let mut totals: BTreeMap<Denom, CoinValue> = BTreeMap::new();
+        for (_, data) in coins {
+            *totals.entry(data.denom).or_default() += data.value;
+        }
+        let mel_total = totals.remove(&Denom::Mel).unwrap_or_default();
+        let mut fee = CoinValue(0);
+        // the fee depends on the size of the signed transaction, which depends very slightly on the fee, so we iterate a few times
+        for _ in 0..5 {
+            let mel_left = mel_total
+                .checked_sub(fee)
+                .context("not enough MEL to pay the sweep fee")?;
+            let mut outputs: Vec<CoinData> = totals
+                .iter()
+                .map(|(denom, value)| CoinData {
+                    covhash: destination,
+                    value: *value,
+                    denom: *denom,
+                    additional_data: Default::default(),
+                })
+                .collect();
+            if mel_left.0 > 0 {
+                outputs.push(CoinData {
+                    covhash: destination,
+                    value: mel_left,
+                    denom: Denom::Mel,
+                    additional_data: Default::default(),
+                });
+            }
+            let txn = Transaction {
+                kind: TxKind::Normal,
+                inputs: coins.iter().map(|(coinid, _)| *coinid).collect(),
+                outputs,
+                fee,
+                covenants: vec![self.covenant.clone().into()],
+                data: vec![].into(),
+                sigs: vec![],
+            };
+            let signed_txn = sign(txn)?;
+            let base_fee = signed_txn.base_fee(fee_multiplier, 0, covenant_weight_from_bytes);
+            if signed_txn.fee >= base_fee {
+                return Ok(signed_txn);
+            }
+            fee = base_fee * 21 / 20;
+        }
+        anyhow::bail!("could not settle on a sweep fee")
     }
 
     /// Sets transactions as sent
     pub async fn commit_sent(&self, txn: Transaction, timeout: BlockHeight) -> anyhow::Result<()> {
-        let mut conn = self.pool.get_conn().await;
-        let conn = conn.transaction()?;
+        let outgoing = self.outgoing_values(&txn);
+        self.commit_inner(txn, timeout, outgoing).await
+    }
+
+    /// Sets a transaction whose fee this wallet sponsored as sent, so that the coins it paid the fee with aren't spent twice.
+    pub async fn commit_sponsored(
+        &self,
+        txn: Transaction,
+        timeout: BlockHeight,
+    ) -> anyhow::Result<()> {
+        let fee = std::iter::once((Denom::Mel, txn.fee.0)).collect();
+        self.commit_inner(txn, timeout, fee).await
+    }
 
-        // add the transaction to the cache
+    /// Sets a transaction as sent, recording that it sent the given amounts, by denomination, out of the wallet.
+    async fn commit_inner(
+        &self,
+        txn: Transaction,
+        timeout: BlockHeight,
+        outgoing: BTreeMap<Denom, u128>,
+    ) -> anyhow::Result<()> {
+        // journal the transaction first, so that it can be recovered even if the database is lost
+        if let Err(err) = self.journal.append(&self.name, &txn) {
+            log::warn!(
+                "cannot journal transaction {}: {:?}",
+                txn.hash_nosigs(),
+                err
+            );
+        }
+        // add the transaction to the cache. This may live in another file, but caching a transaction that ends up not being sent is harmless.
         let txhash = txn.hash_nosigs();
-        conn.execute(
-            "insert into transactions values ($1, $2) on conflict do nothing",
-            params![txhash.to_string(), txn.stdcode()],
-        )?;
+        self.cache_transaction(&txn).await?;
+
+        let mut conn = self.pool.get_conn().await;
+        let conn = conn.transaction()?;
         // spend everything
         for input in txn.inputs.iter() {
             conn.execute(
@@ -561,7 +1019,7 @@ impl Wallet {
                     params![
                         coinid.to_string(),
                         output.covhash.to_string(),
-                        output.value.0.to_string(),
+                        SqlValue(output.value),
                         denom.to_bytes().to_vec(),
                         output.additional_data.to_vec()
                     ],
@@ -572,11 +1030,28 @@ impl Wallet {
                 )?;
             }
         }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
         // add to pending
         conn.execute(
-            "insert into pending values ($1, $2)",
-            params![txhash.to_string(), timeout.0],
+            "insert into pending (txhash, expires, created) values ($1, $2, $3)",
+            params![txhash.to_string(), timeout.0, now],
         )?;
+        // record for anomaly detection
+        for (denom, value) in outgoing {
+            conn.execute(
+                "insert or ignore into send_activity values ($1, $2, $3, $4, $5)",
+                params![
+                    txhash.to_string(),
+                    self.name,
+                    denom.to_bytes().to_vec(),
+                    SqlValue(CoinValue(value)),
+                    now
+                ],
+            )?;
+        }
         // commit
         conn.commit()?;
         Ok(())
@@ -584,8 +1059,8 @@ impl Wallet {
 
     /// Gets any coin.
     pub async fn get_one_coin(&self, coin_id: CoinID) -> Option<CoinData> {
-        let conn = self.pool.get_conn().await;
-        let result: (String, String, Vec<u8>, Vec<u8>) = conn
+        let conn = self.pool.get_read_conn().await;
+        let result: (String, SqlValue, Vec<u8>, Vec<u8>) = conn
             .query_row(
                 "select covhash, value, denom, additional_data from coins where coinid = $1",
                 [coin_id.to_string()],
@@ -595,7 +1070,7 @@ impl Wallet {
             .unwrap()?;
         let cd = CoinData {
             covhash: result.0.parse().unwrap(),
-            value: CoinValue(result.1.parse().unwrap()),
+            value: result.1 .0,
             denom: Denom::from_bytes(&result.2).unwrap(),
             additional_data: result.3.into(),
         };
@@ -605,7 +1080,7 @@ impl Wallet {
     /// Gets the confirmation status of a coin.
     pub async fn get_coin_confirmation(&self, coin_id: CoinID) -> Option<CoinDataHeight> {
         let coindata = self.get_one_coin(coin_id).await?;
-        let conn = self.pool.get_conn().await;
+        let conn = self.pool.get_read_conn().await;
         let height: u64 = conn
             .query_row(
                 "select height from coin_confirmations where coinid = $1",
@@ -622,6 +1097,7 @@ impl Wallet {
 
     async fn full_sync(&self, snapshot: Snapshot) -> anyhow::Result<()> {
         log::warn!("VERY behind, so doing a full sync of {}", self.address());
+        let start = Instant::now();
         let coins: BTreeMap<CoinID, CoinDataHeight> = {
             let address: Address = self.address();
             let coins = snapshot
@@ -631,55 +1107,53 @@ impl Wallet {
             log::debug!("got {} coins for {address}", coins.len());
             coins
         };
+        let fetched = start.elapsed();
+        let count = coins.len();
+        self.replace_coins(coins, snapshot.current_header().height)
+            .await?;
+        log::info!(
+            "full sync of {} fetched {count} coins in {:?} and stored them in {:?}",
+            self.name,
+            fetched,
+            start.elapsed() - fetched
+        );
+        Ok(())
+    }
 
+    /// Replaces everything known about the coins at this wallet's address with the given unspent coins, marking the wallet as synced to `height`.
+    async fn replace_coins(
+        &self,
+        coins: BTreeMap<CoinID, CoinDataHeight>,
+        height: BlockHeight,
+    ) -> anyhow::Result<()> {
         let mut conn = self.pool.get_conn().await;
         let txn = conn.transaction()?;
         txn.execute(
             "delete from coins where covhash = ?",
             params![self.address().to_string()],
         )?;
-        for txhash in coins.keys().map(|c| c.txhash) {
-            txn.execute(
-                "delete from pending where txhash = $1",
-                params![txhash.to_string()],
-            )?;
-        }
-        for (coin, cdh) in coins {
-            txn.execute(
-                "insert into coins values ($1, $2, $3, $4, $5) on conflict do nothing",
-                params![
-                    coin.to_string(),
-                    cdh.coin_data.covhash.to_string(),
-                    cdh.coin_data.value.0.to_string(),
-                    cdh.coin_data.denom.to_bytes().to_vec(),
-                    cdh.coin_data.additional_data.to_vec()
-                ],
-            )
-            .unwrap();
-            txn.execute(
-                "insert into coin_confirmations values ($1, $2) on conflict do nothing",
-                params![coin.to_string(), cdh.height.0],
-            )
-            .unwrap();
-        }
+        clear_pending(&txn, coins.keys().map(|c| c.txhash))?;
+        insert_coins(&txn, coins.iter())?;
         txn.execute(
             "delete from sync_heights where covhash = ?",
             params![self.address().to_string()],
         )?;
         txn.execute(
             "insert into sync_heights (covhash, height) values ($1, $2)",
-            params![
-                self.address().to_string(),
-                snapshot.current_header().height.0
-            ],
+            params![self.address().to_string(), height.0],
         )?;
 
         txn.commit()?;
         Ok(())
     }
 
-    /// Updates the list of coins, given a network snapshot.
-    pub async fn network_sync(&self, snapshot: Snapshot) -> anyhow::Result<()> {
+    /// Updates the list of coins, given a network snapshot. If `paranoid` is set, the transactions that spend from or pay to the wallet are verified rather than trusted; a full sync, which only learns the unspent coins, verifies nothing.
+    pub async fn network_sync(
+        &self,
+        snapshot: Snapshot,
+        cache: &ChainCache,
+        paranoid: bool,
+    ) -> anyhow::Result<()> {
         // we first obtain the current latest sync height
         let latest_sync_height = {
             let conn = self.pool.get_conn().await;
@@ -719,22 +1193,47 @@ impl Wallet {
                 let new_spenders = &new_spenders;
                 async move {
                     log::trace!("going through height {height} for {}", self.address());
-                    let old_snap = snapshot.get_older(height.into()).await?;
-                    let diffs = old_snap.get_coin_changes(self.address()).await?;
+                    let old_snap = cache.get_older(&snapshot, height.into()).await?;
+                    let diffs = cache.get_coin_changes(&old_snap, self.address()).await?;
                     for diff in diffs {
                         match diff {
                             melprot::CoinChange::Add(coinid) => {
-                                let data = old_snap
-                                    .get_coin(coinid)
+                                let data = cache
+                                    .get_coin(&old_snap, coinid)
                                     .await?
                                     .context("coin not found here somehow")?;
+                                if paranoid {
+                                    // coins not created by a transaction, such as those of the genesis block, can't be checked
+                                    match cache.get_transaction(&old_snap, coinid.txhash).await? {
+                                        Some(creator) => verify_received(&creator, coinid, &data)
+                                            .context("node sent a bad coin")?,
+                                        None => log::debug!(
+                                            "cannot verify {coinid}, which no transaction created"
+                                        ),
+                                    }
+                                }
                                 coin_list.lock().insert(coinid, data);
                             }
-                            melprot::CoinChange::Delete(_coinid, txhash) => {
-                                let spender = old_snap
-                                    .get_transaction(txhash)
+                            melprot::CoinChange::Delete(coinid, txhash) => {
+                                let spender = cache
+                                    .get_transaction(&old_snap, txhash)
                                     .await?
                                     .context("tx not found somehow")?;
+                                if paranoid {
+                                    let prev_snap =
+                                        cache.get_older(&snapshot, (height - 1).into()).await?;
+                                    let parent = cache
+                                        .get_coin(&prev_snap, coinid)
+                                        .await?
+                                        .context("spent coin not found")?;
+                                    verify_spend(
+                                        &spender,
+                                        coinid,
+                                        parent,
+                                        prev_snap.current_header(),
+                                    )
+                                    .context("node sent a bad transaction")?;
+                                }
                                 new_spenders.lock().push(spender);
                             }
                         }
@@ -742,7 +1241,7 @@ impl Wallet {
                     anyhow::Ok(())
                 }
             })
-            .buffered(16)
+            .buffered(MAX_CONCURRENCY)
             .try_for_each(|_| async { Ok(()) })
             .await?;
 
@@ -751,24 +1250,7 @@ impl Wallet {
 
         let mut conn = self.pool.get_conn().await;
         let txn = conn.transaction()?;
-        for (coin, cdh) in coin_list.iter() {
-            txn.execute(
-                "insert into coins values ($1, $2, $3, $4, $5) on conflict do nothing",
-                params![
-                    coin.to_string(),
-                    cdh.coin_data.covhash.to_string(),
-                    cdh.coin_data.value.0.to_string(),
-                    cdh.coin_data.denom.to_bytes().to_vec(),
-                    cdh.coin_data.additional_data.to_vec()
-                ],
-            )
-            .unwrap();
-            txn.execute(
-                "insert into coin_confirmations values ($1, $2) on conflict do nothing",
-                params![coin.to_string(), cdh.height.0],
-            )
-            .unwrap();
-        }
+        insert_coins(&txn, coin_list.iter())?;
         for spender in new_spenders {
             let txhash = spender.hash_nosigs();
             for input in spender.inputs {
@@ -777,16 +1259,16 @@ impl Wallet {
                     params![input.to_string(), txhash.to_string()],
                 )?;
             }
-        }
-
-        // remove all pendings that have confirmation
-        for txhash in coin_list.keys().map(|c| c.txhash) {
+            // a confirmed spender is no longer pending, even if none of its outputs come back to us
             txn.execute(
                 "delete from pending where txhash = $1",
                 params![txhash.to_string()],
             )?;
         }
 
+        // remove all pendings that have confirmation
+        clear_pending(&txn, coin_list.keys().map(|c| c.txhash))?;
+
         // Finally, we remove all stupid pending things
         txn.execute("delete from spends where exists (select expires from pending where expires < $1 and txhash = spends.txhash)", params![snapshot.current_header().height.0])?;
 
@@ -0,0 +1,130 @@
+use melstructs::TxHash;
+use rusqlite::{params, OptionalExtension};
+
+use crate::protocol::types::{AlertThresholds, WalletAlert};
+
+use super::{Database, Wallet};
+
+impl Wallet {
+    /// Confirmed transactions that spent this wallet's coins.
+    pub async fn confirmed_spenders(&self) -> anyhow::Result<Vec<TxHash>> {
+        let conn = self.pool.get_read_conn().await;
+        let mut stmt = conn.prepare_cached(
+            r"select distinct spends.txhash from spends natural join coins where covhash = $1
+            and not exists (select txhash from pending where pending.txhash = spends.txhash)",
+        )?;
+        let spenders = stmt
+            .query_map([self.covhash.to_string()], |row| row.get::<_, String>(0))?
+            .map(|txhash| Ok(txhash?.parse()?))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(spenders)
+    }
+}
+
+impl Database {
+    /// Gets the alert thresholds of a wallet, along with whether its balance was below the threshold at the last check.
+    pub async fn alert_thresholds(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<Option<(AlertThresholds, bool)>> {
+        let conn = self.pool.get_conn().await;
+        let row: Option<(String, bool)> = conn
+            .query_row(
+                "select thresholds, low_balance from alert_thresholds where name = $1",
+                [name],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        row.map(|(thresholds, low_balance)| Ok((serde_json::from_str(&thresholds)?, low_balance)))
+            .transpose()
+    }
+
+    /// Sets or, given None, removes the alert thresholds of a wallet. The given transactions, which the wallet already made, are never alerted about.
+    pub async fn set_alert_thresholds(
+        &self,
+        name: &str,
+        thresholds: Option<&AlertThresholds>,
+        already_made: &[TxHash],
+    ) -> anyhow::Result<()> {
+        let mut conn = self.pool.get_conn().await;
+        let txn = conn.transaction()?;
+        match thresholds {
+            Some(thresholds) => {
+                txn.execute(
+                    "insert or replace into alert_thresholds (name, thresholds) values ($1, $2)",
+                    params![name, serde_json::to_string(thresholds)?],
+                )?;
+                for txhash in already_made {
+                    txn.execute(
+                        "insert or ignore into alert_checked values ($1, $2)",
+                        params![name, txhash.to_string()],
+                    )?;
+                }
+            }
+            None => {
+                txn.execute("delete from alert_thresholds where name = $1", [name])?;
+                txn.execute("delete from alert_checked where name = $1", [name])?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Names of all wallets with alert thresholds.
+    pub async fn alerted_wallets(&self) -> anyhow::Result<Vec<String>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached("select name from alert_thresholds")?;
+        let names = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(names)
+    }
+
+    /// Records whether a wallet's balance is below its threshold.
+    pub async fn set_low_balance(&self, name: &str, low_balance: bool) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "update alert_thresholds set low_balance = $1 where name = $2",
+            params![low_balance, name],
+        )?;
+        Ok(())
+    }
+
+    /// Whether a transaction of a wallet was already checked against its outgoing threshold. Marks it checked if it wasn't.
+    pub async fn check_once(&self, name: &str, txhash: TxHash) -> anyhow::Result<bool> {
+        let conn = self.pool.get_conn().await;
+        let inserted = conn.execute(
+            "insert or ignore into alert_checked values ($1, $2)",
+            params![name, txhash.to_string()],
+        )?;
+        Ok(inserted == 0)
+    }
+
+    /// Records an alert raised for a wallet.
+    pub async fn record_alert(&self, alert: &WalletAlert) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into wallet_alerts values ($1, $2)",
+            params![alert.wallet, serde_json::to_string(alert)?],
+        )?;
+        Ok(())
+    }
+
+    /// The latest `limit` alerts raised for a wallet, oldest first.
+    pub async fn wallet_alerts(
+        &self,
+        name: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<WalletAlert>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached(
+            "select alert from wallet_alerts where name = $1 order by rowid desc limit $2",
+        )?;
+        let mut alerts = stmt
+            .query_map(params![name, limit as u64], |row| row.get::<_, String>(0))?
+            .map(|alert| Ok(serde_json::from_str(&alert?)?))
+            .collect::<anyhow::Result<Vec<WalletAlert>>>()?;
+        alerts.reverse();
+        Ok(alerts)
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use melstructs::{Denom, Transaction, TxHash};
+use rusqlite::{params, OptionalExtension};
+use stdcode::StdcodeSerializeExt;
+
+use crate::{
+    anomaly::SendActivity,
+    protocol::types::{AnomalyPolicy, HeldSend, HoldKind},
+};
+
+use super::{values::SqlValue, Database, Wallet};
+
+fn kind_to_str(kind: HoldKind) -> String {
+    serde_json::to_value(kind)
+        .ok()
+        .and_then(|v| v.as_str().map(|s| s.to_owned()))
+        .expect("kind is a plain string")
+}
+
+fn kind_from_str(s: String) -> anyhow::Result<HoldKind> {
+    Ok(serde_json::from_value(serde_json::Value::String(s))?)
+}
+
+impl Wallet {
+    /// MEL that a transaction sends to addresses other than this wallet's.
+    pub fn outgoing_value(&self, tx: &Transaction) -> u128 {
+        self.outgoing_values(tx)
+            .get(&Denom::Mel)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// What a transaction sends to addresses other than this wallet's, by denomination.
+    pub fn outgoing_values(&self, tx: &Transaction) -> BTreeMap<Denom, u128> {
+        let mut values = BTreeMap::new();
+        for output in tx.outputs.iter().filter(|o| o.covhash != self.covhash) {
+            let denom = if output.denom == Denom::NewCustom {
+                Denom::Custom(tx.hash_nosigs())
+            } else {
+                output.denom
+            };
+            *values.entry(denom).or_default() += output.value.0;
+        }
+        values
+    }
+
+    /// Lists the transactions this wallet has sent since the given UNIX timestamp.
+    pub async fn send_activity(&self, since: u64) -> anyhow::Result<Vec<SendActivity>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached(
+            "select txhash, time, denom, value from send_activity where name = $1 and time > $2 order by time, txhash",
+        )?;
+        let mut rows = stmt.query(params![self.name, since])?;
+        let mut toret: Vec<(String, SendActivity)> = vec![];
+        while let Some(row) = rows.next()? {
+            let txhash: String = row.get(0)?;
+            let denom: Vec<u8> = row.get(2)?;
+            let value: SqlValue = row.get(3)?;
+            // rows of the same transaction are adjacent
+            if toret
+                .last()
+                .map(|(last, _)| last != &txhash)
+                .unwrap_or(true)
+            {
+                let activity = SendActivity {
+                    time: row.get(1)?,
+                    values: BTreeMap::new(),
+                };
+                toret.push((txhash, activity));
+            }
+            let (_, activity) = toret.last_mut().unwrap();
+            let denom = Denom::from_bytes(&denom).context("bad denomination in send activity")?;
+            activity.values.insert(denom, value.0 .0);
+        }
+        Ok(toret.into_iter().map(|(_, activity)| activity).collect())
+    }
+}
+
+impl Database {
+    /// Gets the anomaly policy of a wallet, if it has one.
+    pub async fn anomaly_policy(&self, name: &str) -> anyhow::Result<Option<AnomalyPolicy>> {
+        let conn = self.pool.get_conn().await;
+        let policy: Option<String> = conn
+            .query_row(
+                "select policy from anomaly_policies where name = $1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(policy.map(|p| serde_json::from_str(&p)).transpose()?)
+    }
+
+    /// Sets or, given None, removes the anomaly policy of a wallet.
+    pub async fn set_anomaly_policy(
+        &self,
+        name: &str,
+        policy: Option<&AnomalyPolicy>,
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        match policy {
+            Some(policy) => conn.execute(
+                "insert or replace into anomaly_policies values ($1, $2)",
+                params![name, serde_json::to_string(policy)?],
+            )?,
+            None => conn.execute("delete from anomaly_policies where name = $1", [name])?,
+        };
+        Ok(())
+    }
+
+    /// Holds back a transaction of a wallet instead of broadcasting it.
+    pub async fn hold_send(
+        &self,
+        name: &str,
+        tx: &Transaction,
+        kind: HoldKind,
+        reason: &str,
+    ) -> anyhow::Result<HeldSend> {
+        let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert or replace into held_sends values ($1, $2, $3, $4, $5, $6)",
+            params![
+                tx.hash_nosigs().to_string(),
+                name,
+                tx.stdcode(),
+                reason,
+                created,
+                kind_to_str(kind)
+            ],
+        )?;
+        Ok(HeldSend {
+            txhash: tx.hash_nosigs(),
+            wallet_name: name.to_owned(),
+            kind,
+            reason: reason.to_owned(),
+            transaction: tx.clone(),
+            created,
+        })
+    }
+
+    /// Lists the held transactions of a wallet, oldest first.
+    pub async fn list_held_sends(&self, name: &str) -> anyhow::Result<Vec<HeldSend>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached(
+            "select txhash, txblob, reason, created, kind from held_sends where name = $1 order by created",
+        )?;
+        let mut rows = stmt.query([name])?;
+        let mut toret = vec![];
+        while let Some(row) = rows.next()? {
+            let txhash: String = row.get(0)?;
+            let blob: Vec<u8> = row.get(1)?;
+            toret.push(HeldSend {
+                txhash: txhash.parse()?,
+                wallet_name: name.to_owned(),
+                kind: kind_from_str(row.get(4)?)?,
+                reason: row.get(2)?,
+                transaction: stdcode::deserialize(&blob)?,
+                created: row.get(3)?,
+            });
+        }
+        Ok(toret)
+    }
+
+    /// Gets a held transaction of a wallet.
+    pub async fn get_held_send(
+        &self,
+        name: &str,
+        txhash: TxHash,
+    ) -> anyhow::Result<Option<HeldSend>> {
+        Ok(self
+            .list_held_sends(name)
+            .await?
+            .into_iter()
+            .find(|held| held.txhash == txhash))
+    }
+
+    /// Releases a held transaction of a wallet. Returns whether there was such a transaction.
+    pub async fn delete_held_send(&self, name: &str, txhash: TxHash) -> anyhow::Result<bool> {
+        let conn = self.pool.get_conn().await;
+        let removed = conn.execute(
+            "delete from held_sends where name = $1 and txhash = $2",
+            params![name, txhash.to_string()],
+        )?;
+        Ok(removed > 0)
+    }
+}
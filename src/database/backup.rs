@@ -0,0 +1,50 @@
+use std::{collections::BTreeMap, path::Path};
+
+use rusqlite::{params, Connection};
+
+use super::{split::wallet_file_name, Database};
+
+impl Database {
+    /// Takes a consistent copy of the database, returning the contents of the main file and, if wallets are stored separately, of every wallet file by file name. `scratch` is a directory for temporary files.
+    pub async fn snapshot(
+        &self,
+        scratch: &Path,
+    ) -> anyhow::Result<(Vec<u8>, BTreeMap<String, Vec<u8>>)> {
+        let main = vacuum_to_bytes(&*self.pool.get_conn().await, scratch)?;
+        let mut wallet_files = BTreeMap::new();
+        if self.split_dir.is_some() {
+            for name in self.list_wallets().await {
+                let pool = self.wallet_pool(&name).await?;
+                let contents = vacuum_to_bytes(&*pool.get_conn().await, scratch)?;
+                wallet_files.insert(wallet_file_name(&name), contents);
+            }
+        }
+        Ok((main, wallet_files))
+    }
+}
+
+fn vacuum_to_bytes(conn: &Connection, scratch: &Path) -> anyhow::Result<Vec<u8>> {
+    let path = scratch.join(format!(".snapshot-{}.db", fastrand::u64(..)));
+    conn.execute("vacuum into $1", params![path.to_string_lossy()])?;
+    let contents = std::fs::read(&path);
+    let _ = std::fs::remove_file(&path);
+    Ok(contents?)
+}
+
+/// Checks the integrity of a database file, given its contents, returning the names of the wallets it lists.
+pub fn inspect_snapshot(contents: &[u8], scratch: &Path) -> anyhow::Result<Vec<String>> {
+    let path = scratch.join(format!(".inspect-{}.db", fastrand::u64(..)));
+    std::fs::write(&path, contents)?;
+    let res = (|| {
+        let conn = Connection::open(&path)?;
+        let check: String = conn.query_row("pragma integrity_check", [], |row| row.get(0))?;
+        anyhow::ensure!(check == "ok", "integrity check failed: {check}");
+        let mut stmt = conn.prepare("select name from wallet_names order by name")?;
+        let names = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(names)
+    })();
+    let _ = std::fs::remove_file(&path);
+    res
+}
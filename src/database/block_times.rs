@@ -0,0 +1,110 @@
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use melstructs::BlockHeight;
+use rusqlite::{params, OptionalExtension};
+
+use crate::protocol::types::BlockTime;
+
+use super::Database;
+
+/// Seconds between blocks on the Mel network, used to estimate the time of blocks when there's only one seen block to go by.
+const BLOCK_INTERVAL_SECS: u64 = 30;
+
+impl Database {
+    /// Records the current time as the time of a block, unless the block was already seen.
+    pub async fn record_block_time(&self, height: BlockHeight) -> anyhow::Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert or ignore into block_times (height, time) values ($1, $2)",
+            params![height.0, now],
+        )?;
+        Ok(())
+    }
+
+    /// Gets the time of a block, or None if no block has been seen at all yet.
+    pub async fn block_time(&self, height: BlockHeight) -> anyhow::Result<Option<BlockTime>> {
+        let conn = self.pool.get_read_conn().await;
+        let below: Option<(u64, u64)> = conn
+            .query_row(
+                "select height, time from block_times where height <= $1 order by height desc limit 1",
+                params![height.0],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let above: Option<(u64, u64)> = conn
+            .query_row(
+                "select height, time from block_times where height > $1 order by height limit 1",
+                params![height.0],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        Ok(estimate_time(height.0, below, above))
+    }
+
+    /// Gets the times of several blocks, leaving out those whose time isn't known.
+    pub async fn block_times(
+        &self,
+        heights: &[BlockHeight],
+    ) -> anyhow::Result<BTreeMap<BlockHeight, BlockTime>> {
+        let mut toret = BTreeMap::new();
+        for &height in heights {
+            if toret.contains_key(&height) {
+                continue;
+            }
+            if let Some(time) = self.block_time(height).await? {
+                toret.insert(height, time);
+            }
+        }
+        Ok(toret)
+    }
+}
+
+/// Works out the time of the block at `height` from the closest seen blocks at or below it and above it, given as heights and times.
+fn estimate_time(
+    height: u64,
+    below: Option<(u64, u64)>,
+    above: Option<(u64, u64)>,
+) -> Option<BlockTime> {
+    let time = match (below, above) {
+        (Some((below, time)), _) if below == height => {
+            return Some(BlockTime {
+                time,
+                estimated: false,
+            })
+        }
+        // the daemon wasn't running in between, so spread the time evenly
+        (Some((below, below_time)), Some((above, above_time))) => {
+            below_time + above_time.saturating_sub(below_time) * (height - below) / (above - below)
+        }
+        (Some((below, time)), None) => time + (height - below) * BLOCK_INTERVAL_SECS,
+        (None, Some((above, time))) => time.saturating_sub((above - height) * BLOCK_INTERVAL_SECS),
+        (None, None) => return None,
+    };
+    Some(BlockTime {
+        time,
+        estimated: true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_unseen_blocks() {
+        assert_eq!(estimate_time(10, None, None), None);
+        let seen = estimate_time(10, Some((10, 5000)), Some((20, 6000))).unwrap();
+        assert_eq!((seen.time, seen.estimated), (5000, false));
+        let between = estimate_time(15, Some((10, 5000)), Some((20, 6000))).unwrap();
+        assert_eq!((between.time, between.estimated), (5500, true));
+        assert_eq!(
+            estimate_time(12, Some((10, 5000)), None).unwrap().time,
+            5060
+        );
+        assert_eq!(estimate_time(8, None, Some((10, 5000))).unwrap().time, 4940);
+    }
+}
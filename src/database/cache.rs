@@ -0,0 +1,76 @@
+use melstructs::Transaction;
+use rusqlite::params;
+use stdcode::StdcodeSerializeExt;
+
+use crate::protocol::types::TransactionCacheStats;
+
+use super::{search, Database, Wallet};
+
+impl Wallet {
+    /// Puts a transaction into the shared cache, on behalf of this wallet.
+    pub(super) async fn cache_transaction(&self, txn: &Transaction) -> anyhow::Result<()> {
+        let txhash = txn.hash_nosigs().to_string();
+        let mut conn = self.cache.get_conn().await;
+        let conn = conn.transaction()?;
+        conn.execute(
+            "insert into transactions values ($1, $2) on conflict do nothing",
+            params![txhash, txn.stdcode()],
+        )?;
+        conn.execute(
+            "insert into transaction_refs (txhash, name) values ($1, $2) on conflict do nothing",
+            params![txhash, self.name],
+        )?;
+        search::index_transaction(&conn, &self.name, txn)?;
+        conn.commit()?;
+        Ok(())
+    }
+}
+
+impl Database {
+    /// Gathers statistics about the transaction cache.
+    pub async fn transaction_cache_stats(&self) -> anyhow::Result<TransactionCacheStats> {
+        let conn = self.pool.get_conn().await;
+        let (transactions, bytes): (u64, u64) = conn.query_row(
+            "select count(*), coalesce(sum(length(txblob)), 0) from transactions",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let unreferenced: u64 = conn.query_row(
+            "select count(*) from transactions where txhash not in (select txhash from transaction_refs natural join wallet_names)",
+            [],
+            |row| row.get(0),
+        )?;
+        let mut stmt = conn.prepare_cached(
+            "select name, count(*) from transaction_refs natural join transactions where name in (select name from wallet_names) group by name",
+        )?;
+        let per_wallet = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(TransactionCacheStats {
+            transactions,
+            bytes,
+            unreferenced,
+            per_wallet,
+        })
+    }
+
+    /// Removes every cached transaction that no existing wallet refers to, along with references left behind by wallets that no longer exist. Returns the number of transactions removed.
+    pub async fn prune_transaction_cache(&self) -> anyhow::Result<u64> {
+        let mut conn = self.pool.get_conn().await;
+        let txn = conn.transaction()?;
+        txn.execute(
+            "delete from transaction_refs where name not in (select name from wallet_names)",
+            [],
+        )?;
+        txn.execute(
+            "delete from tx_search where name not in (select name from wallet_names)",
+            [],
+        )?;
+        let removed = txn.execute(
+            "delete from transactions where txhash not in (select txhash from transaction_refs)",
+            [],
+        )?;
+        txn.commit()?;
+        Ok(removed as u64)
+    }
+}
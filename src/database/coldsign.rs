@@ -0,0 +1,179 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use melstructs::{BlockHeight, Transaction, TxHash};
+use melvm::Covenant;
+use rusqlite::{params, OptionalExtension};
+use stdcode::StdcodeSerializeExt;
+use tmelcrypt::Ed25519PK;
+
+use crate::{
+    protocol::types::{SigningRequest, SigningStatus},
+    signer::sighashes,
+};
+
+use super::Database;
+
+fn status_to_str(status: SigningStatus) -> String {
+    serde_json::to_value(status)
+        .ok()
+        .and_then(|v| v.as_str().map(|s| s.to_owned()))
+        .expect("status is a plain string")
+}
+
+fn status_from_str(s: String) -> anyhow::Result<SigningStatus> {
+    Ok(serde_json::from_value(serde_json::Value::String(s))?)
+}
+
+impl Database {
+    /// Creates a watch-only wallet, which can track coins and prepare transactions for the given public key, but never sign them.
+    pub async fn create_watch_only_wallet(
+        &self,
+        name: &str,
+        pubkey: Ed25519PK,
+    ) -> anyhow::Result<()> {
+        self.create_wallet(name, Covenant::std_ed25519_pk_new(pubkey))
+            .await?;
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into watch_only_keys values ($1, $2)",
+            params![name, pubkey.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Creates a watch-only wallet from a watch package, recording the height the package was made at. Unlike [Database::create_watch_only_wallet], the wallet has no key to sign with, not even elsewhere.
+    pub async fn create_imported_wallet(
+        &self,
+        name: &str,
+        covenant: Covenant,
+        height: BlockHeight,
+    ) -> anyhow::Result<()> {
+        self.create_wallet(name, covenant).await?;
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into watch_imports values ($1, $2)",
+            params![name, height.0],
+        )?;
+        Ok(())
+    }
+
+    /// Whether a wallet was imported from a watch package.
+    pub async fn is_imported_wallet(&self, name: &str) -> anyhow::Result<bool> {
+        let conn = self.pool.get_conn().await;
+        Ok(conn
+            .query_row(
+                "select 1 from watch_imports where name = $1",
+                [name],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    /// Gets the public key of a watch-only wallet, or None if the wallet isn't watch-only.
+    pub async fn watch_only_key(&self, name: &str) -> anyhow::Result<Option<Ed25519PK>> {
+        let conn = self.pool.get_conn().await;
+        let pubkey: Option<String> = conn
+            .query_row(
+                "select pubkey from watch_only_keys where name = $1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(pubkey.map(|pk| pk.parse()).transpose()?)
+    }
+
+    /// Records a freshly prepared, unsigned transaction of a watch-only wallet.
+    pub async fn insert_signing_request(
+        &self,
+        name: &str,
+        tx: &Transaction,
+    ) -> anyhow::Result<SigningRequest> {
+        let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into signing_requests values ($1, $2, $3, $4, $5)",
+            params![
+                tx.hash_nosigs().to_string(),
+                name,
+                status_to_str(SigningStatus::Prepared),
+                tx.stdcode(),
+                created
+            ],
+        )?;
+        Ok(SigningRequest {
+            txhash: tx.hash_nosigs(),
+            status: SigningStatus::Prepared,
+            sighashes: sighashes(tx),
+            transaction: tx.clone(),
+            created,
+        })
+    }
+
+    /// Gets a signing request of a wallet.
+    pub async fn get_signing_request(
+        &self,
+        name: &str,
+        txhash: TxHash,
+    ) -> anyhow::Result<Option<SigningRequest>> {
+        Ok(self
+            .list_signing_requests(name)
+            .await?
+            .into_iter()
+            .find(|req| req.txhash == txhash))
+    }
+
+    /// Lists all signing requests of a wallet, oldest first.
+    pub async fn list_signing_requests(&self, name: &str) -> anyhow::Result<Vec<SigningRequest>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached(
+            "select txhash, status, txblob, created from signing_requests where name = $1 order by created",
+        )?;
+        let mut rows = stmt.query([name])?;
+        let mut toret = vec![];
+        while let Some(row) = rows.next()? {
+            let txhash: String = row.get(0)?;
+            let blob: Vec<u8> = row.get(2)?;
+            let transaction: Transaction = stdcode::deserialize(&blob)?;
+            toret.push(SigningRequest {
+                txhash: txhash.parse()?,
+                status: status_from_str(row.get(1)?)?,
+                sighashes: sighashes(&transaction),
+                transaction,
+                created: row.get(3)?,
+            });
+        }
+        Ok(toret)
+    }
+
+    /// Moves a signing request to a new status, replacing its transaction (e.g. with a signed version) if given.
+    pub async fn update_signing_request(
+        &self,
+        txhash: TxHash,
+        status: SigningStatus,
+        tx: Option<&Transaction>,
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "update signing_requests set status = $1 where txhash = $2",
+            params![status_to_str(status), txhash.to_string()],
+        )?;
+        if let Some(tx) = tx {
+            conn.execute(
+                "update signing_requests set txblob = $1 where txhash = $2",
+                params![tx.stdcode(), txhash.to_string()],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Discards a signing request. Returns whether there was such a request.
+    pub async fn delete_signing_request(&self, name: &str, txhash: TxHash) -> anyhow::Result<bool> {
+        let conn = self.pool.get_conn().await;
+        let removed = conn.execute(
+            "delete from signing_requests where name = $1 and txhash = $2",
+            params![name, txhash.to_string()],
+        )?;
+        Ok(removed > 0)
+    }
+}
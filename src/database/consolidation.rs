@@ -0,0 +1,55 @@
+use rusqlite::{params, OptionalExtension};
+
+use crate::protocol::types::ConsolidationPolicy;
+
+use super::Database;
+
+impl Database {
+    /// Gets the consolidation policy of a wallet, if it has one.
+    pub async fn consolidation_policy(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<Option<ConsolidationPolicy>> {
+        let conn = self.pool.get_conn().await;
+        let policy: Option<String> = conn
+            .query_row(
+                "select policy from consolidation_policies where name = $1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(policy.map(|p| serde_json::from_str(&p)).transpose()?)
+    }
+
+    /// Sets or, given None, removes the consolidation policy of a wallet.
+    pub async fn set_consolidation_policy(
+        &self,
+        name: &str,
+        policy: Option<&ConsolidationPolicy>,
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        match policy {
+            Some(policy) => conn.execute(
+                "insert or replace into consolidation_policies values ($1, $2)",
+                params![name, serde_json::to_string(policy)?],
+            )?,
+            None => conn.execute("delete from consolidation_policies where name = $1", [name])?,
+        };
+        Ok(())
+    }
+
+    /// Lists every wallet with a consolidation policy, along with it.
+    pub async fn consolidation_policies(
+        &self,
+    ) -> anyhow::Result<Vec<(String, ConsolidationPolicy)>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached("select name, policy from consolidation_policies")?;
+        let mut rows = stmt.query([])?;
+        let mut toret = vec![];
+        while let Some(row) = rows.next()? {
+            let policy: String = row.get(1)?;
+            toret.push((row.get(0)?, serde_json::from_str(&policy)?));
+        }
+        Ok(toret)
+    }
+}
@@ -0,0 +1,89 @@
+use melstructs::{BlockHeight, CoinData, Denom};
+use rusqlite::params;
+
+use crate::{
+    payload,
+    protocol::types::{DataCoin, PayloadTemplate},
+};
+
+use super::{values::SqlValue, Wallet};
+
+fn template_to_str(template: PayloadTemplate) -> &'static str {
+    match template {
+        PayloadTemplate::Nft => "nft",
+        PayloadTemplate::Registry => "registry",
+    }
+}
+
+impl Wallet {
+    /// Indexes the payloads of this wallet's coins that carry data and haven't been looked at yet. Coins whose data isn't a payload are indexed too, without a template, so they're only looked at once.
+    pub async fn index_data_coins(&self) -> anyhow::Result<()> {
+        let mut conn = self.pool.get_conn().await;
+        let txn = conn.transaction()?;
+        let covhash = self.covhash.to_string();
+        let found: Vec<(String, Vec<u8>)> = {
+            let mut stmt = txn.prepare_cached(
+                "select coinid, additional_data from coins where covhash = $1 and length(additional_data) > 0
+                and not exists (select coinid from data_coins where data_coins.coinid = coins.coinid)",
+            )?;
+            let rows = stmt.query_map(params![covhash], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<Result<_, _>>()?
+        };
+        for (coinid, data) in found {
+            let payload = payload::decode(&data);
+            txn.execute(
+                "insert into data_coins (coinid, covhash, template, payload) values ($1, $2, $3, $4)",
+                params![
+                    coinid,
+                    covhash,
+                    payload.as_ref().map(|p| template_to_str(p.template())),
+                    payload.map(|p| serde_json::to_string(&p)).transpose()?
+                ],
+            )?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Lists this wallet's coins carrying payloads, optionally only those of one template, oldest first.
+    pub async fn data_coins(
+        &self,
+        template: Option<PayloadTemplate>,
+    ) -> anyhow::Result<Vec<DataCoin>> {
+        let conn = self.pool.get_read_conn().await;
+        let mut stmt = conn.prepare_cached(
+            "select coins.coinid, coins.value, coins.denom, coins.additional_data, data_coins.payload, coin_confirmations.height,
+                exists (select txhash from spends where spends.coinid = coins.coinid)
+            from data_coins join coins on coins.coinid = data_coins.coinid left join coin_confirmations on coin_confirmations.coinid = coins.coinid
+            where data_coins.covhash = $1 and data_coins.template is not null and ($2 is null or data_coins.template = $2)
+            order by coin_confirmations.height, coins.coinid",
+        )?;
+        let mut rows = stmt.query(params![
+            self.covhash.to_string(),
+            template.map(template_to_str)
+        ])?;
+        let mut toret = vec![];
+        while let Some(row) = rows.next()? {
+            let coin_id: String = row.get(0)?;
+            let value: SqlValue = row.get(1)?;
+            let denom: Vec<u8> = row.get(2)?;
+            let additional_data: Vec<u8> = row.get(3)?;
+            let payload: String = row.get(4)?;
+            let height: Option<u64> = row.get(5)?;
+            toret.push(DataCoin {
+                coin_id: coin_id.parse()?,
+                coin_data: CoinData {
+                    covhash: self.covhash,
+                    value: value.0,
+                    denom: Denom::from_bytes(&denom)
+                        .ok_or_else(|| anyhow::anyhow!("malformed denom in db"))?,
+                    additional_data: additional_data.into(),
+                },
+                payload: serde_json::from_str(&payload)?,
+                height: height.map(BlockHeight),
+                spent: row.get(6)?,
+            });
+        }
+        Ok(toret)
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use melstructs::{BlockHeight, CoinValue, Denom};
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::protocol::types::{DcaJob, DcaRun};
+
+use super::{values::SqlValue, Database};
+
+const DCA_JOB_COLUMNS: &str =
+    "id, name, from_denom, to_denom, amount, interval_secs, max_slippage, next_run, active, created";
+
+fn dca_job_from_row(row: &Row) -> anyhow::Result<DcaJob> {
+    let amount: SqlValue = row.get(4)?;
+    Ok(DcaJob {
+        id: row.get(0)?,
+        wallet_name: row.get(1)?,
+        from: row.get(2)?,
+        to: row.get(3)?,
+        amount: amount.0,
+        interval_secs: row.get(5)?,
+        max_slippage: row.get(6)?,
+        next_run: row.get(7)?,
+        active: row.get(8)?,
+        created: row.get(9)?,
+    })
+}
+
+impl Database {
+    /// Creates a DCA job for a wallet, whose first run is due right away.
+    pub async fn create_dca_job(
+        &self,
+        name: &str,
+        from: Denom,
+        to: Denom,
+        amount: CoinValue,
+        interval_secs: u64,
+        max_slippage: f64,
+    ) -> anyhow::Result<DcaJob> {
+        let mut id = [0u8; 16];
+        getrandom::getrandom(&mut id).expect("no randomness");
+        let id = hex::encode(id);
+        let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into dca_jobs (id, name, from_denom, to_denom, amount, interval_secs, max_slippage, next_run, created) values ($1, $2, $3, $4, $5, $6, $7, $8, $8)",
+            params![
+                id,
+                name,
+                from.to_string(),
+                to.to_string(),
+                SqlValue(amount),
+                interval_secs,
+                max_slippage,
+                created
+            ],
+        )?;
+        drop(conn);
+        self.get_dca_job(&id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("DCA job disappeared"))
+    }
+
+    /// Gets a DCA job by its ID.
+    pub async fn get_dca_job(&self, id: &str) -> anyhow::Result<Option<DcaJob>> {
+        let conn = self.pool.get_conn().await;
+        let job = conn
+            .query_row(
+                &format!("select {DCA_JOB_COLUMNS} from dca_jobs where id = $1"),
+                [id],
+                |row| Ok(dca_job_from_row(row)),
+            )
+            .optional()?;
+        job.transpose()
+    }
+
+    /// Lists the DCA jobs of a wallet, or only the active DCA jobs of all wallets, oldest first.
+    pub async fn list_dca_jobs(&self, name: Option<&str>) -> anyhow::Result<Vec<DcaJob>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached(&format!(
+            "select {DCA_JOB_COLUMNS} from dca_jobs
+            where ($1 is null and active) or name = $1 order by created"
+        ))?;
+        let mut rows = stmt.query([name])?;
+        let mut toret = vec![];
+        while let Some(row) = rows.next()? {
+            toret.push(dca_job_from_row(row)?);
+        }
+        Ok(toret)
+    }
+
+    /// Stops a DCA job from running again.
+    pub async fn cancel_dca_job(&self, id: &str) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute("update dca_jobs set active = 0 where id = $1", [id])?;
+        Ok(())
+    }
+
+    /// Records a run of a DCA job, and when the next one is due.
+    pub async fn record_dca_run(&self, run: &DcaRun, next_run: u64) -> anyhow::Result<()> {
+        let mut conn = self.pool.get_conn().await;
+        let txn = conn.transaction()?;
+        txn.execute(
+            "insert into dca_runs values ($1, $2, $3, $4, $5, $6)",
+            params![
+                run.job_id,
+                run.time,
+                run.height.0,
+                run.txhash.map(|t| t.to_string()),
+                run.output.map(SqlValue),
+                run.error
+            ],
+        )?;
+        txn.execute(
+            "update dca_jobs set next_run = $1 where id = $2",
+            params![next_run, run.job_id],
+        )?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// The latest `limit` runs of a DCA job, oldest first.
+    pub async fn dca_runs(&self, id: &str, limit: usize) -> anyhow::Result<Vec<DcaRun>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached(
+            "select job_id, time, height, txhash, output, error from dca_runs
+            where job_id = $1 order by time desc, rowid desc limit $2",
+        )?;
+        let mut rows = stmt.query(params![id, limit as u64])?;
+        let mut runs = vec![];
+        while let Some(row) = rows.next()? {
+            let txhash: Option<String> = row.get(3)?;
+            let output: Option<SqlValue> = row.get(4)?;
+            runs.push(DcaRun {
+                job_id: row.get(0)?,
+                time: row.get(1)?,
+                height: BlockHeight(row.get(2)?),
+                txhash: txhash.map(|t| t.parse()).transpose()?,
+                output: output.map(|v| v.0),
+                error: row.get(5)?,
+            });
+        }
+        runs.reverse();
+        Ok(runs)
+    }
+}
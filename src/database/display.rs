@@ -0,0 +1,37 @@
+use rusqlite::{params, OptionalExtension};
+
+use crate::protocol::types::DisplayPreferences;
+
+use super::Database;
+
+impl Database {
+    /// Gets the display preferences of a wallet, which are the defaults if none were set.
+    pub async fn display_preferences(&self, name: &str) -> anyhow::Result<DisplayPreferences> {
+        let conn = self.pool.get_conn().await;
+        let preferences: Option<String> = conn
+            .query_row(
+                "select preferences from display_preferences where name = $1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(preferences
+            .map(|p| serde_json::from_str(&p))
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    /// Sets the display preferences of a wallet.
+    pub async fn set_display_preferences(
+        &self,
+        name: &str,
+        preferences: &DisplayPreferences,
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert or replace into display_preferences values ($1, $2)",
+            params![name, serde_json::to_string(preferences)?],
+        )?;
+        Ok(())
+    }
+}
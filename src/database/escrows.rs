@@ -0,0 +1,168 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use melstructs::{CoinID, CoinValue, Transaction};
+use melvm::Covenant;
+use rusqlite::{params, OptionalExtension, Row};
+use tmelcrypt::Ed25519PK;
+
+use crate::{
+    escrow::{escrow_covenant, signed_by},
+    protocol::types::{Escrow, EscrowStatus},
+};
+
+use super::{values::SqlValue, Database};
+
+fn status_to_str(status: EscrowStatus) -> &'static str {
+    match status {
+        EscrowStatus::Created => "created",
+        EscrowStatus::Funded => "funded",
+        EscrowStatus::Releasing => "releasing",
+        EscrowStatus::Refunding => "refunding",
+        EscrowStatus::Released => "released",
+        EscrowStatus::Refunded => "refunded",
+    }
+}
+
+fn status_from_str(s: &str) -> anyhow::Result<EscrowStatus> {
+    Ok(match s {
+        "created" => EscrowStatus::Created,
+        "funded" => EscrowStatus::Funded,
+        "releasing" => EscrowStatus::Releasing,
+        "refunding" => EscrowStatus::Refunding,
+        "released" => EscrowStatus::Released,
+        "refunded" => EscrowStatus::Refunded,
+        other => anyhow::bail!("unknown escrow status {other}"),
+    })
+}
+
+const ESCROW_COLUMNS: &str =
+    "id, buyer, seller, arbiter, nonce, status, coinid, value, txblob, created";
+
+/// An escrow, along with what is needed to spend from it.
+pub struct EscrowRecord {
+    pub escrow: Escrow,
+    pub parties: [Ed25519PK; 3],
+    pub covenant: Covenant,
+}
+
+fn escrow_from_row(row: &Row) -> anyhow::Result<EscrowRecord> {
+    let keys: [String; 3] = [row.get(1)?, row.get(2)?, row.get(3)?];
+    let parties = [keys[0].parse()?, keys[1].parse()?, keys[2].parse()?];
+    let nonce: Vec<u8> = row.get(4)?;
+    let status: String = row.get(5)?;
+    let coin: Option<String> = row.get(6)?;
+    let value: Option<SqlValue> = row.get(7)?;
+    let txblob: Option<Vec<u8>> = row.get(8)?;
+    let transaction: Option<Transaction> =
+        txblob.map(|blob| stdcode::deserialize(&blob)).transpose()?;
+    let covenant = escrow_covenant(parties, &nonce);
+    let [buyer, seller, arbiter] = keys;
+    Ok(EscrowRecord {
+        escrow: Escrow {
+            id: row.get(0)?,
+            address: covenant.hash(),
+            buyer,
+            seller,
+            arbiter,
+            status: status_from_str(&status)?,
+            coin: coin.map(|c| c.parse()).transpose()?,
+            value: value.map(|v| v.0),
+            signed_by: transaction
+                .as_ref()
+                .map(|txn| signed_by(parties, txn))
+                .unwrap_or_default(),
+            transaction,
+            created: row.get(9)?,
+        },
+        parties,
+        covenant,
+    })
+}
+
+impl Database {
+    /// Creates an escrow between three parties, waiting to be funded.
+    pub async fn create_escrow(&self, parties: [Ed25519PK; 3]) -> anyhow::Result<EscrowRecord> {
+        let mut nonce = [0u8; 16];
+        getrandom::getrandom(&mut nonce).expect("no randomness");
+        let id = hex::encode(nonce);
+        let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into escrows (id, buyer, seller, arbiter, nonce, status, created) values ($1, $2, $3, $4, $5, $6, $7)",
+            params![
+                id,
+                parties[0].to_string(),
+                parties[1].to_string(),
+                parties[2].to_string(),
+                nonce.to_vec(),
+                status_to_str(EscrowStatus::Created),
+                created
+            ],
+        )?;
+        drop(conn);
+        self.get_escrow(&id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("escrow vanished"))
+    }
+
+    /// Gets an escrow by its ID.
+    pub async fn get_escrow(&self, id: &str) -> anyhow::Result<Option<EscrowRecord>> {
+        let conn = self.pool.get_conn().await;
+        let record = conn
+            .query_row(
+                &format!("select {ESCROW_COLUMNS} from escrows where id = $1"),
+                [id],
+                |row| Ok(escrow_from_row(row)),
+            )
+            .optional()?;
+        record.transpose()
+    }
+
+    /// Lists all escrows, oldest first.
+    pub async fn list_escrows(&self) -> anyhow::Result<Vec<Escrow>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare(&format!(
+            "select {ESCROW_COLUMNS} from escrows order by created"
+        ))?;
+        let escrows = stmt
+            .query_map([], |row| Ok(escrow_from_row(row)))?
+            .map(|record| Ok(record??.escrow))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(escrows)
+    }
+
+    /// Records that an escrow was funded with a coin.
+    pub async fn fund_escrow(
+        &self,
+        id: &str,
+        coin: CoinID,
+        value: CoinValue,
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "update escrows set status = $2, coinid = $3, value = $4 where id = $1",
+            params![
+                id,
+                status_to_str(EscrowStatus::Funded),
+                coin.to_string(),
+                SqlValue(value)
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Moves an escrow to a new state, with the release or refund transaction collecting signatures.
+    pub async fn update_escrow(
+        &self,
+        id: &str,
+        status: EscrowStatus,
+        txn: &Transaction,
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "update escrows set status = $2, txblob = $3 where id = $1",
+            params![id, status_to_str(status), stdcode::serialize(txn)?],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use melprot::Snapshot;
+use melstructs::{Address, CoinDataHeight, CoinID};
+use melvm::Covenant;
+use rusqlite::params;
+
+use super::{values::SqlValue, Wallet};
+
+impl Wallet {
+    /// Adds a confirmed coin guarded by some covenant other than the wallet's own, which the wallet has been checked to be able to spend.
+    pub async fn import_coin(
+        &self,
+        coinid: CoinID,
+        cdh: &CoinDataHeight,
+        covenant: &Covenant,
+    ) -> anyhow::Result<()> {
+        let mut conn = self.pool.get_conn().await;
+        let txn = conn.transaction()?;
+        if cdh.coin_data.covhash != self.covhash {
+            txn.execute(
+                "insert into imported_covenants values ($1, $2, $3) on conflict do nothing",
+                params![
+                    self.name,
+                    cdh.coin_data.covhash.to_string(),
+                    covenant.to_bytes().to_vec()
+                ],
+            )?;
+        }
+        txn.execute(
+            "insert into coins values ($1, $2, $3, $4, $5) on conflict do nothing",
+            params![
+                coinid.to_string(),
+                cdh.coin_data.covhash.to_string(),
+                SqlValue(cdh.coin_data.value),
+                cdh.coin_data.denom.to_bytes().to_vec(),
+                cdh.coin_data.additional_data.to_vec()
+            ],
+        )?;
+        txn.execute(
+            "insert into coin_confirmations values ($1, $2) on conflict do nothing",
+            params![coinid.to_string(), cdh.height.0],
+        )?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Gets the covenants guarding coins imported into this wallet, by address.
+    pub async fn imported_covenants(&self) -> anyhow::Result<BTreeMap<Address, Bytes>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn
+            .prepare_cached("select covhash, covenant from imported_covenants where name = $1")?;
+        let mut rows = stmt.query([&self.name])?;
+        let mut toret = BTreeMap::new();
+        while let Some(row) = rows.next()? {
+            let covhash: String = row.get(0)?;
+            let covenant: Vec<u8> = row.get(1)?;
+            toret.insert(covhash.parse()?, covenant.into());
+        }
+        Ok(toret)
+    }
+
+    /// Forgets imported coins that have been spent elsewhere. The wallet's sync only follows its own address, so this is the only way it finds out.
+    pub async fn sync_imported(&self, snapshot: Snapshot) -> anyhow::Result<()> {
+        let imported = self.imported_covenants().await?;
+        if imported.is_empty() {
+            return Ok(());
+        }
+        for (coinid, data) in self.get_coin_mapping(true, true).await {
+            if !imported.contains_key(&data.covhash) || snapshot.get_coin(coinid).await?.is_some() {
+                continue;
+            }
+            log::info!("imported coin {coinid} of {} is gone", self.name);
+            let conn = self.pool.get_conn().await;
+            conn.execute("delete from coins where coinid = $1", [coinid.to_string()])?;
+            conn.execute(
+                "delete from coin_confirmations where coinid = $1",
+                [coinid.to_string()],
+            )?;
+        }
+        Ok(())
+    }
+}
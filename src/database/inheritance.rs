@@ -0,0 +1,124 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use melstructs::{Address, Transaction};
+use rusqlite::{params, OptionalExtension, Row};
+
+use super::Database;
+
+/// The dead-man switch of a wallet.
+pub struct InheritanceRecord {
+    pub recovery: Address,
+    pub inactivity_secs: u64,
+    /// UNIX timestamp at which the wallet was last unlocked or pinged
+    pub last_activity: u64,
+    /// Pre-signed transactions sweeping the wallet to the recovery address
+    pub sweeps: Vec<Transaction>,
+    /// UNIX timestamp at which the sweeps were broadcast
+    pub triggered: Option<u64>,
+}
+
+fn record_from_row(row: &Row) -> anyhow::Result<InheritanceRecord> {
+    let recovery: String = row.get(0)?;
+    let sweeps: Vec<u8> = row.get(3)?;
+    Ok(InheritanceRecord {
+        recovery: recovery.parse()?,
+        inactivity_secs: row.get(1)?,
+        last_activity: row.get(2)?,
+        sweeps: stdcode::deserialize(&sweeps)?,
+        triggered: row.get(4)?,
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock before 1970")
+        .as_secs()
+}
+
+impl Database {
+    /// Sets up (or reconfigures) the dead-man switch of a wallet, restarting its timer.
+    pub async fn set_inheritance(
+        &self,
+        name: &str,
+        recovery: Address,
+        inactivity_secs: u64,
+        sweeps: &[Transaction],
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert or replace into inheritance values ($1, $2, $3, $4, $5, null)",
+            params![
+                name,
+                recovery.to_string(),
+                inactivity_secs,
+                now(),
+                stdcode::serialize(&sweeps)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Gets the dead-man switch of a wallet, if it has one.
+    pub async fn get_inheritance(&self, name: &str) -> anyhow::Result<Option<InheritanceRecord>> {
+        let conn = self.pool.get_conn().await;
+        let record = conn
+            .query_row(
+                "select recovery, inactivity_secs, last_activity, sweeps, triggered from inheritance where name = $1",
+                [name],
+                |row| Ok(record_from_row(row)),
+            )
+            .optional()?;
+        record.transpose()
+    }
+
+    /// Lists the wallets with a dead-man switch.
+    pub async fn list_inheritance(&self) -> anyhow::Result<Vec<String>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached("select name from inheritance")?;
+        let names = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(names)
+    }
+
+    /// Restarts the timer of a wallet's dead-man switch, if it has one.
+    pub async fn touch_inheritance(&self, name: &str) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "update inheritance set last_activity = $2 where name = $1",
+            params![name, now()],
+        )?;
+        Ok(())
+    }
+
+    /// Replaces the pre-signed sweeps of a wallet's dead-man switch.
+    pub async fn set_inheritance_sweeps(
+        &self,
+        name: &str,
+        sweeps: &[Transaction],
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "update inheritance set sweeps = $2 where name = $1",
+            params![name, stdcode::serialize(&sweeps)?],
+        )?;
+        Ok(())
+    }
+
+    /// Records that a wallet's dead-man switch went off.
+    pub async fn mark_inheritance_triggered(&self, name: &str) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "update inheritance set triggered = $2 where name = $1",
+            params![name, now()],
+        )?;
+        Ok(())
+    }
+
+    /// Removes the dead-man switch of a wallet. Returns whether it had one.
+    pub async fn delete_inheritance(&self, name: &str) -> anyhow::Result<bool> {
+        let conn = self.pool.get_conn().await;
+        Ok(conn.execute("delete from inheritance where name = $1", [name])? > 0)
+    }
+}
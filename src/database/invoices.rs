@@ -0,0 +1,177 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use melstructs::{BlockHeight, CoinValue, Denom};
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::protocol::types::{Invoice, InvoiceStatus};
+
+use super::{
+    values::{joined_sum, SqlValue, SPLIT_SUM},
+    Database, Wallet,
+};
+
+fn status_to_str(status: InvoiceStatus) -> &'static str {
+    match status {
+        InvoiceStatus::Open => "open",
+        InvoiceStatus::Paid => "paid",
+        InvoiceStatus::Expired => "expired",
+    }
+}
+
+fn status_from_str(s: &str) -> anyhow::Result<InvoiceStatus> {
+    Ok(match s {
+        "open" => InvoiceStatus::Open,
+        "paid" => InvoiceStatus::Paid,
+        "expired" => InvoiceStatus::Expired,
+        other => anyhow::bail!("unknown invoice status {other}"),
+    })
+}
+
+const INVOICE_COLUMNS: &str = "id, invoices.name, covhash, amount, denom, memo, webhook, status, received, paid_height, created, expires";
+
+fn invoice_from_row(row: &Row) -> anyhow::Result<Invoice> {
+    let covhash: String = row.get(2)?;
+    let amount: SqlValue = row.get(3)?;
+    let memo: Vec<u8> = row.get(5)?;
+    let status: String = row.get(7)?;
+    let received: SqlValue = row.get(8)?;
+    Ok(Invoice {
+        id: row.get(0)?,
+        wallet_name: row.get(1)?,
+        address: covhash.parse()?,
+        amount: amount.0,
+        denom: row.get(4)?,
+        memo: hex::encode(memo),
+        webhook: row.get(6)?,
+        status: status_from_str(&status)?,
+        received: received.0,
+        paid_height: row.get::<_, Option<u64>>(9)?.map(BlockHeight),
+        created: row.get(10)?,
+        expires: row.get(11)?,
+    })
+}
+
+impl Database {
+    /// Creates an open invoice for a wallet.
+    pub async fn create_invoice(
+        &self,
+        name: &str,
+        amount: CoinValue,
+        denom: Denom,
+        expires_in_secs: u64,
+        webhook: Option<String>,
+    ) -> anyhow::Result<Invoice> {
+        let memo: [u8; 16] = rand_bytes();
+        let id = hex::encode(memo);
+        let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into invoices (id, name, amount, denom, memo, webhook, status, received, created, expires) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            params![
+                id,
+                name,
+                SqlValue(amount),
+                denom.to_string(),
+                memo.to_vec(),
+                webhook,
+                status_to_str(InvoiceStatus::Open),
+                SqlValue(CoinValue(0)),
+                created,
+                created.saturating_add(expires_in_secs)
+            ],
+        )?;
+        drop(conn);
+        self.get_invoice(&id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("invoice disappeared"))
+    }
+
+    /// Gets an invoice by its ID.
+    pub async fn get_invoice(&self, id: &str) -> anyhow::Result<Option<Invoice>> {
+        let conn = self.pool.get_conn().await;
+        let invoice = conn
+            .query_row(
+                &format!("select {INVOICE_COLUMNS} from invoices join wallet_names on invoices.name = wallet_names.name where id = $1"),
+                [id],
+                |row| Ok(invoice_from_row(row)),
+            )
+            .optional()?;
+        invoice.transpose()
+    }
+
+    /// Lists the invoices of a wallet, or only the open invoices of all wallets, oldest first.
+    pub async fn list_invoices(&self, name: Option<&str>) -> anyhow::Result<Vec<Invoice>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached(&format!(
+            "select {INVOICE_COLUMNS} from invoices join wallet_names on invoices.name = wallet_names.name
+            where ($1 is null and status = 'open') or invoices.name = $1 order by created"
+        ))?;
+        let mut rows = stmt.query([name])?;
+        let mut toret = vec![];
+        while let Some(row) = rows.next()? {
+            toret.push(invoice_from_row(row)?);
+        }
+        Ok(toret)
+    }
+
+    /// Records the payments received for an invoice, and its resulting status.
+    pub async fn update_invoice(
+        &self,
+        id: &str,
+        status: InvoiceStatus,
+        received: CoinValue,
+        paid_height: Option<BlockHeight>,
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "update invoices set status = $1, received = $2, paid_height = $3 where id = $4",
+            params![
+                status_to_str(status),
+                SqlValue(received),
+                paid_height.map(|h| h.0),
+                id
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+impl Wallet {
+    /// Sums up the confirmed coins of a denomination carrying the given `additional_data`, and returns the total along with the height at which the last of them confirmed.
+    pub async fn received_with_memo(
+        &self,
+        denom: Denom,
+        memo: &[u8],
+    ) -> anyhow::Result<(CoinValue, Option<BlockHeight>)> {
+        let conn = self.pool.get_conn().await;
+        let (high, low, last_height): (Option<i64>, Option<i64>, Option<u64>) = conn.query_row(
+            &format!(
+                "select {SPLIT_SUM}, max(height) from coins natural join coin_confirmations
+                where covhash = $1 and denom = $2 and additional_data = $3"
+            ),
+            params![self.covhash.to_string(), denom.to_bytes().to_vec(), memo],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        let mut total = joined_sum(high, low);
+        // values too large for SQL to sum
+        let mut stmt = conn.prepare_cached(
+            "select value from coins natural join coin_confirmations
+            where covhash = $1 and denom = $2 and additional_data = $3 and typeof(value) = 'text'",
+        )?;
+        let mut rows = stmt.query(params![
+            self.covhash.to_string(),
+            denom.to_bytes().to_vec(),
+            memo
+        ])?;
+        while let Some(row) = rows.next()? {
+            total += row.get::<_, SqlValue>(0)?.0;
+        }
+        Ok((total, last_height.map(BlockHeight)))
+    }
+}
+
+fn rand_bytes() -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("no randomness");
+    bytes
+}
@@ -0,0 +1,44 @@
+use crate::{journal::SendJournal, protocol::types::JournalReplay};
+
+use super::Database;
+
+impl Database {
+    /// Journal of every transaction sent by the daemon.
+    pub fn journal(&self) -> &SendJournal {
+        &self.journal
+    }
+
+    /// Restores the send history of wallets from the journal, putting every journaled transaction back into the transaction cache. Confirmed coins come back by syncing, but the transactions a wallet sent, including their outputs to others, can only come back from the journal. If `only` is given, only that wallet's transactions are restored.
+    pub async fn rebuild_from_journal(&self, only: Option<&str>) -> anyhow::Result<JournalReplay> {
+        let (entries, corrupt) = self.journal.entries()?;
+        let mut replay = JournalReplay {
+            entries: entries.len(),
+            corrupt,
+            ..Default::default()
+        };
+        for entry in entries {
+            if only.is_some_and(|name| name != entry.wallet) {
+                continue;
+            }
+            let wallet = match self.get_wallet(&entry.wallet).await {
+                Some(wallet) => wallet,
+                None => {
+                    replay.skipped += 1;
+                    continue;
+                }
+            };
+            let txhash = entry.transaction.hash_nosigs();
+            if wallet.get_cached_transaction(txhash).await.is_some() {
+                replay.already_known += 1;
+                continue;
+            }
+            wallet.cache_transaction(&entry.transaction).await?;
+            log::info!(
+                "restored transaction {txhash} of {} from journal",
+                entry.wallet
+            );
+            replay.restored += 1;
+        }
+        Ok(replay)
+    }
+}
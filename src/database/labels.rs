@@ -0,0 +1,45 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use melstructs::CoinID;
+use rusqlite::params;
+
+use super::Wallet;
+
+impl Wallet {
+    /// Gets the labels of every labeled coin of the wallet.
+    pub async fn coin_labels(&self) -> anyhow::Result<BTreeMap<CoinID, BTreeSet<String>>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt =
+            conn.prepare_cached("select coinid, label from coin_labels where name = $1")?;
+        let rows: Vec<(String, String)> = stmt
+            .query_map(params![self.name], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        let mut labels: BTreeMap<CoinID, BTreeSet<String>> = BTreeMap::new();
+        for (coinid, label) in rows {
+            labels.entry(coinid.parse()?).or_default().insert(label);
+        }
+        Ok(labels)
+    }
+
+    /// Replaces the labels of a coin. An empty set removes all its labels.
+    pub async fn set_coin_labels(
+        &self,
+        coinid: CoinID,
+        labels: &BTreeSet<String>,
+    ) -> anyhow::Result<()> {
+        let mut conn = self.pool.get_conn().await;
+        let txn = conn.transaction()?;
+        txn.execute(
+            "delete from coin_labels where name = $1 and coinid = $2",
+            params![self.name, coinid.to_string()],
+        )?;
+        for label in labels {
+            txn.execute(
+                "insert into coin_labels (name, coinid, label) values ($1, $2, $3)",
+                params![self.name, coinid.to_string(), label],
+            )?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
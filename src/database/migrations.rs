@@ -0,0 +1,419 @@
+use std::path::Path;
+
+use anyhow::Context;
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// A single step in the evolution of the database schema.
+struct Migration {
+    description: &'static str,
+    sql: &'static str,
+}
+
+/// All schema migrations, in the order they must be applied. The schema version of a database is the number of migrations that have been applied to it.
+///
+/// Never edit or reorder migrations that have been released; append new ones instead. The first few use `if not exists`, since they also have to bring databases from before versioning was introduced up to date.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "initial schema",
+        sql: r"
+        -- *all* known coins, spent and unspent and 'virtual' and whatever
+        create table if not exists coins (coinid primary key, covhash, value, denom, additional_data);
+        create index if not exists coins_index on coins(covhash);
+        -- all confirmed coins
+        create table if not exists coin_confirmations (coinid primary key, height not null);
+        -- all pending coins
+        create table if not exists pending_coins (coinid primary key, txhash not null);
+        -- transactions to the coins that they spend
+        create table if not exists spends (coinid primary key, txhash not null);
+        -- pending spends with expiration block height
+        create table if not exists pending (txhash primary key, expires not null);
+        -- a *cache* of all known transactions
+        create table if not exists transactions (txhash primary key, txblob not null);
+        -- wallets by name
+        create table if not exists wallet_names (name primary key, covhash not null, covenant not null);
+        -- sync records in the past
+        create table if not exists sync_heights (covhash primary key not null, height not null);
+        ",
+    },
+    Migration {
+        description: "key rotations",
+        sql: r"
+        -- in-progress key rotations, with the new covenant and its (still encrypted) secret
+        create table if not exists key_rotations (name primary key, covhash not null, covenant not null, secret not null);
+        -- sweep transactions moving coins to the new key of a rotation
+        create table if not exists key_rotation_sweeps (txhash primary key, name not null);
+        ",
+    },
+    Migration {
+        description: "observe-only addresses",
+        sql: r"
+        -- observe-only addresses, with their balances as of the last sync
+        create table if not exists tracked_addresses (covhash primary key, label not null, coin_count, balances, height);
+        ",
+    },
+    Migration {
+        description: "per-wallet settings",
+        sql: r"
+        create table if not exists wallet_settings (name primary key, default_fee_ballast not null default 0);
+        ",
+    },
+    Migration {
+        description: "transaction cache references",
+        sql: r"
+        -- which wallets refer to each cached transaction
+        create table transaction_refs (txhash not null, name not null, primary key (txhash, name));
+        create index transaction_refs_name on transaction_refs(name);
+        -- transactions that spend or create a wallet's coins are referenced by that wallet
+        insert or ignore into transaction_refs
+            select spends.txhash, wallet_names.name from spends
+            natural join coins join wallet_names on coins.covhash = wallet_names.covhash;
+        insert or ignore into transaction_refs
+            select pending_coins.txhash, wallet_names.name from pending_coins
+            natural join coins join wallet_names on coins.covhash = wallet_names.covhash;
+        -- coin IDs are formatted as <txhash>-<index>
+        insert or ignore into transaction_refs
+            select substr(coins.coinid, 1, instr(coins.coinid, '-') - 1), wallet_names.name from coins
+            join wallet_names on coins.covhash = wallet_names.covhash;
+        ",
+    },
+    Migration {
+        description: "watch-only wallets and cold signing",
+        sql: r"
+        -- public keys of wallets whose secret key is kept elsewhere
+        create table watch_only_keys (name primary key, pubkey not null);
+        -- transactions of watch-only wallets going through cold signing
+        create table signing_requests (txhash primary key, name not null, status not null, txblob not null, created not null);
+        create index signing_requests_name on signing_requests(name);
+        ",
+    },
+    Migration {
+        description: "imported coins",
+        sql: r"
+        -- covenants, other than its own, guarding coins imported into a wallet
+        create table imported_covenants (name not null, covhash not null, covenant not null, primary key (name, covhash));
+        ",
+    },
+    Migration {
+        description: "invoices",
+        sql: r"
+        -- invoices awaiting payment to a wallet, identified by the memo that payments must carry
+        create table invoices (id primary key, name not null, amount not null, denom not null, memo not null, webhook, status not null, received not null default '0', paid_height, created not null, expires not null);
+        create index invoices_status on invoices(status);
+        ",
+    },
+    Migration {
+        description: "wallet types",
+        sql: r"
+        create table wallet_types (name primary key, kind not null, params not null);
+        ",
+    },
+    Migration {
+        description: "escrows",
+        sql: r"
+        create table escrows (id primary key, buyer not null, seller not null, arbiter not null, nonce not null, status not null, coinid, value, txblob, created not null);
+        ",
+    },
+    Migration {
+        description: "timelocks",
+        sql: r"
+        alter table imported_covenants add column unlock_height;
+        create table timelocks (coinid primary key, name not null, recipient not null, unlock_height not null, value not null, denom not null, status not null);
+        ",
+    },
+    Migration {
+        description: "inheritance",
+        sql: r"
+        create table inheritance (name primary key, recovery not null, inactivity_secs not null, last_activity not null, sweeps not null, triggered);
+        ",
+    },
+    Migration {
+        description: "anomaly detection",
+        sql: r"
+        -- outgoing transactions, for measuring how much wallets normally send
+        create table send_activity (txhash primary key, name not null, value not null, time not null);
+        create index send_activity_name on send_activity(name, time);
+        create table anomaly_policies (name primary key, policy not null);
+        -- transactions held back instead of being broadcast
+        create table held_sends (txhash primary key, name not null, txblob not null, reason not null, created not null);
+        ",
+    },
+    Migration {
+        description: "spend approval",
+        sql: r"
+        alter table held_sends add column kind not null default 'anomaly';
+        -- hash of the token that approves spends of wallets in approval mode
+        alter table wallet_settings add column approval_hash;
+        ",
+    },
+    Migration {
+        description: "transaction search",
+        sql: r"
+        -- full-text index over cached transactions, one row per wallet referring to each
+        create virtual table tx_search using fts5(txhash unindexed, name unindexed, content);
+        -- transactions cached before the index existed are indexed by the next sync
+        alter table transaction_refs add column searchable not null default 0;
+        ",
+    },
+    Migration {
+        description: "coin labels",
+        sql: r"
+        -- user-chosen labels of a wallet's coins, for coin control
+        create table coin_labels (name not null, coinid not null, label not null, primary key (name, coinid, label));
+        ",
+    },
+    Migration {
+        description: "signing limits",
+        sql: r"
+        create table signing_limits (name primary key, signing_limit not null);
+        ",
+    },
+    Migration {
+        description: "cached header",
+        sql: r"
+        create table cached_header (id integer primary key check (id = 0), header blob not null);
+        ",
+    },
+    Migration {
+        description: "wallet alerts",
+        sql: r"
+        -- per-wallet alert thresholds, and whether the wallet's balance was below its threshold at the last check
+        create table alert_thresholds (name primary key, thresholds not null, low_balance not null default 0);
+        -- transactions spending each wallet's coins that were already checked against its outgoing threshold
+        create table alert_checked (name not null, txhash not null, primary key (name, txhash));
+        -- alerts raised, oldest first
+        create table wallet_alerts (name not null, alert not null);
+        create index wallet_alerts_name on wallet_alerts(name);
+        ",
+    },
+    Migration {
+        description: "pending transaction age and retransmits",
+        sql: r"
+        alter table pending add column created;
+        alter table pending add column retransmits not null default 0;
+        ",
+    },
+    Migration {
+        description: "internal transfers",
+        sql: r"
+        create table internal_transfers (txhash primary key, from_wallet not null, to_wallet not null);
+        ",
+    },
+    Migration {
+        description: "swap orders",
+        sql: r"
+        create table swap_orders (id primary key, name not null, from_denom not null, to_denom not null, amount not null, limit_price not null, expiry not null, status not null, txhash, created not null);
+        create index swap_orders_status on swap_orders(status);
+        ",
+    },
+    Migration {
+        description: "dca jobs",
+        sql: r"
+        create table dca_jobs (id primary key, name not null, from_denom not null, to_denom not null, amount not null, interval_secs not null, max_slippage not null, next_run not null, active not null default 1, created not null);
+        create table dca_runs (job_id not null, time not null, height not null, txhash, output, error);
+        create index dca_runs_job on dca_runs(job_id, time);
+        ",
+    },
+    Migration {
+        description: "display preferences",
+        sql: r"
+        create table display_preferences (name primary key, preferences not null);
+        ",
+    },
+    Migration {
+        description: "integer coin values",
+        sql: r"
+        -- coin values become integers where they fit, so that SQL can compare and sum them; larger ones stay decimal strings
+        update coins set value = cast(value as integer) where typeof(value) = 'text' and (length(value) < 19 or (length(value) = 19 and value <= '9223372036854775807'));
+        update invoices set amount = cast(amount as integer) where typeof(amount) = 'text' and (length(amount) < 19 or (length(amount) = 19 and amount <= '9223372036854775807'));
+        update invoices set received = cast(received as integer) where typeof(received) = 'text' and (length(received) < 19 or (length(received) = 19 and received <= '9223372036854775807'));
+        update escrows set value = cast(value as integer) where typeof(value) = 'text' and (length(value) < 19 or (length(value) = 19 and value <= '9223372036854775807'));
+        update timelocks set value = cast(value as integer) where typeof(value) = 'text' and (length(value) < 19 or (length(value) = 19 and value <= '9223372036854775807'));
+        update send_activity set value = cast(value as integer) where typeof(value) = 'text' and (length(value) < 19 or (length(value) = 19 and value <= '9223372036854775807'));
+        update swap_orders set amount = cast(amount as integer) where typeof(amount) = 'text' and (length(amount) < 19 or (length(amount) = 19 and amount <= '9223372036854775807'));
+        update dca_jobs set amount = cast(amount as integer) where typeof(amount) = 'text' and (length(amount) < 19 or (length(amount) = 19 and amount <= '9223372036854775807'));
+        update dca_runs set output = cast(output as integer) where typeof(output) = 'text' and (length(output) < 19 or (length(output) = 19 and output <= '9223372036854775807'));
+        create index coins_denom_value on coins(covhash, denom, value);
+        ",
+    },
+    Migration {
+        description: "excluded denominations",
+        sql: r"
+        alter table wallet_settings add column exclude_denoms;
+        ",
+    },
+    Migration {
+        description: "consolidation policies",
+        sql: r"
+        create table consolidation_policies (name primary key, policy not null);
+        ",
+    },
+    Migration {
+        description: "block times",
+        sql: r"
+        create table block_times (height integer primary key, time integer not null);
+        ",
+    },
+    Migration {
+        description: "sync errors",
+        sql: r"
+        create table sync_errors (covhash text not null, time integer not null, height integer not null, message text not null);
+        create index sync_errors_covhash on sync_errors(covhash, time);
+        ",
+    },
+    Migration {
+        description: "trades",
+        sql: r"
+        create table trades (id primary key, name not null, terms not null, status not null, txhash, created not null);
+        ",
+    },
+    Migration {
+        description: "index of coins carrying data payloads",
+        sql: r"
+        create table data_coins (coinid primary key, covhash not null, template, payload);
+        create index data_coins_covhash on data_coins (covhash);
+        ",
+    },
+    Migration {
+        description: "pending rotation keys move to the secret store",
+        sql: r"
+        -- secrets of rotations started by older versions, until moved into the secret store
+        create table legacy_rotation_secrets (name primary key, covhash not null, secret not null);
+        insert into legacy_rotation_secrets select name, covhash, secret from key_rotations;
+        alter table key_rotations drop column secret;
+        ",
+    },
+    Migration {
+        description: "numeric balances of observe-only addresses",
+        sql: r"
+        -- balances of observe-only addresses as of the last sync, one row per denomination; refilled by the next sync
+        create table tracked_balances (covhash not null, denom not null, value not null, primary key (covhash, denom));
+        alter table tracked_addresses drop column balances;
+        ",
+    },
+    Migration {
+        description: "watch package imports",
+        sql: r"
+        create table watch_imports (name primary key, height not null);
+        ",
+    },
+    Migration {
+        description: "per-denomination send activity",
+        sql: r"
+        -- one row per denomination a transaction sends out; everything recorded so far was MEL
+        create table send_activity_new (txhash not null, name not null, denom not null, value not null, time not null, primary key (txhash, denom));
+        insert into send_activity_new select txhash, name, X'6d', value, time from send_activity;
+        drop table send_activity;
+        alter table send_activity_new rename to send_activity;
+        create index send_activity_name on send_activity(name, time);
+        ",
+    },
+];
+
+/// Checks that a database has exactly the schema this version expects, without changing it.
+pub fn check_version(conn: &Connection) -> anyhow::Result<()> {
+    let version: usize = conn
+        .query_row("select version from schema_version", [], |row| row.get(0))
+        .optional()
+        .context("no schema version")?
+        .unwrap_or_default();
+    anyhow::ensure!(
+        version == MIGRATIONS.len(),
+        "database has schema version {version}, but this melwalletd expects {}",
+        MIGRATIONS.len()
+    );
+    Ok(())
+}
+
+/// Brings the schema of a database up to date. If `backup_path` is given, an existing database is first backed up there, before any migration touches it.
+pub fn migrate(conn: &mut Connection, backup_path: Option<&Path>) -> anyhow::Result<()> {
+    conn.execute(
+        "create table if not exists schema_version (version not null)",
+        [],
+    )?;
+    let version: usize = conn
+        .query_row("select version from schema_version", [], |row| row.get(0))
+        .optional()?
+        .unwrap_or_default();
+    if version > MIGRATIONS.len() {
+        anyhow::bail!(
+            "database has schema version {version}, but this melwalletd only knows up to {}; refusing to downgrade",
+            MIGRATIONS.len()
+        );
+    }
+    if version == MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    // only back up databases that actually have something in them
+    let existing: bool = conn.query_row(
+        "select exists (select name from sqlite_master where type = 'table' and name = 'wallet_names')",
+        [],
+        |row| row.get(0),
+    )?;
+    if let (true, Some(backup_path)) = (existing, backup_path) {
+        let _ = std::fs::remove_file(backup_path);
+        conn.execute("vacuum into $1", params![backup_path.to_string_lossy()])
+            .context("cannot back up database before migrating")?;
+        log::info!(
+            "backed up database at schema version {version} to {:?}",
+            backup_path
+        );
+    }
+
+    for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let txn = conn.transaction()?;
+        txn.execute_batch(migration.sql)
+            .with_context(|| format!("migration {} failed", idx + 1))?;
+        txn.execute("delete from schema_version", [])?;
+        txn.execute("insert into schema_version values ($1)", params![idx + 1])?;
+        txn.commit()?;
+        log::info!(
+            "migrated database to schema version {} ({})",
+            idx + 1,
+            migration.description
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idempotent() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, None).unwrap();
+        migrate(&mut conn, None).unwrap();
+        let version: usize = conn
+            .query_row("select version from schema_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn refuses_downgrade() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, None).unwrap();
+        conn.execute(
+            "update schema_version set version = $1",
+            params![MIGRATIONS.len() + 1],
+        )
+        .unwrap();
+        assert!(migrate(&mut conn, None).is_err());
+    }
+
+    #[test]
+    fn checks_version_without_migrating() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert!(check_version(&conn).is_err());
+        migrate(&mut conn, None).unwrap();
+        check_version(&conn).unwrap();
+        conn.execute(
+            "update schema_version set version = $1",
+            params![MIGRATIONS.len() - 1],
+        )
+        .unwrap();
+        assert!(check_version(&conn).is_err());
+    }
+}
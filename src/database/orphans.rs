@@ -0,0 +1,38 @@
+use rusqlite::params;
+
+use super::{Database, Wallet};
+
+impl Database {
+    /// Deletes a wallet from the wallet list, along with its type, watch-only key and watch package import, for undoing a wallet whose creation failed halfway. Coins already synced stay in the database, unused, and are picked up again if a wallet with the same covenant is created.
+    pub async fn remove_wallet(&self, name: &str) -> anyhow::Result<()> {
+        {
+            let mut conn = self.pool.get_conn().await;
+            let txn = conn.transaction()?;
+            txn.execute("delete from wallet_names where name = $1", params![name])?;
+            txn.execute("delete from wallet_types where name = $1", params![name])?;
+            txn.execute("delete from watch_only_keys where name = $1", params![name])?;
+            txn.execute("delete from watch_imports where name = $1", params![name])?;
+            txn.commit()?;
+        }
+        if self.split_dir.is_some() {
+            let pool = self.wallet_pool(name).await?;
+            pool.get_conn()
+                .await
+                .execute("delete from wallet_names where name = $1", params![name])?;
+            self.wallet_pools.remove(name);
+        }
+        Ok(())
+    }
+}
+
+impl Wallet {
+    /// Whether any coin, spent or not, has ever been synced into this wallet.
+    pub async fn has_coins(&self) -> anyhow::Result<bool> {
+        let conn = self.pool.get_read_conn().await;
+        Ok(conn.query_row(
+            "select exists (select coinid from coins where covhash = $1)",
+            params![self.covhash.to_string()],
+            |row| row.get(0),
+        )?)
+    }
+}
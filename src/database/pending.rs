@@ -0,0 +1,75 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use melstructs::BlockHeight;
+use rusqlite::params;
+
+use super::Wallet;
+use crate::protocol::types::{PendingPurge, PendingTransaction};
+
+/// Pending transactions that spend or pay this wallet's coins.
+const WALLET_PENDING: &str = r"select txhash from pending where txhash in (
+    select spends.txhash from spends natural join coins where covhash = $1
+    union select pending_coins.txhash from pending_coins natural join coins where covhash = $1)";
+
+impl Wallet {
+    /// This wallet's pending transactions, oldest first.
+    pub async fn pending_transactions(&self) -> anyhow::Result<Vec<PendingTransaction>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let conn = self.pool.get_read_conn().await;
+        let mut stmt = conn.prepare_cached(&format!(
+            "select txhash, expires, retransmits, created from pending where txhash in ({WALLET_PENDING}) order by created, txhash"
+        ))?;
+        let mut rows = stmt.query(params![self.covhash.to_string()])?;
+        let mut pending = vec![];
+        while let Some(row) = rows.next()? {
+            let txhash: String = row.get(0)?;
+            let created: Option<u64> = row.get(3)?;
+            pending.push(PendingTransaction {
+                wallet: self.name.clone(),
+                txhash: txhash.parse()?,
+                expires: BlockHeight(row.get(1)?),
+                retransmits: row.get(2)?,
+                age_secs: created.map(|created| now.saturating_sub(created)),
+            });
+        }
+        Ok(pending)
+    }
+
+    /// Forgets this wallet's pending transactions that expired before `height`, releasing the coins they spent, just like the next sync would.
+    pub async fn purge_expired(&self, height: BlockHeight) -> anyhow::Result<PendingPurge> {
+        let mut conn = self.pool.get_conn().await;
+        let txn = conn.transaction()?;
+        let covhash = self.covhash.to_string();
+        let expired: Vec<String> = {
+            let mut stmt = txn.prepare(&format!("{WALLET_PENDING} and expires < $2"))?;
+            let expired = stmt
+                .query_map(params![covhash, height.0], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            expired
+        };
+        let mut purged = vec![];
+        let mut released_coins = 0;
+        for txhash in expired {
+            released_coins +=
+                txn.execute("delete from spends where txhash = $1", params![txhash])?;
+            txn.execute(
+                "delete from pending_coins where txhash = $1",
+                params![txhash],
+            )?;
+            txn.execute("delete from pending where txhash = $1", params![txhash])?;
+            purged.push(txhash.parse()?);
+        }
+        let still_pending = txn.query_row(
+            &format!("select count(*) from ({WALLET_PENDING})"),
+            params![covhash],
+            |row| row.get(0),
+        )?;
+        txn.commit()?;
+        Ok(PendingPurge {
+            height,
+            purged,
+            released_coins,
+            still_pending,
+        })
+    }
+}
@@ -0,0 +1,32 @@
+use rusqlite::{params, OptionalExtension};
+
+use super::Database;
+
+impl Database {
+    /// Records that a wallet is of a type provided by a plugin, along with its type-specific parameters.
+    pub async fn set_wallet_type(
+        &self,
+        name: &str,
+        kind: &str,
+        params: &[u8],
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert or replace into wallet_types values ($1, $2, $3)",
+            params![name, kind, params],
+        )?;
+        Ok(())
+    }
+
+    /// Gets the plugin-provided type of a wallet and its parameters, or None for an ordinary wallet.
+    pub async fn wallet_type(&self, name: &str) -> anyhow::Result<Option<(String, Vec<u8>)>> {
+        let conn = self.pool.get_conn().await;
+        Ok(conn
+            .query_row(
+                "select kind, params from wallet_types where name = $1",
+                [name],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?)
+    }
+}
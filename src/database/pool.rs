@@ -1,9 +1,10 @@
 use std::{
     ops::{Deref, DerefMut},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use rusqlite::Connection;
+use rusqlite::{Connection, OpenFlags};
 use smol::channel::{Receiver, Sender};
 
 /// A pool of connections to a particular SQL database.
@@ -11,24 +12,69 @@ use smol::channel::{Receiver, Sender};
 pub struct ConnPool {
     send_conn: Sender<Connection>,
     recv_conn: Receiver<Connection>,
+    /// Read-only connections, if any, which serve read-only queries so that the read-write connections stay free for writes
+    readers: Option<(Sender<Connection>, Receiver<Connection>)>,
+    path: Arc<PathBuf>,
+    /// Number of read-write connections, which [ConnPool::resize] changes
+    size: Arc<smol::lock::Mutex<usize>>,
+}
+
+/// Opens a read-write connection.
+fn open_conn(path: &Path) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.query_row("pragma journal_mode=WAL", [], |_| Ok(()))?;
+    conn.execute("pragma synchronous=NORMAL", [])?;
+    Ok(conn)
 }
 
 impl ConnPool {
-    /// Creates a new connection pool to the SQLite database at the specified path.
-    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+    /// Creates a new connection pool, with the given number of connections, to the SQLite database at the specified path.
+    pub fn open(path: impl AsRef<Path>, size: usize) -> rusqlite::Result<Self> {
         let (send_conn, recv_conn) = smol::channel::bounded(64);
-        for _ in 0..8 {
-            let conn = Connection::open(path.as_ref())?;
-            conn.query_row("pragma journal_mode=WAL", [], |_| Ok(()))?;
-            conn.execute("pragma synchronous=NORMAL", [])?;
-            send_conn.try_send(conn).unwrap();
+        for _ in 0..size {
+            send_conn.try_send(open_conn(path.as_ref())?).unwrap();
         }
         Ok(Self {
             send_conn,
             recv_conn,
+            readers: None,
+            path: Arc::new(path.as_ref().to_owned()),
+            size: Arc::new(smol::lock::Mutex::new(size)),
         })
     }
 
+    /// Changes the number of read-write connections, closing connections as they are returned to the pool or opening new ones. There is always at least one.
+    pub async fn resize(&self, size: usize) -> rusqlite::Result<()> {
+        let size = size.clamp(1, 64);
+        let mut current = self.size.lock().await;
+        while *current > size {
+            drop(self.recv_conn.recv().await.expect("wtf"));
+            *current -= 1;
+        }
+        while *current < size {
+            self.send_conn.try_send(open_conn(&self.path)?).unwrap();
+            *current += 1;
+        }
+        Ok(())
+    }
+
+    /// Adds the given number of read-only connections to the pool. The database must already be in WAL mode, which [ConnPool::open] ensures, so that readers never block writers.
+    pub fn with_readers(mut self, path: impl AsRef<Path>, count: usize) -> rusqlite::Result<Self> {
+        if count == 0 {
+            return Ok(self);
+        }
+        let (send_conn, recv_conn) = smol::channel::bounded(count.max(64));
+        for _ in 0..count {
+            let conn = Connection::open_with_flags(
+                path.as_ref(),
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?;
+            send_conn.try_send(conn).unwrap();
+        }
+        self.readers = Some((send_conn, recv_conn));
+        Ok(self)
+    }
+
     /// Gets a connection.
     pub async fn get_conn(&self) -> impl DerefMut<Target = Connection> {
         PooledConnection {
@@ -36,6 +82,19 @@ impl ConnPool {
             send_conn: self.send_conn.clone(),
         }
     }
+
+    /// Gets a connection for read-only queries. This is a read-only connection if the pool has any, and a read-write one otherwise.
+    pub async fn get_read_conn(&self) -> impl Deref<Target = Connection> {
+        let (send_conn, recv_conn) = self
+            .readers
+            .as_ref()
+            .map(|(send_conn, recv_conn)| (send_conn, recv_conn))
+            .unwrap_or((&self.send_conn, &self.recv_conn));
+        PooledConnection {
+            inner: Some(recv_conn.recv().await.expect("wtf")),
+            send_conn: send_conn.clone(),
+        }
+    }
 }
 
 /// A wrapped connection, that returns to the pool on drop.
@@ -0,0 +1,219 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use rusqlite::{params, Connection};
+
+use super::migrations;
+use crate::protocol::types::DatabaseRepair;
+
+/// Tables that are not salvaged when repairing a database, since a full sync rebuilds them. Dropping the sync heights is what makes the next sync start from scratch.
+const RESYNCED_TABLES: &[&str] = &["coins", "coin_confirmations", "sync_heights"];
+
+/// Most integrity problems kept in a repair report.
+const MAX_PROBLEMS: usize = 10;
+
+/// Runs an integrity check on a database file. If the file is corrupt, rebuilds it: every table except [RESYNCED_TABLES] is salvaged, row by row where possible, into a fresh file, and the corrupt file is set aside. Returns a report of the repair, if one was needed.
+pub(super) fn check_and_repair(path: &Path) -> anyhow::Result<Option<DatabaseRepair>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut problems = match integrity_problems(path) {
+        Ok(problems) if problems.is_empty() => return Ok(None),
+        Ok(problems) => problems,
+        Err(err) => vec![err.to_string()],
+    };
+    log::error!(
+        "database {:?} is corrupt, repairing it: {}",
+        path,
+        problems.join("; ")
+    );
+
+    let fresh = with_suffix(path, ".repairing");
+    let _ = std::fs::remove_file(&fresh);
+    let mut salvaged = vec![];
+    let mut skipped_rows = BTreeMap::new();
+    let mut lost = vec![];
+    {
+        let mut conn = Connection::open(&fresh)?;
+        migrations::migrate(&mut conn, None)?;
+        let tables: Vec<String> = {
+            let mut stmt = conn.prepare(
+                "select name from sqlite_master where type = 'table' and name != 'schema_version' and name not like 'sqlite_%'",
+            )?;
+            let tables = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            tables
+        };
+        conn.execute("attach database $1 as old", params![path.to_string_lossy()])?;
+        for table in tables {
+            if RESYNCED_TABLES.contains(&table.as_str()) {
+                continue;
+            }
+            match salvage_table(&conn, &table) {
+                Ok(0) => salvaged.push(table),
+                Ok(skipped) => {
+                    log::warn!(
+                        "skipped {skipped} unreadable rows of table {table} of {:?}",
+                        path
+                    );
+                    skipped_rows.insert(table.clone(), skipped);
+                    salvaged.push(table);
+                }
+                Err(err) => {
+                    log::warn!("cannot salvage table {table} of {:?}: {:?}", path, err);
+                    lost.push(table);
+                }
+            }
+        }
+        conn.execute("detach database old", [])?;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let corrupt_path = with_suffix(path, &format!(".corrupt-{timestamp}"));
+    std::fs::rename(path, &corrupt_path)?;
+    for suffix in ["-wal", "-shm"].iter() {
+        let _ = std::fs::rename(
+            with_suffix(path, suffix),
+            with_suffix(&corrupt_path, suffix),
+        );
+    }
+    std::fs::rename(&fresh, path)?;
+    log::error!(
+        "repaired database {:?}; the corrupt file was kept at {:?}. Lost tables: {:?}. Coins will be resynced from scratch.",
+        path,
+        corrupt_path,
+        lost
+    );
+    problems.truncate(MAX_PROBLEMS);
+    Ok(Some(DatabaseRepair {
+        file: path.to_string_lossy().into_owned(),
+        corrupt_file: corrupt_path.to_string_lossy().into_owned(),
+        time: timestamp,
+        problems,
+        salvaged,
+        skipped_rows,
+        lost,
+    }))
+}
+
+/// Returns every problem found by `pragma integrity_check`, or nothing if the file is fine.
+pub(super) fn integrity_problems(path: &Path) -> anyhow::Result<Vec<String>> {
+    let conn = Connection::open(path)?;
+    let mut stmt = conn.prepare("pragma integrity_check")?;
+    let problems: Vec<String> = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(problems.into_iter().filter(|p| p != "ok").collect())
+}
+
+/// Copies the rows of a table from the attached corrupt database, keeping only the columns that the current schema has. If the table cannot be read in one go, copies whatever rows can be read one by one. Returns the number of rows that could not be read; fails if the table cannot be read at all, or its rows cannot be walked past some point.
+fn salvage_table(conn: &Connection, table: &str) -> anyhow::Result<u64> {
+    let columns: Vec<String> = {
+        let mut stmt = conn.prepare(&format!("pragma old.table_info(\"{table}\")"))?;
+        let columns = stmt
+            .query_map([], |row| row.get(1))?
+            .collect::<Result<_, _>>()?;
+        columns
+    };
+    anyhow::ensure!(!columns.is_empty(), "table is missing");
+    let current: Vec<String> = {
+        let mut stmt = conn.prepare(&format!("pragma main.table_info(\"{table}\")"))?;
+        let current = stmt
+            .query_map([], |row| row.get(1))?
+            .collect::<Result<_, _>>()?;
+        current
+    };
+    let columns = columns
+        .into_iter()
+        .filter(|c| current.contains(c))
+        .map(|c| format!("\"{c}\""))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let bulk = format!(
+        "insert or ignore into main.\"{table}\" ({columns}) select {columns} from old.\"{table}\""
+    );
+    if conn.execute(&bulk, []).is_ok() {
+        return Ok(0);
+    }
+    // fall back to going row by row, skipping unreadable rows
+    let mut rowid: i64 = conn
+        .query_row(
+            &format!("select min(rowid) from old.\"{table}\""),
+            [],
+            |row| row.get::<_, Option<i64>>(0),
+        )?
+        .unwrap_or_default();
+    let mut skipped = 0;
+    loop {
+        if conn
+            .execute(&format!("{bulk} where rowid = $1"), params![rowid])
+            .is_err()
+        {
+            skipped += 1;
+        }
+        let next: Option<i64> = conn
+            .query_row(
+                &format!("select min(rowid) from old.\"{table}\" where rowid > $1"),
+                params![rowid],
+                |row| row.get(0),
+            )
+            .with_context(|| format!("cannot read past row {rowid}"))?;
+        match next {
+            Some(next) => rowid = next,
+            None => return Ok(skipped),
+        }
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repairs_corrupt_file() {
+        let dir = std::env::temp_dir().join(format!("melwalletd-repair-{}", fastrand::u64(..)));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("wallets.db");
+        {
+            let mut conn = Connection::open(&path).unwrap();
+            migrations::migrate(&mut conn, None).unwrap();
+            conn.execute("insert into wallet_names values ('alice', 'x', x'00')", [])
+                .unwrap();
+            conn.execute("insert into sync_heights values ('x', 100)", [])
+                .unwrap();
+        }
+        assert!(check_and_repair(&path).unwrap().is_none());
+
+        // clobber the end of the file, where the last pages are
+        let mut contents = std::fs::read(&path).unwrap();
+        let len = contents.len();
+        contents[len - 4096..].iter_mut().for_each(|b| *b = 0xff);
+        std::fs::write(&path, contents).unwrap();
+
+        let repair = check_and_repair(&path).unwrap().expect("not repaired");
+        assert!(!repair.problems.is_empty());
+        assert!(std::path::Path::new(&repair.corrupt_file).exists());
+        let conn = Connection::open(&path).unwrap();
+        let heights: u64 = conn
+            .query_row("select count(*) from sync_heights", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(heights, 0);
+        drop(conn);
+        assert!(check_and_repair(&path).unwrap().is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,128 @@
+use melstructs::{Address, TxHash};
+use melvm::Covenant;
+use rusqlite::{params, OptionalExtension};
+
+use crate::secrets::PersistentSecret;
+
+use super::Database;
+
+/// An in-progress key rotation of a wallet.
+pub struct KeyRotation {
+    /// Address of the new covenant. Its secret key is in the secret store, under [crate::rotation::pending_secret_name].
+    pub covhash: Address,
+    /// Sweep transactions sent so far.
+    pub sweeps: Vec<TxHash>,
+}
+
+impl Database {
+    /// Records the start of a key rotation. If the wallet is already being rotated, the existing rotation is kept.
+    pub async fn start_rotation(&self, name: &str, covenant: Covenant) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into key_rotations values ($1, $2, $3) on conflict do nothing",
+            params![
+                name,
+                covenant.hash().to_string(),
+                covenant.to_bytes().to_vec()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Gets the in-progress key rotation of a wallet, if any.
+    pub async fn get_rotation(&self, name: &str) -> anyhow::Result<Option<KeyRotation>> {
+        let conn = self.pool.get_conn().await;
+        let covhash: Option<String> = conn
+            .query_row(
+                "select covhash from key_rotations where name = $1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let covhash = if let Some(covhash) = covhash {
+            covhash
+        } else {
+            return Ok(None);
+        };
+        let mut stmt =
+            conn.prepare_cached("select txhash from key_rotation_sweeps where name = $1")?;
+        let sweeps = stmt
+            .query_map([name], |row| row.get::<_, String>(0))?
+            .map(|txhash| Ok(txhash?.parse()?))
+            .collect::<anyhow::Result<Vec<TxHash>>>()?;
+        Ok(Some(KeyRotation {
+            covhash: covhash.parse()?,
+            sweeps,
+        }))
+    }
+
+    /// Lists the names of all wallets with an in-progress key rotation.
+    pub async fn list_rotations(&self) -> anyhow::Result<Vec<String>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached("select name from key_rotations")?;
+        let names = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(names)
+    }
+
+    /// Lists the secrets of rotations started by older versions, which kept them in the database, as wallet name, address of the new covenant and secret.
+    pub async fn legacy_rotation_secrets(
+        &self,
+    ) -> anyhow::Result<Vec<(String, Address, PersistentSecret)>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt =
+            conn.prepare_cached("select name, covhash, secret from legacy_rotation_secrets")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(name, covhash, secret)| {
+                Ok((name, covhash.parse()?, serde_json::from_str(&secret)?))
+            })
+            .collect()
+    }
+
+    /// Forgets the secret of a rotation started by an older version, once it is in the secret store.
+    pub async fn delete_legacy_rotation_secret(&self, name: &str) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "delete from legacy_rotation_secrets where name = $1",
+            [name],
+        )?;
+        Ok(())
+    }
+
+    /// Records a sweep transaction of a key rotation.
+    pub async fn add_rotation_sweep(&self, name: &str, txhash: TxHash) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into key_rotation_sweeps values ($1, $2) on conflict do nothing",
+            params![txhash.to_string(), name],
+        )?;
+        Ok(())
+    }
+
+    /// Finishes a key rotation, switching the wallet over to the new covenant.
+    pub async fn finish_rotation(&self, name: &str) -> anyhow::Result<()> {
+        {
+            let mut conn = self.pool.get_conn().await;
+            let txn = conn.transaction()?;
+            txn.execute(
+                "update wallet_names set (covhash, covenant) = (select covhash, covenant from key_rotations where key_rotations.name = wallet_names.name)
+                where name = $1 and exists (select covhash from key_rotations where key_rotations.name = wallet_names.name)",
+                [name],
+            )?;
+            txn.execute("delete from key_rotations where name = $1", [name])?;
+            txn.execute("delete from key_rotation_sweeps where name = $1", [name])?;
+            txn.commit()?;
+        }
+        self.copy_wallet_record(name).await
+    }
+}
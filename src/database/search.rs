@@ -0,0 +1,146 @@
+use std::collections::BTreeSet;
+
+use melprot::Snapshot;
+use melstructs::{Transaction, TxHash};
+use rusqlite::{params, Transaction as SqlTransaction};
+
+use crate::protocol::types::TransactionSearchHit;
+
+use super::{Database, Wallet};
+
+/// Most transactions indexed for a wallet per sync, so that indexing a long history doesn't hold up syncing.
+const INDEX_BATCH: usize = 100;
+
+/// The text a transaction is found by: its hash and kind, any memo text, and the address, amount and token of every output.
+fn search_text(txn: &Transaction) -> String {
+    let mut words = vec![txn.hash_nosigs().to_string(), txn.kind.to_string()];
+    words.extend(memo_text(&txn.data));
+    for output in txn.outputs.iter() {
+        words.push(output.covhash.to_string());
+        words.push(output.value.0.to_string());
+        words.push(output.value.to_string());
+        words.push(output.denom.to_string());
+        words.extend(memo_text(&output.additional_data));
+    }
+    words.join(" ")
+}
+
+fn memo_text(data: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(data).ok()?.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Turns what a user typed into an FTS5 query matching transactions that contain every word, each possibly as a prefix. Returns None if nothing was typed.
+fn fts_query(input: &str) -> Option<String> {
+    let terms: Vec<String> = input
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// Adds a transaction to the search index on behalf of a wallet, unless it is already there.
+pub(super) fn index_transaction(
+    conn: &SqlTransaction,
+    name: &str,
+    txn: &Transaction,
+) -> anyhow::Result<()> {
+    let txhash = txn.hash_nosigs().to_string();
+    let updated = conn.execute(
+        "update transaction_refs set searchable = 1 where txhash = $1 and name = $2 and searchable = 0",
+        params![txhash, name],
+    )?;
+    if updated > 0 {
+        conn.execute(
+            "insert into tx_search (txhash, name, content) values ($1, $2, $3)",
+            params![txhash, name, search_text(txn)],
+        )?;
+    }
+    Ok(())
+}
+
+impl Wallet {
+    /// Indexes transactions in this wallet's history that aren't searchable yet, fetching them from the network if they aren't cached. At most [INDEX_BATCH] transactions are indexed per call.
+    pub async fn index_history(&self, snapshot: Snapshot) -> anyhow::Result<()> {
+        let indexed: BTreeSet<TxHash> = {
+            let conn = self.cache.get_conn().await;
+            let mut stmt = conn.prepare_cached(
+                "select txhash from transaction_refs where name = $1 and searchable = 1",
+            )?;
+            let rows: Vec<String> = stmt
+                .query_map(params![self.name], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            rows.iter()
+                .filter_map(|txhash| txhash.parse().ok())
+                .collect()
+        };
+        let pending: Vec<TxHash> = self
+            .get_transaction_history()
+            .await
+            .into_iter()
+            .map(|(txhash, _)| txhash)
+            .filter(|txhash| !indexed.contains(txhash))
+            .take(INDEX_BATCH)
+            .collect();
+        for txhash in pending {
+            // cached transactions go through the index as they are fetched
+            if let Some(txn) = self.get_transaction(txhash, snapshot.clone()).await? {
+                let mut conn = self.cache.get_conn().await;
+                let conn = conn.transaction()?;
+                conn.execute(
+                    "insert into transaction_refs (txhash, name) values ($1, $2) on conflict do nothing",
+                    params![txhash.to_string(), self.name],
+                )?;
+                index_transaction(&conn, &self.name, &txn)?;
+                conn.commit()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Database {
+    /// Searches the transactions of all wallets. Returns at most `limit` hits, best matches first.
+    pub async fn search_transactions(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<TransactionSearchHit>> {
+        let query = match fts_query(query) {
+            Some(query) => query,
+            None => return Ok(vec![]),
+        };
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached(
+            "select name, txhash, snippet(tx_search, 2, '[', ']', '...', 8) from tx_search where tx_search match $1 and name in (select name from wallet_names) order by rank limit $2",
+        )?;
+        let rows: Vec<(String, String, String)> = stmt
+            .query_map(params![query, limit], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<Result<_, _>>()?;
+        rows.into_iter()
+            .map(|(wallet_name, txhash, snippet)| {
+                Ok(TransactionSearchHit {
+                    wallet_name,
+                    txhash: txhash.parse()?,
+                    snippet,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_prefix_queries() {
+        assert_eq!(fts_query("  "), None);
+        assert_eq!(
+            fts_query("abc 1.5 say\"hi"),
+            Some("\"abc\"* \"1.5\"* \"say\"\"hi\"*".to_string())
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeSet;
+
+use melstructs::Denom;
+use rusqlite::{params, OptionalExtension};
+
+use tmelcrypt::HashVal;
+
+use super::Wallet;
+
+impl Wallet {
+    /// Gets the fee ballast applied to prepared transactions that do not specify one.
+    pub async fn default_fee_ballast(&self) -> usize {
+        let conn = self.pool.get_conn().await;
+        let ballast: Option<u64> = conn
+            .query_row(
+                "select default_fee_ballast from wallet_settings where name = $1",
+                params![self.name],
+                |row| row.get(0),
+            )
+            .optional()
+            .expect("db failed");
+        ballast.unwrap_or_default() as usize
+    }
+
+    /// Sets the fee ballast applied to prepared transactions that do not specify one.
+    pub async fn set_default_fee_ballast(&self, ballast: usize) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into wallet_settings (name, default_fee_ballast) values ($1, $2)
+            on conflict do update set default_fee_ballast = excluded.default_fee_ballast",
+            params![self.name, ballast as u64],
+        )?;
+        Ok(())
+    }
+
+    /// Gets the denominations whose coins prepared transactions that do not say otherwise never pick as inputs.
+    pub async fn default_exclude_denoms(&self) -> BTreeSet<Denom> {
+        let conn = self.pool.get_conn().await;
+        let denoms: Option<String> = conn
+            .query_row(
+                "select exclude_denoms from wallet_settings where name = $1",
+                params![self.name],
+                |row| row.get(0),
+            )
+            .optional()
+            .expect("db failed")
+            .flatten();
+        denoms
+            .map(|d| serde_json::from_str(&d).expect("malformed excluded denominations in db"))
+            .unwrap_or_default()
+    }
+
+    /// Sets the denominations whose coins prepared transactions that do not say otherwise never pick as inputs.
+    pub async fn set_default_exclude_denoms(&self, denoms: &BTreeSet<Denom>) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into wallet_settings (name, exclude_denoms) values ($1, $2)
+            on conflict do update set exclude_denoms = excluded.exclude_denoms",
+            params![self.name, serde_json::to_string(denoms)?],
+        )?;
+        Ok(())
+    }
+
+    /// Gets the hash of the token that approves this wallet's spends, if the wallet is in approval mode.
+    pub async fn approval_hash(&self) -> Option<HashVal> {
+        let conn = self.pool.get_conn().await;
+        let hash: Option<String> = conn
+            .query_row(
+                "select approval_hash from wallet_settings where name = $1",
+                params![self.name],
+                |row| row.get(0),
+            )
+            .optional()
+            .expect("db failed")
+            .flatten();
+        hash.map(|h| h.parse().expect("malformed approval hash in db"))
+    }
+
+    /// Puts the wallet in approval mode with the given approver token hash, or takes it out of approval mode given None.
+    pub async fn set_approval_hash(&self, hash: Option<HashVal>) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into wallet_settings (name, approval_hash) values ($1, $2)
+            on conflict do update set approval_hash = excluded.approval_hash",
+            params![self.name, hash.map(|h| h.to_string())],
+        )?;
+        Ok(())
+    }
+}
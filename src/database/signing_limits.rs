@@ -0,0 +1,37 @@
+use rusqlite::{params, OptionalExtension};
+
+use crate::protocol::types::SigningLimit;
+
+use super::Database;
+
+impl Database {
+    /// Gets the signing limit of a wallet, if it has one.
+    pub async fn signing_limit(&self, name: &str) -> anyhow::Result<Option<SigningLimit>> {
+        let conn = self.pool.get_conn().await;
+        let limit: Option<String> = conn
+            .query_row(
+                "select signing_limit from signing_limits where name = $1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(limit.map(|l| serde_json::from_str(&l)).transpose()?)
+    }
+
+    /// Sets or, given None, removes the signing limit of a wallet.
+    pub async fn set_signing_limit(
+        &self,
+        name: &str,
+        limit: Option<&SigningLimit>,
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        match limit {
+            Some(limit) => conn.execute(
+                "insert or replace into signing_limits values ($1, $2)",
+                params![name, serde_json::to_string(limit)?],
+            )?,
+            None => conn.execute("delete from signing_limits where name = $1", [name])?,
+        };
+        Ok(())
+    }
+}
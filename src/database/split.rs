@@ -0,0 +1,186 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use rusqlite::params;
+
+use super::{open_pool, pool::ConnPool, Database};
+
+/// Number of connections kept open to each per-wallet file.
+const WALLET_POOL_SIZE: usize = 2;
+
+/// File name for a wallet's own database. Names that aren't safe as file names are hex-encoded.
+pub(super) fn wallet_file_name(name: &str) -> String {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        format!("{name}.db")
+    } else {
+        format!("~{}.db", hex::encode(name))
+    }
+}
+
+impl Database {
+    fn wallet_file(&self, name: &str) -> Option<PathBuf> {
+        Some(self.split_dir.as_ref()?.join(wallet_file_name(name)))
+    }
+
+    /// Gets the pool holding a wallet's coins. This is the main pool, unless wallets are stored in separate files.
+    pub(super) async fn wallet_pool(&self, name: &str) -> anyhow::Result<ConnPool> {
+        let path = if let Some(path) = self.wallet_file(name) {
+            path
+        } else {
+            return Ok(self.pool.clone());
+        };
+        if let Some(pool) = self.wallet_pools.get(name) {
+            return Ok(pool.clone());
+        }
+        let pool = open_pool(&path, WALLET_POOL_SIZE, self.read_connections).await?;
+        Ok(self
+            .wallet_pools
+            .entry(name.to_owned())
+            .or_insert(pool)
+            .clone())
+    }
+
+    /// Gets every distinct pool that holds wallet coins.
+    pub(super) async fn all_wallet_pools(&self) -> anyhow::Result<Vec<ConnPool>> {
+        if self.split_dir.is_none() {
+            return Ok(vec![self.pool.clone()]);
+        }
+        let mut pools = vec![];
+        for name in self.list_wallets().await {
+            pools.push(self.wallet_pool(&name).await?);
+        }
+        Ok(pools)
+    }
+
+    /// Copies a wallet's entry in the wallet list into its own file, so that the file can be restored on its own.
+    pub(super) async fn copy_wallet_record(&self, name: &str) -> anyhow::Result<()> {
+        if self.split_dir.is_none() {
+            return Ok(());
+        }
+        let (covhash, covenant): (String, Vec<u8>) = self.pool.get_conn().await.query_row(
+            "select covhash, covenant from wallet_names where name = $1",
+            [name],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let pool = self.wallet_pool(name).await?;
+        let conn = pool.get_conn().await;
+        conn.execute(
+            "insert or replace into wallet_names values ($1, $2, $3)",
+            params![name, covhash, covenant],
+        )?;
+        Ok(())
+    }
+
+    /// Copies the data of every wallet that doesn't have its own file yet out of the main file.
+    ///
+    /// The data is left in the main file, so that going back to a single file doesn't lose anything; it simply goes stale until the next sync.
+    pub(super) async fn split_combined(&self) -> anyhow::Result<()> {
+        for name in self.list_wallets().await {
+            let path = self.wallet_file(&name).context("not splitting wallets")?;
+            if path.exists() {
+                continue;
+            }
+            // create the file with an up-to-date schema
+            drop(self.wallet_pool(&name).await?);
+            let mut conn = self.pool.get_conn().await;
+            conn.execute("attach database $1 as w", params![path.to_string_lossy()])?;
+            let res = (|| {
+                let txn = conn.transaction()?;
+                let covhash: String = txn.query_row(
+                    "select covhash from wallet_names where name = $1",
+                    [&name],
+                    |row| row.get(0),
+                )?;
+                txn.execute(
+                    "create temp table split_coins as select coinid from main.coins where covhash = $1",
+                    [&covhash],
+                )?;
+                txn.execute_batch(
+                    r"insert or ignore into w.coins select * from main.coins where coinid in (select coinid from split_coins);
+                    insert or ignore into w.coin_confirmations select * from main.coin_confirmations where coinid in (select coinid from split_coins);
+                    insert or ignore into w.pending_coins select * from main.pending_coins where coinid in (select coinid from split_coins);
+                    insert or ignore into w.spends select * from main.spends where coinid in (select coinid from split_coins);
+                    insert or ignore into w.pending select * from main.pending where txhash in (select txhash from w.spends union select txhash from w.pending_coins);
+                    drop table split_coins;",
+                )?;
+                txn.execute(
+                    "insert or ignore into w.sync_heights select * from main.sync_heights where covhash = $1",
+                    [&covhash],
+                )?;
+                txn.execute(
+                    "insert or ignore into w.wallet_settings select * from main.wallet_settings where name = $1",
+                    [&name],
+                )?;
+                txn.execute(
+                    "insert or replace into w.wallet_names select * from main.wallet_names where name = $1",
+                    [&name],
+                )?;
+                txn.commit()
+            })();
+            conn.execute("detach database w", [])?;
+            res.with_context(|| format!("cannot split out wallet {name}"))?;
+            log::info!("moved wallet {name} to its own file {:?}", path);
+        }
+        Ok(())
+    }
+
+    /// Adds wallets whose files were placed in the wallet file directory (for example, when restoring a backup) to the wallet list.
+    pub(super) async fn register_wallet_files(&self) -> anyhow::Result<()> {
+        let dir = self.split_dir.as_ref().context("not splitting wallets")?;
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("db") {
+                continue;
+            }
+            let records: Vec<(String, String, Vec<u8>)> = {
+                let conn = rusqlite::Connection::open(&path)?;
+                let mut stmt = conn.prepare("select name, covhash, covenant from wallet_names")?;
+                let records = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                    .collect::<Result<_, _>>()?;
+                records
+            };
+            for (name, covhash, covenant) in records {
+                if wallet_file_name(&name) != path.file_name().unwrap_or_default().to_string_lossy()
+                {
+                    log::warn!("ignoring wallet {name} in misnamed file {:?}", path);
+                    continue;
+                }
+                let inserted = self.pool.get_conn().await.execute(
+                    "insert into wallet_names values ($1, $2, $3) on conflict do nothing",
+                    params![name, covhash, covenant],
+                )?;
+                if inserted > 0 {
+                    log::info!("registered wallet {name} from {:?}", path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of coins known across all wallets, and the total size of all database files in bytes.
+    pub async fn usage(&self) -> anyhow::Result<(u64, u64)> {
+        let file_size = |conn: &rusqlite::Connection| -> rusqlite::Result<u64> {
+            conn.query_row(
+                "select page_count * page_size from pragma_page_count(), pragma_page_size()",
+                [],
+                |row| row.get(0),
+            )
+        };
+        let mut bytes = file_size(&*self.pool.get_conn().await)?;
+        let mut coins = 0;
+        for pool in self.all_wallet_pools().await? {
+            let conn = pool.get_conn().await;
+            coins +=
+                conn.query_row("select count(*) from coins", [], |row| row.get::<_, u64>(0))?;
+            if self.split_dir.is_some() {
+                bytes += file_size(&conn)?;
+            }
+        }
+        Ok((coins, bytes))
+    }
+}
@@ -0,0 +1,130 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use melstructs::{BlockHeight, CoinValue, Denom, TxHash};
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::protocol::types::{SwapOrder, SwapOrderStatus};
+
+use super::{values::SqlValue, Database};
+
+fn status_to_str(status: SwapOrderStatus) -> &'static str {
+    match status {
+        SwapOrderStatus::Open => "open",
+        SwapOrderStatus::Executed => "executed",
+        SwapOrderStatus::Expired => "expired",
+        SwapOrderStatus::Cancelled => "cancelled",
+        SwapOrderStatus::Held => "held",
+    }
+}
+
+fn status_from_str(s: &str) -> anyhow::Result<SwapOrderStatus> {
+    Ok(match s {
+        "open" => SwapOrderStatus::Open,
+        "executed" => SwapOrderStatus::Executed,
+        "expired" => SwapOrderStatus::Expired,
+        "cancelled" => SwapOrderStatus::Cancelled,
+        "held" => SwapOrderStatus::Held,
+        other => anyhow::bail!("unknown swap order status {other}"),
+    })
+}
+
+const SWAP_ORDER_COLUMNS: &str =
+    "id, name, from_denom, to_denom, amount, limit_price, expiry, status, txhash, created";
+
+fn swap_order_from_row(row: &Row) -> anyhow::Result<SwapOrder> {
+    let amount: SqlValue = row.get(4)?;
+    let status: String = row.get(7)?;
+    let txhash: Option<String> = row.get(8)?;
+    Ok(SwapOrder {
+        id: row.get(0)?,
+        wallet_name: row.get(1)?,
+        from: row.get(2)?,
+        to: row.get(3)?,
+        amount: amount.0,
+        limit_price: row.get(5)?,
+        expiry: BlockHeight(row.get(6)?),
+        status: status_from_str(&status)?,
+        txhash: txhash.map(|t| t.parse()).transpose()?,
+        created: row.get(9)?,
+    })
+}
+
+impl Database {
+    /// Places an open swap order for a wallet.
+    pub async fn create_swap_order(
+        &self,
+        name: &str,
+        from: Denom,
+        to: Denom,
+        amount: CoinValue,
+        limit_price: f64,
+        expiry: BlockHeight,
+    ) -> anyhow::Result<SwapOrder> {
+        let mut id = [0u8; 16];
+        getrandom::getrandom(&mut id).expect("no randomness");
+        let id = hex::encode(id);
+        let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into swap_orders (id, name, from_denom, to_denom, amount, limit_price, expiry, status, created) values ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            params![
+                id,
+                name,
+                from.to_string(),
+                to.to_string(),
+                SqlValue(amount),
+                limit_price,
+                expiry.0,
+                status_to_str(SwapOrderStatus::Open),
+                created
+            ],
+        )?;
+        drop(conn);
+        self.get_swap_order(&id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("swap order disappeared"))
+    }
+
+    /// Gets a swap order by its ID.
+    pub async fn get_swap_order(&self, id: &str) -> anyhow::Result<Option<SwapOrder>> {
+        let conn = self.pool.get_conn().await;
+        let order = conn
+            .query_row(
+                &format!("select {SWAP_ORDER_COLUMNS} from swap_orders where id = $1"),
+                [id],
+                |row| Ok(swap_order_from_row(row)),
+            )
+            .optional()?;
+        order.transpose()
+    }
+
+    /// Lists the swap orders of a wallet, or only the open swap orders of all wallets, oldest first.
+    pub async fn list_swap_orders(&self, name: Option<&str>) -> anyhow::Result<Vec<SwapOrder>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached(&format!(
+            "select {SWAP_ORDER_COLUMNS} from swap_orders
+            where ($1 is null and status = 'open') or name = $1 order by created"
+        ))?;
+        let mut rows = stmt.query([name])?;
+        let mut toret = vec![];
+        while let Some(row) = rows.next()? {
+            toret.push(swap_order_from_row(row)?);
+        }
+        Ok(toret)
+    }
+
+    /// Records the new status of a swap order, along with its transaction once executed.
+    pub async fn update_swap_order(
+        &self,
+        id: &str,
+        status: SwapOrderStatus,
+        txhash: Option<TxHash>,
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "update swap_orders set status = $1, txhash = $2 where id = $3",
+            params![status_to_str(status), txhash.map(|t| t.to_string()), id],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use melstructs::BlockHeight;
+use rusqlite::params;
+
+use crate::protocol::types::SyncError;
+
+use super::Wallet;
+
+/// Most sync errors kept per wallet. Older ones are forgotten.
+const MAX_SYNC_ERRORS: usize = 50;
+
+impl Wallet {
+    /// Records a failure to sync this wallet to `height`.
+    pub async fn record_sync_error(
+        &self,
+        height: BlockHeight,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut conn = self.pool.get_conn().await;
+        let txn = conn.transaction()?;
+        let covhash = self.covhash.to_string();
+        txn.execute(
+            "insert into sync_errors (covhash, time, height, message) values ($1, $2, $3, $4)",
+            params![covhash, now, height.0, message],
+        )?;
+        txn.execute(
+            "delete from sync_errors where covhash = $1 and rowid not in (select rowid from sync_errors where covhash = $1 order by time desc, rowid desc limit $2)",
+            params![covhash, MAX_SYNC_ERRORS],
+        )?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// The latest failures to sync this wallet, newest first.
+    pub async fn sync_errors(&self) -> anyhow::Result<Vec<SyncError>> {
+        let conn = self.pool.get_read_conn().await;
+        let mut stmt = conn.prepare_cached(
+            "select time, height, message from sync_errors where covhash = $1 order by time desc, rowid desc",
+        )?;
+        let mut rows = stmt.query(params![self.covhash.to_string()])?;
+        let mut toret = vec![];
+        while let Some(row) = rows.next()? {
+            toret.push(SyncError {
+                time: row.get(0)?,
+                height: BlockHeight(row.get(1)?),
+                message: row.get(2)?,
+            });
+        }
+        Ok(toret)
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+
+use melstructs::{Address, BlockHeight, CoinData, CoinDataHeight, CoinID, Denom, Header, TxHash};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use stdcode::StdcodeSerializeExt;
+
+use super::{insert_coins, values::SqlValue, Database, Wallet};
+
+impl Wallet {
+    /// The height this wallet's coins were last synced to, or None if it has never synced.
+    pub async fn sync_height(&self) -> anyhow::Result<Option<BlockHeight>> {
+        let conn = self.pool.get_read_conn().await;
+        let height: Option<u64> = conn
+            .query_row(
+                "select height from sync_heights where covhash = $1",
+                params![self.covhash.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(height.map(BlockHeight))
+    }
+
+    /// The height this wallet is synced to, along with its unspent coins as of that height, or None if it has never synced. Coins spent by pending transactions are included, since they are still unspent on chain.
+    pub async fn synced_coins(
+        &self,
+    ) -> anyhow::Result<Option<(BlockHeight, BTreeMap<CoinID, CoinDataHeight>)>> {
+        let conn = self.pool.get_read_conn().await;
+        let height: Option<u64> = conn
+            .query_row(
+                "select height from sync_heights where covhash = $1",
+                params![self.covhash.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let height = match height {
+            Some(height) => BlockHeight(height),
+            None => return Ok(None),
+        };
+        let coins = load_coins(
+            &conn,
+            r"select coins.coinid, value, denom, additional_data, height from coins
+            join coin_confirmations on coins.coinid = coin_confirmations.coinid
+            where covhash = $1
+            and not exists (select txhash from spends where spends.coinid = coins.coinid
+                and not exists (select txhash from pending where spends.txhash = pending.txhash))",
+            self.covhash,
+        )?;
+        Ok(Some((height, coins)))
+    }
+
+    /// Like [Wallet::synced_coins], but with every confirmed coin the wallet has held, spent or not, along with the transactions that spent the spent ones. Spends by pending transactions are left out, since they may never confirm.
+    pub async fn synced_history(&self) -> anyhow::Result<Option<SyncedHistory>> {
+        let height = match self.sync_height().await? {
+            Some(height) => height,
+            None => return Ok(None),
+        };
+        let conn = self.pool.get_read_conn().await;
+        let coins = load_coins(
+            &conn,
+            r"select coins.coinid, value, denom, additional_data, height from coins
+            join coin_confirmations on coins.coinid = coin_confirmations.coinid
+            where covhash = $1",
+            self.covhash,
+        )?;
+        let mut stmt = conn.prepare_cached(
+            r"select spends.coinid, spends.txhash from spends natural join coins
+            where covhash = $1 and spends.txhash not in (select txhash from pending)",
+        )?;
+        let mut rows = stmt.query(params![self.covhash.to_string()])?;
+        let mut spends = BTreeMap::new();
+        while let Some(row) = rows.next()? {
+            let coinid: String = row.get(0)?;
+            let txhash: String = row.get(1)?;
+            spends.insert(coinid.parse()?, txhash.parse()?);
+        }
+        Ok(Some((height, coins, spends)))
+    }
+
+    /// Takes the given coins and spends, as returned by [Wallet::synced_history], as this wallet's history as of `height`, as though it had synced to that height. Syncing then carries on from there.
+    pub async fn restore_history(
+        &self,
+        height: BlockHeight,
+        coins: BTreeMap<CoinID, CoinDataHeight>,
+        spends: BTreeMap<CoinID, TxHash>,
+    ) -> anyhow::Result<()> {
+        let covhash = self.covhash.to_string();
+        let mut conn = self.pool.get_conn().await;
+        let txn = conn.transaction()?;
+        txn.execute("delete from coins where covhash = $1", params![covhash])?;
+        insert_coins(&txn, coins.iter())?;
+        for (coinid, txhash) in spends {
+            txn.execute(
+                "insert into spends values ($1, $2) on conflict do nothing",
+                params![coinid.to_string(), txhash.to_string()],
+            )?;
+        }
+        txn.execute(
+            "insert or replace into sync_heights (covhash, height) values ($1, $2)",
+            params![covhash, height.0],
+        )?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Takes the given unspent coins as this wallet's coins as of `height`, as though it had just fully synced to that height. Syncing then carries on from there.
+    pub async fn restore_synced_coins(
+        &self,
+        height: BlockHeight,
+        coins: BTreeMap<CoinID, CoinDataHeight>,
+    ) -> anyhow::Result<()> {
+        self.replace_coins(coins, height).await
+    }
+}
+
+/// A wallet's history as of the height it synced to: the height, every confirmed coin it held, and the transactions that spent the spent ones.
+pub type SyncedHistory = (
+    BlockHeight,
+    BTreeMap<CoinID, CoinDataHeight>,
+    BTreeMap<CoinID, TxHash>,
+);
+
+/// Runs a query for coins at `covhash`, which must select their IDs, values, denominations, additional data and confirmation heights in that order.
+fn load_coins(
+    conn: &Connection,
+    sql: &str,
+    covhash: Address,
+) -> anyhow::Result<BTreeMap<CoinID, CoinDataHeight>> {
+    let mut stmt = conn.prepare_cached(sql)?;
+    let mut rows = stmt.query(params![covhash.to_string()])?;
+    let mut coins = BTreeMap::new();
+    while let Some(row) = rows.next()? {
+        let coinid: String = row.get(0)?;
+        let value: SqlValue = row.get(1)?;
+        let denom: Vec<u8> = row.get(2)?;
+        let additional_data: Vec<u8> = row.get(3)?;
+        let coin_height: u64 = row.get(4)?;
+        let coin_data = CoinData {
+            covhash,
+            value: value.0,
+            denom: Denom::from_bytes(&denom)
+                .ok_or_else(|| anyhow::anyhow!("malformed denom in db"))?,
+            additional_data: additional_data.into(),
+        };
+        coins.insert(
+            coinid.parse()?,
+            CoinDataHeight {
+                coin_data,
+                height: BlockHeight(coin_height),
+            },
+        );
+    }
+    Ok(coins)
+}
+
+impl Database {
+    /// Remembers the latest block header seen, for preparing transactions while offline.
+    pub async fn cache_header(&self, header: Header) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert or replace into cached_header values (0, $1)",
+            [header.stdcode()],
+        )?;
+        Ok(())
+    }
+
+    /// The latest block header seen, if the daemon was ever online.
+    pub async fn cached_header(&self) -> anyhow::Result<Option<Header>> {
+        let conn = self.pool.get_read_conn().await;
+        let blob: Option<Vec<u8>> = conn
+            .query_row("select header from cached_header where id = 0", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(blob.map(|b| stdcode::deserialize(&b)).transpose()?)
+    }
+}
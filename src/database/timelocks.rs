@@ -0,0 +1,171 @@
+use melprot::Snapshot;
+use melstructs::{BlockHeight, CoinDataHeight, CoinID, CoinValue, Denom};
+use melvm::Covenant;
+use rusqlite::{params, Row};
+use tmelcrypt::Ed25519PK;
+
+use crate::{
+    protocol::types::{TimelockDirection, TimelockStatus, TimelockedCoin},
+    timelock::timelock_covenant,
+};
+
+use super::{values::SqlValue, Database, Wallet};
+
+fn status_to_str(status: TimelockStatus) -> &'static str {
+    match status {
+        TimelockStatus::Unconfirmed => "unconfirmed",
+        TimelockStatus::Locked => "locked",
+        TimelockStatus::Mature => "mature",
+        TimelockStatus::Spent => "spent",
+    }
+}
+
+fn status_from_str(s: &str) -> anyhow::Result<TimelockStatus> {
+    Ok(match s {
+        "unconfirmed" => TimelockStatus::Unconfirmed,
+        "locked" => TimelockStatus::Locked,
+        "mature" => TimelockStatus::Mature,
+        "spent" => TimelockStatus::Spent,
+        other => anyhow::bail!("unknown timelock status {other}"),
+    })
+}
+
+fn timelock_from_row(row: &Row) -> anyhow::Result<TimelockedCoin> {
+    let coinid: String = row.get(0)?;
+    let recipient: String = row.get(2)?;
+    let recipient: Ed25519PK = recipient.parse()?;
+    let unlock_height = BlockHeight(row.get(3)?);
+    let value: SqlValue = row.get(4)?;
+    let status: String = row.get(6)?;
+    Ok(TimelockedCoin {
+        coin_id: coinid.parse()?,
+        // filled in by the caller, who knows whose point of view it is
+        direction: TimelockDirection::Outgoing,
+        sender: row.get(1)?,
+        address: timelock_covenant(recipient, unlock_height).hash(),
+        recipient,
+        unlock_height,
+        value: value.0,
+        denom: row.get(5)?,
+        status: status_from_str(&status)?,
+    })
+}
+
+impl Database {
+    /// Records a timelocked coin created by a transaction a wallet prepared.
+    pub async fn insert_timelock(
+        &self,
+        name: &str,
+        coinid: CoinID,
+        recipient: Ed25519PK,
+        unlock_height: BlockHeight,
+        value: CoinValue,
+        denom: Denom,
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert or replace into timelocks values ($1, $2, $3, $4, $5, $6, $7)",
+            params![
+                coinid.to_string(),
+                name,
+                recipient.to_string(),
+                unlock_height.0,
+                SqlValue(value),
+                denom.to_string(),
+                status_to_str(TimelockStatus::Unconfirmed)
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Lists timelocked coins sent by or to a wallet.
+    pub async fn list_timelocks(&self, wallet: &Wallet) -> anyhow::Result<Vec<TimelockedCoin>> {
+        let mut toret = vec![];
+        for mut coin in self.all_timelocks().await? {
+            if coin.sender == wallet.name {
+                coin.direction = TimelockDirection::Outgoing;
+            } else if Covenant::std_ed25519_pk_new(coin.recipient).hash() == wallet.covhash {
+                coin.direction = TimelockDirection::Incoming;
+            } else {
+                continue;
+            }
+            toret.push(coin);
+        }
+        Ok(toret)
+    }
+
+    async fn all_timelocks(&self) -> anyhow::Result<Vec<TimelockedCoin>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached(
+            "select coinid, name, recipient, unlock_height, value, denom, status from timelocks order by unlock_height",
+        )?;
+        let coins = stmt
+            .query_map([], |row| Ok(timelock_from_row(row)))?
+            .map(|coin| coin?)
+            .collect::<anyhow::Result<_>>()?;
+        Ok(coins)
+    }
+
+    /// Follows timelocked coins as they confirm, mature and get spent. Coins whose recipient is a wallet here are imported into it, so that the wallet can spend them once they mature.
+    pub async fn sync_timelocks(&self, snapshot: Snapshot) -> anyhow::Result<()> {
+        let height = snapshot.current_header().height;
+        for coin in self.all_timelocks().await? {
+            if coin.status == TimelockStatus::Spent {
+                continue;
+            }
+            let status = match snapshot.get_coin(coin.coin_id).await? {
+                Some(cdh) => {
+                    if coin.status == TimelockStatus::Unconfirmed {
+                        self.import_timelock(&coin, &cdh).await?;
+                    }
+                    if height >= coin.unlock_height {
+                        TimelockStatus::Mature
+                    } else {
+                        TimelockStatus::Locked
+                    }
+                }
+                None if coin.status == TimelockStatus::Unconfirmed => continue,
+                None => TimelockStatus::Spent,
+            };
+            if status != coin.status {
+                log::debug!("timelocked coin {} is now {:?}", coin.coin_id, status);
+                let conn = self.pool.get_conn().await;
+                conn.execute(
+                    "update timelocks set status = $2 where coinid = $1",
+                    params![coin.coin_id.to_string(), status_to_str(status)],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Imports a freshly confirmed timelocked coin into the wallet it is for, if that wallet is here.
+    async fn import_timelock(
+        &self,
+        coin: &TimelockedCoin,
+        cdh: &CoinDataHeight,
+    ) -> anyhow::Result<()> {
+        let recipient = coin.recipient;
+        let recipient_address = Covenant::std_ed25519_pk_new(recipient).hash();
+        for name in self.list_wallets().await {
+            let wallet = match self.get_wallet(&name).await {
+                Some(wallet) if wallet.covhash == recipient_address => wallet,
+                _ => continue,
+            };
+            let covenant = timelock_covenant(recipient, coin.unlock_height);
+            // record the unlock height first, so that the coin never looks spendable too early
+            wallet.pool.get_conn().await.execute(
+                "insert or replace into imported_covenants values ($1, $2, $3, $4)",
+                params![
+                    name,
+                    cdh.coin_data.covhash.to_string(),
+                    covenant.to_bytes().to_vec(),
+                    coin.unlock_height.0
+                ],
+            )?;
+            wallet.import_coin(coin.coin_id, cdh, &covenant).await?;
+            log::info!("imported timelocked coin {} into {name}", coin.coin_id);
+        }
+        Ok(())
+    }
+}
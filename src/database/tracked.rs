@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use melprot::Snapshot;
+use melstructs::{Address, CoinValue};
+use rusqlite::params;
+
+use crate::protocol::types::TrackedAddress;
+
+use super::{values::SqlValue, Database};
+
+impl Database {
+    /// Starts tracking an address, or relabels an address already tracked.
+    pub async fn track_address(&self, covhash: Address, label: &str) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into tracked_addresses (covhash, label) values ($1, $2) on conflict do update set label = excluded.label",
+            params![covhash.to_string(), label],
+        )?;
+        Ok(())
+    }
+
+    /// Stops tracking an address. Returns whether the address was tracked at all.
+    pub async fn untrack_address(&self, covhash: Address) -> anyhow::Result<bool> {
+        let mut conn = self.pool.get_conn().await;
+        let txn = conn.transaction()?;
+        let removed = txn.execute(
+            "delete from tracked_addresses where covhash = $1",
+            params![covhash.to_string()],
+        )?;
+        txn.execute(
+            "delete from tracked_balances where covhash = $1",
+            params![covhash.to_string()],
+        )?;
+        txn.commit()?;
+        Ok(removed > 0)
+    }
+
+    /// Lists all tracked addresses, along with what was last seen of them.
+    pub async fn list_tracked(&self) -> anyhow::Result<Vec<TrackedAddress>> {
+        let conn = self.pool.get_conn().await;
+        let mut balances: BTreeMap<String, BTreeMap<String, CoinValue>> = BTreeMap::new();
+        let mut stmt = conn.prepare_cached("select covhash, denom, value from tracked_balances")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let value: SqlValue = row.get(2)?;
+            balances
+                .entry(row.get(0)?)
+                .or_default()
+                .insert(row.get(1)?, value.0);
+        }
+        let mut stmt = conn
+            .prepare_cached("select covhash, label, coin_count, height from tracked_addresses")?;
+        let mut rows = stmt.query([])?;
+        let mut toret = vec![];
+        while let Some(row) = rows.next()? {
+            let covhash: String = row.get(0)?;
+            let height: Option<u64> = row.get(3)?;
+            toret.push(TrackedAddress {
+                address: covhash.parse()?,
+                label: row.get(1)?,
+                coin_count: row.get::<_, Option<u64>>(2)?.unwrap_or_default(),
+                balances: balances.remove(&covhash).unwrap_or_default(),
+                last_height: height.map(|h| h.into()),
+            });
+        }
+        Ok(toret)
+    }
+
+    /// Refreshes the balances and coin counts of all tracked addresses. An address that fails to sync keeps what was last seen of it, without holding up the others.
+    pub async fn sync_tracked(&self, snapshot: Snapshot) -> anyhow::Result<()> {
+        for tracked in self.list_tracked().await? {
+            if let Err(err) = self.sync_tracked_address(&snapshot, tracked.address).await {
+                log::warn!(
+                    "failed to sync tracked address {} ({}): {:?}",
+                    tracked.address,
+                    tracked.label,
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Refreshes the balances and coin count of a single tracked address.
+    async fn sync_tracked_address(
+        &self,
+        snapshot: &Snapshot,
+        covhash: Address,
+    ) -> anyhow::Result<()> {
+        let coins = snapshot
+            .get_coins(covhash)
+            .await?
+            .context("server does not provide coin index")?;
+        let mut balances: BTreeMap<String, CoinValue> = BTreeMap::new();
+        for cdh in coins.values() {
+            *balances.entry(cdh.coin_data.denom.to_string()).or_default() += cdh.coin_data.value;
+        }
+        let mut conn = self.pool.get_conn().await;
+        let txn = conn.transaction()?;
+        txn.execute(
+            "update tracked_addresses set coin_count = $1, height = $2 where covhash = $3",
+            params![
+                coins.len() as u64,
+                snapshot.current_header().height.0,
+                covhash.to_string()
+            ],
+        )?;
+        txn.execute(
+            "delete from tracked_balances where covhash = $1",
+            params![covhash.to_string()],
+        )?;
+        for (denom, value) in balances {
+            txn.execute(
+                "insert into tracked_balances values ($1, $2, $3)",
+                params![covhash.to_string(), denom, SqlValue(value)],
+            )?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use melstructs::TxHash;
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::protocol::types::{Trade, TradeStatus, TradeTerms};
+
+use super::Database;
+
+fn status_to_str(status: TradeStatus) -> &'static str {
+    match status {
+        TradeStatus::Proposed => "proposed",
+        TradeStatus::Completed => "completed",
+    }
+}
+
+fn status_from_str(s: &str) -> anyhow::Result<TradeStatus> {
+    Ok(match s {
+        "proposed" => TradeStatus::Proposed,
+        "completed" => TradeStatus::Completed,
+        other => anyhow::bail!("unknown trade status {other}"),
+    })
+}
+
+const TRADE_COLUMNS: &str = "id, name, terms, status, txhash, created";
+
+fn trade_from_row(row: &Row) -> anyhow::Result<Trade> {
+    let terms: String = row.get(2)?;
+    let status: String = row.get(3)?;
+    let txhash: Option<String> = row.get(4)?;
+    Ok(Trade {
+        id: row.get(0)?,
+        wallet_name: row.get(1)?,
+        terms: serde_json::from_str(&terms)?,
+        status: status_from_str(&status)?,
+        txhash: txhash.map(|t| t.parse()).transpose()?,
+        created: row.get(5)?,
+    })
+}
+
+impl Database {
+    /// Records a trade proposed by a wallet.
+    pub async fn create_trade(&self, name: &str, terms: &TradeTerms) -> anyhow::Result<Trade> {
+        let mut id = [0u8; 16];
+        getrandom::getrandom(&mut id).expect("no randomness");
+        let id = hex::encode(id);
+        let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert into trades (id, name, terms, status, created) values ($1, $2, $3, $4, $5)",
+            params![
+                id,
+                name,
+                serde_json::to_string(terms)?,
+                status_to_str(TradeStatus::Proposed),
+                created
+            ],
+        )?;
+        drop(conn);
+        self.get_trade(&id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("trade disappeared"))
+    }
+
+    /// Gets a trade by its ID.
+    pub async fn get_trade(&self, id: &str) -> anyhow::Result<Option<Trade>> {
+        let conn = self.pool.get_conn().await;
+        let trade = conn
+            .query_row(
+                &format!("select {TRADE_COLUMNS} from trades where id = $1"),
+                [id],
+                |row| Ok(trade_from_row(row)),
+            )
+            .optional()?;
+        trade.transpose()
+    }
+
+    /// Lists the trades proposed by a wallet, oldest first.
+    pub async fn list_trades(&self, name: &str) -> anyhow::Result<Vec<Trade>> {
+        let conn = self.pool.get_conn().await;
+        let mut stmt = conn.prepare_cached(&format!(
+            "select {TRADE_COLUMNS} from trades where name = $1 order by created"
+        ))?;
+        let mut rows = stmt.query([name])?;
+        let mut toret = vec![];
+        while let Some(row) = rows.next()? {
+            toret.push(trade_from_row(row)?);
+        }
+        Ok(toret)
+    }
+
+    /// Marks a trade as completed by the given transaction.
+    pub async fn complete_trade(&self, id: &str, txhash: TxHash) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "update trades set status = $1, txhash = $2 where id = $3",
+            params![
+                status_to_str(TradeStatus::Completed),
+                txhash.to_string(),
+                id
+            ],
+        )?;
+        Ok(())
+    }
+}
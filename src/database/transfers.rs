@@ -0,0 +1,53 @@
+use melstructs::TxHash;
+use rusqlite::{params, OptionalExtension};
+
+use crate::protocol::types::InternalTransfer;
+
+use super::Database;
+
+impl Database {
+    /// Records that a transaction moves funds between two wallets of this daemon.
+    pub async fn record_internal_transfer(
+        &self,
+        txhash: TxHash,
+        transfer: &InternalTransfer,
+    ) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "insert or replace into internal_transfers values ($1, $2, $3)",
+            params![txhash.to_string(), transfer.from_wallet, transfer.to_wallet],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets that a transaction is an internal transfer, once it will never be sent.
+    pub async fn remove_internal_transfer(&self, txhash: TxHash) -> anyhow::Result<()> {
+        let conn = self.pool.get_conn().await;
+        conn.execute(
+            "delete from internal_transfers where txhash = $1",
+            [txhash.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// The wallets a transaction moved funds between, if it is an internal transfer.
+    pub async fn internal_transfer(
+        &self,
+        txhash: TxHash,
+    ) -> anyhow::Result<Option<InternalTransfer>> {
+        let conn = self.pool.get_read_conn().await;
+        let transfer = conn
+            .query_row(
+                "select from_wallet, to_wallet from internal_transfers where txhash = $1",
+                [txhash.to_string()],
+                |row| {
+                    Ok(InternalTransfer {
+                        from_wallet: row.get(0)?,
+                        to_wallet: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(transfer)
+    }
+}
@@ -0,0 +1,85 @@
+use std::convert::TryFrom;
+
+use melstructs::CoinValue;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, Value, ValueRef};
+
+/// A coin value as stored in the database. Values are stored as integers, so that SQL can compare and sum them, except for those too large for SQLite's 64-bit integers, which are stored as decimal strings. SQLite orders every integer before every string, so comparing against an integer still orders both kinds correctly.
+pub struct SqlValue(pub CoinValue);
+
+impl ToSql for SqlValue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Owned(match i64::try_from(self.0 .0) {
+            Ok(value) => Value::Integer(value),
+            Err(_) => Value::Text(self.0 .0.to_string()),
+        }))
+    }
+}
+
+impl FromSql for SqlValue {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Integer(value) => u128::try_from(value)
+                .map(|value| SqlValue(CoinValue(value)))
+                .map_err(|_| FromSqlError::OutOfRange(value)),
+            // too large for an integer, or not yet migrated
+            ValueRef::Text(text) => std::str::from_utf8(text)
+                .map_err(|err| FromSqlError::Other(Box::new(err)))?
+                .parse()
+                .map(|value| SqlValue(CoinValue(value)))
+                .map_err(|err| FromSqlError::Other(Box::new(err))),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// SQL summing the integer values of a `value` column without overflowing, as two partial sums of their upper and lower 32 bits, which [joined_sum] puts back together. Values stored as strings must be added separately.
+pub const SPLIT_SUM: &str =
+    "sum(case when typeof(value) = 'integer' then value >> 32 end), sum(case when typeof(value) = 'integer' then value & 4294967295 end)";
+
+/// Puts together the partial sums computed by [SPLIT_SUM].
+pub fn joined_sum(high: Option<i64>, low: Option<i64>) -> CoinValue {
+    CoinValue(((high.unwrap_or_default() as u128) << 32) + low.unwrap_or_default() as u128)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::{params, Connection};
+
+    use super::*;
+
+    #[test]
+    fn sums_without_overflow() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("create table coins (value)", []).unwrap();
+        let values = [i64::MAX as u128, i64::MAX as u128, 1, u128::MAX / 4];
+        for value in values {
+            conn.execute(
+                "insert into coins values ($1)",
+                params![SqlValue(CoinValue(value))],
+            )
+            .unwrap();
+        }
+        let (high, low) = conn
+            .query_row(&format!("select {SPLIT_SUM} from coins"), [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        let huge: SqlValue = conn
+            .query_row(
+                "select value from coins where typeof(value) = 'text'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(
+            joined_sum(high, low) + huge.0,
+            CoinValue(values.iter().sum())
+        );
+        let small: u64 = conn
+            .query_row("select count(*) from coins where value < 2", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(small, 1);
+    }
+}
@@ -0,0 +1,56 @@
+use melvm::{opcode::OpCode, Covenant};
+use tmelcrypt::Ed25519PK;
+
+use crate::plugin::WalletPlugin;
+
+/// Renders the descriptor of a wallet: its type, followed by its hex-encoded public key and, if any, its hex-encoded type parameters, such as `standard(<pubkey>)`.
+pub fn descriptor_string(kind: &str, pubkey: Ed25519PK, params: &[u8]) -> String {
+    if params.is_empty() {
+        format!("{kind}({pubkey})")
+    } else {
+        format!("{kind}({pubkey},{})", hex::encode(params))
+    }
+}
+
+/// Recovers the public key a wallet's covenant was made from, without needing the wallet's secret key. Every 32-byte constant in the covenant is tried as a key, and kept if the wallet type turns it back into the very same covenant.
+pub fn recover_public_key(
+    covenant: &Covenant,
+    plugin: &dyn WalletPlugin,
+    params: &[u8],
+) -> Option<Ed25519PK> {
+    covenant.to_ops().into_iter().find_map(|op| match op {
+        OpCode::PushB(bytes) if bytes.len() == 32 => {
+            let pubkey = Ed25519PK::from_bytes(&bytes)?;
+            let rebuilt = plugin.covenant(pubkey, params).ok()?;
+            (rebuilt.to_bytes() == covenant.to_bytes()).then_some(pubkey)
+        }
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tmelcrypt::Ed25519SK;
+
+    use super::*;
+    use crate::plugin::{PluginRegistry, STANDARD_WALLET};
+
+    #[test]
+    fn recover() {
+        let pubkey = Ed25519SK::generate().to_public();
+        let plugin = PluginRegistry::default().get(STANDARD_WALLET).unwrap();
+        let covenant = Covenant::std_ed25519_pk_new(pubkey);
+        assert_eq!(
+            recover_public_key(&covenant, plugin.as_ref(), &[]),
+            Some(pubkey)
+        );
+        assert_eq!(
+            recover_public_key(&Covenant::always_true(), plugin.as_ref(), &[]),
+            None
+        );
+        assert_eq!(
+            descriptor_string(STANDARD_WALLET, pubkey, &[]),
+            format!("standard({pubkey})")
+        );
+    }
+}
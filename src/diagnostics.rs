@@ -0,0 +1,155 @@
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use melstructs::CoinID;
+
+use crate::{
+    address::parse_address,
+    build_info::build_info,
+    logs::LOG_BUFFER,
+    protocol::types::{DiagnosticsBundle, LogLevel, PendingDiagnostics, WalletDiagnostics},
+    state::AppState,
+};
+
+/// Most log records put in a diagnostics bundle.
+pub const MAX_DIAGNOSTIC_LOGS: usize = 1000;
+
+/// What addresses, transaction hashes and hex strings are replaced with in redacted text.
+const REDACTED: &str = "<redacted>";
+
+/// Redacts a piece of text: every word that parses as an address, in any encoding, or as a 64-digit hex string such as a transaction hash, is replaced with [REDACTED], as is the transaction hash in every coin ID, and every wallet name with its alias.
+fn redact_text(text: &str, aliases: &BTreeMap<String, String>) -> String {
+    let is_word_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c| is_word_char(c) != rest.starts_with(is_word_char))
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(split);
+        if let Some(alias) = aliases.get(chunk) {
+            redacted.push_str(alias);
+        } else if chunk.starts_with(is_word_char) && parse_address(chunk).is_some() {
+            redacted.push_str(REDACTED);
+        } else if let Ok(coin) = chunk.parse::<CoinID>() {
+            // a coin ID joins its transaction hash and index with a dash, so it reads as one word
+            redacted.push_str(&format!("{REDACTED}-{}", coin.index));
+        } else {
+            redacted.push_str(chunk);
+        }
+        rest = tail;
+    }
+    redacted
+}
+
+impl AppState {
+    /// Gathers what's needed to diagnose a problem with the daemon: build and node information, every wallet's sync height and pending transactions, and the latest log records, except in a user's namespace. Secrets are never included. If `redact` is set, wallet names are replaced with aliases, and addresses and transaction hashes are left out, including from log messages.
+    pub async fn collect_diagnostics(&self, redact: bool, log_limit: usize) -> DiagnosticsBundle {
+        let names = self.database.list_wallets().await;
+        let aliases: BTreeMap<String, String> = if redact {
+            names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (name.clone(), format!("wallet-{}", i + 1)))
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
+        let mut wallets = vec![];
+        for name in names.iter() {
+            let wallet = match self.get_wallet(name).await {
+                Some(wallet) => wallet,
+                None => continue,
+            };
+            let pending = wallet
+                .pending_transactions()
+                .await
+                .expect("db failed")
+                .into_iter()
+                .map(|pending| PendingDiagnostics {
+                    txhash: (!redact).then_some(pending.txhash),
+                    expires: pending.expires,
+                })
+                .collect();
+            wallets.push(WalletDiagnostics {
+                name: aliases.get(name).unwrap_or(name).clone(),
+                address: (!redact).then(|| wallet.address().to_string()),
+                sync_height: wallet.sync_height().await.expect("db failed"),
+                pending,
+            });
+        }
+        let latest_height = if self.config.offline {
+            self.database
+                .cached_header()
+                .await
+                .expect("db failed")
+                .map(|header| header.height)
+        } else {
+            self.latest_snapshot()
+                .await
+                .ok()
+                .map(|snap| snap.current_header().height)
+        };
+        // the log is the whole daemon's, so a user's bundle would show other users' wallets
+        let log_limit = if self.user.is_some() { 0 } else { log_limit };
+        let logs = LOG_BUFFER
+            .tail(LogLevel::Trace, log_limit.min(MAX_DIAGNOSTIC_LOGS))
+            .into_iter()
+            .map(|mut record| {
+                if redact {
+                    record.message = redact_text(&record.message, &aliases);
+                }
+                record
+            })
+            .collect();
+        DiagnosticsBundle {
+            generated_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("clock before 1970")
+                .as_secs(),
+            redacted: redact,
+            build: build_info(),
+            network: self.network,
+            offline: self.config.offline,
+            uptime_secs: self.started.elapsed().as_secs(),
+            latest_height,
+            node: self.nodes.diagnostics(),
+            wallets,
+            logs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use melstructs::Address;
+
+    use super::*;
+
+    #[test]
+    fn redacts_addresses_and_names() {
+        let address: Address = tmelcrypt::hash_single(b"wallet").into();
+        let aliases: BTreeMap<String, String> =
+            std::iter::once(("alice".to_string(), "wallet-1".to_string())).collect();
+        let text = format!(
+            "synced alice at {address}, sent {}; alicexyz is fine",
+            tmelcrypt::hash_single(b"tx")
+        );
+        assert_eq!(
+            redact_text(&text, &aliases),
+            "synced wallet-1 at <redacted>, sent <redacted>; alicexyz is fine"
+        );
+        assert_eq!(redact_text("", &aliases), "");
+
+        let coin_id = CoinID {
+            txhash: tmelcrypt::hash_single(b"tx").into(),
+            index: 3,
+        };
+        assert_eq!(
+            redact_text(&format!("imported coin {coin_id} into alice"), &aliases),
+            "imported coin <redacted>-3 into wallet-1"
+        );
+    }
+}
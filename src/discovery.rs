@@ -0,0 +1,284 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    time::Duration,
+};
+
+use anyhow::Context;
+use smol::Async;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::cli::Config;
+
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+/// DNS-SD service type that melwalletd advertises itself under.
+pub const SERVICE_TYPE: &str = "_melwalletd._tcp.local";
+const SERVICE_ENUMERATION: &str = "_services._dns-sd._udp.local";
+/// Seconds that receivers may cache the advertisement.
+const TTL: u32 = 120;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+
+/// What the daemon advertises about itself over mDNS.
+#[derive(Clone, Debug)]
+pub struct Advertisement {
+    /// Service instance name, such as `melwalletd on myhost._melwalletd._tcp.local`
+    pub instance: String,
+    /// Host name that the instance's address record is under, such as `melwalletd-myhost.local`
+    pub host: String,
+    pub ip: Ipv4Addr,
+    pub port: u16,
+    /// `key=value` entries of the TXT record
+    pub txt: Vec<String>,
+}
+
+impl Advertisement {
+    /// Describes a daemon with the given config, listening at the given LAN address.
+    pub fn new(config: &Config, ip: Ipv4Addr) -> Self {
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .ok()
+            .map(|h| h.trim().replace('.', "-"))
+            .filter(|h| !h.is_empty())
+            .unwrap_or_else(|| "melwalletd".into());
+        let auth = if config.users.is_empty() {
+            "none"
+        } else {
+            "token"
+        };
+        Self {
+            instance: format!("melwalletd on {hostname}.{SERVICE_TYPE}"),
+            host: format!("melwalletd-{hostname}.local"),
+            ip,
+            port: config.listen.port(),
+            txt: vec![
+                format!("version={}", env!("CARGO_PKG_VERSION")),
+                format!("network={:?}", config.network).to_ascii_lowercase(),
+                format!("auth={auth}"),
+                "path=/".into(),
+            ],
+        }
+    }
+
+    /// Whether a query asks about anything this advertisement answers.
+    fn answers(&self, question: &(String, u16)) -> bool {
+        let (name, qtype) = question;
+        let is = |other: &str, types: &[u16]| {
+            name.eq_ignore_ascii_case(other) && (*qtype == TYPE_ANY || types.contains(qtype))
+        };
+        is(SERVICE_TYPE, &[TYPE_PTR])
+            || is(SERVICE_ENUMERATION, &[TYPE_PTR])
+            || is(&self.instance, &[TYPE_SRV, TYPE_TXT])
+            || is(&self.host, &[TYPE_A])
+    }
+
+    /// The mDNS response carrying the whole advertisement.
+    fn response(&self) -> Vec<u8> {
+        let mut records = vec![];
+        // shared records, which many daemons may answer
+        records.push(record(
+            SERVICE_ENUMERATION,
+            TYPE_PTR,
+            false,
+            name_bytes(SERVICE_TYPE),
+        ));
+        records.push(record(
+            SERVICE_TYPE,
+            TYPE_PTR,
+            false,
+            name_bytes(&self.instance),
+        ));
+        // unique records, which only this daemon answers
+        let mut srv = vec![0, 0, 0, 0];
+        srv.extend_from_slice(&self.port.to_be_bytes());
+        srv.extend(name_bytes(&self.host));
+        records.push(record(&self.instance, TYPE_SRV, true, srv));
+        let txt = self
+            .txt
+            .iter()
+            .flat_map(|entry| {
+                let entry = &entry.as_bytes()[..entry.len().min(255)];
+                std::iter::once(entry.len() as u8).chain(entry.iter().copied())
+            })
+            .collect();
+        records.push(record(&self.instance, TYPE_TXT, true, txt));
+        records.push(record(&self.host, TYPE_A, true, self.ip.octets().to_vec()));
+
+        // header: no ID, an authoritative response, and only answers
+        let mut packet = vec![0, 0, 0x84, 0, 0, 0];
+        packet.extend_from_slice(&(records.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        for record in records {
+            packet.extend(record);
+        }
+        packet
+    }
+}
+
+/// Encodes a DNS name, without compression.
+fn name_bytes(name: &str) -> Vec<u8> {
+    // the instance part of a service instance name is a single label, whatever it contains
+    let mut out = vec![];
+    let (instance, rest) = match name.strip_suffix(&format!(".{SERVICE_TYPE}")) {
+        Some(instance) => (Some(instance), SERVICE_TYPE),
+        None => (None, name),
+    };
+    for label in instance.into_iter().chain(rest.split('.')) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+    out
+}
+
+fn record(name: &str, rtype: u16, unique: bool, rdata: Vec<u8>) -> Vec<u8> {
+    let mut out = name_bytes(name);
+    out.extend_from_slice(&rtype.to_be_bytes());
+    // class IN, with the cache-flush bit for records only we own
+    let class: u16 = if unique { 0x8001 } else { 1 };
+    out.extend_from_slice(&class.to_be_bytes());
+    out.extend_from_slice(&TTL.to_be_bytes());
+    out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    out.extend(rdata);
+    out
+}
+
+/// Reads a possibly compressed DNS name at `pos`, returning it and the position just after it.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = vec![];
+    let mut end = None;
+    // bound the number of jumps, so that pointer loops can't hang us
+    for _ in 0..128 {
+        let len = *packet.get(pos)? as usize;
+        if len == 0 {
+            return Some((labels.join("."), end.unwrap_or(pos + 1)));
+        }
+        if len & 0xc0 == 0xc0 {
+            let target = (len & 0x3f) << 8 | *packet.get(pos + 1)? as usize;
+            end.get_or_insert(pos + 2);
+            pos = target;
+            continue;
+        }
+        let label = packet.get(pos + 1..pos + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        pos += 1 + len;
+    }
+    None
+}
+
+/// Parses the questions of an mDNS query. Responses and malformed packets yield nothing.
+fn parse_questions(packet: &[u8]) -> Vec<(String, u16)> {
+    let mut questions = vec![];
+    if packet.len() < 12 || packet[2] & 0x80 != 0 {
+        return questions;
+    }
+    let count = u16::from_be_bytes([packet[4], packet[5]]);
+    let mut pos = 12;
+    for _ in 0..count {
+        let (name, next) = match read_name(packet, pos) {
+            Some(parsed) => parsed,
+            None => break,
+        };
+        let qtype = match packet.get(next..next + 2) {
+            Some(qtype) => u16::from_be_bytes([qtype[0], qtype[1]]),
+            None => break,
+        };
+        questions.push((name, qtype));
+        pos = next + 4;
+    }
+    questions
+}
+
+/// Finds the IPv4 address that LAN clients can reach a daemon listening at `listen` on, if any.
+fn lan_address(listen: SocketAddr) -> Option<Ipv4Addr> {
+    match listen.ip() {
+        IpAddr::V4(ip) if ip.is_loopback() => None,
+        IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+        IpAddr::V4(_) => {
+            // the address of the interface that multicast goes out of; connecting a UDP socket sends nothing
+            let probe = UdpSocket::bind("0.0.0.0:0").ok()?;
+            probe.connect((MDNS_GROUP, MDNS_PORT)).ok()?;
+            match probe.local_addr().ok()?.ip() {
+                IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+                _ => None,
+            }
+        }
+        IpAddr::V6(_) => None,
+    }
+}
+
+fn mdns_socket() -> anyhow::Result<Async<UdpSocket>> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // other responders, such as the system's, share the port
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_PORT).into())?;
+    socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+    Ok(Async::new(UdpSocket::from(socket))?)
+}
+
+/// Advertises the daemon over mDNS until dropped, answering queries for [SERVICE_TYPE] so that GUIs on the LAN can find it.
+pub async fn advertise(config: &Config) -> anyhow::Result<()> {
+    let ip = lan_address(config.listen).with_context(|| {
+        format!(
+            "not advertising over mDNS, since {} is not reachable from the LAN",
+            config.listen
+        )
+    })?;
+    let ad = Advertisement::new(config, ip);
+    let socket = mdns_socket().context("cannot open mDNS socket")?;
+    let group = SocketAddr::from((MDNS_GROUP, MDNS_PORT));
+    let response = ad.response();
+    log::info!("advertising {} at {ip}:{} over mDNS", ad.instance, ad.port);
+    // announce twice at startup, as RFC 6762 recommends
+    for _ in 0..2 {
+        socket.send_to(&response, group).await?;
+        smol::Timer::after(Duration::from_secs(1)).await;
+    }
+    let mut buf = [0u8; 9000];
+    loop {
+        let (len, _) = socket.recv_from(&mut buf).await?;
+        if parse_questions(&buf[..len]).iter().any(|q| ad.answers(q)) {
+            socket.send_to(&response, group).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_service_queries() {
+        let ad = Advertisement {
+            instance: format!("melwalletd on box.{SERVICE_TYPE}"),
+            host: "melwalletd-box.local".into(),
+            ip: Ipv4Addr::new(192, 168, 1, 2),
+            port: 11773,
+            txt: vec!["network=mainnet".into()],
+        };
+        // a PTR query for the service type
+        let mut query = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend(name_bytes(SERVICE_TYPE));
+        query.extend_from_slice(&[0, 12, 0, 1]);
+        let questions = parse_questions(&query);
+        assert_eq!(questions, vec![(SERVICE_TYPE.to_string(), TYPE_PTR)]);
+        assert!(ad.answers(&questions[0]));
+        assert!(!ad.answers(&("_http._tcp.local".into(), TYPE_PTR)));
+
+        // the response's PTR record points at the instance, whose label keeps its spaces and dots intact
+        let response = ad.response();
+        assert!(parse_questions(&response).is_empty());
+        let instance = name_bytes(&ad.instance);
+        assert_eq!(instance[0] as usize, "melwalletd on box".len());
+        let ptr_start = name_bytes(SERVICE_ENUMERATION).len() + 10 + name_bytes(SERVICE_TYPE).len();
+        let (first_record, _) = read_name(&response, 12 + ptr_start).unwrap();
+        assert_eq!(first_record, SERVICE_TYPE);
+    }
+}
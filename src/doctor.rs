@@ -0,0 +1,319 @@
+use std::{os::unix::fs::PermissionsExt, path::Path};
+
+use melstructs::NetID;
+use serde::Serialize;
+use smol_timeout::TimeoutExt;
+
+use crate::{
+    cli::Config,
+    database::Database,
+    node_select::probe,
+    proxy::{connect_node, Socks5Proxy},
+    secrets::{entry_dir, SecretStore},
+};
+
+/// How long the node has to verify the chain from the checkpoint.
+const TRUST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+    Skipped,
+}
+
+/// The outcome of one check, with a hint at how to fix whatever is wrong.
+#[derive(Serialize, Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn skipped(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Skipped,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn problem(
+        name: &'static str,
+        status: Status,
+        detail: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Checks everything the daemon needs in order to start and sync, without changing anything. Prints a report, as JSON if asked to, and returns whether every check passed or was skipped.
+pub async fn run_doctor(config: &Config, json: bool) -> anyhow::Result<bool> {
+    let mut checks = vec![check_wallet_dir(&config.wallet_dir)];
+    let wallets = Database::wallet_names_in(&config.db_path());
+    checks.push(check_secrets(&config.secrets_path(), &wallets));
+    checks.push(check_database(config));
+    if config.offline {
+        for name in ["node", "checkpoint"] {
+            checks.push(Check::skipped(name, "the daemon runs offline"));
+        }
+    } else {
+        match config.proxy.as_deref().map(Socks5Proxy::parse).transpose() {
+            Ok(proxy) => {
+                let node = check_node(config, proxy.as_ref()).await;
+                let reachable = node.status == Status::Ok;
+                checks.push(node);
+                checks.push(if reachable {
+                    check_checkpoint(config, proxy.as_ref()).await
+                } else {
+                    Check::skipped("checkpoint", "the node is unreachable")
+                });
+            }
+            Err(err) => checks.push(Check::problem(
+                "node",
+                Status::Fail,
+                format!("bad proxy: {err}"),
+                "give the proxy as socks5://host:port",
+            )),
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&checks)?);
+    } else {
+        for check in checks.iter() {
+            let status = match check.status {
+                Status::Ok => "ok",
+                Status::Warn => "WARN",
+                Status::Fail => "FAIL",
+                Status::Skipped => "skip",
+            };
+            println!("[{status:>4}] {}: {}", check.name, check.detail);
+            if let Some(hint) = check.hint.as_ref() {
+                println!("       hint: {hint}");
+            }
+        }
+    }
+    Ok(checks.iter().all(|c| c.status != Status::Fail))
+}
+
+fn check_wallet_dir(dir: &Path) -> Check {
+    const NAME: &str = "wallet_dir";
+    let metadata =
+        match std::fs::metadata(dir) {
+            Ok(metadata) => metadata,
+            Err(err) => return Check::problem(
+                NAME,
+                Status::Fail,
+                format!("cannot access {:?}: {err}", dir),
+                "create it with `melwalletd init`, or point --wallet-dir at the right directory",
+            ),
+        };
+    if !metadata.is_dir() {
+        return Check::problem(
+            NAME,
+            Status::Fail,
+            format!("{:?} is not a directory", dir),
+            "point --wallet-dir at a directory",
+        );
+    }
+    if metadata.permissions().readonly() {
+        return Check::problem(
+            NAME,
+            Status::Fail,
+            format!("{:?} is read-only", dir),
+            format!("make it writable with `chmod u+w {:?}`", dir),
+        );
+    }
+    let mode = metadata.permissions().mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return Check::problem(
+            NAME,
+            Status::Warn,
+            format!(
+                "{:?} has mode {mode:o}, so other users can see into it",
+                dir
+            ),
+            format!("restrict it with `chmod 700 {:?}`", dir),
+        );
+    }
+    Check::ok(NAME, format!("{:?} is private and writable", dir))
+}
+
+fn check_secrets(path: &Path, wallets: &Option<Vec<String>>) -> Check {
+    const NAME: &str = "secrets";
+    if !path.exists() && !entry_dir(path).exists() {
+        return Check::ok(NAME, "no secrets stored yet");
+    }
+    let store = match SecretStore::open(path) {
+        Ok(store) => store,
+        Err(err) => {
+            return Check::problem(
+                NAME,
+                Status::Fail,
+                format!("cannot read the secret store: {err:#}"),
+                "restore the secrets directory from a backup; wallets whose secrets are lost can only be recovered from their keys",
+            )
+        }
+    };
+    if store.is_sealed() {
+        return Check::problem(
+            NAME,
+            Status::Warn,
+            "the secret store is sealed with a master passphrase",
+            "set MELWALLETD_MASTER_PASSPHRASE, or call unseal_secrets once the daemon is running",
+        );
+    }
+    let names = store.names();
+    let missing: Vec<&String> = wallets
+        .iter()
+        .flatten()
+        .filter(|wallet| store.load(wallet).is_none())
+        .collect();
+    if !missing.is_empty() {
+        return Check::problem(
+            NAME,
+            Status::Warn,
+            format!(
+                "{} secrets stored, but none for {:?}",
+                names.len(),
+                missing
+            ),
+            "these wallets cannot sign unless they are watch-only; import their keys again to fix that",
+        );
+    }
+    Check::ok(NAME, format!("{} secrets stored", names.len()))
+}
+
+fn check_database(config: &Config) -> Check {
+    const NAME: &str = "database";
+    let main = config.db_path();
+    if !main.exists() {
+        return Check::ok(NAME, "no database yet; it is created on first start");
+    }
+    let mut files = vec![main.clone()];
+    if let Ok(entries) = std::fs::read_dir(main.with_extension("d")) {
+        files.extend(
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|file| file.extension().and_then(|e| e.to_str()) == Some("db")),
+        );
+    }
+    for file in files.iter() {
+        match Database::integrity_problems(file) {
+            Ok(problems) if problems.is_empty() => {}
+            Ok(problems) => return Check::problem(
+                NAME,
+                Status::Fail,
+                format!("{:?} is corrupt: {}", file, problems.join("; ")),
+                "start the daemon to repair it automatically; coins are then resynced from scratch",
+            ),
+            Err(err) => {
+                return Check::problem(
+                    NAME,
+                    Status::Fail,
+                    format!("cannot check {:?}: {err}", file),
+                    "make sure no other program holds it, and that it is readable",
+                )
+            }
+        }
+    }
+    Check::ok(
+        NAME,
+        format!("{} files passed the integrity check", files.len()),
+    )
+}
+
+async fn check_node(config: &Config, proxy: Option<&Socks5Proxy>) -> Check {
+    const NAME: &str = "node";
+    let probe = probe(config.network, config.network_addr, proxy).await;
+    match (probe.height, probe.latency_ms) {
+        (Some(height), Some(latency_ms)) => Check::ok(
+            NAME,
+            format!(
+                "{} is at height {height}, answering in {latency_ms} ms",
+                config.network_addr
+            ),
+        ),
+        _ => Check::problem(
+            NAME,
+            Status::Fail,
+            format!(
+                "{} did not answer: {}",
+                config.network_addr,
+                probe.error.unwrap_or_default()
+            ),
+            if proxy.is_some() {
+                "check that the proxy is running, or try another node with --connect"
+            } else {
+                "check the network connection, or try another node with --connect"
+            },
+        ),
+    }
+}
+
+async fn check_checkpoint(config: &Config, proxy: Option<&Socks5Proxy>) -> Check {
+    const NAME: &str = "checkpoint";
+    let network = config.network;
+    if network != NetID::Mainnet && network != NetID::Testnet {
+        return Check::problem(
+            NAME,
+            Status::Warn,
+            format!(
+                "there is no checkpoint for {:?}, so the node is trusted blindly",
+                network
+            ),
+            "only use custom networks with a node you run yourself",
+        );
+    }
+    let checkpoint = melbootstrap::checkpoint_height(network).expect("no checkpoint");
+    let height = checkpoint.height;
+    let verified = async {
+        let client = connect_node(network, config.network_addr, proxy).await?;
+        client.trust(checkpoint);
+        client.latest_snapshot().await?;
+        anyhow::Ok(())
+    };
+    match verified.timeout(TRUST_TIMEOUT).await {
+        Some(Ok(())) => Check::ok(
+            NAME,
+            format!(
+                "the node's chain verifies from the checkpoint at height {}",
+                height
+            ),
+        ),
+        Some(Err(err)) => Check::problem(
+            NAME,
+            Status::Fail,
+            format!("the node's chain does not verify from the checkpoint: {err}"),
+            "the node may be on a fork or misbehaving; try another node with --connect",
+        ),
+        None => Check::problem(
+            NAME,
+            Status::Warn,
+            "the node took too long to verify from the checkpoint",
+            "try again, or try a faster node with --connect",
+        ),
+    }
+}
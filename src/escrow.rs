@@ -0,0 +1,139 @@
+use anyhow::Context;
+use melstructs::{Address, CoinData, CoinID, CoinValue, Denom, Transaction, TxKind};
+use melvm::{covenant_weight_from_bytes, opcode::OpCode, Covenant};
+use tmelcrypt::Ed25519PK;
+
+use crate::protocol::types::EscrowRole;
+
+/// Heap addresses where melvm puts the spending transaction and its hash.
+const HADDR_SPENDER_TX: u16 = 0;
+const HADDR_SPENDER_TXHASH: u16 = 1;
+
+/// Parties of an escrow, in the order of their signature slots.
+pub const ROLES: [EscrowRole; 3] = [EscrowRole::Buyer, EscrowRole::Seller, EscrowRole::Arbiter];
+
+/// Signatures needed to spend an escrow.
+pub const THRESHOLD: usize = 2;
+
+/// Index in `sigs` of the signature of each party. Every input of an escrow transaction is the escrow coin, so fixed slots work no matter how many inputs there are.
+pub fn slot(role: EscrowRole) -> usize {
+    ROLES.iter().position(|r| *r == role).unwrap()
+}
+
+/// Returns a covenant that lets any two of the three parties spend a coin together. The nonce makes the address of every escrow unique, even between the same parties.
+pub fn escrow_covenant(parties: [Ed25519PK; 3], nonce: &[u8]) -> Covenant {
+    let mut ops = vec![
+        // the nonce is left at the bottom of the stack, where it doesn't affect the result
+        OpCode::PushB(nonce.to_vec()),
+        OpCode::PushI(1u32.into()),
+    ];
+    for (i, pk) in parties.iter().enumerate() {
+        ops.extend_from_slice(&[
+            OpCode::PushI((i as u32).into()),
+            OpCode::PushI(6u32.into()),
+            OpCode::LoadImm(HADDR_SPENDER_TX),
+            OpCode::VRef,
+            OpCode::VRef,
+            OpCode::PushB(pk.0.to_vec()),
+            OpCode::LoadImm(HADDR_SPENDER_TXHASH),
+            OpCode::SigEOk(32),
+        ]);
+    }
+    // more than one valid signature
+    ops.extend_from_slice(&[OpCode::Add, OpCode::Add, OpCode::Gt]);
+    Covenant::from_ops(&ops)
+}
+
+/// Builds the transaction paying out an escrowed MEL coin to a destination, with empty signature slots. The fee is deducted from the coin.
+pub fn settlement_tx(
+    coin: CoinID,
+    value: CoinValue,
+    covenant: &Covenant,
+    destination: Address,
+    fee_multiplier: u128,
+) -> anyhow::Result<Transaction> {
+    let mut fee = CoinValue(0);
+    // the fee depends on the size of the transaction, which depends very slightly on the fee, so we iterate a few times
+    for _ in 0..5 {
+        let txn = Transaction {
+            kind: TxKind::Normal,
+            inputs: vec![coin],
+            outputs: vec![CoinData {
+                covhash: destination,
+                value: value
+                    .checked_sub(fee)
+                    .context("escrow too small to pay the fee")?,
+                denom: Denom::Mel,
+                additional_data: Default::default(),
+            }],
+            fee,
+            covenants: vec![covenant.to_bytes()],
+            data: vec![].into(),
+            sigs: vec![Default::default(); ROLES.len()],
+        };
+        // weigh the transaction as if it were fully signed
+        let mut signed_txn = txn.clone();
+        signed_txn.sigs = vec![vec![0u8; 64].into(); ROLES.len()];
+        let base_fee = signed_txn.base_fee(fee_multiplier, 0, covenant_weight_from_bytes);
+        if txn.fee >= base_fee {
+            return Ok(txn);
+        }
+        fee = base_fee * 21 / 20;
+    }
+    anyhow::bail!("could not settle on an escrow fee")
+}
+
+/// Returns the parties whose signatures on an escrow transaction are present and valid.
+pub fn signed_by(parties: [Ed25519PK; 3], txn: &Transaction) -> Vec<EscrowRole> {
+    let h = txn.hash_nosigs();
+    ROLES
+        .iter()
+        .zip(parties)
+        .filter(|(role, pk)| {
+            txn.sigs
+                .get(slot(**role))
+                .is_some_and(|sig| pk.verify(&h.0, sig))
+        })
+        .map(|(role, _)| *role)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tmelcrypt::{Ed25519SK, HashVal};
+
+    #[test]
+    fn two_of_three() {
+        let keys: Vec<Ed25519SK> = (0..3).map(|_| Ed25519SK::generate()).collect();
+        let parties = [
+            keys[0].to_public(),
+            keys[1].to_public(),
+            keys[2].to_public(),
+        ];
+        let covenant = escrow_covenant(parties, b"nonce");
+        let coin = CoinID {
+            txhash: HashVal::default().into(),
+            index: 0,
+        };
+        let mut txn = settlement_tx(
+            coin,
+            CoinValue(1_000_000),
+            &covenant,
+            Address::coin_destroy(),
+            1 << 16,
+        )
+        .unwrap();
+        let h = txn.hash_nosigs();
+        let passes = |txn: &Transaction| covenant.execute(txn, None).is_some_and(|v| v.into_bool());
+        assert!(!passes(&txn));
+        txn.sigs[slot(EscrowRole::Seller)] = keys[1].sign(&h.0).into();
+        assert!(!passes(&txn));
+        txn.sigs[slot(EscrowRole::Arbiter)] = keys[2].sign(&h.0).into();
+        assert!(passes(&txn));
+        assert_eq!(
+            signed_by(parties, &txn),
+            vec![EscrowRole::Seller, EscrowRole::Arbiter]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use melstructs::{CoinData, CoinID, CoinValue, Denom, Transaction, TxKind};
+use melvm::{covenant_weight_from_bytes, Covenant};
+use tmelcrypt::Ed25519SK;
+
+use crate::{protocol::types::FeeTrend, signer::Signer};
+
+/// Relative change in the fee multiplier, between the older and newer halves of the blocks looked at, beyond which fees are rising or falling rather than steady.
+const TREND_THRESHOLD: f64 = 0.05;
+
+/// A transaction as most payments look: one coin of a standard wallet spent, paying a recipient and sending back change.
+fn typical_transaction() -> Transaction {
+    let sk = Ed25519SK::generate();
+    let covenant = Covenant::std_ed25519_pk_new(sk.to_public());
+    let output = CoinData {
+        covhash: covenant.hash(),
+        value: CoinValue(1_000_000),
+        denom: Denom::Mel,
+        additional_data: Default::default(),
+    };
+    let tx = Transaction {
+        kind: TxKind::Normal,
+        inputs: vec![CoinID {
+            txhash: tmelcrypt::HashVal::default().into(),
+            index: 0,
+        }],
+        outputs: vec![output.clone(), output],
+        fee: CoinValue(0),
+        covenants: vec![covenant.to_bytes()],
+        data: Default::default(),
+        sigs: vec![],
+    };
+    sk.sign_tx(tx, 0).expect("cannot sign typical transaction")
+}
+
+/// Weight of [typical_transaction], and the smallest fee the network accepts for it at a fee multiplier.
+pub fn typical_fee(fee_multiplier: u128) -> (u128, CoinValue) {
+    let tx = typical_transaction();
+    (
+        tx.weight(covenant_weight_from_bytes),
+        tx.base_fee(fee_multiplier, 0, covenant_weight_from_bytes),
+    )
+}
+
+/// Weight, as computed by [Transaction::weight], that a set of outputs adds to a transaction on their own.
+pub fn outputs_weight(outputs: &[CoinData]) -> u128 {
+    let tx = Transaction {
+        outputs: outputs.to_vec(),
+        ..Transaction::new(TxKind::Normal)
+    };
+    tx.weight(covenant_weight_from_bytes)
+        - Transaction::new(TxKind::Normal).weight(covenant_weight_from_bytes)
+}
+
+/// Splits outputs, in order, into groups of at most `max_outputs` each, whose outputs weigh at most `max_weight` unless a single output is heavier.
+pub fn split_outputs(
+    outputs: &[CoinData],
+    max_outputs: usize,
+    max_weight: u128,
+) -> Vec<Vec<CoinData>> {
+    let mut groups: Vec<Vec<CoinData>> = vec![vec![]];
+    for output in outputs {
+        let group = groups.last_mut().expect("no groups");
+        group.push(output.clone());
+        if group.len() > 1 && (group.len() > max_outputs || outputs_weight(group) > max_weight) {
+            let output = group.pop().expect("empty group");
+            groups.push(vec![output]);
+        }
+    }
+    groups
+}
+
+/// Which way fee multipliers, oldest first, are heading, comparing the average of the newer half to that of the older half.
+pub fn fee_trend(multipliers: &[u128]) -> FeeTrend {
+    if multipliers.len() < 2 {
+        return FeeTrend::Steady;
+    }
+    let (older, newer) = multipliers.split_at(multipliers.len() / 2);
+    let average = |m: &[u128]| m.iter().map(|&m| m as f64).sum::<f64>() / m.len() as f64;
+    let (older, newer) = (average(older), average(newer));
+    if newer > older * (1.0 + TREND_THRESHOLD) {
+        FeeTrend::Rising
+    } else if newer < older * (1.0 - TREND_THRESHOLD) {
+        FeeTrend::Falling
+    } else {
+        FeeTrend::Steady
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{MAX_TX_OUTPUTS, MAX_TX_WEIGHT};
+
+    use super::*;
+
+    #[test]
+    fn trends_and_typical_fee() {
+        assert_eq!(fee_trend(&[100, 100, 100, 100]), FeeTrend::Steady);
+        assert_eq!(fee_trend(&[100, 101, 120, 130]), FeeTrend::Rising);
+        assert_eq!(fee_trend(&[130, 120, 101, 100]), FeeTrend::Falling);
+        assert_eq!(fee_trend(&[100]), FeeTrend::Steady);
+
+        let (weight, fee) = typical_fee(1000);
+        assert!(weight > 0);
+        assert!(typical_fee(2000).1 > fee);
+    }
+
+    #[test]
+    fn splits_outputs_by_count_and_weight() {
+        let output = |data: usize| CoinData {
+            covhash: Covenant::always_true().hash(),
+            value: CoinValue(1),
+            denom: Denom::Mel,
+            additional_data: vec![0u8; data].into(),
+        };
+        let plain: Vec<CoinData> = (0..MAX_TX_OUTPUTS).map(|_| output(0)).collect();
+        // a transaction with as many plain outputs as allowed stays well within the limit
+        assert!(outputs_weight(&plain) * 3 < MAX_TX_WEIGHT);
+
+        let groups = split_outputs(&plain, 100, MAX_TX_WEIGHT);
+        assert_eq!(
+            groups.iter().map(|g| g.len()).collect::<Vec<_>>(),
+            vec![100, 100, MAX_TX_OUTPUTS - 200]
+        );
+
+        let heavy: Vec<CoinData> = (0..10).map(|_| output(10_000)).collect();
+        let one = outputs_weight(&heavy[..1]);
+        let groups = split_outputs(&heavy, 100, one * 3);
+        assert_eq!(groups.len(), 4);
+        for group in groups.iter() {
+            assert!(outputs_weight(group) <= one * 3);
+        }
+        assert_eq!(groups.concat(), heavy);
+
+        // a single output heavier than the limit still gets a group of its own
+        assert_eq!(split_outputs(&heavy[..2], 100, one / 2).len(), 2);
+    }
+}
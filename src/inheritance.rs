@@ -0,0 +1,105 @@
+use std::{
+    collections::BTreeSet,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::DashMap;
+use melprot::Snapshot;
+use melstructs::{Address, BlockHeight, CoinID, Transaction};
+
+use crate::{
+    database::{Database, Wallet},
+    rotation::prepare_sweeps,
+    signer::Signer,
+};
+
+/// Pre-signed sweeps may be broadcast long after they are signed, when fees may well be higher, so they pay this many times the current fee.
+pub const SWEEP_FEE_HEADROOM: u128 = 4;
+
+/// Signs transactions sweeping all the coins a wallet currently has to its recovery address.
+pub async fn presign_sweeps(
+    wallet: &Wallet,
+    recovery: Address,
+    snapshot: &Snapshot,
+    signer: &dyn Signer,
+) -> anyhow::Result<Vec<Transaction>> {
+    let fee_multiplier = snapshot.current_header().fee_multiplier * SWEEP_FEE_HEADROOM;
+    prepare_sweeps(wallet, recovery, fee_multiplier, signer).await
+}
+
+/// Runs the dead-man switches of all wallets that have one. Called from the confirmation loop.
+///
+/// Unlocked wallets count as active, and have their pre-signed sweeps kept up to date with the coins they hold. Wallets that have been locked for longer than their inactivity period are swept to their recovery addresses. Sweeps that cannot reach the node are kept and sent again on later calls; the switch only counts as triggered once every sweep was either accepted or rejected by the node.
+pub async fn check_inheritance(
+    database: &Database,
+    snapshot: &Snapshot,
+    unlocked_signers: &DashMap<String, Arc<dyn Signer>>,
+) -> anyhow::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    for name in database.list_inheritance().await? {
+        let (wallet, record) = match (
+            database.get_wallet(&name).await,
+            database.get_inheritance(&name).await?,
+        ) {
+            (Some(wallet), Some(record)) if record.triggered.is_none() => (wallet, record),
+            _ => continue,
+        };
+
+        let signer = unlocked_signers.get(&name).map(|s| s.clone());
+        if let Some(signer) = signer {
+            database.touch_inheritance(&name).await?;
+            let coins: BTreeSet<CoinID> = wallet
+                .get_coin_mapping(true, false)
+                .await
+                .into_keys()
+                .collect();
+            let swept: BTreeSet<CoinID> = record
+                .sweeps
+                .iter()
+                .flat_map(|tx| tx.inputs.iter().copied())
+                .collect();
+            if coins != swept {
+                let sweeps =
+                    presign_sweeps(&wallet, record.recovery, snapshot, signer.as_ref()).await?;
+                database.set_inheritance_sweeps(&name, &sweeps).await?;
+                log::debug!("re-signed {} inheritance sweeps of {name}", sweeps.len());
+            }
+            continue;
+        }
+
+        if now < record.last_activity.saturating_add(record.inactivity_secs) {
+            continue;
+        }
+        log::warn!(
+            "{name} has been inactive for too long; sweeping it to {}",
+            record.recovery
+        );
+        let mut retry = vec![];
+        for tx in record.sweeps {
+            let txhash = tx.hash_nosigs();
+            match snapshot.get_raw().send_tx(tx.clone()).await {
+                Ok(Ok(())) => {
+                    wallet
+                        .commit_sent(tx, snapshot.current_header().height + BlockHeight(10))
+                        .await?;
+                    log::info!("sent inheritance sweep {txhash} of {name}");
+                }
+                Ok(Err(err)) => log::warn!("inheritance sweep {txhash} of {name} rejected: {err}"),
+                Err(err) => {
+                    log::warn!(
+                        "cannot send inheritance sweep {txhash} of {name}, will retry: {err}"
+                    );
+                    retry.push(tx);
+                }
+            }
+        }
+        if retry.is_empty() {
+            database.mark_inheritance_triggered(&name).await?;
+        } else {
+            // sweeps already sent are dropped, so that they aren't committed twice
+            database.set_inheritance_sweeps(&name, &retry).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,211 @@
+use std::{
+    ffi::CString,
+    io::{BufRead, Write},
+    net::SocketAddr,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::Context;
+use melstructs::NetID;
+use melvm::Covenant;
+use tmelcrypt::Ed25519SK;
+
+use crate::{
+    cli::{first_bootstrap_route, Config, InitArgs},
+    database::Database,
+    password::PasswordPolicy,
+    secrets::{EncryptedSK, PersistentSecret, SecretStore},
+};
+
+/// Environment variable holding the first wallet's password when setting up non-interactively.
+const PASSWORD_VAR: &str = "MELWALLETD_PASSWORD";
+
+/// Default RPC listening address.
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:11773";
+
+/// Creates the wallet directory if needed, making sure only its owner can access it.
+pub fn create_wallet_dir(path: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(path).context("cannot create wallet_dir")?;
+    // SAFETY: this is perfectly safe because chmod cannot lead to memory unsafety.
+    unsafe {
+        libc::chmod(
+            CString::new(path.to_string_lossy().as_bytes().to_vec())?.as_ptr(),
+            0o700,
+        );
+    }
+    Ok(())
+}
+
+/// Sets up a new daemon, asking for whatever wasn't given on the command line unless running non-interactively.
+pub async fn run_init(args: InitArgs) -> anyhow::Result<()> {
+    let interactive = !args.non_interactive;
+    if interactive {
+        println!("Setting up melwalletd. Press enter to accept the default in brackets.\n");
+    }
+
+    let default_dir = std::env::var("HOME")
+        .map(|home| PathBuf::from(home).join(".melwalletd"))
+        .unwrap_or_else(|_| PathBuf::from(".melwalletd"));
+    let wallet_dir: PathBuf = resolve(
+        interactive,
+        args.wallet_dir,
+        "Wallet directory",
+        &default_dir.to_string_lossy(),
+    )?;
+    let network: NetID = resolve(interactive, args.network, "Network", "mainnet")?;
+    // a node picked from the bootstrap list is only a fallback for the one probing picks at startup
+    let (network_addr, auto_select_node) = match (args.connect, first_bootstrap_route(network)) {
+        (Some(addr), _) => (addr, false),
+        (None, Some(addr)) => (addr, true),
+        (None, None) if interactive => (ask("Full node address", None)?, false),
+        (None, None) => anyhow::bail!("no bootstrap nodes for {network:?}; pass --connect"),
+    };
+    let listen: SocketAddr = resolve(
+        interactive,
+        args.listen,
+        "RPC listening address",
+        DEFAULT_LISTEN,
+    )?;
+    let config_path = args
+        .config
+        .unwrap_or_else(|| wallet_dir.join("melwalletd.yaml"));
+    if config_path.exists() && !args.force {
+        anyhow::bail!(
+            "{:?} already exists; pass --force to overwrite it",
+            config_path
+        );
+    }
+    let wallet_name = match args.wallet_name {
+        Some(name) => Some(name),
+        None if interactive => {
+            let name: String =
+                ask("Name of a first wallet (leave empty to skip)", None).unwrap_or_default();
+            Some(name).filter(|name| !name.is_empty())
+        }
+        None => None,
+    };
+
+    let mut config = Config::new(
+        wallet_dir.clone(),
+        listen,
+        vec![],
+        network_addr,
+        network,
+        PasswordPolicy::default(),
+        false,
+    );
+    config.auto_select_node = auto_select_node;
+    create_wallet_dir(&wallet_dir)?;
+    std::fs::write(&config_path, serde_yaml::to_string(&config)?)
+        .with_context(|| format!("cannot write {:?}", config_path))?;
+    std::fs::set_permissions(&config_path, std::fs::Permissions::from_mode(0o600))?;
+
+    let first_wallet = match wallet_name {
+        Some(name) => Some(create_first_wallet(&config, &name, interactive).await?),
+        None => None,
+    };
+
+    println!("\nmelwalletd is set up!");
+    println!("  config file:   {}", config_path.display());
+    println!("  wallet dir:    {}", wallet_dir.display());
+    println!("  network:       {network:?} (full node {network_addr})");
+    println!("  RPC endpoint:  http://{listen}");
+    if let Some((name, address)) = first_wallet {
+        println!("  first wallet:  {name} ({address})");
+    }
+    println!("\nStart the daemon with:");
+    println!("  melwalletd --config {}", config_path.display());
+    Ok(())
+}
+
+/// Creates a standard wallet, returning its name and address.
+async fn create_first_wallet(
+    config: &Config,
+    name: &str,
+    interactive: bool,
+) -> anyhow::Result<(String, String)> {
+    let password = if interactive {
+        loop {
+            let password = rpassword::prompt_password(format!("Password for wallet {name}: "))?;
+            let strength = config.password_policy.check(&password);
+            if strength.score < 2 {
+                println!(
+                    "Warning: this password is weak. {}",
+                    strength.warning.unwrap_or_default()
+                );
+            }
+            if rpassword::prompt_password("Repeat the password: ")? == password {
+                break password;
+            }
+            println!("The passwords don't match; try again.");
+        }
+    } else {
+        std::env::var(PASSWORD_VAR)
+            .with_context(|| format!("set {PASSWORD_VAR} to the first wallet's password"))?
+    };
+
+    let db = Database::open(
+        config.db_path(),
+        config.split_wallet_files,
+        config.read_connections,
+    )
+    .await?;
+    let secrets = SecretStore::open(&config.secrets_path())?;
+    if db.get_wallet(name).await.is_some() {
+        anyhow::bail!("wallet {name} already exists");
+    }
+    let sk = Ed25519SK::generate();
+    let covenant = Covenant::std_ed25519_pk_new(sk.to_public());
+    let address = covenant.hash().to_string();
+    db.create_wallet(name, covenant).await?;
+    if let Err(err) = secrets.store(
+        name.to_owned(),
+        PersistentSecret::PasswordEncrypted(EncryptedSK::new(sk, &password)),
+    ) {
+        db.remove_wallet(name).await?;
+        return Err(err);
+    }
+    Ok((name.to_owned(), address))
+}
+
+/// Uses the value given on the command line if any, otherwise asks for one when interactive, otherwise uses the default.
+fn resolve<T: FromStr>(
+    interactive: bool,
+    given: Option<T>,
+    prompt: &str,
+    default: &str,
+) -> anyhow::Result<T> {
+    match given {
+        Some(value) => Ok(value),
+        None if interactive => ask(prompt, Some(default)),
+        None => default
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid default {default:?}")),
+    }
+}
+
+/// Asks for a value on the terminal until a valid one is given. An empty answer picks the default, if there is one.
+fn ask<T: FromStr>(prompt: &str, default: Option<&str>) -> anyhow::Result<T> {
+    let stdin = std::io::stdin();
+    loop {
+        match default {
+            Some(d) => print!("{prompt} [{d}]: "),
+            None => print!("{prompt}: "),
+        }
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            anyhow::bail!("no answer for {prompt:?}");
+        }
+        let line = match (line.trim(), default) {
+            ("", Some(d)) => d,
+            (line, _) => line,
+        };
+        match line.parse() {
+            Ok(value) => return Ok(value),
+            Err(_) => println!("Invalid value {line:?}; try again."),
+        }
+    }
+}
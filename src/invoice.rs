@@ -0,0 +1,92 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use http_types::{Body, Method, Request, StatusCode, Url};
+use melstructs::Denom;
+use serde::Serialize;
+
+use crate::{
+    database::Database,
+    protocol::types::{Invoice, InvoiceStatus},
+};
+
+/// How many times a webhook is tried before giving up.
+const WEBHOOK_ATTEMPTS: u32 = 5;
+
+/// Brings open invoices up to date with the confirmed coins of their wallets, marking them paid or expired. Webhooks of newly paid invoices are fired in the background. Called from the confirmation loop.
+pub async fn check_invoices(database: &Database) -> anyhow::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    for invoice in database.list_invoices(None).await? {
+        let wallet = if let Some(wallet) = database.get_wallet(&invoice.wallet_name).await {
+            wallet
+        } else {
+            continue;
+        };
+        let denom: Denom = invoice
+            .denom
+            .parse()
+            .map_err(|_| anyhow::anyhow!("bad denom in invoice {}", invoice.id))?;
+        let (received, height) = wallet
+            .received_with_memo(denom, &hex::decode(&invoice.memo)?)
+            .await?;
+        if received >= invoice.amount {
+            database
+                .update_invoice(&invoice.id, InvoiceStatus::Paid, received, height)
+                .await?;
+            log::info!("invoice {} paid", invoice.id);
+            if let Some(url) = invoice.webhook.clone() {
+                let invoice = Invoice {
+                    status: InvoiceStatus::Paid,
+                    received,
+                    paid_height: height,
+                    ..invoice
+                };
+                let label = format!("invoice {}", invoice.id);
+                smolscale::spawn(fire_webhook(url, label, invoice)).detach();
+            }
+        } else if now > invoice.expires {
+            database
+                .update_invoice(&invoice.id, InvoiceStatus::Expired, received, None)
+                .await?;
+            log::info!("invoice {} expired", invoice.id);
+        } else if received != invoice.received {
+            database
+                .update_invoice(&invoice.id, InvoiceStatus::Open, received, None)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a webhook URL is something [fire_webhook] can deliver to.
+pub fn valid_webhook(url: &str) -> bool {
+    Url::parse(url)
+        .map(|url| url.scheme() == "http" && url.host_str().is_some())
+        .unwrap_or(false)
+}
+
+/// POSTs a JSON body to a webhook, retrying with exponential backoff. The label says what the webhook is for in logs.
+pub async fn fire_webhook(url: String, label: String, body: impl Serialize) {
+    for attempt in 0..WEBHOOK_ATTEMPTS {
+        match post_json(&url, &body).await {
+            Ok(status) if status.is_success() => return,
+            Ok(status) => log::warn!("webhook for {label} returned {status}"),
+            Err(err) => log::warn!("webhook for {label} failed: {:?}", err),
+        }
+        smol::Timer::after(Duration::from_secs(5 << attempt)).await;
+    }
+    log::warn!("giving up on webhook for {label} after {WEBHOOK_ATTEMPTS} attempts");
+}
+
+async fn post_json(url: &str, body: &impl Serialize) -> anyhow::Result<StatusCode> {
+    let url = Url::parse(url)?;
+    let host = url.host_str().context("webhook URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let stream = smol::net::TcpStream::connect((host, port)).await?;
+    let mut req = Request::new(Method::Post, url.clone());
+    req.set_body(Body::from_json(body).map_err(|e| e.into_inner())?);
+    let resp = async_h1::connect(stream, req)
+        .await
+        .map_err(|e| e.into_inner())?;
+    Ok(resp.status())
+}
@@ -0,0 +1,104 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use melstructs::Transaction;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// Size past which the journal is rotated to a numbered file.
+const MAX_JOURNAL_BYTES: u64 = 16 * 1024 * 1024;
+
+/// An append-only log of every transaction the daemon broadcasts, kept outside the database so that send history survives database corruption. Each line is a JSON-encoded [JournalEntry].
+///
+/// Once the journal grows past [MAX_JOURNAL_BYTES], it is renamed to `<path>.<n>` and a fresh one is started. Rotated files are never deleted.
+pub struct SendJournal {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+/// A transaction in the journal.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    /// UNIX timestamp of when the transaction was sent
+    pub time: u64,
+    /// Wallet that sent the transaction, or `escrow:<id>` for escrow settlements
+    pub wallet: String,
+    pub transaction: Transaction,
+}
+
+impl SendJournal {
+    /// Opens a journal, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            path: path.to_owned(),
+            file: Mutex::new(open_append(path)?),
+        })
+    }
+
+    /// Appends a transaction to the journal, making sure it reaches the disk.
+    pub fn append(&self, wallet: &str, transaction: &Transaction) -> anyhow::Result<()> {
+        let entry = JournalEntry {
+            time: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            wallet: wallet.to_owned(),
+            transaction: transaction.clone(),
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        let mut file = self.file.lock();
+        if file.metadata()?.len() > MAX_JOURNAL_BYTES {
+            let rotated = rotated_path(&self.path, self.rotated_count() + 1);
+            std::fs::rename(&self.path, &rotated)?;
+            *file = open_append(&self.path)?;
+            log::info!("rotated transaction journal to {:?}", rotated);
+        }
+        file.write_all(&line)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Reads back every entry, oldest first, across all rotated files. Lines that cannot be parsed, such as one torn by a crash, are skipped; the number of skipped lines is returned alongside the entries.
+    pub fn entries(&self) -> anyhow::Result<(Vec<JournalEntry>, usize)> {
+        let _guard = self.file.lock();
+        let mut entries = vec![];
+        let mut corrupt = 0;
+        let paths = (1..=self.rotated_count())
+            .map(|n| rotated_path(&self.path, n))
+            .chain(std::iter::once(self.path.clone()));
+        for path in paths {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                match serde_json::from_str(&line?) {
+                    Ok(entry) => entries.push(entry),
+                    Err(err) => {
+                        log::warn!("skipping corrupt line in {:?}: {:?}", path, err);
+                        corrupt += 1;
+                    }
+                }
+            }
+        }
+        Ok((entries, corrupt))
+    }
+
+    /// Number of rotated files, which are numbered from 1.
+    fn rotated_count(&self) -> usize {
+        (1..)
+            .take_while(|&n| rotated_path(&self.path, n).exists())
+            .count()
+    }
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{n}"));
+    rotated.into()
+}
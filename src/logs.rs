@@ -0,0 +1,197 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use event_listener::{Event, EventListener};
+use log::{LevelFilter, Log, Metadata, Record};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::protocol::types::{LogLevel, LogRecord};
+
+/// Most log records kept in memory.
+const CAPACITY: usize = 2000;
+
+/// Recent log records, for the `tail_logs` and `follow_logs` RPC methods.
+pub static LOG_BUFFER: Lazy<LogBuffer> = Lazy::new(LogBuffer::default);
+
+/// The level the logger was configured with, and how many threads are tracing melvm, which needs every level let through while it lasts.
+static LEVELS: Mutex<(LevelFilter, usize)> = parking_lot::const_mutex((LevelFilter::Off, 0));
+
+thread_local! {
+    /// Messages logged by melvm on this thread, while it's being traced
+    static MELVM_TRACE: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// A ring buffer of the latest [CAPACITY] log records.
+#[derive(Default)]
+pub struct LogBuffer {
+    /// Records oldest first, along with the sequence number of the next one
+    records: Mutex<(VecDeque<LogRecord>, u64)>,
+    appended: Event,
+}
+
+impl LogBuffer {
+    fn push(&self, level: LogLevel, target: &str, message: String) {
+        let mut records = self.records.lock();
+        let (records, next_seq) = &mut *records;
+        if records.len() == CAPACITY {
+            records.pop_front();
+        }
+        records.push_back(LogRecord {
+            seq: *next_seq,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            level,
+            target: target.to_owned(),
+            message,
+        });
+        *next_seq += 1;
+        self.appended.notify(usize::MAX);
+    }
+
+    /// The latest `limit` records at least as severe as `level`, oldest first.
+    pub fn tail(&self, level: LogLevel, limit: usize) -> Vec<LogRecord> {
+        let records = self.records.lock();
+        let mut tail: Vec<LogRecord> = records
+            .0
+            .iter()
+            .rev()
+            .filter(|r| r.level <= level)
+            .take(limit)
+            .cloned()
+            .collect();
+        tail.reverse();
+        tail
+    }
+
+    /// Records after sequence number `after` at least as severe as `level`, oldest first.
+    pub fn since(&self, after: Option<u64>, level: LogLevel) -> Vec<LogRecord> {
+        let records = self.records.lock();
+        records
+            .0
+            .iter()
+            .filter(|r| after.is_none_or(|after| r.seq > after) && r.level <= level)
+            .cloned()
+            .collect()
+    }
+
+    /// Fires on the next record appended. Listen before checking for records, so that none are missed.
+    pub fn listen(&self) -> EventListener {
+        self.appended.listen()
+    }
+}
+
+impl From<log::Level> for LogLevel {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => LogLevel::Error,
+            log::Level::Warn => LogLevel::Warn,
+            log::Level::Info => LogLevel::Info,
+            log::Level::Debug => LogLevel::Debug,
+            log::Level::Trace => LogLevel::Trace,
+        }
+    }
+}
+
+/// Logs through env_logger as usual, keeping a copy of every record it lets through in [LOG_BUFFER].
+struct BufferedLogger {
+    inner: env_logger::Logger,
+}
+
+impl Log for BufferedLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.target().starts_with("melvm") {
+            MELVM_TRACE.with(|trace| {
+                if let Some(trace) = trace.borrow_mut().as_mut() {
+                    trace.push(record.args().to_string());
+                }
+            });
+        }
+        if !self.inner.matches(record) {
+            return;
+        }
+        self.inner.log(record);
+        LOG_BUFFER.push(
+            record.level().into(),
+            record.target(),
+            record.args().to_string(),
+        );
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Installs the logger, configured by `RUST_LOG` like env_logger.
+pub fn init() {
+    let inner = env_logger::Builder::from_default_env().build();
+    LEVELS.lock().0 = inner.filter();
+    log::set_max_level(inner.filter());
+    log::set_boxed_logger(Box::new(BufferedLogger { inner })).expect("logger already set");
+}
+
+/// Runs `f`, returning what it returns along with the messages melvm logged on this thread meanwhile, which trace the instructions it ran. Nothing is traced unless the logger is installed.
+pub fn trace_melvm<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+    {
+        let mut levels = LEVELS.lock();
+        levels.1 += 1;
+        log::set_max_level(LevelFilter::Trace);
+    }
+    MELVM_TRACE.with(|trace| *trace.borrow_mut() = Some(vec![]));
+    let result = f();
+    let trace = MELVM_TRACE.with(|trace| trace.borrow_mut().take().unwrap_or_default());
+    let mut levels = LEVELS.lock();
+    levels.1 -= 1;
+    if levels.1 == 0 {
+        log::set_max_level(levels.0);
+    }
+    (result, trace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_latest_records() {
+        let buffer = LogBuffer::default();
+        for i in 0..CAPACITY + 10 {
+            let level = if i % 2 == 0 {
+                LogLevel::Warn
+            } else {
+                LogLevel::Debug
+            };
+            buffer.push(level, "melwalletd", format!("record {i}"));
+        }
+        let tail = buffer.tail(LogLevel::Trace, 3);
+        assert_eq!(
+            tail.iter().map(|r| r.seq).collect::<Vec<_>>(),
+            vec![
+                CAPACITY as u64 + 7,
+                CAPACITY as u64 + 8,
+                CAPACITY as u64 + 9
+            ]
+        );
+        let warnings = buffer.tail(LogLevel::Warn, 2);
+        assert!(warnings.iter().all(|r| r.level == LogLevel::Warn));
+        assert_eq!(warnings[1].message, format!("record {}", CAPACITY + 8));
+        // the oldest records are gone
+        assert_eq!(buffer.since(None, LogLevel::Trace).len(), CAPACITY);
+        assert_eq!(
+            buffer
+                .since(Some(CAPACITY as u64 + 8), LogLevel::Trace)
+                .len(),
+            1
+        );
+    }
+}
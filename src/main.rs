@@ -1,47 +1,111 @@
+mod accounting;
+mod address;
+mod alerts;
+mod anomaly;
+mod backup;
+mod bench;
+mod build_info;
+mod chain_cache;
 mod cli;
+mod consolidate;
+mod covenant;
 mod database;
+mod descriptor;
+mod diagnostics;
+mod discovery;
+mod doctor;
+mod escrow;
+mod fees;
+mod inheritance;
+mod init;
+mod invoice;
+mod journal;
+mod logs;
+mod mint;
+mod node_select;
+mod offline;
+mod password;
+mod payload;
+mod payment_proof;
+mod payment_uri;
+mod plugin;
+mod power;
 mod protocol;
+mod provision;
+mod proxy;
+mod reconcile;
+mod rotation;
 mod secrets;
 mod signer;
+mod signing_limit;
 mod state;
+mod swap;
+mod sync_snapshot;
+mod throttle;
+mod timelock;
+mod totp;
+mod trade;
+mod units;
+mod users;
+mod verify;
+mod watch_package;
 use std::convert::TryFrom;
 
-use std::{ffi::CString, sync::Arc};
-
-use anyhow::Context;
+use std::sync::Arc;
 
 use http_types::headers::HeaderValue;
 
-use melprot::Client;
 use state::AppState;
-use tap::Tap;
 
 use clap::Parser;
 use tide::{security::CorsMiddleware, Server};
 
 use crate::{
+    backup::apply_staged_restore,
+    bench::run_bench,
     cli::*,
+    doctor::run_doctor,
+    init::{create_wallet_dir, run_init},
+    node_select::NodeSelection,
+    offline::offline_client,
     protocol::{legacy::route_legacy, route_rpc},
+    provision::provision_wallets,
+    proxy::{connect_node, Socks5Proxy},
+    users::UserConfig,
 };
 
-use crate::{database::Database, secrets::SecretStore};
+use crate::{database::Database, protocol::types::WalletMismatchKind, secrets::SecretStore};
 
 use melstructs::NetID;
 
+/// Environment variable holding the master passphrase of the secret store, for unsealing it at startup without a terminal.
+const MASTER_PASSPHRASE_VAR: &str = "MELWALLETD_MASTER_PASSPHRASE";
+
 fn main() -> anyhow::Result<()> {
     let log_conf = std::env::var("RUST_LOG").unwrap_or_else(|_| "melwalletd=debug,warn".into());
     std::env::set_var("RUST_LOG", log_conf);
-    env_logger::init();
+    logs::init();
     smolscale::block_on(async {
         // let clap = __clap;
         let cmd_args = Args::from_args();
+        match cmd_args.command.clone() {
+            Some(Command::Init(init)) => return run_init(init).await,
+            Some(Command::Doctor(doctor)) => {
+                if !run_doctor(&doctor.config()?, doctor.json).await? {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+            Some(Command::Bench(bench)) => return run_bench(bench).await,
+            None => {}
+        }
         let output_config = cmd_args.output_config;
         let dry_run = cmd_args.dry_run;
+        let rebuild_from_journal = cmd_args.rebuild_from_journal;
+        let repair_wallets = cmd_args.repair_wallets;
 
         let config = Config::try_from(cmd_args).expect("Unable to create config from cmd args");
         let network = config.network;
-        let addr = config.network_addr;
-        let db_name = format!("{network:?}-wallets.db").to_ascii_lowercase();
 
         if output_config {
             println!(
@@ -55,49 +119,185 @@ fn main() -> anyhow::Result<()> {
             return Ok(());
         }
 
-        std::fs::create_dir_all(&config.wallet_dir).context("cannot create wallet_dir")?;
-        // SAFETY: this is perfectly safe because chmod cannot lead to memory unsafety.
-        unsafe {
-            libc::chmod(
-                CString::new(config.wallet_dir.to_string_lossy().as_bytes().to_vec())?.as_ptr(),
-                0o700,
-            );
-        }
-
-        let db = Database::open(config.wallet_dir.clone().tap_mut(|p| p.push(db_name))).await?;
+        UserConfig::validate_all(&config.users)?;
+        create_wallet_dir(&config.wallet_dir)?;
+        let db = open_database(&config).await?;
 
-        let mut secret_path = config.wallet_dir.clone();
-        secret_path.push(".secrets.json");
-        let secrets = SecretStore::open(&secret_path)?;
-
-        let client = Client::connect_http(network, addr).await?;
+        if rebuild_from_journal {
+            let replay = db.rebuild_from_journal(None).await?;
+            println!("{}", serde_json::to_string_pretty(&replay)?);
+            return Ok(());
+        }
 
-        log::info!("using node RPC {addr}");
+        let secrets = SecretStore::open(&config.secrets_path())?;
+        unseal_at_startup(&secrets, "the secret store")?;
+        provision_wallets(&db, &secrets, &config.wallets).await?;
+        check_wallets(&config, &db, &secrets, repair_wallets).await;
 
-        if network == NetID::Mainnet || network == NetID::Testnet {
-            client.trust(melbootstrap::checkpoint_height(network).unwrap());
+        let proxy = config
+            .proxy
+            .as_deref()
+            .map(Socks5Proxy::parse)
+            .transpose()?;
+        let nodes = Arc::new(NodeSelection::select(&config, proxy.clone()).await);
+        let addr = nodes.current();
+        let client = if config.offline {
+            log::warn!("running offline; nothing will sync, and nothing can be sent");
+            offline_client(network)
         } else {
-            log::warn!("** BLINDLY TRUSTING FULL NODE due to custom network **");
-            client.dangerously_trust_latest().await?;
-        }
+            let client = connect_node(network, addr, proxy.as_ref()).await?;
+            match proxy.as_ref() {
+                Some(proxy) => log::info!("using node RPC {addr} through {:?}", proxy),
+                None => log::info!("using node RPC {addr}"),
+            }
+
+            if network == NetID::Mainnet || network == NetID::Testnet {
+                client.trust(melbootstrap::checkpoint_height(network).unwrap());
+            } else {
+                log::warn!("** BLINDLY TRUSTING FULL NODE due to custom network **");
+                client.dangerously_trust_latest().await?;
+            }
+            client
+        };
 
         // Prepare to create server
-        let state = AppState::new(db, network, secrets, addr, client);
         let config = Arc::new(config);
+        let mut state = AppState::new(
+            db,
+            network,
+            secrets,
+            nodes.clone(),
+            client.clone(),
+            config.clone(),
+        );
+        if !config.users.is_empty() {
+            let mut users = vec![];
+            for user in config.users.iter() {
+                let user_config = config.for_user(user);
+                create_wallet_dir(&user_config.wallet_dir)?;
+                let db = open_database(&user_config).await?;
+                let secrets = SecretStore::open(&user_config.secrets_path())?;
+                unseal_at_startup(&secrets, &format!("the secret store of user {}", user.name))?;
+                check_wallets(&user_config, &db, &secrets, repair_wallets).await;
+                let mut user_state = AppState::new(
+                    db,
+                    network,
+                    secrets,
+                    nodes.clone(),
+                    client.clone(),
+                    Arc::new(user_config),
+                );
+                user_state.user = Some(user.name.clone());
+                users.push((user.clone(), user_state));
+            }
+            log::info!("serving {} users, each with their own wallets", users.len());
+            state.users = Some(Arc::new(users));
+        }
 
+        // swap orders, DCA jobs and consolidation sign and send through the same checks as RPC calls, so they run with the state rather than in the confirmation loop
+        let _sending_tasks: Vec<_> = std::iter::once(&state)
+            .chain(
+                state
+                    .users
+                    .iter()
+                    .flat_map(|users| users.iter().map(|(_, s)| s)),
+            )
+            .filter(|_| !config.offline)
+            .map(|state| smolscale::spawn(state.clone().sending_loop()))
+            .collect();
         let mut app = init_server(config.clone(), state).await?;
 
         let sock = config.listen;
         // new RPC interface
         route_rpc(&mut app);
-        // old REST-based interface
-        route_legacy(&mut app);
+        // old REST-based interface, which has no notion of users
+        if config.users.is_empty() {
+            route_legacy(&mut app);
+        } else {
+            log::warn!("the REST interface is disabled, since there are several users");
+        }
+        let _reprobe_task = (!config.offline).then(|| smolscale::spawn(nodes.reprobe_loop()));
+        let _mdns_task = config.mdns.then(|| {
+            let config = config.clone();
+            smolscale::spawn(async move {
+                if let Err(err) = discovery::advertise(&config).await {
+                    log::warn!("mDNS advertisement stopped: {:?}", err);
+                }
+            })
+        });
         log::info!("starting RPC server at {}", config.listen);
         app.listen(sock).await?;
         Ok(())
     })
 }
 
+/// Unseals a secret store protected by a master passphrase, with the passphrase in [MASTER_PASSPHRASE_VAR] or, failing that, by asking for it if running in a terminal. Otherwise, the store stays sealed until unsealed through the RPC interface.
+fn unseal_at_startup(secrets: &SecretStore, what: &str) -> anyhow::Result<()> {
+    if !secrets.is_sealed() {
+        return Ok(());
+    }
+    if let Ok(passphrase) = std::env::var(MASTER_PASSPHRASE_VAR) {
+        match secrets.unseal(&passphrase) {
+            Ok(()) => return Ok(()),
+            Err(err) => log::warn!("cannot unseal {what} with {MASTER_PASSPHRASE_VAR}: {err}"),
+        }
+    }
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 {
+        for _ in 0..3 {
+            let passphrase = rpassword::prompt_password(format!("Master passphrase of {what}: "))?;
+            match secrets.unseal(&passphrase) {
+                Ok(()) => return Ok(()),
+                Err(err) => println!("{err}"),
+            }
+        }
+    }
+    log::warn!("{what} is sealed; unseal it with the unseal_secrets RPC method");
+    Ok(())
+}
+
+/// Logs wallets whose database record and secret key disagree, removing those left by failed creations if `repair` is set. Skipped while the secret store is sealed.
+async fn check_wallets(config: &Config, db: &Database, secrets: &SecretStore, repair: bool) {
+    if secrets.is_sealed() {
+        return;
+    }
+    let elsewhere = reconcile::other_network_wallets(&config.wallet_dir, &config.db_path());
+    match reconcile::verify_wallets(db, secrets, &elsewhere, repair).await {
+        Ok(mismatches) => {
+            for mismatch in mismatches {
+                match mismatch.kind {
+                    WalletMismatchKind::MissingSecret if !mismatch.repaired => log::warn!(
+                        "wallet {} has no secret key or coins, probably from a failed creation; start with --repair-wallets to remove it",
+                        mismatch.name
+                    ),
+                    WalletMismatchKind::MissingSecret => {}
+                    WalletMismatchKind::OrphanSecret => log::warn!(
+                        "the secret key of wallet {} has no wallet in the database; recreate it with recover_wallets_from_secrets",
+                        mismatch.name
+                    ),
+                }
+            }
+        }
+        Err(err) => log::warn!("cannot check wallets against the secret store: {:?}", err),
+    }
+}
+
+/// Opens the database of a wallet directory, first swapping in a backup if one was restored.
+async fn open_database(config: &Config) -> anyhow::Result<Database> {
+    if apply_staged_restore(
+        &config.wallet_dir,
+        &config.db_path(),
+        &config.secrets_path(),
+    )? {
+        log::warn!("restored a backup; the previous database and secrets were set aside");
+    }
+    Database::open(
+        config.db_path(),
+        config.split_wallet_files,
+        config.read_connections,
+    )
+    .await
+}
+
 async fn init_server<T: Send + Sync + Clone + 'static>(
     config: Arc<Config>,
     state: T,
@@ -0,0 +1,52 @@
+use melstructs::MICRO_CONVERTER;
+
+/// Blocks per day, at the network's 30-second block time. A DOSC is a day of sequential computation at the network's DOSC speed.
+pub const BLOCKS_PER_DAY: u128 = 2880;
+
+/// Base-2 logarithm of the sequential work, in hashes, that makes up one DOSC at the given DOSC speed (hashes per block). This is the melPoW difficulty a minter running exactly at DOSC speed would reach after a day.
+pub fn dosc_difficulty(dosc_speed: u128) -> f64 {
+    (dosc_speed.max(1) as f64 * BLOCKS_PER_DAY as f64).log2()
+}
+
+/// Micro-DOSC rewarded by Melmint for a proof of the given difficulty that took `blocks` blocks to compute.
+///
+/// The reward is the work done, in DOSCs, scaled by how fast the minter was relative to the DOSC speed, so minters slower than the fastest ones are rewarded less than proportionally.
+pub fn mint_reward(dosc_speed: u128, difficulty: u32, blocks: u64) -> u128 {
+    if difficulty >= 128 || dosc_speed == 0 {
+        return 0;
+    }
+    let work = 1u128 << difficulty;
+    let speed = work / blocks.max(1) as u128;
+    // work * speed can overflow u128, so divide in steps while keeping six digits of precision
+    let per_dosc = dosc_speed.saturating_mul(BLOCKS_PER_DAY);
+    let work_doscs = work.saturating_mul(MICRO_CONVERTER) / per_dosc;
+    let remainder = work.saturating_mul(MICRO_CONVERTER) % per_dosc;
+    work_doscs
+        .saturating_mul(speed)
+        .saturating_add(remainder.saturating_mul(speed) / per_dosc)
+        / dosc_speed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_speed_day_is_one_dosc() {
+        let dosc_speed = 1 << 20;
+        let difficulty = dosc_difficulty(dosc_speed);
+        assert!((difficulty - (20.0 + (BLOCKS_PER_DAY as f64).log2())).abs() < 1e-9);
+
+        // a day of work at exactly the DOSC speed
+        let work_difficulty = 20 + 11;
+        let blocks = (1u128 << work_difficulty) / dosc_speed;
+        let reward = mint_reward(dosc_speed, work_difficulty, blocks as u64);
+        let expected = (1u128 << work_difficulty) * MICRO_CONVERTER / (dosc_speed * BLOCKS_PER_DAY);
+        assert_eq!(reward, expected);
+
+        // twice as slow earns half as much for the same work
+        let slow = mint_reward(dosc_speed, work_difficulty, 2 * blocks as u64);
+        assert_eq!(slow, expected / 2);
+        assert_eq!(mint_reward(0, 30, 10), 0);
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use melstructs::NetID;
+use parking_lot::RwLock;
+use smol_timeout::TimeoutExt;
+
+use crate::{
+    cli::Config,
+    protocol::types::{NetworkDiagnostics, NodeProbe},
+    proxy::{connect_node, Socks5Proxy},
+};
+
+/// Nodes within this many blocks of the highest node probed count as caught up.
+const HEIGHT_TOLERANCE: u64 = 2;
+/// How long a node has to answer a probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often nodes are probed again after startup.
+const REPROBE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Asks a node for its latest block, timing how long it takes to answer.
+pub async fn probe(network: NetID, addr: SocketAddr, proxy: Option<&Socks5Proxy>) -> NodeProbe {
+    let failed = |error: String| NodeProbe {
+        addr,
+        latency_ms: None,
+        height: None,
+        error: Some(error),
+    };
+    let client = match connect_node(network, addr, proxy).await {
+        Ok(client) => client,
+        Err(err) => return failed(err.to_string()),
+    };
+    // the client is thrown away after the probe, so trusting whatever the node says is harmless
+    let start = Instant::now();
+    match client
+        .dangerously_trust_latest()
+        .timeout(PROBE_TIMEOUT)
+        .await
+    {
+        None => return failed("timed out".into()),
+        Some(Err(err)) => return failed(err.to_string()),
+        Some(Ok(())) => {}
+    }
+    let latency_ms = start.elapsed().as_millis() as u64;
+    match client.latest_snapshot().timeout(PROBE_TIMEOUT).await {
+        None => failed("timed out".into()),
+        Some(Err(err)) => failed(err.to_string()),
+        Some(Ok(snapshot)) => NodeProbe {
+            addr,
+            latency_ms: Some(latency_ms),
+            height: Some(snapshot.current_header().height),
+            error: None,
+        },
+    }
+}
+
+/// Probes several nodes at once.
+pub async fn probe_all(
+    network: NetID,
+    addrs: &[SocketAddr],
+    proxy: Option<&Socks5Proxy>,
+) -> Vec<NodeProbe> {
+    futures::future::join_all(addrs.iter().map(|addr| probe(network, *addr, proxy))).await
+}
+
+/// The nodes that answered and are caught up with the highest one.
+fn caught_up(probes: &[NodeProbe]) -> impl Iterator<Item = &NodeProbe> {
+    let top = probes
+        .iter()
+        .filter_map(|p| p.height)
+        .max()
+        .unwrap_or_default();
+    probes.iter().filter(move |p| {
+        p.latency_ms.is_some() && p.height.is_some_and(|h| h.0 + HEIGHT_TOLERANCE >= top.0)
+    })
+}
+
+/// Picks the lowest-latency node of those caught up with the highest one, if any answered.
+pub fn best(probes: &[NodeProbe]) -> Option<&NodeProbe> {
+    caught_up(probes).min_by_key(|p| p.latency_ms)
+}
+
+/// Every node worth probing: the network's bootstrap nodes, along with the configured one.
+fn candidates(config: &Config) -> Vec<SocketAddr> {
+    let mut addrs = melbootstrap::bootstrap_routes(config.network);
+    if !addrs.contains(&config.network_addr) {
+        addrs.push(config.network_addr);
+    }
+    addrs
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock before 1970")
+        .as_secs()
+}
+
+/// The full node the daemon is connected to, along with the latest probes of the alternatives.
+pub struct NodeSelection {
+    network: NetID,
+    current: SocketAddr,
+    auto_selected: bool,
+    candidates: Vec<SocketAddr>,
+    proxy: Option<Socks5Proxy>,
+    /// When the nodes were last probed, and how they did
+    probes: RwLock<Option<(u64, Vec<NodeProbe>)>>,
+}
+
+impl NodeSelection {
+    /// Picks the node to connect to. If the config asks for it, every candidate node is probed, and the best one is picked; otherwise, or if none answer, the configured node is. Nothing is probed when offline.
+    pub async fn select(config: &Config, proxy: Option<Socks5Proxy>) -> Self {
+        let candidates = candidates(config);
+        if !config.auto_select_node || config.offline {
+            return Self {
+                network: config.network,
+                current: config.network_addr,
+                auto_selected: false,
+                candidates,
+                proxy,
+                probes: Default::default(),
+            };
+        }
+        let probes = probe_all(config.network, &candidates, proxy.as_ref()).await;
+        let current = match best(&probes) {
+            Some(best) => {
+                log::info!(
+                    "picked node {} at height {:?}, answering in {:?} ms, out of {} probed",
+                    best.addr,
+                    best.height,
+                    best.latency_ms,
+                    probes.len()
+                );
+                best.addr
+            }
+            None => {
+                log::warn!(
+                    "no node answered probes; falling back to {}",
+                    config.network_addr
+                );
+                config.network_addr
+            }
+        };
+        Self {
+            network: config.network,
+            current,
+            auto_selected: true,
+            candidates,
+            proxy,
+            probes: RwLock::new(Some((unix_now(), probes))),
+        }
+    }
+
+    /// The node the daemon is connected to.
+    pub fn current(&self) -> SocketAddr {
+        self.current
+    }
+
+    /// The current node, and how the candidates did in the latest probes.
+    pub fn diagnostics(&self) -> NetworkDiagnostics {
+        let probes = self.probes.read();
+        let (probed_at, probes) = match probes.as_ref() {
+            Some((probed_at, probes)) => (Some(*probed_at), probes.clone()),
+            None => (None, vec![]),
+        };
+        NetworkDiagnostics {
+            current_node: self.current,
+            auto_selected: self.auto_selected,
+            best_node: best(&probes).map(|p| p.addr),
+            probes,
+            probed_at,
+        }
+    }
+
+    /// Probes the candidate nodes every [REPROBE_INTERVAL], warning when the current node falls behind the best one. The daemon stays connected to the current node; a better one is only picked up on restart.
+    pub async fn reprobe_loop(self: Arc<Self>) {
+        loop {
+            smol::Timer::after(REPROBE_INTERVAL).await;
+            let probes = probe_all(self.network, &self.candidates, self.proxy.as_ref()).await;
+            if let Some(best) = best(&probes) {
+                if !caught_up(&probes).any(|p| p.addr == self.current) {
+                    log::warn!(
+                        "node {} is unreachable or behind; {} would be better after a restart",
+                        self.current,
+                        best.addr
+                    );
+                }
+            }
+            *self.probes.write() = Some((unix_now(), probes));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use melstructs::BlockHeight;
+
+    use super::*;
+
+    fn answered(port: u16, latency_ms: Option<u64>, height: u64) -> NodeProbe {
+        NodeProbe {
+            addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            latency_ms,
+            height: latency_ms.map(|_| BlockHeight(height)),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn prefers_fast_caught_up_nodes() {
+        assert!(best(&[]).is_none());
+        assert!(best(&[answered(1, None, 0)]).is_none());
+        let probes = [
+            answered(1, Some(300), 100),
+            answered(2, Some(50), 90),
+            answered(3, Some(120), 99),
+            answered(4, None, 0),
+        ];
+        // the fastest node is behind, so the fastest caught-up one wins
+        assert_eq!(best(&probes).unwrap().addr.port(), 3);
+    }
+}
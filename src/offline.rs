@@ -0,0 +1,24 @@
+use async_trait::async_trait;
+use melprot::{Client, NodeRpcClient};
+use melstructs::NetID;
+use nanorpc::{JrpcRequest, JrpcResponse, RpcTransport};
+
+/// Why anything needing a full node fails in offline mode.
+pub const OFFLINE_ERROR: &str = "the daemon was started with --offline, and this needs a full node";
+
+/// A node connection that fails every call, for running without a node.
+struct OfflineTransport;
+
+#[async_trait]
+impl RpcTransport for OfflineTransport {
+    type Error = anyhow::Error;
+
+    async fn call_raw(&self, _: JrpcRequest) -> Result<JrpcResponse, Self::Error> {
+        Err(anyhow::anyhow!(OFFLINE_ERROR))
+    }
+}
+
+/// A client that never reaches any node, so that everything needing one fails right away with [OFFLINE_ERROR].
+pub fn offline_client(network: NetID) -> Client {
+    Client::new(network, NodeRpcClient(OfflineTransport))
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::types::PasswordStrength;
+
+/// Requirements that passwords protecting wallet secrets must satisfy. The default policy accepts any password, including the empty one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct PasswordPolicy {
+    /// Minimum length of the password, in characters.
+    #[serde(default)]
+    pub min_length: usize,
+    /// Minimum zxcvbn strength score, from 0 (anything goes) to 4 (very hard to guess).
+    #[serde(default)]
+    pub min_score: u8,
+}
+
+impl PasswordPolicy {
+    /// Estimates the strength of a password, and whether it satisfies this policy.
+    pub fn check(&self, pwd: &str) -> PasswordStrength {
+        let length = pwd.chars().count();
+        // zxcvbn refuses to rate an empty password, which is the weakest password there is anyway
+        let (score, guesses_log10, warning, suggestions) = match zxcvbn::zxcvbn(pwd, &[]) {
+            Ok(entropy) => {
+                let (warning, suggestions) = entropy
+                    .feedback()
+                    .as_ref()
+                    .map(|fb| {
+                        (
+                            fb.warning().map(|w| w.to_string()),
+                            fb.suggestions().iter().map(|s| s.to_string()).collect(),
+                        )
+                    })
+                    .unwrap_or_default();
+                (
+                    entropy.score(),
+                    entropy.guesses_log10(),
+                    warning,
+                    suggestions,
+                )
+            }
+            Err(_) => (0, 0.0, None, vec!["Use a password.".into()]),
+        };
+        PasswordStrength {
+            score,
+            guesses_log10,
+            length,
+            acceptable: length >= self.min_length && score >= self.min_score,
+            warning,
+            suggestions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy() {
+        let lax = PasswordPolicy::default();
+        assert!(lax.check("").acceptable);
+        assert!(lax.check("hunter2").acceptable);
+
+        let strict = PasswordPolicy {
+            min_length: 12,
+            min_score: 3,
+        };
+        assert!(!strict.check("").acceptable);
+        assert!(!strict.check("password1234").acceptable);
+        assert!(strict.check("correct horse battery staple").acceptable);
+    }
+}
@@ -0,0 +1,34 @@
+use crate::protocol::types::DataPayload;
+
+/// Marks `additional_data` holding a [DataPayload], followed by the payload as JSON, so that other tools can read it too.
+const PAYLOAD_MAGIC: &[u8] = b"mwd-payload-v1:";
+
+/// Encodes a payload for a coin's `additional_data`.
+pub fn encode(payload: &DataPayload) -> Vec<u8> {
+    let mut data = PAYLOAD_MAGIC.to_vec();
+    data.extend(serde_json::to_vec(payload).expect("cannot serialize payload"));
+    data
+}
+
+/// Decodes the payload of a coin's `additional_data`, if it has one.
+pub fn decode(data: &[u8]) -> Option<DataPayload> {
+    serde_json::from_slice(data.strip_prefix(PAYLOAD_MAGIC)?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_roundtrip() {
+        let payload = DataPayload::Nft {
+            collection: "birds".into(),
+            token_id: 42,
+            uri: Some("ipfs://bafy".into()),
+        };
+        let data = encode(&payload);
+        assert_eq!(decode(&data), Some(payload));
+        assert_eq!(decode(b"hello"), None);
+        assert_eq!(decode(&data[..data.len() - 1]), None);
+    }
+}
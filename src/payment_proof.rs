@@ -0,0 +1,198 @@
+use base32::Alphabet;
+use melstructs::{BlockHeight, CoinData, Header, Transaction};
+use novasmt::CompressedProof;
+use serde::{Deserialize, Serialize};
+use tmelcrypt::HashVal;
+
+/// Proof that a transaction, and so each of its outputs, made it into the blockchain, which a third party can check knowing only the hash of one later block header. Discloses that one transaction and nothing else of the wallet.
+///
+/// The transaction is proven to be in the transactions tree of the header of its block, which is proven to be in the history tree of the anchor header. Whoever verifies the proof must get the anchor's hash from a source they trust, such as their own node.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PaymentProof {
+    pub tx: Transaction,
+    /// The output the proof is about
+    pub output_index: u8,
+    /// Header of the block confirming the transaction
+    pub header: Header,
+    tx_branch: CompressedProof,
+    /// Header the proof is anchored to, at or above `header`
+    pub anchor: Header,
+    /// Branch of the anchor's history tree proving `header`, unless it is the anchor itself
+    history_branch: Option<CompressedProof>,
+}
+
+/// Key of a value in a state tree, as the nodes compute it.
+fn smt_key(key: impl Serialize) -> HashVal {
+    tmelcrypt::hash_single(stdcode::serialize(&key).expect("cannot serialize key"))
+}
+
+/// Checks a compressed branch proving `val` under `key` in the tree with root `root`.
+fn verify_branch(branch: &CompressedProof, root: HashVal, key: HashVal, val: &[u8]) -> bool {
+    branch
+        .decompress()
+        .map(|branch| branch.verify(root.0, key.0, val))
+        .unwrap_or(false)
+}
+
+impl PaymentProof {
+    /// Assembles a proof from branches fetched from a node: `tx_branch` from the transactions tree at the transaction's height, and `history_branch` from the history tree at the anchor's height.
+    pub fn new(
+        tx: Transaction,
+        output_index: u8,
+        header: Header,
+        tx_branch: CompressedProof,
+        anchor: Header,
+        history_branch: Option<CompressedProof>,
+    ) -> Self {
+        Self {
+            tx,
+            output_index,
+            header,
+            tx_branch,
+            anchor,
+            history_branch,
+        }
+    }
+
+    /// Key of the transaction in the transactions tree.
+    pub fn tx_key(tx: &Transaction) -> HashVal {
+        smt_key(tx.hash_nosigs())
+    }
+
+    /// Key of the header of a block in the history tree of later blocks.
+    pub fn history_key(height: BlockHeight) -> HashVal {
+        smt_key(height)
+    }
+
+    /// Checks that the proof holds, relative to its anchor, and returns the output it is about. Checking that the anchor is really part of the blockchain is up to the caller.
+    pub fn verify(&self) -> Result<&CoinData, String> {
+        let output = self
+            .tx
+            .outputs
+            .get(self.output_index as usize)
+            .ok_or_else(|| format!("transaction has no output {}", self.output_index))?;
+        let tx_bytes = stdcode::serialize(&self.tx).expect("cannot serialize transaction");
+        if !verify_branch(
+            &self.tx_branch,
+            self.header.transactions_hash,
+            Self::tx_key(&self.tx),
+            &tx_bytes,
+        ) {
+            return Err("transaction is not in its block".into());
+        }
+        match &self.history_branch {
+            None if self.anchor == self.header => (),
+            None => return Err("missing proof of the block's header".into()),
+            Some(branch) => {
+                if self.anchor.network != self.header.network
+                    || self.anchor.height <= self.header.height
+                {
+                    return Err("anchor does not follow the block".into());
+                }
+                let header_bytes =
+                    stdcode::serialize(&self.header).expect("cannot serialize header");
+                if !verify_branch(
+                    branch,
+                    self.anchor.history_hash,
+                    Self::history_key(self.header.height),
+                    &header_bytes,
+                ) {
+                    return Err("block is not in the anchor's history".into());
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Encodes the proof in a form that is easy to copy around.
+    pub fn encode(&self) -> String {
+        let bytes = stdcode::serialize(self).expect("cannot serialize proof");
+        base32::encode(Alphabet::Crockford, &bytes)
+    }
+
+    /// Decodes a proof encoded with [PaymentProof::encode]. Does not verify it.
+    pub fn decode(s: &str) -> Option<Self> {
+        let bytes = base32::decode(Alphabet::Crockford, s.trim())?;
+        stdcode::deserialize(&bytes).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use melstructs::{CoinValue, Denom, NetID, TxKind};
+    use novasmt::{Database, InMemoryCas};
+
+    use super::*;
+
+    fn header(height: u64, transactions_hash: HashVal, history_hash: HashVal) -> Header {
+        Header {
+            network: NetID::Testnet,
+            previous: HashVal::default(),
+            height: BlockHeight(height),
+            history_hash,
+            coins_hash: HashVal::default(),
+            transactions_hash,
+            fee_pool: CoinValue(0),
+            fee_multiplier: 1,
+            dosc_speed: 1,
+            pools_hash: HashVal::default(),
+            stakes_hash: HashVal::default(),
+        }
+    }
+
+    #[test]
+    fn proof_roundtrip() {
+        let tx = Transaction {
+            kind: TxKind::Normal,
+            inputs: vec![],
+            outputs: vec![CoinData {
+                covhash: HashVal([3; 32]).into(),
+                value: CoinValue(1000),
+                denom: Denom::Mel,
+                additional_data: Default::default(),
+            }],
+            fee: CoinValue(10),
+            covenants: vec![],
+            data: vec![].into(),
+            sigs: vec![],
+        };
+        let forest = Database::new(InMemoryCas::default());
+        let txs = forest.get_tree(Default::default()).unwrap().with(
+            PaymentProof::tx_key(&tx).0,
+            &stdcode::serialize(&tx).unwrap(),
+        );
+        let (_, tx_branch) = txs.get_with_proof(PaymentProof::tx_key(&tx).0);
+        let block = header(100, HashVal(txs.root_hash()), HashVal::default());
+        let history = forest.get_tree(Default::default()).unwrap().with(
+            PaymentProof::history_key(block.height).0,
+            &stdcode::serialize(&block).unwrap(),
+        );
+        let (_, history_branch) = history.get_with_proof(PaymentProof::history_key(block.height).0);
+        let anchor = header(150, HashVal::default(), HashVal(history.root_hash()));
+
+        let proof = PaymentProof::new(
+            tx.clone(),
+            0,
+            block,
+            tx_branch.compress(),
+            anchor,
+            Some(history_branch.compress()),
+        );
+        let proof = PaymentProof::decode(&proof.encode()).unwrap();
+        assert_eq!(proof.verify().unwrap().value, CoinValue(1000));
+
+        // anchored to the block itself
+        let direct = PaymentProof::new(tx.clone(), 0, block, tx_branch.compress(), block, None);
+        assert!(direct.verify().is_ok());
+
+        let mut forged = proof.clone();
+        forged.tx.outputs[0].value = CoinValue(1_000_000);
+        assert!(forged.verify().is_err());
+        let mut moved = proof.clone();
+        moved.header.height = BlockHeight(99);
+        assert!(moved.verify().is_err());
+        let mut missing = proof;
+        missing.output_index = 1;
+        assert!(missing.verify().is_err());
+    }
+}
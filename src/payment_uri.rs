@@ -0,0 +1,76 @@
+use melstructs::{CoinData, CoinValue, Denom};
+
+use crate::{
+    address::parse_address,
+    protocol::types::{PaymentUriError, UnitConversionError},
+    units::TokenRegistry,
+};
+
+/// URI scheme of payment links, as in `mel:<address>?amount=1.5`.
+pub const URI_SCHEME: &str = "mel";
+
+/// Example payment link, showing every recognized parameter.
+pub const URI_EXAMPLE: &str = "mel:<address>?amount=1.5&denom=MEL&data=<hex>&label=<text>";
+
+/// Parses a payment link into the output it asks for. The amount is in display units of the denomination, which defaults to MEL. Unknown parameters, such as `label` and `message`, are ignored.
+pub fn parse_payment_uri(uri: &str, registry: &TokenRegistry) -> Result<CoinData, PaymentUriError> {
+    let url = url::Url::parse(uri).map_err(|e| PaymentUriError::Malformed(e.to_string()))?;
+    if url.scheme() != URI_SCHEME {
+        return Err(PaymentUriError::Malformed(format!(
+            "scheme must be {URI_SCHEME}, not {}",
+            url.scheme()
+        )));
+    }
+    let covhash = parse_address(url.path())
+        .ok_or_else(|| PaymentUriError::InvalidAddress(url.path().to_owned()))?;
+    let mut amount = None;
+    let mut denom = Denom::Mel;
+    let mut additional_data = vec![];
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "amount" => amount = Some(value.into_owned()),
+            "denom" => {
+                denom = value
+                    .parse()
+                    .map_err(|_| UnitConversionError::InvalidDenom(value.into_owned()))?
+            }
+            "data" => {
+                additional_data = hex::decode(value.as_ref())
+                    .map_err(|e| PaymentUriError::InvalidData(e.to_string()))?
+            }
+            _ => (),
+        }
+    }
+    let value: CoinValue =
+        registry.parse_display(denom, &amount.ok_or(PaymentUriError::MissingAmount)?)?;
+    Ok(CoinData {
+        covhash,
+        value,
+        denom,
+        additional_data: additional_data.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use melstructs::Address;
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let registry = TokenRegistry::default();
+        let address = Address::coin_destroy();
+        let output = parse_payment_uri(
+            &format!("mel:{address}?amount=1.5&data=cafe&label=Coffee%20shop"),
+            &registry,
+        )
+        .unwrap();
+        assert_eq!(output.covhash, address);
+        assert_eq!(output.value, CoinValue(1_500_000));
+        assert_eq!(output.denom, Denom::Mel);
+        assert_eq!(output.additional_data.as_ref(), &[0xca, 0xfe]);
+        assert!(parse_payment_uri(&format!("mel:{address}"), &registry).is_err());
+        assert!(parse_payment_uri(&format!("bitcoin:{address}?amount=1"), &registry).is_err());
+    }
+}
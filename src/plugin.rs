@@ -0,0 +1,83 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use melstructs::Transaction;
+use melvm::Covenant;
+use parking_lot::RwLock;
+use tmelcrypt::{Ed25519PK, Ed25519SK};
+
+use crate::signer::Signer;
+
+/// Name of the built-in wallet type: a single key guarding a standard ed25519 covenant.
+pub const STANDARD_WALLET: &str = "standard";
+
+/// A type of wallet, defining how the wallet's key turns into a covenant and a signer. Wallet types are registered in a [PluginRegistry] at startup, so that new kinds of wallets (escrows, bridges...) can be added without touching the rest of the daemon.
+///
+/// Every wallet type is given the wallet's key along with arbitrary type-specific parameters, fixed when the wallet is created (for example, the other parties' public keys).
+pub trait WalletPlugin: Send + Sync + 'static {
+    /// Name of the wallet type.
+    fn kind(&self) -> &str;
+
+    /// Covenant guarding the coins of a wallet of this type.
+    fn covenant(&self, pubkey: Ed25519PK, params: &[u8]) -> anyhow::Result<Covenant>;
+
+    /// Signer for a wallet of this type, once it is unlocked.
+    fn signer(&self, key: Ed25519SK, params: &[u8]) -> anyhow::Result<Arc<dyn Signer>>;
+
+    /// Adjusts a transaction prepared by a wallet of this type, before it is signed. By default, leaves it as is.
+    fn prepare_hook(&self, tx: Transaction, _params: &[u8]) -> anyhow::Result<Transaction> {
+        Ok(tx)
+    }
+}
+
+/// The ordinary wallet type, which needs no parameters.
+struct StandardWallet;
+
+impl WalletPlugin for StandardWallet {
+    fn kind(&self) -> &str {
+        STANDARD_WALLET
+    }
+
+    fn covenant(&self, pubkey: Ed25519PK, params: &[u8]) -> anyhow::Result<Covenant> {
+        anyhow::ensure!(params.is_empty(), "standard wallets take no parameters");
+        Ok(Covenant::std_ed25519_pk_new(pubkey))
+    }
+
+    fn signer(&self, key: Ed25519SK, _params: &[u8]) -> anyhow::Result<Arc<dyn Signer>> {
+        Ok(Arc::new(key))
+    }
+}
+
+/// The wallet types known to the daemon, by name.
+#[derive(Clone)]
+pub struct PluginRegistry {
+    plugins: Arc<RwLock<BTreeMap<String, Arc<dyn WalletPlugin>>>>,
+}
+
+impl Default for PluginRegistry {
+    fn default() -> Self {
+        let registry = Self {
+            plugins: Default::default(),
+        };
+        registry.register(Arc::new(StandardWallet));
+        registry
+    }
+}
+
+impl PluginRegistry {
+    /// Registers a wallet type, replacing any previously registered type of the same name.
+    pub fn register(&self, plugin: Arc<dyn WalletPlugin>) {
+        self.plugins
+            .write()
+            .insert(plugin.kind().to_owned(), plugin);
+    }
+
+    /// Gets a wallet type by name.
+    pub fn get(&self, kind: &str) -> Option<Arc<dyn WalletPlugin>> {
+        self.plugins.read().get(kind).cloned()
+    }
+
+    /// Lists the names of all registered wallet types.
+    pub fn kinds(&self) -> Vec<String> {
+        self.plugins.read().keys().cloned().collect()
+    }
+}
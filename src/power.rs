@@ -0,0 +1,152 @@
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use event_listener::Event;
+use parking_lot::Mutex;
+
+use crate::protocol::types::{PowerMode, PowerStatus};
+
+/// How long after an RPC call the daemon stays at full activity, even if it would otherwise save power.
+const INTERACTIVE_GRACE: Duration = Duration::from_secs(300);
+/// How long whether the machine runs on battery is remembered before checking again.
+const BATTERY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Where Linux lists batteries and chargers.
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// Whether the daemon should save power: run on battery, or told to, and not used interactively lately. Shared by the RPC server, which reports calls, and the background loops, which slow down while it says so.
+#[derive(Clone, Default)]
+pub struct PowerState {
+    inner: Arc<Mutex<PowerInner>>,
+    /// Notified when an RPC call ends low-power mode, so that sleeping loops catch up at once
+    pub woken: Arc<Event>,
+}
+
+#[derive(Default)]
+struct PowerInner {
+    mode: PowerMode,
+    last_interactive: Option<Instant>,
+    battery: Option<(Instant, Option<bool>)>,
+}
+
+impl PowerInner {
+    fn on_battery(&mut self) -> Option<bool> {
+        match self.battery {
+            Some((checked, on_battery)) if checked.elapsed() < BATTERY_CHECK_INTERVAL => on_battery,
+            _ => {
+                let on_battery = detect_battery(Path::new(POWER_SUPPLY_DIR));
+                self.battery = Some((Instant::now(), on_battery));
+                on_battery
+            }
+        }
+    }
+
+    fn low_power(&mut self) -> bool {
+        if matches!(self.last_interactive, Some(last) if last.elapsed() < INTERACTIVE_GRACE) {
+            return false;
+        }
+        match self.mode {
+            PowerMode::Auto => self.on_battery().unwrap_or(false),
+            PowerMode::LowPower => true,
+            PowerMode::Full => false,
+        }
+    }
+}
+
+impl PowerState {
+    /// Whether the daemon should save power right now.
+    pub fn low_power(&self) -> bool {
+        self.inner.lock().low_power()
+    }
+
+    pub fn set_mode(&self, mode: PowerMode) {
+        let mut inner = self.inner.lock();
+        let was_low = inner.low_power();
+        inner.mode = mode;
+        if was_low && !inner.low_power() {
+            self.woken.notify(usize::MAX);
+        }
+    }
+
+    /// Notes an RPC call, which lifts low-power mode for a while.
+    pub fn interactive(&self) {
+        let mut inner = self.inner.lock();
+        let was_low = inner.low_power();
+        inner.last_interactive = Some(Instant::now());
+        if was_low {
+            self.woken.notify(usize::MAX);
+        }
+    }
+
+    pub fn status(&self) -> PowerStatus {
+        let mut inner = self.inner.lock();
+        PowerStatus {
+            mode: inner.mode,
+            on_battery: inner.on_battery(),
+            low_power: inner.low_power(),
+        }
+    }
+}
+
+/// Works out whether the machine runs on battery from the power supplies listed in `dir`, given as the `type` and `status` files of each: it does if any battery is discharging. Returns None if there's no battery, or no such directory, as on anything but Linux.
+fn detect_battery(dir: &Path) -> Option<bool> {
+    let supplies = std::fs::read_dir(dir).ok()?.filter_map(|entry| {
+        let path = entry.ok()?.path();
+        let kind = std::fs::read_to_string(path.join("type")).ok()?;
+        let status = std::fs::read_to_string(path.join("status")).unwrap_or_default();
+        Some((kind, status))
+    });
+    on_battery(supplies)
+}
+
+fn on_battery(supplies: impl IntoIterator<Item = (String, String)>) -> Option<bool> {
+    let mut found = None;
+    for (kind, status) in supplies {
+        if kind.trim() == "Battery" {
+            if status.trim() == "Discharging" {
+                return Some(true);
+            }
+            found = Some(false);
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supply(kind: &str, status: &str) -> (String, String) {
+        (format!("{kind}\n"), format!("{status}\n"))
+    }
+
+    #[test]
+    fn battery_detection() {
+        assert_eq!(on_battery(vec![]), None);
+        assert_eq!(on_battery(vec![supply("Mains", "")]), None);
+        assert_eq!(
+            on_battery(vec![supply("Mains", ""), supply("Battery", "Charging")]),
+            Some(false)
+        );
+        assert_eq!(
+            on_battery(vec![
+                supply("Battery", "Full"),
+                supply("Battery", "Discharging")
+            ]),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn interactive_calls_lift_low_power() {
+        let power = PowerState::default();
+        power.set_mode(PowerMode::LowPower);
+        assert!(power.low_power());
+        power.interactive();
+        assert!(!power.low_power());
+        power.set_mode(PowerMode::Full);
+        assert!(!power.status().low_power);
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::BTreeSet;
+
+use crate::cli::Config;
+
+use super::types::Capabilities;
+
+/// Version of the JSON-RPC protocol served at `/v1`. Bumped, with a new endpoint, only when methods change incompatibly; methods added without breaking anything are announced through [Capabilities::methods] instead.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Path of the endpoint serving [PROTOCOL_VERSION] of the protocol. The root path stays an alias of it, for older clients.
+pub const ENDPOINT: &str = "/v1";
+
+/// Methods of [melwalletd_prot::MelwalletdProtocol], which this daemon serves in full.
+const UPSTREAM_METHODS: &[&str] = &[
+    "list_wallets",
+    "wallet_summary",
+    "latest_header",
+    "melswap_info",
+    "simulate_swap",
+    "create_wallet",
+    "dump_coins",
+    "dump_transactions",
+    "lock_wallet",
+    "unlock_wallet",
+    "export_sk",
+    "prepare_tx",
+    "send_tx",
+    "tx_balance",
+    "tx_status",
+    "send_faucet",
+];
+
+/// Names of the methods of [super::ext::MelwalletdExtProtocol], read from its definition so that the list can never fall out of date.
+fn ext_methods() -> impl Iterator<Item = &'static str> {
+    include_str!("ext.rs").lines().filter_map(|line| {
+        let rest = line.trim_start().strip_prefix("async fn ")?;
+        rest.split(['(', '<']).next()
+    })
+}
+
+/// Every method served.
+fn methods() -> BTreeSet<String> {
+    UPSTREAM_METHODS
+        .iter()
+        .copied()
+        .chain(ext_methods())
+        .map(String::from)
+        .collect()
+}
+
+/// Describes what a daemon with the given config supports.
+pub fn capabilities(config: &Config) -> Capabilities {
+    let features = [
+        ("backup", config.backup.is_some()),
+        ("mdns", config.mdns),
+        ("split_wallet_files", config.split_wallet_files),
+        ("read_connections", config.read_connections > 0),
+        ("offline", config.offline),
+    ]
+    .iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| feature.to_string())
+    .collect();
+    Capabilities {
+        protocol_version: PROTOCOL_VERSION,
+        daemon_version: env!("CARGO_PKG_VERSION").into(),
+        endpoint: ENDPOINT.into(),
+        methods: methods(),
+        features,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_methods() {
+        let methods = methods();
+        for method in [
+            "send_tx",
+            "prepare_tx_verbose",
+            "capabilities",
+            "address_forms",
+        ] {
+            assert!(methods.contains(method), "{} missing", method);
+        }
+        assert!(methods.iter().all(|m| m
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')));
+    }
+}
@@ -0,0 +1,933 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use async_trait::async_trait;
+use melstructs::{
+    BlockHeight, CoinData, CoinDataHeight, CoinID, CoinValue, Denom, NetID, Transaction, TxHash,
+};
+use melwalletd_prot::types::{
+    CreateWalletError, NeedWallet, NetworkError, PrepareTxError, WalletAccessError,
+};
+use nanorpc::nanorpc_derive;
+
+use super::types::{
+    AccountingFormat, AddressForms, AddressOwnership, AlertError, AlertThresholds, AnomalyError,
+    AnomalyPolicy, ApprovalError, BackupError, BackupInfo, BlockTime, BuildInfo, Capabilities,
+    CloneWalletError, CoinSelection, CoinSelectionPreview, ColdSigningError, ConfirmationOutcome,
+    ConsolidationPolicy, CovenantEvalError, CovenantEvaluation, DaemonStats, DataCoin, DataPayload,
+    DataPayloadError, DcaError, DcaJob, DcaRun, DiagnosticsBundle, DisplayPreferences,
+    DisplayPreferencesError, Escrow, EscrowError, EvaluateCovenantArgs, HeldSend, HistoryEntry,
+    HistoryExportError, ImportCoinError, InheritanceError, InheritanceStatus,
+    InternalTransferError, InvalidAddressError, Invoice, InvoiceError, JournalReplay,
+    KeyRotationStatus, LabeledCoin, LogLevel, LogRecord, MasterPassphraseError, MintRewardEstimate,
+    MintingInfo, NetworkDiagnostics, NetworkFees, PasswordStrength, PayloadTemplate,
+    PaymentProofError, PaymentUriError, PendingFilter, PendingPage, PendingPurge, PowerMode,
+    PowerStatus, PrepareTxArgs, PreparedTx, PreparedTxDetails, SecretsStatus, SendError,
+    SigningActivity, SigningBundle, SigningLimit, SigningLimitError, SigningRequest, SwapOrder,
+    SwapOrderError, SyncError, SyncSnapshotError, TimelockedCoin, TimelockedOutput, TotpEnrollment,
+    TotpError, TotpStatus, TrackedAddress, Trade, TradeError, TradeOffer, TradeTerms,
+    TransactionCacheStats, TransactionSearchHit, TrustedHeader, TxBalanceDetails, TxDecodeError,
+    UnitConversionError, UriHandlerInfo, VerifiedPayment, VerifyWalletsError, WalletAlert,
+    WalletDescriptor, WalletMismatch, WalletSyncSummary, WatchPackageError, WeakPasswordError,
+};
+
+#[nanorpc_derive]
+#[async_trait]
+/// A [macro@nanorpc_derive] trait describing the RPC methods melwalletd exposes in addition to [melwalletd_prot::MelwalletdProtocol].
+///
+/// Both protocols are served at the same endpoint. If a method exists in both, the one defined here takes precedence.
+pub trait MelwalletdExtProtocol: Send + Sync {
+    /// Estimates the strength of a password, and whether it satisfies the daemon's password policy.
+    async fn password_strength(&self, password: String) -> PasswordStrength;
+
+    /// Changes the password protecting a wallet's secret key. The new password must satisfy the daemon's password policy. If the old password is incorrect, will return [melwalletd_prot::types::WalletAccessError::Locked].
+    async fn change_password(
+        &self,
+        wallet_name: String,
+        old_password: String,
+        new_password: String,
+    ) -> Result<PasswordStrength, NeedWallet<WeakPasswordError>>;
+
+    /// Rotates the key of a standard wallet: a new key is generated, and all coins are swept to its address in batches. Once every sweep confirms, the wallet switches over to the new key, under the same password. The new key is kept in the secret store from the start, so that coins swept to it are as recoverable as the wallet's own. Calling this again on a wallet whose rotation is in progress re-sweeps any coins left at the old address.
+    async fn rotate_key(
+        &self,
+        wallet_name: String,
+        password: String,
+    ) -> Result<KeyRotationStatus, NeedWallet<NetworkError>>;
+
+    /// Returns the status of the in-progress key rotation of a wallet, or `Ok(None)` if the wallet is not being rotated.
+    async fn key_rotation_status(
+        &self,
+        wallet_name: String,
+    ) -> Result<Option<KeyRotationStatus>, WalletAccessError>;
+
+    /// Starts tracking the balance and coin count of an arbitrary address, without creating a wallet for it. Tracking an already-tracked address changes its label.
+    async fn track_address(
+        &self,
+        address: String,
+        label: String,
+    ) -> Result<(), InvalidAddressError>;
+
+    /// Stops tracking an address. Returns whether the address was tracked.
+    async fn untrack_address(&self, address: String) -> Result<bool, InvalidAddressError>;
+
+    /// Lists all tracked addresses, with their balances as of the latest sync.
+    async fn tracked_addresses(&self) -> Vec<TrackedAddress>;
+
+    /// Encodes a transaction in the hex-encoded binary (stdcode) format used by full nodes. This is exactly the encoding that is hashed and signed.
+    async fn encode_tx(&self, tx: Transaction) -> String;
+
+    /// Decodes a hex-encoded binary (stdcode) transaction, the inverse of [MelwalletdExtProtocol::encode_tx].
+    async fn decode_tx(&self, hex: String) -> Result<Transaction, TxDecodeError>;
+
+    /// Prepares a transaction according to a template (see [PrepareTxArgs]). Like [melwalletd_prot::MelwalletdProtocol::prepare_tx], but accepting the extended set of arguments, and returning the transaction's weight along with it. Transactions of wallets whose sends are checked carry placeholder signatures until sent; see [MelwalletdExtProtocol::set_approval_mode].
+    async fn prepare_tx(
+        &self,
+        wallet_name: String,
+        request: PrepareTxArgs,
+    ) -> Result<PreparedTx, NeedWallet<PrepareTxError>>;
+
+    /// Prepares one or more transactions that together pay the outputs of a template. If the outputs don't fit in a single transaction, they are split across several, which spend disjoint coins and can be sent in any order. Explicitly given inputs are all spent by the first transaction.
+    async fn prepare_split_tx(
+        &self,
+        wallet_name: String,
+        request: PrepareTxArgs,
+    ) -> Result<Vec<PreparedTx>, NeedWallet<PrepareTxError>>;
+
+    /// Sets the fee ballast used by [MelwalletdExtProtocol::prepare_tx] for this wallet when the request does not specify one.
+    async fn set_default_fee_ballast(
+        &self,
+        wallet_name: String,
+        fee_ballast: usize,
+    ) -> Result<(), WalletAccessError>;
+
+    /// Returns the default fee ballast of a wallet.
+    async fn default_fee_ballast(&self, wallet_name: String) -> Result<usize, WalletAccessError>;
+
+    /// Sets the denominations whose coins [MelwalletdExtProtocol::prepare_tx] never picks as inputs for this wallet when the request does not say otherwise, such as tokens the wallet should only ever hold.
+    async fn set_default_exclude_denoms(
+        &self,
+        wallet_name: String,
+        denoms: BTreeSet<Denom>,
+    ) -> Result<(), WalletAccessError>;
+
+    /// Returns the denominations a wallet never picks inputs of by default.
+    async fn default_exclude_denoms(
+        &self,
+        wallet_name: String,
+    ) -> Result<BTreeSet<Denom>, WalletAccessError>;
+
+    /// Sets or, given None, removes the consolidation policy of a wallet. While the wallet is unlocked and holds more coins than the policy allows, the daemon merges its smallest coins whenever the fee multiplier is low enough, so that preparing transactions stays fast.
+    async fn set_consolidation_policy(
+        &self,
+        wallet_name: String,
+        policy: Option<ConsolidationPolicy>,
+    ) -> Result<(), WalletAccessError>;
+
+    /// Returns the consolidation policy of a wallet, if it has one.
+    async fn consolidation_policy(
+        &self,
+        wallet_name: String,
+    ) -> Result<Option<ConsolidationPolicy>, WalletAccessError>;
+
+    /// Returns statistics about the transaction cache shared by all wallets.
+    async fn transaction_cache_stats(&self) -> TransactionCacheStats;
+
+    /// Removes cached transactions that no existing wallet refers to, returning how many were removed. Pruned transactions are fetched again from the node if needed, so pruning assumes that the node still serves them; one that no longer does, such as a pruning node, cannot give them back.
+    async fn prune_transaction_cache(&self) -> u64;
+
+    /// Creates a watch-only ("hot") wallet for a public key whose secret key is kept in a "cold" wallet elsewhere, typically on an offline machine. The public key is hex-encoded, as returned by [MelwalletdExtProtocol::export_public_key] on the cold side.
+    async fn create_watch_only_wallet(
+        &self,
+        wallet_name: String,
+        public_key: String,
+    ) -> Result<(), CreateWalletError>;
+
+    /// Returns the hex-encoded public key of a wallet, for creating its watch-only counterpart.
+    async fn export_public_key(
+        &self,
+        wallet_name: String,
+        password: String,
+    ) -> Result<String, WalletAccessError>;
+
+    /// Prepares a transaction from a watch-only wallet, like [MelwalletdExtProtocol::prepare_tx], but leaves it unsigned. The transaction starts the cold-signing workflow in the [crate::protocol::types::SigningStatus::Prepared] state, and the returned request lists the hash each input's signature must sign.
+    async fn prepare_unsigned_tx(
+        &self,
+        wallet_name: String,
+        request: PrepareTxArgs,
+    ) -> Result<SigningRequest, NeedWallet<PrepareTxError>>;
+
+    /// Exports a prepared transaction of a watch-only wallet for its cold wallet to sign.
+    async fn export_signing_bundle(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+    ) -> Result<SigningBundle, NeedWallet<ColdSigningError>>;
+
+    /// Signs a bundle exported by a watch-only wallet, using the (unlocked) cold wallet holding the key. Needs no network access, and changes nothing in the cold wallet.
+    async fn sign_bundle(
+        &self,
+        wallet_name: String,
+        bundle: SigningBundle,
+    ) -> Result<Transaction, NeedWallet<ColdSigningError>>;
+
+    /// Imports a transaction signed by the cold wallet. Every signature is checked against the wallet's public key.
+    async fn import_signed_tx(
+        &self,
+        wallet_name: String,
+        tx: Transaction,
+    ) -> Result<SigningRequest, NeedWallet<ColdSigningError>>;
+
+    /// Broadcasts a signed transaction of a watch-only wallet, after which it is tracked like any sent transaction.
+    async fn broadcast_signed_tx(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+    ) -> Result<TxHash, NeedWallet<ColdSigningError>>;
+
+    /// Completes a transaction prepared by [MelwalletdExtProtocol::prepare_unsigned_tx] with hex-encoded signatures, one per input over that input's entry in [crate::protocol::types::SigningRequest::sighashes], then broadcasts it. This lets clients that hold their own keys, such as browser extensions, use the daemon only for coin selection, balancing and broadcasting.
+    async fn submit_signatures(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+        sigs: Vec<String>,
+    ) -> Result<TxHash, NeedWallet<ColdSigningError>>;
+
+    /// Lists the transactions of a watch-only wallet in the cold-signing workflow.
+    async fn signing_requests(
+        &self,
+        wallet_name: String,
+    ) -> Result<Vec<SigningRequest>, WalletAccessError>;
+
+    /// Abandons a transaction in the cold-signing workflow. Returns whether there was such a transaction. Transactions already broadcast cannot be recalled this way; only the record is removed.
+    async fn discard_signing_request(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+    ) -> Result<bool, WalletAccessError>;
+
+    /// Returns operational statistics about the daemon. These are only ever reported through this call; nothing is sent anywhere.
+    async fn daemon_stats(&self) -> DaemonStats;
+
+    /// Imports an unspent coin that the wallet's sync doesn't pick up, because it is guarded by a covenant other than the wallet's own (for example, an old-style covenant of the same key). The covenant, hex-encoded, is needed unless the coin is at the wallet's own address. The wallet must be unlocked, so that it can check that it is actually able to spend the coin.
+    async fn import_coin(
+        &self,
+        wallet_name: String,
+        coin_id: CoinID,
+        covenant: Option<String>,
+    ) -> Result<CoinDataHeight, NeedWallet<ImportCoinError>>;
+
+    /// Runs a covenant against a transaction locally, for debugging covenants against real transactions. Returns whether the covenant passes, along with a trace of the instructions it ran. Covenants heavier than [MAX_EVAL_WEIGHT] are refused, and nothing is sent to the network.
+    async fn evaluate_covenant(
+        &self,
+        request: EvaluateCovenantArgs,
+    ) -> Result<CovenantEvaluation, CovenantEvalError>;
+
+    /// Formats a raw value of a denomination (such as `MEL`, or `CUSTOM-...`) in display units, according to the daemon's token registry. For example, 1500000 raw MEL is `"1.5"` MEL.
+    async fn to_display_units(
+        &self,
+        denom: String,
+        raw: CoinValue,
+    ) -> Result<String, UnitConversionError>;
+
+    /// Parses a value in display units into a raw value, the inverse of [MelwalletdExtProtocol::to_display_units]. Values more precise than the denomination allows are rejected rather than rounded.
+    #[allow(clippy::wrong_self_convention)]
+    async fn from_display_units(
+        &self,
+        denom: String,
+        display: String,
+    ) -> Result<CoinValue, UnitConversionError>;
+
+    /// Waits until a transaction of a wallet confirms or is lost, or until `timeout_secs` (at most [MAX_CONFIRMATION_WAIT_SECS]) elapse. Unlike polling [melwalletd_prot::MelwalletdProtocol::tx_status], this returns as soon as the daemon's sync loop sees the change.
+    async fn wait_for_confirmation(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+        timeout_secs: u64,
+    ) -> Result<ConfirmationOutcome, WalletAccessError>;
+
+    /// Cancels an RPC call in flight. Any call, except those of [SENDING_METHODS], can be made cancellable by sending a handle of the client's choosing in the [CANCEL_HANDLE_HEADER] HTTP header along with it; cancelling it with that handle stops it where it is, releasing whatever it holds, and makes it fail with error code [CANCELLED_ERROR_CODE]. Returns whether a call with that handle was in flight.
+    async fn cancel(&self, handle: String) -> bool;
+
+    /// Creates an invoice for a payment to a wallet. A payment counts towards the invoice if it is an output to the wallet's address of the right denomination, whose `additional_data` is the invoice's memo. Once confirmed payments add up to the amount, the invoice is paid and its webhook, if any, is sent the invoice as an HTTP POST. Unpaid invoices expire after `expires_in_secs`.
+    async fn create_invoice(
+        &self,
+        wallet_name: String,
+        amount: CoinValue,
+        denom: String,
+        expires_in_secs: u64,
+        webhook: Option<String>,
+    ) -> Result<Invoice, InvoiceError>;
+
+    /// Returns the current state of an invoice, or `null` if there is no invoice with that ID.
+    async fn invoice_status(&self, invoice_id: String) -> Option<Invoice>;
+
+    /// Lists all invoices of a wallet, oldest first.
+    async fn list_invoices(&self, wallet_name: String) -> Result<Vec<Invoice>, WalletAccessError>;
+
+    /// Lists the wallet types registered with the daemon. Ordinary wallets are of type `"standard"`.
+    async fn wallet_types(&self) -> Vec<String>;
+
+    /// Creates a wallet of a registered type, like [melwalletd_prot::MelwalletdProtocol::create_wallet]. The hex-encoded `params` are specific to the wallet type, and fixed for the lifetime of the wallet.
+    async fn create_typed_wallet(
+        &self,
+        wallet_name: String,
+        kind: String,
+        password: String,
+        secret: Option<String>,
+        params: String,
+    ) -> Result<(), CreateWalletError>;
+
+    /// Returns the type of a wallet.
+    async fn wallet_type(&self, wallet_name: String) -> Result<String, WalletAccessError>;
+
+    /// Prepares a transaction paying outputs that their recipients can only spend from `unlock_height` onwards. The daemon follows the timelocked coins, and recipients that are wallets here get them in their balance once they mature. The transaction still needs to be sent with [melwalletd_prot::MelwalletdProtocol::send_tx].
+    async fn prepare_timelocked_tx(
+        &self,
+        wallet_name: String,
+        outputs: Vec<TimelockedOutput>,
+        unlock_height: BlockHeight,
+    ) -> Result<Transaction, NeedWallet<PrepareTxError>>;
+
+    /// Lists the timelocked coins sent by or to a wallet, in order of unlock height.
+    async fn timelocked_coins(
+        &self,
+        wallet_name: String,
+    ) -> Result<Vec<TimelockedCoin>, WalletAccessError>;
+
+    /// Sets up a dead-man switch for a wallet: if the wallet is not unlocked (or pinged) for `inactivity_secs`, the daemon broadcasts pre-signed transactions sweeping it to a recovery address. The sweeps are signed now, and re-signed whenever the wallet is unlocked and its coins have changed, so coins received while the wallet stays locked are not covered. Reconfiguring restarts the timer.
+    async fn configure_inheritance(
+        &self,
+        wallet_name: String,
+        password: String,
+        recovery_address: String,
+        inactivity_secs: u64,
+    ) -> Result<InheritanceStatus, NeedWallet<InheritanceError>>;
+
+    /// Restarts the timer of a wallet's dead-man switch without unlocking the wallet, re-signing its sweeps.
+    async fn ping_inheritance(
+        &self,
+        wallet_name: String,
+        password: String,
+    ) -> Result<InheritanceStatus, NeedWallet<InheritanceError>>;
+
+    /// Removes the dead-man switch of a wallet. Returns whether it had one.
+    async fn cancel_inheritance(
+        &self,
+        wallet_name: String,
+        password: String,
+    ) -> Result<bool, WalletAccessError>;
+
+    /// Returns the state of a wallet's dead-man switch, or `null` if it doesn't have one.
+    async fn inheritance_status(
+        &self,
+        wallet_name: String,
+    ) -> Result<Option<InheritanceStatus>, WalletAccessError>;
+
+    /// Returns what a desktop integrator needs to register the daemon as the local handler of payment URIs (such as `mel:<address>?amount=1.5`).
+    async fn uri_handler_info(&self) -> UriHandlerInfo;
+
+    /// Turns a payment URI into arguments for [MelwalletdExtProtocol::prepare_tx], paying the output it asks for from the given wallet. The amount in the URI is in display units (see [MelwalletdExtProtocol::from_display_units]).
+    async fn parse_payment_uri(
+        &self,
+        wallet_name: String,
+        uri: String,
+    ) -> Result<PrepareTxArgs, NeedWallet<PaymentUriError>>;
+
+    /// Exports a descriptor of a wallet: its type, public key, key origin, covenant and imported covenants, from which other tooling can reconstruct a watch-only view of the wallet. Needs no password, since it reveals nothing secret.
+    async fn export_descriptor(
+        &self,
+        wallet_name: String,
+    ) -> Result<WalletDescriptor, WalletAccessError>;
+
+    /// Takes an encrypted backup of the secrets and the database, and uploads it to the configured object storage.
+    async fn backup_now(&self) -> Result<BackupInfo, BackupError>;
+
+    /// Lists the backups in object storage, oldest first.
+    async fn list_backups(&self) -> Result<Vec<String>, BackupError>;
+
+    /// Downloads and decrypts a backup, checking the integrity of everything in it, without restoring it.
+    async fn verify_backup(&self, name: String) -> Result<BackupInfo, BackupError>;
+
+    /// Verifies a backup, then stages it to replace the current database and secrets. The backup is restored when the daemon restarts; whatever it replaces is set aside rather than deleted. The restored secrets are protected by the current master passphrase, if one is set, so the secret store must be unsealed.
+    async fn restore_backup(&self, name: String) -> Result<BackupInfo, BackupError>;
+
+    /// Restores send history from the journal of sent transactions, which is kept outside the database, after the database was lost or rebuilt. If `wallet_name` is given, only that wallet's history is restored.
+    async fn rebuild_from_journal(
+        &self,
+        wallet_name: Option<String>,
+    ) -> Result<JournalReplay, WalletAccessError>;
+
+    /// Sets up anomaly detection for a wallet, or turns it off given `null`. Once set up, transactions sent with `send_tx` that make the wallet's send count, or volume of any token, over the last `window_secs` exceed its baseline are not broadcast, but held until confirmed with [MelwalletdExtProtocol::confirm_held_send], and an alert is POSTed to the policy's webhook, if any. Needs the wallet's password, so that whoever can merely send from the wallet cannot loosen its policy.
+    async fn set_anomaly_policy(
+        &self,
+        wallet_name: String,
+        password: String,
+        policy: Option<AnomalyPolicy>,
+    ) -> Result<(), NeedWallet<AnomalyError>>;
+
+    /// Returns the anomaly policy of a wallet, or `null` if it doesn't have one.
+    async fn anomaly_policy(
+        &self,
+        wallet_name: String,
+    ) -> Result<Option<AnomalyPolicy>, WalletAccessError>;
+
+    /// Lists the transactions of a wallet that were held back rather than broadcast, oldest first.
+    async fn list_held_sends(
+        &self,
+        wallet_name: String,
+    ) -> Result<Vec<HeldSend>, WalletAccessError>;
+
+    /// Broadcasts a held transaction, checking the wallet's password.
+    async fn confirm_held_send(
+        &self,
+        wallet_name: String,
+        password: String,
+        txhash: TxHash,
+    ) -> Result<TxHash, NeedWallet<AnomalyError>>;
+
+    /// Drops a held transaction without broadcasting it. Returns whether there was such a transaction.
+    async fn discard_held_send(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+    ) -> Result<bool, WalletAccessError>;
+
+    /// Puts a wallet in approval mode, or takes it out of it. In approval mode, transactions sent with `send_tx` are not broadcast, but queued until approved with [MelwalletdExtProtocol::approve_send]. Queued transactions are listed with [MelwalletdExtProtocol::list_held_sends].
+    ///
+    /// Enabling approval mode returns a fresh approver token, which approving and rejecting needs instead of the wallet's password, so that whoever sends from the wallet need not be able to approve. The token is only shown once; enabling approval mode again replaces it. Once approval mode is on, taking the wallet out of it or replacing the token also needs the current `approver_token`, so that the wallet's password alone cannot get around the approver.
+    ///
+    /// Transactions prepared for a wallet whose sends are checked, by approval mode, TOTP or an anomaly policy, carry placeholder signatures, and are only signed once sent with `send_tx` and let through, or released; so they can't be broadcast elsewhere, and the wallet must be unlocked to release them. Signatures handed to others on purpose, by [MelwalletdExtProtocol::accept_trade], [MelwalletdExtProtocol::sign_bundle] or escrow signing, are not checked.
+    async fn set_approval_mode(
+        &self,
+        wallet_name: String,
+        password: String,
+        enabled: bool,
+        approver_token: Option<String>,
+    ) -> Result<Option<String>, NeedWallet<ApprovalError>>;
+
+    /// Broadcasts a transaction awaiting approval.
+    async fn approve_send(
+        &self,
+        wallet_name: String,
+        approver_token: String,
+        txhash: TxHash,
+    ) -> Result<TxHash, NeedWallet<ApprovalError>>;
+
+    /// Drops a transaction awaiting approval without broadcasting it.
+    async fn reject_send(
+        &self,
+        wallet_name: String,
+        approver_token: String,
+        txhash: TxHash,
+    ) -> Result<(), NeedWallet<ApprovalError>>;
+
+    /// Starts enrolling a wallet in TOTP protection, returning a fresh secret for an authenticator app. Nothing needs a code until the enrollment is activated with [MelwalletdExtProtocol::activate_totp]; enrolling again before then replaces the secret.
+    ///
+    /// Once active, `export_sk` and `change_password` refuse to work, and so does `send_tx` for transactions sending more than `send_threshold` micromel, or any amount of another token, to other addresses. Their `_with_totp` counterparts must be used instead, with a code that hasn't been used before. After a few wrong codes in a row, the wallet's codes are refused for periods that double with every further wrong code.
+    async fn enable_totp(
+        &self,
+        wallet_name: String,
+        password: String,
+        send_threshold: CoinValue,
+    ) -> Result<TotpEnrollment, NeedWallet<TotpError>>;
+
+    /// Activates a wallet's TOTP protection, given a code from the authenticator app that was enrolled.
+    async fn activate_totp(
+        &self,
+        wallet_name: String,
+        totp_code: String,
+    ) -> Result<TotpStatus, NeedWallet<TotpError>>;
+
+    /// Removes a wallet's TOTP protection. If it is active, a code is needed.
+    async fn disable_totp(
+        &self,
+        wallet_name: String,
+        password: String,
+        totp_code: String,
+    ) -> Result<(), NeedWallet<TotpError>>;
+
+    /// Returns the TOTP protection of a wallet, or `null` if it has none.
+    async fn totp_status(
+        &self,
+        wallet_name: String,
+    ) -> Result<Option<TotpStatus>, WalletAccessError>;
+
+    /// Like [melwalletd_prot::MelwalletdProtocol::export_sk], for wallets with TOTP protection.
+    async fn export_sk_with_totp(
+        &self,
+        wallet_name: String,
+        password: String,
+        totp_code: String,
+    ) -> Result<String, NeedWallet<TotpError>>;
+
+    /// Like [MelwalletdExtProtocol::change_password], for wallets with TOTP protection.
+    async fn change_password_with_totp(
+        &self,
+        wallet_name: String,
+        old_password: String,
+        new_password: String,
+        totp_code: String,
+    ) -> Result<PasswordStrength, NeedWallet<TotpError>>;
+
+    /// Sends a transaction. Like [melwalletd_prot::MelwalletdProtocol::send_tx], but telling a transaction held back by the wallet's approval mode or anomaly policy, or one that needs a TOTP code, apart from one that failed to send.
+    async fn send_tx(
+        &self,
+        wallet_name: String,
+        tx: Transaction,
+    ) -> Result<TxHash, NeedWallet<SendError>>;
+
+    /// Like [melwalletd_prot::MelwalletdProtocol::send_tx], for wallets with TOTP protection. The transaction may still be held by the wallet's approval mode or anomaly policy.
+    async fn send_tx_with_totp(
+        &self,
+        wallet_name: String,
+        tx: Transaction,
+        totp_code: String,
+    ) -> Result<TxHash, NeedWallet<TotpError>>;
+
+    /// Gets the current Melmint economics from the latest block: the DOSC speed, the difficulty of a DOSC, and the ERG/MEL exchange rate.
+    async fn minting_info(&self) -> Result<MintingInfo, NetworkError>;
+
+    /// Estimates the reward for a minting proof of the given difficulty that takes the given number of blocks to compute, at the latest block's DOSC speed.
+    async fn estimate_mint_reward(
+        &self,
+        difficulty: u32,
+        blocks: u64,
+    ) -> Result<MintRewardEstimate, NetworkError>;
+
+    /// Reports fee conditions before a payment is composed: the fee multiplier of the latest block, those of the last `blocks` blocks (at most [MAX_FEE_HISTORY_BLOCKS]) and which way they are heading, and the fee of a typical payment.
+    async fn network_fees(&self, blocks: u64) -> Result<NetworkFees, NetworkError>;
+
+    /// Like [melwalletd_prot::MelwalletdProtocol::dump_transactions], but with the time of the block confirming each transaction.
+    async fn transaction_history(
+        &self,
+        wallet_name: String,
+    ) -> Result<Vec<HistoryEntry>, WalletAccessError>;
+
+    /// Returns the time of the block confirming a transaction in a wallet's history, to go along with [melwalletd_prot::MelwalletdProtocol::tx_status]. Returns None if the transaction isn't in the history or is still pending.
+    async fn tx_time(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+    ) -> Result<Option<BlockTime>, WalletAccessError>;
+
+    /// Exports the transaction history of a wallet as CSV, with a header row and the columns `txhash`, `height`, `time` (in RFC 3339 form, UTC) and `time_estimated`. Pending transactions have empty `height` and `time` columns.
+    async fn export_history_csv(&self, wallet_name: String) -> Result<String, WalletAccessError>;
+
+    /// Exports the confirmed transactions of a wallet that moved its balance of one denomination, for importing into accounting software such as GnuCash or Quicken. Amounts are net changes of the balance, fee included, in display units. Each denomination a wallet holds is a separate account; OFX statements identify it as `<wallet name>:<denom>`, and end with the balance the exported transactions add up to.
+    async fn export_history(
+        &self,
+        wallet_name: String,
+        format: AccountingFormat,
+        denom: String,
+    ) -> Result<String, HistoryExportError>;
+
+    /// Searches the transaction history of all wallets for transactions matching every word of a query. Words match transaction hash prefixes, memo text, counterparty addresses, amounts and token names. Returns at most `limit` hits, best matches first.
+    ///
+    /// Transactions are indexed as wallets sync, so a freshly restored wallet's older history becomes searchable over several sync rounds.
+    async fn search_transactions(&self, query: String, limit: usize) -> Vec<TransactionSearchHit>;
+
+    /// Lists the confirmed unspent coins of a wallet, along with their labels.
+    async fn list_coins(&self, wallet_name: String) -> Result<Vec<LabeledCoin>, WalletAccessError>;
+
+    /// Replaces the labels of a coin of a wallet, such as "salary" or "exchange withdrawal". Labels let [MelwalletdExtProtocol::prepare_tx] restrict which coins it spends. An empty set removes all labels.
+    async fn set_coin_labels(
+        &self,
+        wallet_name: String,
+        coin_id: CoinID,
+        labels: BTreeSet<String>,
+    ) -> Result<(), WalletAccessError>;
+
+    /// Prepares a transaction like [MelwalletdExtProtocol::prepare_tx], but also explains it: why each input was picked, which outputs are change, and how the fee was calculated.
+    async fn prepare_tx_verbose(
+        &self,
+        wallet_name: String,
+        request: PrepareTxArgs,
+    ) -> Result<PreparedTxDetails, NeedWallet<PrepareTxError>>;
+
+    /// Lists wallets whose secret key survives in the secrets file, but which are missing from the database, for example because the database file was lost. Since networks share the secrets file, wallets in the database of another network are not listed.
+    async fn recoverable_wallets(&self) -> Vec<String>;
+
+    /// Recreates wallets missing from the database from their secret keys in the secrets file, given the password of each, keyed by wallet name. Wallets are recreated as standard wallets, and fully rescanned from the blockchain by the next sync. Returns the address of each recovered wallet; wallets without a password, or with a wrong one, are skipped.
+    async fn recover_wallets_from_secrets(
+        &self,
+        passwords: BTreeMap<String, String>,
+    ) -> BTreeMap<String, String>;
+
+    /// Converts an address in any accepted encoding (the standard `t...` form, with or without dashes and in either case; 64 hex digits; or bech32 with the `mel` prefix) into all of them. Methods of this protocol that take addresses accept every one of these encodings.
+    async fn address_forms(&self, address: String) -> Result<AddressForms, InvalidAddressError>;
+
+    /// Describes what this daemon supports: the protocol version, every method it serves, and the optional features it has enabled. Clients should check this rather than assume a method exists.
+    async fn capabilities(&self) -> Capabilities;
+
+    /// Exports a wallet's unspent coins as of the height it has synced to, as a compact snapshot signed by the wallet's key. Importing the snapshot into the same wallet on another machine, with [MelwalletdExtProtocol::import_sync_snapshot], spares it a full resync, as long as the snapshot is imported within about 1,000 blocks of being taken.
+    async fn export_sync_snapshot(
+        &self,
+        wallet_name: String,
+        password: String,
+    ) -> Result<String, NeedWallet<SyncSnapshotError>>;
+
+    /// Imports a snapshot made by [MelwalletdExtProtocol::export_sync_snapshot], replacing what the wallet knows about its coins. The snapshot must be signed by the wallet's key, and be newer than what the wallet has already synced. Returns the height the wallet is now synced to; syncing carries on from there.
+    async fn import_sync_snapshot(
+        &self,
+        wallet_name: String,
+        snapshot: String,
+    ) -> Result<BlockHeight, NeedWallet<SyncSnapshotError>>;
+
+    /// Exports a wallet's covenant and history, as of the height it has synced to, as a compact package signed by the wallet's key. Importing it into another daemon with [MelwalletdExtProtocol::import_watch_package] gives, say, an accountant a wallet showing the same balances and history, which cannot sign anything. The package should be imported within about 1,000 blocks of being exported; after that, the imported wallet resyncs from scratch and only keeps its unspent coins.
+    async fn export_watch_package(
+        &self,
+        wallet_name: String,
+        password: String,
+    ) -> Result<String, NeedWallet<WatchPackageError>>;
+
+    /// Creates a wallet from a package made by [MelwalletdExtProtocol::export_watch_package]. The wallet has no key, so it can only be watched, and syncs onwards from the package's height. Returns that height.
+    async fn import_watch_package(
+        &self,
+        wallet_name: String,
+        package: String,
+    ) -> Result<BlockHeight, WatchPackageError>;
+
+    /// Proposes a peer-to-peer trade of some of a wallet's tokens for some of the counterparty's, settled atomically by a single transaction spending coins of both. Returns an offer holding the wallet's half of the transaction, unsigned, to hand to the counterparty, who completes it with [MelwalletdExtProtocol::accept_trade] and hands it back for [MelwalletdExtProtocol::complete_trade]. The counterparty pays the fee.
+    async fn propose_trade(
+        &self,
+        wallet_name: String,
+        terms: TradeTerms,
+    ) -> Result<TradeOffer, NeedWallet<TradeError>>;
+
+    /// Accepts a trade offer made to a wallet, adding the wallet's coins, change and the fee to the offered transaction and signing the wallet's inputs. The offer is checked to give the wallet exactly what its terms say, and the result to take from it only what they say plus the fee. Returns the half-signed transaction, to hand back to the proposer.
+    async fn accept_trade(
+        &self,
+        wallet_name: String,
+        offer: TradeOffer,
+    ) -> Result<Transaction, NeedWallet<TradeError>>;
+
+    /// Completes a trade proposed with [MelwalletdExtProtocol::propose_trade], given the transaction returned by the counterparty's [MelwalletdExtProtocol::accept_trade]. After checking that the proposing wallet gains and loses exactly what the terms say, signs its inputs and sends the transaction.
+    async fn complete_trade(
+        &self,
+        trade_id: String,
+        tx: Transaction,
+    ) -> Result<TxHash, NeedWallet<TradeError>>;
+
+    /// Lists the trades proposed by a wallet, oldest first.
+    async fn list_trades(&self, wallet_name: String) -> Result<Vec<Trade>, WalletAccessError>;
+
+    /// Builds an output carrying a payload made from one of the daemon's templates, such as an NFT or a registry entry, in its `additional_data`. Pass it to [MelwalletdExtProtocol::prepare_tx] like any other output. Coins carrying payloads are indexed as wallets sync, and listed by [MelwalletdExtProtocol::data_coins].
+    async fn build_data_output(
+        &self,
+        address: String,
+        payload: DataPayload,
+        denom: String,
+        value: CoinValue,
+    ) -> Result<CoinData, DataPayloadError>;
+
+    /// Lists a wallet's coins carrying payloads built from the daemon's templates, spent or not, optionally only those of one template.
+    async fn data_coins(
+        &self,
+        wallet_name: String,
+        template: Option<PayloadTemplate>,
+    ) -> Result<Vec<DataCoin>, WalletAccessError>;
+
+    /// Produces a proof that a confirmed transaction in a wallet's history paid a particular output, for settling disputes. The proof discloses that one transaction and nothing else of the wallet: it holds the transaction, the header of its block with a Merkle proof of the transaction, and a Merkle proof of that header in the history of the latest block, its anchor. Anyone can check it with [MelwalletdExtProtocol::verify_payment_proof].
+    async fn prove_payment(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+        output_index: u8,
+    ) -> Result<String, PaymentProofError>;
+
+    /// Verifies a proof made by [MelwalletdExtProtocol::prove_payment], returning the payment it shows. The proof's anchor must match `trusted`, the hash of a header the caller trusts, such as one read off their own node; given `null`, the anchor is checked against the blockchain as this daemon sees it. Needs no wallet.
+    async fn verify_payment_proof(
+        &self,
+        proof: String,
+        trusted: Option<TrustedHeader>,
+    ) -> Result<VerifiedPayment, PaymentProofError>;
+
+    /// Sets whether the daemon saves power. In low-power mode, which by default is on while the machine runs on battery, wallets sync every few minutes rather than every block, fewer database connections are kept open, and transaction indexing and coin consolidation are paused. Any RPC call other than this one and [MelwalletdExtProtocol::power_status] lifts low-power mode for a few minutes, and wallets catch up at once.
+    async fn set_power_mode(&self, mode: PowerMode);
+
+    /// Shows whether the daemon is saving power, and why.
+    async fn power_status(&self) -> PowerStatus;
+
+    /// Checks the wallets in the database against the keys in the secret store, listing wallets left without a key by a failed creation, and keys whose wallet isn't in the database. With `repair`, wallets without a key, which never received a coin, are removed; keys are never removed, but their wallets can be recreated with [MelwalletdExtProtocol::recover_wallets_from_secrets].
+    async fn verify_wallets(&self, repair: bool)
+        -> Result<Vec<WalletMismatch>, VerifyWalletsError>;
+
+    /// Describes exactly how this daemon was built: the commit, build time, toolchain and the versions of consensus-critical crates. Include it when reporting bugs, since some only affect particular versions.
+    async fn build_info(&self) -> BuildInfo;
+
+    /// Prepares unsigned transactions sweeping every confirmed, unspent coin of a wallet to a destination, without unlocking the wallet, for when the machine the daemon runs on may be compromised. Each transaction starts the cold-signing workflow like [MelwalletdExtProtocol::prepare_unsigned_tx]: export it with [MelwalletdExtProtocol::export_signing_bundle], sign it offline, and bring it back with [MelwalletdExtProtocol::import_signed_tx] or [MelwalletdExtProtocol::submit_signatures]. Wallets with many coins are swept in several transactions. Fees are set well above the current ones, since offline signing may take a while.
+    async fn prepare_emergency_sweep(
+        &self,
+        wallet_name: String,
+        destination: String,
+    ) -> Result<Vec<SigningRequest>, NeedWallet<ColdSigningError>>;
+
+    /// Reports whether the secret store is protected by a master passphrase, and whether it is still sealed.
+    async fn secrets_status(&self) -> SecretsStatus;
+
+    /// Unseals the secret store with the master passphrase. A daemon whose secret store has a master passphrase starts sealed, unless the passphrase is given at startup, and serves nothing but this method, [MelwalletdExtProtocol::secrets_status], [MelwalletdExtProtocol::reload_secrets], [MelwalletdExtProtocol::capabilities] and [MelwalletdExtProtocol::build_info] until unsealed. Declared wallets with keys, which can't be provisioned at startup while sealed, are provisioned on unsealing; failing to provision them fails the call, but leaves the store unsealed.
+    async fn unseal_secrets(&self, passphrase: String) -> Result<(), MasterPassphraseError>;
+
+    /// Re-reads the secret store from disk, so that secrets restored or edited while the daemon runs, including an old-style `.secrets.json` file, become usable without a restart. If the restored files are encrypted under a different master passphrase, the store becomes sealed again. Can be called while sealed.
+    async fn reload_secrets(&self) -> Result<SecretsStatus, MasterPassphraseError>;
+
+    /// Sets, changes or, given no new passphrase, removes the master passphrase that encrypts the whole secret store at rest, including wallets without a password. If a master passphrase is already set, it must be given as `current`. Backups hold the secrets encrypted under the backup passphrase instead, so a restored backup has no master passphrase.
+    async fn set_master_passphrase(
+        &self,
+        current: Option<String>,
+        new: Option<String>,
+    ) -> Result<(), MasterPassphraseError>;
+
+    /// Caps how often a wallet may sign while unlocked, or removes the cap given `null`. Every transaction prepared with the wallet's unlocked key counts as a signature; one over `max_signatures` in `window_secs` is refused, relocks the wallet so that it cannot be unlocked again for `lockout_secs`, and POSTs an alarm to the limit's webhook, if any. Needs the wallet's password, and lifts any lockout in progress.
+    async fn set_signing_limit(
+        &self,
+        wallet_name: String,
+        password: String,
+        limit: Option<SigningLimit>,
+    ) -> Result<(), NeedWallet<SigningLimitError>>;
+
+    /// Reports how much a wallet has signed lately, against its signing limit.
+    async fn signing_activity(
+        &self,
+        wallet_name: String,
+    ) -> Result<SigningActivity, WalletAccessError>;
+
+    /// Like [melwalletd_prot::MelwalletdProtocol::tx_balance], but with the fee set apart from the MEL that moved: lists the value of the outputs the wallet paid for and received, by denom, the fee it paid, and the net change in its balance, fee included.
+    async fn tx_balance_details(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+    ) -> Result<Option<TxBalanceDetails>, WalletAccessError>;
+
+    /// Reports which full node the daemon is connected to, whether it was picked by probing the bootstrap nodes, and the latency and height of every node in the latest probes, which are repeated every ten minutes.
+    async fn network_diagnostics(&self) -> NetworkDiagnostics;
+
+    /// Like [melwalletd_prot::MelwalletdProtocol::wallet_summary], along with the height the wallet last synced to and whether its balances may be outdated, so that clients can warn about stale balances rather than show them as current, the wallet's display preferences, and whether it can sign. A wallet is stale if it never synced, is more than [STALE_AFTER_BLOCKS] blocks behind, or the latest block cannot be learned.
+    async fn wallet_sync_summary(
+        &self,
+        wallet_name: String,
+    ) -> Result<WalletSyncSummary, WalletAccessError>;
+
+    /// Lists the latest failures to sync a wallet, newest first, such as timeouts or coins the node reported inconsistently. Only the last few dozen are kept.
+    async fn sync_errors(&self, wallet_name: String) -> Result<Vec<SyncError>, WalletAccessError>;
+
+    /// Shows which coins a transaction with the given outputs would spend under a coin selection strategy, or under each strategy given `null`, along with the change and fee that would result. Nothing is signed or recorded, and the wallet needn't be unlocked. Pick a strategy for real with [PrepareTxArgs::coin_selection].
+    async fn preview_coin_selection(
+        &self,
+        wallet_name: String,
+        outputs: Vec<CoinData>,
+        strategy: Option<CoinSelection>,
+    ) -> Result<Vec<CoinSelectionPreview>, NeedWallet<PrepareTxError>>;
+
+    /// Forgets the wallet's pending transactions that have expired, releasing the coins they spent, without waiting for the next sync to do so. The response lists what was purged and how many transactions remain pending, so that scripts can check the wallet is clean before sending again.
+    async fn purge_expired(
+        &self,
+        wallet_name: String,
+    ) -> Result<PendingPurge, NeedWallet<NetworkError>>;
+
+    /// Checks whether an address, in any accepted encoding, belongs to the given wallet, or to any wallet if `wallet_name` is null. This tells internal transfers apart from payments to outsiders.
+    async fn is_mine(
+        &self,
+        wallet_name: Option<String>,
+        address: String,
+    ) -> Result<AddressOwnership, NeedWallet<InvalidAddressError>>;
+
+    /// Returns the latest `limit` records the daemon logged at `level` or above (all levels if null), oldest first. Only the latest few thousand records logged are kept, and only those `RUST_LOG` lets through. Since the log is the whole daemon's, users of a multi-user daemon can't call this or [MelwalletdExtProtocol::follow_logs].
+    async fn tail_logs(&self, level: Option<LogLevel>, limit: usize) -> Vec<LogRecord>;
+
+    /// Streams log records: returns the records at `level` or above logged after sequence number `after` (or every record kept, if null), waiting up to `timeout_secs` (at most [MAX_LOG_WAIT_SECS]) for one if there are none yet. Calling this again with the last sequence number returned follows the log as it is written.
+    async fn follow_logs(
+        &self,
+        after: Option<u64>,
+        level: Option<LogLevel>,
+        timeout_secs: u64,
+    ) -> Vec<LogRecord>;
+
+    /// Copies a wallet's key into a new wallet named `dest` in the database of another network, such as testnet, so that mainnet operations can be rehearsed there with the same address. The clone is protected by the same password, and shows up in the daemon of that network using the same wallet directory. That network's database must already exist, with the schema of this version; since its daemon may be running, it is never migrated or repaired from here. Since the secrets file is shared between networks, `dest` must not name a wallet of any network. Returns the clone's address.
+    async fn clone_wallet(
+        &self,
+        source: String,
+        password: String,
+        dest: String,
+        target_network: NetID,
+    ) -> Result<String, NeedWallet<CloneWalletError>>;
+
+    /// Gathers build and node information, every wallet's sync height and pending transactions, and up to `log_limit` (at most [crate::diagnostics::MAX_DIAGNOSTIC_LOGS]) of the latest log records into one bundle to attach to a bug report. Secrets are never included, and neither are log records for users of a multi-user daemon, since the log is the whole daemon's. With `redact` set, wallet names are replaced with aliases, and addresses and transaction hashes are left out, including from log messages.
+    async fn collect_diagnostics(&self, redact: bool, log_limit: usize) -> DiagnosticsBundle;
+
+    /// Sets the thresholds that raise alerts for a wallet, or removes them given `null`. After every sync, an alert is raised when the wallet's MEL balance drops below `min_balance`, or when a newly confirmed transaction sends more than `max_outgoing` MEL out of it. Alerts are logged, kept for [MelwalletdExtProtocol::wallet_alerts], and POSTed to the webhook, if any. Transactions confirmed before the thresholds were set are never alerted about. Needs the wallet's password.
+    async fn set_alert_thresholds(
+        &self,
+        wallet_name: String,
+        password: String,
+        thresholds: Option<AlertThresholds>,
+    ) -> Result<(), NeedWallet<AlertError>>;
+
+    /// Gets the alert thresholds of a wallet, if any.
+    async fn alert_thresholds(
+        &self,
+        wallet_name: String,
+    ) -> Result<Option<AlertThresholds>, WalletAccessError>;
+
+    /// Lists the latest `limit` alerts raised for a wallet, oldest first.
+    async fn wallet_alerts(
+        &self,
+        wallet_name: String,
+        limit: usize,
+    ) -> Result<Vec<WalletAlert>, WalletAccessError>;
+
+    /// Lists pending transactions across every wallet, oldest first, with the wallet each belongs to, the height it expires at, how often it was retransmitted and how long ago it was sent. A transaction between two of the daemon's wallets is listed once for each. `filter` narrows the list down and picks a page of it.
+    async fn list_pending(&self, filter: PendingFilter) -> PendingPage;
+
+    /// Sends `value` of `denom` from one (unlocked) wallet of this daemon to another, in one transaction. The transaction is marked as an internal transfer in the [TxBalanceDetails] of both wallets. Subject to the sending wallet's TOTP, approval and anomaly policies, like any other send.
+    async fn internal_transfer(
+        &self,
+        from_wallet: String,
+        to_wallet: String,
+        value: CoinValue,
+        denom: Denom,
+    ) -> Result<TxHash, NeedWallet<InternalTransferError>>;
+
+    /// Places an order to swap `amount` of the `from` token for the `to` token on melswap, once swapping it fetches at least `limit_price` of `to` per unit of `from`, slippage and pool fee included. The pool's price is checked after every sync until `expiry`; the swap is sent from the wallet only while it is unlocked, and is subject to the wallet's signing limit, approval mode, anomaly policy and TOTP threshold like any other send; an order whose swap is held back becomes held.
+    async fn place_swap_order(
+        &self,
+        wallet_name: String,
+        from: String,
+        to: String,
+        amount: CoinValue,
+        limit_price: f64,
+        expiry: BlockHeight,
+    ) -> Result<SwapOrder, SwapOrderError>;
+
+    /// Lists the swap orders of a wallet, or, given no wallet, the open swap orders of all wallets, oldest first.
+    async fn list_swap_orders(&self, wallet_name: Option<String>) -> Vec<SwapOrder>;
+
+    /// Cancels an open or held swap order. Cancelling a held order leaves its held transaction, if any, to be rejected separately.
+    async fn cancel_swap_order(&self, order_id: String) -> Result<SwapOrder, SwapOrderError>;
+
+    /// Creates a job that swaps `amount` of the `from` token for the `to` token on melswap every `interval_secs`, starting right away. A run is skipped if the wallet is locked, or if the swap would fetch more than `max_slippage` (a fraction, such as 0.02) less than the pool's spot price, counting the pool's 0.5% fee. Each swap is subject to the wallet's signing limit, approval mode, anomaly policy and TOTP threshold like any other send. Every run, skipped or not, is recorded for [MelwalletdExtProtocol::dca_runs].
+    async fn create_dca_job(
+        &self,
+        wallet_name: String,
+        from: String,
+        to: String,
+        amount: CoinValue,
+        interval_secs: u64,
+        max_slippage: f64,
+    ) -> Result<DcaJob, DcaError>;
+
+    /// Lists the DCA jobs of a wallet, or, given no wallet, the active DCA jobs of all wallets, oldest first.
+    async fn list_dca_jobs(&self, wallet_name: Option<String>) -> Vec<DcaJob>;
+
+    /// Cancels a DCA job, so that it never runs again. Its run history is kept.
+    async fn cancel_dca_job(&self, job_id: String) -> Result<DcaJob, DcaError>;
+
+    /// Lists the latest `limit` runs of a DCA job, oldest first.
+    async fn dca_runs(&self, job_id: String, limit: usize) -> Result<Vec<DcaRun>, DcaError>;
+
+    /// Sets how frontends should show a wallet's balances: in which unit, whether to leave out empty balances, and which tokens to hide. They are returned along with the wallet's balances by [MelwalletdExtProtocol::wallet_sync_summary].
+    async fn set_display_preferences(
+        &self,
+        wallet_name: String,
+        preferences: DisplayPreferences,
+    ) -> Result<(), NeedWallet<DisplayPreferencesError>>;
+
+    /// Gets a wallet's display preferences, which are the defaults if none were set.
+    async fn display_preferences(
+        &self,
+        wallet_name: String,
+    ) -> Result<DisplayPreferences, WalletAccessError>;
+
+    /// Creates a 2-of-3 escrow between a buyer, a seller and an arbiter, given their hex-encoded public keys (as returned by [MelwalletdExtProtocol::export_public_key]). None of the parties need to have a wallet in this daemon; the daemon only coordinates.
+    async fn create_escrow(
+        &self,
+        buyer: String,
+        seller: String,
+        arbiter: String,
+    ) -> Result<Escrow, EscrowError>;
+
+    /// Returns the current state of an escrow, or `null` if there is no escrow with that ID.
+    async fn escrow_status(&self, escrow_id: String) -> Option<Escrow>;
+
+    /// Lists all escrows, oldest first.
+    async fn list_escrows(&self) -> Vec<Escrow>;
+
+    /// Funds a newly created escrow with MEL from an (unlocked) wallet, normally the buyer's.
+    async fn fund_escrow(
+        &self,
+        escrow_id: String,
+        wallet_name: String,
+        value: CoinValue,
+    ) -> Result<Escrow, NeedWallet<EscrowError>>;
+
+    /// Prepares an unsigned transaction paying the escrowed funds, minus the fee, to the seller. Once the funding transaction confirms, this can be called at any time before the escrow is settled; any signatures collected for an earlier release or refund are discarded.
+    async fn release_escrow(&self, escrow_id: String) -> Result<Escrow, EscrowError>;
+
+    /// Like [MelwalletdExtProtocol::release_escrow], but pays the funds back to the buyer.
+    async fn refund_escrow(&self, escrow_id: String) -> Result<Escrow, EscrowError>;
+
+    /// Signs the pending release or refund transaction of an escrow with the key of a wallet in this daemon, which must be one of the parties. Once two parties have signed, the transaction is broadcast.
+    async fn sign_escrow(
+        &self,
+        escrow_id: String,
+        wallet_name: String,
+        password: String,
+    ) -> Result<Escrow, NeedWallet<EscrowError>>;
+
+    /// Adds a hex-encoded signature by one of the parties over the hash of the pending release or refund transaction, for parties without a wallet in this daemon. Once two parties have signed, the transaction is broadcast.
+    async fn add_escrow_signature(
+        &self,
+        escrow_id: String,
+        signature: String,
+    ) -> Result<Escrow, EscrowError>;
+}
+
+/// Longest that [MelwalletdExtProtocol::wait_for_confirmation] waits.
+pub const MAX_CONFIRMATION_WAIT_SECS: u64 = 600;
+
+/// Blocks a wallet can be behind the latest one before [MelwalletdExtProtocol::wallet_sync_summary] calls it stale.
+pub const STALE_AFTER_BLOCKS: u64 = 2;
+
+/// Longest that [MelwalletdExtProtocol::follow_logs] waits for a log record.
+pub const MAX_LOG_WAIT_SECS: u64 = 60;
+
+/// Most blocks [MelwalletdExtProtocol::network_fees] looks back over.
+pub const MAX_FEE_HISTORY_BLOCKS: u64 = 100;
+
+/// HTTP header giving an RPC call a handle by which [MelwalletdExtProtocol::cancel] can cancel it.
+pub const CANCEL_HANDLE_HEADER: &str = "X-Cancel-Handle";
+
+/// Methods that can send transactions, which refuse a [CANCEL_HANDLE_HEADER] and are exempt from the daemon's RPC timeouts: stopped after the node accepted its transaction but before recording it, a call would leave the wallet unaware of what it sent.
+pub const SENDING_METHODS: &[&str] = &[
+    "send_tx",
+    "send_tx_with_totp",
+    "send_faucet",
+    "approve_send",
+    "confirm_held_send",
+    "internal_transfer",
+    "broadcast_signed_tx",
+    "submit_signatures",
+    "complete_trade",
+    "fund_escrow",
+    "sign_escrow",
+    "add_escrow_signature",
+    "rotate_key",
+];
+
+/// JSON-RPC error code of calls cancelled with [MelwalletdExtProtocol::cancel].
+pub const CANCELLED_ERROR_CODE: i64 = -32800;
+
+/// JSON-RPC error code of calls that took longer than the daemon's configured timeout for their method.
+pub const TIMEOUT_ERROR_CODE: i64 = -32001;
+
+/// Heaviest covenant [MelwalletdExtProtocol::evaluate_covenant] runs.
+pub const MAX_EVAL_WEIGHT: u128 = 1_000_000;
+
+/// Most steps of a covenant [MelwalletdExtProtocol::evaluate_covenant] traces.
+pub const MAX_TRACE_STEPS: usize = 10_000;
+
+/// Largest [DataPayload] [MelwalletdExtProtocol::build_data_output] puts in a coin, encoded.
+pub const MAX_PAYLOAD_BYTES: usize = 1024;
+
+/// Methods that don't count as interactive use, and so don't lift low-power mode.
+pub const POWER_METHODS: &[&str] = &["set_power_mode", "power_status"];
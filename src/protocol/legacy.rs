@@ -1,5 +1,9 @@
+use dashmap::DashSet;
+use futures::Future;
 use melwalletd_prot::types::{PrepareTxArgs, WalletAccessError};
 use melwalletd_prot::MelwalletdProtocol;
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
 use tide::{Request, Server};
 
 use crate::state::AppState;
@@ -10,6 +14,66 @@ use melstructs::{Denom, PoolKey, Transaction};
 use std::fmt::Debug;
 use tmelcrypt::HashVal;
 
+/// Clients and the JSON-RPC methods replacing the routes they used, for which a deprecation warning was already logged, so that each is logged only once.
+static DEPRECATION_WARNED: Lazy<DashSet<(String, &'static str)>> = Lazy::new(DashSet::new);
+
+/// Identifies a client of the REST interface by the `User-Agent` it sends, such as `melwallet-cli/0.5.1`.
+fn client_version(req: &Request<AppState>) -> String {
+    req.header("user-agent")
+        .map(|h| h.as_str().to_owned())
+        .unwrap_or_else(|| "unknown client".into())
+}
+
+/// Wraps a handler of the REST interface, logging once per client that it should call the JSON-RPC `method` instead.
+fn deprecated<F, Fut>(
+    method: &'static str,
+    handler: F,
+) -> impl Fn(Request<AppState>) -> std::pin::Pin<Box<dyn Future<Output = tide::Result<Body>> + Send>>
+       + Clone
+       + Send
+       + Sync
+       + 'static
+where
+    F: Fn(Request<AppState>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = tide::Result<Body>> + Send + 'static,
+{
+    move |req: Request<AppState>| {
+        let client = client_version(&req);
+        if DEPRECATION_WARNED.insert((client.clone(), method)) {
+            log::warn!(
+                "{client} used the deprecated REST route {} {}; it should call the JSON-RPC method {method} instead",
+                req.method(),
+                req.url().path()
+            );
+        }
+        Box::pin(handler(req))
+    }
+}
+
+/// Reads a JSON body the way older clients send it: a missing or empty body, or missing fields, mean defaults.
+async fn legacy_body<T: DeserializeOwned + Default>(
+    req: &mut Request<AppState>,
+) -> tide::Result<T> {
+    let body = req.body_string().await?;
+    if body.trim().is_empty() {
+        return Ok(T::default());
+    }
+    serde_json::from_str(&body).map_err(to_badreq)
+}
+
+/// Parses a denomination as older clients send it, hex-encoded, or in the standard string form.
+fn legacy_denom(s: &str) -> anyhow::Result<Denom> {
+    match hex::decode(s)
+        .ok()
+        .and_then(|bytes| Denom::from_bytes(&bytes))
+    {
+        Some(denom) => Ok(denom),
+        None => s
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid denomination {s}")),
+    }
+}
+
 fn to_badreq<E: Into<anyhow::Error> + Send + 'static + Sync + Debug>(e: E) -> tide::Error {
     tide::Error::new(StatusCode::BadRequest, e)
 }
@@ -57,8 +121,8 @@ pub async fn get_pool_info(req: Request<AppState>) -> tide::Result<Body> {
     }
     let query: Req = req.query()?;
     let value = query.value;
-    let from = Denom::from_bytes(&hex::decode(&query.from)?).context("oh no")?;
-    let to = Denom::from_bytes(&hex::decode(&query.to)?).context("oh no")?;
+    let from = legacy_denom(&query.from).map_err(to_badreq)?;
+    let to = legacy_denom(&query.to).map_err(to_badreq)?;
     Body::from_json(&req.state().simulate_swap(to, from, value).await?)
 }
 
@@ -73,8 +137,7 @@ pub async fn create_wallet(mut req: Request<AppState>) -> tide::Result<Body> {
         secret: Option<String>,
     }
 
-    let body = &req.body_string().await?;
-    let query: Query = serde_json::from_str(body)?;
+    let query: Query = legacy_body(&mut req).await?;
 
     let wallet_name = req.param("name").map(|v| v.to_string())?;
     Body::from_json(
@@ -110,12 +173,12 @@ pub async fn lock_wallet(req: Request<AppState>) -> tide::Result<Body> {
 }
 
 pub async fn unlock_wallet(mut req: Request<AppState>) -> tide::Result<Body> {
-    #[derive(Deserialize)]
+    #[derive(Deserialize, Default)]
     struct Req {
         password: Option<String>,
     }
     let wallet_name = req.param("name").map(|v| v.to_string())?;
-    let request: Req = req.body_json().await?;
+    let request: Req = legacy_body(&mut req).await?;
     // attempt to unlock
     let rpc = req.state();
     rpc.unlock_wallet(wallet_name, request.password.unwrap_or_default())
@@ -124,16 +187,19 @@ pub async fn unlock_wallet(mut req: Request<AppState>) -> tide::Result<Body> {
 }
 
 pub async fn export_sk_from_wallet(mut req: Request<AppState>) -> tide::Result<Body> {
-    #[derive(Deserialize)]
+    // older clients leave out the password of wallets without one
+    #[derive(Deserialize, Default)]
     struct Req {
-        password: String,
+        password: Option<String>,
     }
     let wallet_name = req.param("name").map(|v| v.to_string())?;
-    let request: Req = req.body_json().await?;
+    let request: Req = legacy_body(&mut req).await?;
     let rpc = req.state();
 
     // attempt to unlock
-    let sk = rpc.export_sk(wallet_name, request.password).await?;
+    let sk = rpc
+        .export_sk(wallet_name, request.password.unwrap_or_default())
+        .await?;
 
     Body::from_json(&sk)
 }
@@ -190,23 +256,38 @@ pub async fn send_faucet(req: Request<AppState>) -> tide::Result<Body> {
 //     todo!()
 // }
 
+/// Serves the old REST interface, mapping the requests of older clients onto the JSON-RPC protocol and logging which clients still use it.
 pub fn route_legacy(app: &mut Server<AppState>) {
-    app.at("/summary").get(get_summary);
-    app.at("/pools/:pair").get(get_pool);
-    app.at("/pool_info").post(get_pool_info);
-    app.at("/wallets").get(list_wallets);
-    app.at("/wallets/:name").get(summarize_wallet);
-    app.at("/wallets/:name").put(create_wallet);
-    app.at("/wallets/:name/lock").post(lock_wallet);
-    app.at("/wallets/:name/unlock").post(unlock_wallet);
+    app.at("/summary")
+        .get(deprecated("latest_header", get_summary));
+    app.at("/pools/:pair")
+        .get(deprecated("melswap_info", get_pool));
+    app.at("/pool_info")
+        .post(deprecated("simulate_swap", get_pool_info));
+    app.at("/wallets")
+        .get(deprecated("list_wallets", list_wallets));
+    app.at("/wallets/:name")
+        .get(deprecated("wallet_summary", summarize_wallet));
+    app.at("/wallets/:name")
+        .put(deprecated("create_wallet", create_wallet));
+    app.at("/wallets/:name/lock")
+        .post(deprecated("lock_wallet", lock_wallet));
+    app.at("/wallets/:name/unlock")
+        .post(deprecated("unlock_wallet", unlock_wallet));
     app.at("/wallets/:name/export-sk")
-        .post(export_sk_from_wallet);
-    app.at("/wallets/:name/coins").get(dump_coins);
-    app.at("/wallets/:name/prepare-tx").post(prepare_tx);
-    app.at("/wallets/:name/send-tx").post(send_tx);
-    app.at("/wallets/:name/send-faucet").post(send_faucet);
-    app.at("/wallets/:name/transactions").get(dump_transactions);
-    app.at("/wallets/:name/transactions/:txhash").get(get_tx);
+        .post(deprecated("export_sk", export_sk_from_wallet));
+    app.at("/wallets/:name/coins")
+        .get(deprecated("dump_coins", dump_coins));
+    app.at("/wallets/:name/prepare-tx")
+        .post(deprecated("prepare_tx", prepare_tx));
+    app.at("/wallets/:name/send-tx")
+        .post(deprecated("send_tx", send_tx));
+    app.at("/wallets/:name/send-faucet")
+        .post(deprecated("send_faucet", send_faucet));
+    app.at("/wallets/:name/transactions")
+        .get(deprecated("dump_transactions", dump_transactions));
+    app.at("/wallets/:name/transactions/:txhash")
+        .get(deprecated("tx_status", get_tx));
     app.at("/wallets/:name/transactions/:txhash/balance")
-        .get(get_tx_balance);
+        .get(deprecated("tx_balance", get_tx_balance));
 }
@@ -1,4 +1,7 @@
+pub mod capabilities;
+pub mod ext;
 pub mod legacy;
 pub mod rpc;
+pub mod types;
 
 pub use rpc::*;
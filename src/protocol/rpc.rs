@@ -1,15 +1,90 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use crate::state::AppState;
+use crate::{
+    accounting::{self, ActivityLine},
+    address::{address_forms, parse_address},
+    build_info, covenant,
+    database::{Database, EscrowRecord, Wallet, MAX_TX_OUTPUTS, MAX_TX_WEIGHT},
+    descriptor::{descriptor_string, recover_public_key},
+    escrow, fees,
+    inheritance::{presign_sweeps, SWEEP_FEE_HEADROOM},
+    invoice::valid_webhook,
+    logs::LOG_BUFFER,
+    mint,
+    offline::OFFLINE_ERROR,
+    payload,
+    payment_proof::PaymentProof,
+    payment_uri::{parse_payment_uri, URI_EXAMPLE, URI_SCHEME},
+    plugin::STANDARD_WALLET,
+    protocol::{
+        capabilities,
+        ext::{
+            MelwalletdExtProtocol, MelwalletdExtService, CANCELLED_ERROR_CODE,
+            CANCEL_HANDLE_HEADER, MAX_CONFIRMATION_WAIT_SECS, MAX_EVAL_WEIGHT,
+            MAX_FEE_HISTORY_BLOCKS, MAX_LOG_WAIT_SECS, MAX_PAYLOAD_BYTES, POWER_METHODS,
+            SENDING_METHODS, STALE_AFTER_BLOCKS, TIMEOUT_ERROR_CODE,
+        },
+        types::{
+            AccountingFormat, AddressForms, AddressOwner, AddressOwnership, AlertError,
+            AlertThresholds, AnomalyError, AnomalyPolicy, ApprovalError, BackupError, BackupInfo,
+            BlockTime, BuildInfo, Capabilities, CloneWalletError, CoinSelection,
+            CoinSelectionPreview, ColdSigningError, ConfirmationOutcome, ConsolidationPolicy,
+            CovenantEvalError, CovenantEvaluation, DaemonStats, DataCoin, DataPayload,
+            DataPayloadError, DcaError, DcaJob, DcaRun, DescriptorCovenant, DiagnosticsBundle,
+            DisplayPreferences, DisplayPreferencesError, Escrow, EscrowError, EscrowRole,
+            EscrowStatus, EvaluateCovenantArgs, FeeBreakdown, FeeSample, HeldSend, HistoryEntry,
+            HistoryExportError, HoldKind, ImportCoinError, InheritanceError, InheritanceStatus,
+            InputSelection, InsufficientFunds, InternalTransfer, InternalTransferError,
+            InvalidAddressError, Invoice, InvoiceError, JournalReplay, KeyOrigin,
+            KeyRotationStatus, KeyStatus, LabeledCoin, LogLevel, LogRecord, MasterPassphraseError,
+            MintRewardEstimate, MintingInfo, NetworkDiagnostics, NetworkFees, OwnershipKind,
+            PasswordStrength, PayloadTemplate, PaymentProofError, PaymentUriError, PendingFilter,
+            PendingPage, PendingPurge, PendingTransaction, PowerMode, PowerStatus,
+            PrepareTxArgs as ExtPrepareTxArgs, PreparedTx, PreparedTxDetails, SecretsStatus,
+            SelectedInput, SendError, SigningActivity, SigningBundle, SigningLimit,
+            SigningLimitError, SigningRequest, SigningStatus, SwapOrder, SwapOrderError,
+            SwapOrderStatus, SyncError, SyncSnapshotError, TimelockedCoin, TimelockedOutput,
+            TotpEnrollment, TotpError, TotpStatus, TrackedAddress, Trade, TradeError, TradeOffer,
+            TradeStatus, TradeTerms, TransactionCacheStats, TransactionSearchHit, TrustedHeader,
+            TxBalanceDetails, TxDecodeError, UnitConversionError, UriHandlerInfo, VerifiedPayment,
+            VerifyWalletsError, WalletAlert, WalletDescriptor, WalletMismatch, WalletSyncSummary,
+            WatchPackageError, WeakPasswordError,
+        },
+    },
+    provision::provision_wallets,
+    reconcile,
+    rotation::prepare_sweeps,
+    secrets::{EncryptedSK, PersistentSecret},
+    signer::{is_placeholder, verify_signatures, DeferredSigner, PlaceholderSigner, Signer},
+    state::{AppState, SEND_RETRIES, SEND_RETRY_DELAY},
+    sync_snapshot::SyncSnapshot,
+    timelock::timelock_covenant,
+    totp::TotpSecret,
+    trade,
+    users::constant_time_eq,
+    watch_package::WatchPackage,
+};
 use async_trait::async_trait;
 use base32::Alphabet;
 
 use bytes::Bytes;
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures::{
+    future::{AbortHandle, Abortable, Aborted},
+    FutureExt,
+};
 use http_types::Body;
+use melprot::Substate;
 use melstructs::{
-    BlockHeight, CoinData, CoinID, CoinValue, Denom, Header, NetID, PoolKey, PoolState,
-    Transaction, TxHash, TxKind,
+    Address, BlockHeight, CoinData, CoinDataHeight, CoinID, CoinValue, Denom, Header, NetID,
+    PoolKey, PoolState, Transaction, TxHash, TxKind,
 };
+use melvm::{covenant_weight_from_bytes, Covenant, CovenantEnv};
 use melwalletd_prot::{
     types::{
         AnnCoinID, CreateWalletError, NeedWallet, NetworkError, PrepareTxArgs, PrepareTxError,
@@ -17,9 +92,11 @@ use melwalletd_prot::{
     },
     MelwalletdProtocol, MelwalletdService,
 };
-use nanorpc::RpcService;
-use tide::{Request, Server};
-use tmelcrypt::{Ed25519SK, HashVal, Hashable};
+use nanorpc::{JrpcError, JrpcId, JrpcResponse, OrService, RpcService};
+use smol_timeout::TimeoutExt;
+use stdcode::StdcodeSerializeExt;
+use tide::{Request, Server, StatusCode};
+use tmelcrypt::{Ed25519PK, Ed25519SK, HashVal, Hashable};
 
 #[async_trait]
 impl MelwalletdProtocol for AppState {
@@ -39,8 +116,15 @@ impl MelwalletdProtocol for AppState {
     }
 
     async fn latest_header(&self) -> Result<Header, NetworkError> {
+        if self.config.offline {
+            return self
+                .database
+                .cached_header()
+                .await
+                .expect("db failed")
+                .ok_or_else(|| NetworkError::Fatal(OFFLINE_ERROR.into()));
+        }
         let snap = self
-            .client()
             .latest_snapshot()
             .await
             .map_err(|e| NetworkError::Transient(e.to_string()))?;
@@ -49,7 +133,6 @@ impl MelwalletdProtocol for AppState {
 
     async fn melswap_info(&self, pool_key: PoolKey) -> Result<Option<PoolState>, NetworkError> {
         let snapshot = self
-            .client()
             .latest_snapshot()
             .await
             .map_err(|e| NetworkError::Transient(e.to_string()))?;
@@ -70,7 +153,6 @@ impl MelwalletdProtocol for AppState {
         let pool_key = PoolKey::new(to, from);
 
         let pool_state = if let Some(state) = self
-            .client()
             .latest_snapshot()
             .await
             .map_err(|e| NetworkError::Transient(e.to_string()))?
@@ -115,22 +197,13 @@ impl MelwalletdProtocol for AppState {
         password: String,
         secret: Option<String>,
     ) -> Result<(), CreateWalletError> {
-        let sk = if let Some(secret) = secret {
-            // We must reconstruct the secret key using the ed25519-dalek library
-            let secret = base32::decode(Alphabet::Crockford, &secret).ok_or_else(|| {
-                CreateWalletError::SecretKey("Failed to decode secret key".to_owned())
-            })?;
-            let secret = ed25519_dalek::SecretKey::from_bytes(&secret).map_err(|_| {
-                CreateWalletError::SecretKey("Failed to create secret key".to_owned())
-            })?;
-            let public: ed25519_dalek::PublicKey = (&secret).into();
-            let mut vv = [0u8; 64];
-            vv[0..32].copy_from_slice(&secret.to_bytes());
-            vv[32..].copy_from_slice(&public.to_bytes());
-            Ed25519SK(vv)
-        } else {
-            Ed25519SK::generate()
-        };
+        let strength = self.config.password_policy.check(&password);
+        if !strength.acceptable {
+            return Err(CreateWalletError::Other(
+                WeakPasswordError(strength).to_string(),
+            ));
+        }
+        let sk = Self::secret_key(secret)?;
         match self.create_wallet_inner(&wallet_name, sk, password).await {
             Ok(_) => Ok(()),
             Err(e) => Err(CreateWalletError::Other(e.to_string())),
@@ -174,6 +247,7 @@ impl MelwalletdProtocol for AppState {
     ) -> Result<(), WalletAccessError> {
         // TODO handle the wallet not found case correctly
         self.unlock(&wallet_name, password)
+            .await
             .ok_or(WalletAccessError::Locked)?;
         Ok(())
     }
@@ -183,14 +257,12 @@ impl MelwalletdProtocol for AppState {
         wallet_name: String,
         password: String,
     ) -> Result<String, WalletAccessError> {
-        let secret = self
-            .get_secret_key(&wallet_name, &password)
-            .map_err(|_| WalletAccessError::Locked)?
-            .ok_or(WalletAccessError::NotFound)?;
-
-        // We always return Some right now. In the future, when we have cool stuff like hardware wallets, we might return None.
-        let encoded: String = base32::encode(Alphabet::Crockford, &secret.0[..32]);
-        Ok(encoded)
+        if self.totp_active(&wallet_name) {
+            return Err(WalletAccessError::Other(
+                "TOTP is enabled; use export_sk_with_totp".into(),
+            ));
+        }
+        self.export_sk_inner(&wallet_name, &password)
     }
 
     async fn prepare_tx(
@@ -198,60 +270,11 @@ impl MelwalletdProtocol for AppState {
         wallet_name: String,
         request: PrepareTxArgs,
     ) -> Result<Transaction, NeedWallet<PrepareTxError>> {
-        let signing_key = self
-            .get_signer(&wallet_name)
-            .ok_or(NeedWallet::Wallet(WalletAccessError::Locked))?;
-        let wallet = self
-            .get_wallet(&wallet_name)
-            .await
-            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
-
-        // calculate fees
-        let snapshot = self
-            .client()
-            .latest_snapshot()
-            .await
-            .map_err(|e| PrepareTxError::Network(NetworkError::Transient(e.to_string())))?;
-        let fee_multiplier = snapshot.current_header().fee_multiplier;
-
-        let sign = {
-            let covenants: Vec<Bytes> = request
-                .covenants
-                .iter()
-                .map(|cb| Bytes::copy_from_slice(cb))
-                .collect();
-            let kind = request.kind;
-            let data: Bytes = request.data.into();
-            move |mut tx: Transaction| {
-                tx.kind = kind;
-
-                tx.data = data.clone();
-
-                tx.covenants.extend_from_slice(&covenants);
-                for i in 0..tx.inputs.len() {
-                    tx = signing_key.sign_tx(tx, i)?;
-                }
-                Ok(tx)
-            }
-        };
-        // TODO this returns the wrong error. We should have Wallet return a PrepareTxError.
-        let prepared_tx = wallet
-            .prepare(
-                request.inputs.clone(),
-                request.outputs.clone(),
-                fee_multiplier,
-                Arc::new(Box::new(sign)),
-                request.nobalance.clone(),
-                request.fee_ballast,
-                self.client()
-                    .latest_snapshot()
-                    .await
-                    .map_err(|e| PrepareTxError::Network(NetworkError::Transient(e.to_string())))?,
-            )
-            .await
-            .map_err(|e| PrepareTxError::Network(NetworkError::Fatal(e.to_string())))?;
-
-        Ok(prepared_tx)
+        Ok(
+            MelwalletdExtProtocol::prepare_tx(self, wallet_name, request.into())
+                .await?
+                .transaction,
+        )
     }
 
     async fn send_tx(
@@ -259,34 +282,12 @@ impl MelwalletdProtocol for AppState {
         wallet_name: String,
         tx: Transaction,
     ) -> Result<TxHash, NeedWallet<NetworkError>> {
-        let wallet = self
-            .get_wallet(&wallet_name)
-            .await
-            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
-        let snapshot = self
-            .client()
-            .latest_snapshot()
+        MelwalletdExtProtocol::send_tx(self, wallet_name, tx)
             .await
-            .map_err(|e| NetworkError::Transient(e.to_string()))?;
-
-        // we send it off ourselves
-        snapshot
-            .get_raw()
-            .send_tx(tx.clone())
-            .await
-            .map_err(|e| NetworkError::Transient(e.to_string()))?
-            .map_err(|e| NetworkError::Fatal(e.to_string()))?;
-
-        // we mark the TX as sent in this thread.
-        wallet
-            .commit_sent(
-                tx.clone(),
-                snapshot.current_header().height + BlockHeight(10),
-            )
-            .await
-            .map_err(|e| NetworkError::Fatal(e.to_string()))?;
-        log::info!("sent transaction with hash {}", tx.hash_nosigs());
-        Ok(tx.hash_nosigs())
+            .map_err(|e| match e {
+                NeedWallet::Wallet(e) => NeedWallet::Wallet(e),
+                NeedWallet::Other(e) => NeedWallet::Other(e.into()),
+            })
     }
 
     async fn tx_balance(
@@ -302,7 +303,6 @@ impl MelwalletdProtocol for AppState {
         // TODO the backend should expose infallible methods for these things, and do the network sync in the background. That way, network failures would just delay the time at which txx are marked confirmed, rather than causing failures.
         // The current approach is incorrect and returns a misleading error message.
         let snapshot = self
-            .client()
             .latest_snapshot()
             .await
             .map_err(|e| WalletAccessError::Other(e.to_string()))?;
@@ -316,35 +316,21 @@ impl MelwalletdProtocol for AppState {
             return Ok(None);
         };
 
-        // Is this self-originated? We check the covenants
-        let self_originated = raw.covenants.iter().any(|c| c.hash() == wallet.address().0);
-        // Total balance out
-        let mut balance: BTreeMap<String, i128> = BTreeMap::new();
-        // Add all outputs to balance
-
-        if self_originated {
-            *balance
-                .entry(hex::encode(Denom::Mel.to_bytes()))
-                .or_default() -= raw.fee.0 as i128;
-        }
-        for (idx, output) in raw.outputs.iter().enumerate() {
-            let coinid = raw.output_coinid(idx as u8);
-            let denom_key = hex::encode(output.denom.to_bytes());
-            // first we *deduct* any balance if this self-originated
-            if self_originated {
-                *balance.entry(denom_key).or_default() -= output.value.0 as i128;
-            }
-            // then, if we find this value in our coins, we add it back. this turns out to take care of swap tx well
-            if let Some(ours) = wallet.get_one_coin(coinid).await {
-                let denom_key = hex::encode(ours.denom.to_bytes());
-                if ours.covhash == wallet.address() {
-                    *balance.entry(denom_key).or_default() += ours.value.0 as i128;
-                }
-            }
-        }
-        let r = TxBalance(self_originated, raw.kind, balance);
-
-        Ok(Some(r))
+        let details = self.balance_details(&wallet, &raw).await;
+        // the legacy form keys balances by hex-encoded denom
+        let balance = details
+            .net
+            .into_iter()
+            .map(|(denom, delta)| {
+                let denom: Denom = denom.parse().expect("denom keys are standard strings");
+                (hex::encode(denom.to_bytes()), delta)
+            })
+            .collect();
+        Ok(Some(TxBalance(
+            details.self_originated,
+            details.kind,
+            balance,
+        )))
     }
 
     async fn tx_status(
@@ -439,15 +425,4042 @@ impl MelwalletdProtocol for AppState {
     }
 }
 
-/// Starts the RPC tide route
-pub fn route_rpc(app: &mut Server<AppState>) {
-    app.at("").post(move |mut r: Request<AppState>| {
-        let service = r.state().clone();
-        async move {
-            let request_body: nanorpc::JrpcRequest = r.body_json().await?;
-            let service = MelwalletdService(service);
-            let rpc_res = service.respond_raw(request_body).await;
-            Body::from_json(&rpc_res)
-        }
-    });
+impl AppState {
+    /// Works out how a transaction moved the balance of a wallet. Only outputs, and not inputs, are looked at: a wallet that sent a transaction is taken to have paid for every output and the fee, and to have received whichever outputs came back to it.
+    async fn balance_details(&self, wallet: &Wallet, raw: &Transaction) -> TxBalanceDetails {
+        let self_originated = raw.covenants.iter().any(|c| c.hash() == wallet.address().0);
+        let mut outgoing: BTreeMap<String, u128> = BTreeMap::new();
+        let mut incoming: BTreeMap<String, u128> = BTreeMap::new();
+        let mut net: BTreeMap<String, i128> = BTreeMap::new();
+        let fee = if self_originated {
+            raw.fee
+        } else {
+            CoinValue(0)
+        };
+        if self_originated {
+            *net.entry(Denom::Mel.to_string()).or_default() -= fee.0 as i128;
+        }
+        for (idx, output) in raw.outputs.iter().enumerate() {
+            if self_originated {
+                let denom = output.denom.to_string();
+                *outgoing.entry(denom.clone()).or_default() += output.value.0;
+                *net.entry(denom).or_default() -= output.value.0 as i128;
+            }
+            // the stored coin, unlike the output, has new tokens under their final denom. this also takes care of swaps
+            if let Some(ours) = wallet.get_one_coin(raw.output_coinid(idx as u8)).await {
+                if ours.covhash == wallet.address() {
+                    let denom = ours.denom.to_string();
+                    *incoming.entry(denom.clone()).or_default() += ours.value.0;
+                    *net.entry(denom).or_default() += ours.value.0 as i128;
+                }
+            }
+        }
+        TxBalanceDetails {
+            self_originated,
+            kind: raw.kind,
+            outgoing,
+            incoming,
+            fee,
+            net,
+            internal: self
+                .database
+                .internal_transfer(raw.hash_nosigs())
+                .await
+                .expect("db failed"),
+        }
+    }
+
+    /// Broadcasts a transaction of a wallet and marks it as sent.
+    pub async fn broadcast_tx(
+        &self,
+        wallet: &Wallet,
+        tx: Transaction,
+    ) -> Result<TxHash, NetworkError> {
+        // we send it off ourselves, retrying with a fresh snapshot if the node can't be reached; only a rejection by the node is final
+        let mut retries = 0;
+        let snapshot = loop {
+            let attempt = async {
+                let snapshot = if retries == 0 {
+                    self.latest_snapshot().await
+                } else {
+                    self.fresh_snapshot().await
+                }
+                .map_err(|e| NetworkError::Transient(e.to_string()))?;
+                snapshot
+                    .get_raw()
+                    .send_tx(tx.clone())
+                    .await
+                    .map_err(|e| NetworkError::Transient(e.to_string()))?
+                    .map_err(|e| NetworkError::Fatal(e.to_string()))?;
+                Ok(snapshot)
+            };
+            match attempt.await {
+                Ok(snapshot) => break snapshot,
+                Err(NetworkError::Transient(err)) if retries < SEND_RETRIES => {
+                    log::warn!(
+                        "cannot reach node to send {} ({err}); retrying",
+                        tx.hash_nosigs()
+                    );
+                    smol::Timer::after(SEND_RETRY_DELAY * 2u32.pow(retries)).await;
+                    retries += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        };
+
+        // we mark the TX as sent in this thread.
+        let timeout = snapshot.current_header().height + BlockHeight(10);
+        wallet
+            .commit_sent(tx.clone(), timeout)
+            .await
+            .map_err(|e| NetworkError::Fatal(e.to_string()))?;
+        // as well as in any other wallet of ours that sponsored its fee
+        for covenant in tx.covenants.iter() {
+            let covhash = match Covenant::from_bytes(covenant) {
+                Ok(covenant) if covenant.hash() != wallet.address() => covenant.hash(),
+                _ => continue,
+            };
+            if let Some(sponsor) = self.database.wallet_with_address(covhash).await {
+                if let Some(sponsor) = self.get_wallet(&sponsor).await {
+                    sponsor
+                        .commit_sponsored(tx.clone(), timeout)
+                        .await
+                        .map_err(|e| NetworkError::Fatal(e.to_string()))?;
+                }
+            }
+        }
+        if retries > 0 {
+            log::info!(
+                "sent transaction with hash {} after {retries} retries",
+                tx.hash_nosigs()
+            );
+        } else {
+            log::info!("sent transaction with hash {}", tx.hash_nosigs());
+        }
+        Ok(tx.hash_nosigs())
+    }
+
+    /// Whether sends from a wallet are checked by approval mode, TOTP or an anomaly policy.
+    async fn send_gated(&self, wallet_name: &str, wallet: &Wallet) -> bool {
+        self.totp_active(wallet_name)
+            || wallet.approval_hash().await.is_some()
+            || self
+                .database
+                .anomaly_policy(wallet_name)
+                .await
+                .expect("db failed")
+                .is_some()
+    }
+
+    /// The signer to prepare a transaction of a wallet with, if the wallet is unlocked. Transactions of a wallet whose sends are checked are only signed once sent, so that they can't be broadcast around the checks; they get placeholder signatures from a [DeferredSigner]. Otherwise, it's the wallet's own signer, counted against its signing limit.
+    async fn prepare_signer(&self, wallet_name: &str) -> Option<Arc<dyn Signer>> {
+        let wallet = self.get_wallet(wallet_name).await?;
+        if self.send_gated(wallet_name, &wallet).await {
+            let signer = self.get_signer(wallet_name)?;
+            Some(Arc::new(DeferredSigner(signer)))
+        } else {
+            self.use_signer(wallet_name).await
+        }
+    }
+
+    /// Signs the inputs of a wallet's transaction that were left with placeholder signatures. Returns None if there are any, but the wallet is locked.
+    async fn sign_deferred(&self, wallet_name: &str, mut tx: Transaction) -> Option<Transaction> {
+        let deferred: Vec<usize> = (0..tx.sigs.len())
+            .filter(|&i| is_placeholder(&tx.sigs[i]))
+            .collect();
+        if deferred.is_empty() {
+            return Some(tx);
+        }
+        let signer = self.use_signer(wallet_name).await?;
+        for i in deferred {
+            tx = signer.sign_tx(tx, i).ok()?;
+        }
+        Some(tx)
+    }
+
+    /// Signs and broadcasts a held transaction, no longer holding it unless broadcasting fails.
+    async fn release_held_send(
+        &self,
+        wallet: &Wallet,
+        held: HeldSend,
+    ) -> Result<TxHash, NetworkError> {
+        let tx = self
+            .sign_deferred(&held.wallet_name, held.transaction.clone())
+            .await
+            .ok_or_else(|| {
+                NetworkError::Fatal(format!(
+                    "{} must be unlocked to sign the transaction",
+                    held.wallet_name
+                ))
+            })?;
+        self.database
+            .delete_held_send(&held.wallet_name, held.txhash)
+            .await
+            .expect("db failed");
+        let res = self.broadcast_tx(wallet, tx).await;
+        if res.is_err() {
+            // keep it held, so that releasing it can be retried
+            self.database
+                .hold_send(
+                    &held.wallet_name,
+                    &held.transaction,
+                    held.kind,
+                    &held.reason,
+                )
+                .await
+                .expect("db failed");
+        }
+        res
+    }
+
+    /// Gets a transaction awaiting approval, checking the approver token.
+    async fn pending_approval(
+        &self,
+        name: &str,
+        approver_token: &str,
+        txhash: TxHash,
+    ) -> Result<(Wallet, HeldSend), NeedWallet<ApprovalError>> {
+        let wallet = self
+            .get_wallet(name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let hash = wallet
+            .approval_hash()
+            .await
+            .ok_or(ApprovalError::NotEnabled)?;
+        check_approver(hash, approver_token)?;
+        let held = self
+            .database
+            .get_held_send(name, txhash)
+            .await
+            .expect("db failed")
+            .filter(|held| held.kind == HoldKind::Approval)
+            .ok_or(ApprovalError::NotPending)?;
+        Ok((wallet, held))
+    }
+
+    /// Sends a transaction, unless it needs a TOTP code that wasn't checked, or is held back by the wallet's approval mode or anomaly policy.
+    pub async fn send_tx_inner(
+        &self,
+        wallet_name: &str,
+        tx: Transaction,
+        totp_checked: bool,
+    ) -> Result<TxHash, NeedWallet<SendError>> {
+        let wallet = self
+            .get_wallet(wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        if !totp_checked {
+            if let Some(totp) = self.secrets.totp(wallet_name).filter(|t| t.active) {
+                if totp.send_needs_code(&wallet.outgoing_values(&tx)) {
+                    return Err(SendError::NeedsTotp(totp.send_threshold).into());
+                }
+            }
+        }
+        let held = if wallet.approval_hash().await.is_some() {
+            let held = self
+                .database
+                .hold_send(wallet_name, &tx, HoldKind::Approval, "awaiting approval")
+                .await
+                .expect("db failed");
+            log::info!(
+                "transaction {} of {wallet_name} awaits approval",
+                held.txhash
+            );
+            Some(held)
+        } else {
+            self.hold_if_anomalous(wallet_name, &wallet, &tx)
+                .await
+                .expect("db failed")
+        };
+        if let Some(held) = held {
+            return Err(SendError::Held {
+                txhash: held.txhash,
+                kind: held.kind,
+                reason: held.reason,
+            }
+            .into());
+        }
+        let tx = self
+            .sign_deferred(wallet_name, tx)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::Locked))?;
+        Ok(self
+            .broadcast_tx(&wallet, tx)
+            .await
+            .map_err(SendError::Network)?)
+    }
+
+    /// Exports a wallet's secret key in the standard base32 format, checking the password.
+    fn export_sk_inner(&self, name: &str, password: &str) -> Result<String, WalletAccessError> {
+        let secret = self
+            .get_secret_key(name, password)
+            .map_err(|_| WalletAccessError::Locked)?
+            .ok_or(WalletAccessError::NotFound)?;
+
+        // We always return Some right now. In the future, when we have cool stuff like hardware wallets, we might return None.
+        let encoded: String = base32::encode(Alphabet::Crockford, &secret.0[..32]);
+        Ok(encoded)
+    }
+
+    /// Re-encrypts a wallet's secret key under a new password, which must satisfy the password policy.
+    async fn change_password_inner(
+        &self,
+        name: &str,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<PasswordStrength, NeedWallet<WeakPasswordError>> {
+        if self.get_wallet(name).await.is_none() {
+            return Err(NeedWallet::Wallet(WalletAccessError::NotFound));
+        }
+        let strength = self.config.password_policy.check(new_password);
+        if !strength.acceptable {
+            return Err(WeakPasswordError(strength).into());
+        }
+        self.change_password(name, old_password, new_password)
+            .ok_or(NeedWallet::Wallet(WalletAccessError::Locked))?;
+        Ok(strength)
+    }
+
+    /// Whether a wallet has active TOTP protection.
+    fn totp_active(&self, name: &str) -> bool {
+        self.secrets.totp(name).is_some_and(|t| t.active)
+    }
+
+    /// Checks a TOTP code of a wallet. Wallets without active TOTP protection need no code, so any code passes.
+    fn check_totp_code(&self, name: &str, code: &str) -> Result<(), TotpError> {
+        if self.totp_active(name) {
+            self.secrets.check_totp(name, code)?;
+        }
+        Ok(())
+    }
+
+    /// Gets a wallet along with its secret key, checking the password.
+    async fn wallet_with_key<E: std::error::Error>(
+        &self,
+        name: &str,
+        password: &str,
+    ) -> Result<(Wallet, Ed25519SK), NeedWallet<E>> {
+        let wallet = self
+            .get_wallet(name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let sk = self
+            .get_secret_key(name, password)
+            .map_err(|_| NeedWallet::Wallet(WalletAccessError::Locked))?
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        Ok((wallet, sk))
+    }
+
+    /// Whether a wallet can sign, going by its unlocked signer and where its key lives.
+    async fn key_status(&self, name: &str) -> anyhow::Result<KeyStatus> {
+        Ok(if self.unlocked_signers.contains_key(name) {
+            KeyStatus::Unlocked
+        } else if self.database.watch_only_key(name).await?.is_some() {
+            KeyStatus::ExternalSigner
+        } else if self.secrets.load(name).is_some() {
+            KeyStatus::Locked
+        } else {
+            KeyStatus::WatchOnly
+        })
+    }
+
+    /// The transaction history of a wallet, with the times of the blocks confirming them.
+    async fn history_entries(&self, wallet: &Wallet) -> anyhow::Result<Vec<HistoryEntry>> {
+        let history = wallet.get_transaction_history().await;
+        let heights: Vec<BlockHeight> = history.iter().filter_map(|(_, height)| *height).collect();
+        let times = self.database.block_times(&heights).await?;
+        Ok(history
+            .into_iter()
+            .map(|(txhash, height)| HistoryEntry {
+                txhash,
+                height,
+                time: height.and_then(|height| times.get(&height).copied()),
+            })
+            .collect())
+    }
+
+    /// The public key behind a wallet's covenant, if it can be worked out without the password.
+    async fn wallet_public_key(
+        &self,
+        name: &str,
+        wallet: &Wallet,
+    ) -> anyhow::Result<Option<Ed25519PK>> {
+        if let Some(pubkey) = self.database.watch_only_key(name).await? {
+            return Ok(Some(pubkey));
+        }
+        let (plugin, params) = match self.wallet_plugin(name).await? {
+            Some(plugin) => plugin,
+            None => (
+                self.plugins
+                    .get(STANDARD_WALLET)
+                    .expect("standard wallet type missing"),
+                vec![],
+            ),
+        };
+        let covenant = Covenant::from_bytes(wallet.covenant())?;
+        Ok(recover_public_key(&covenant, plugin.as_ref(), &params))
+    }
+
+    /// Signs fresh dead-man switch sweeps of a wallet.
+    async fn presign_inheritance_sweeps(
+        &self,
+        wallet: &Wallet,
+        recovery: Address,
+        sk: &Ed25519SK,
+    ) -> Result<Vec<Transaction>, InheritanceError> {
+        let snapshot = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| InheritanceError::Sweep(e.to_string()))?;
+        presign_sweeps(wallet, recovery, &snapshot, sk)
+            .await
+            .map_err(|e| InheritanceError::Sweep(e.to_string()))
+    }
+
+    /// Gets the state of a wallet's dead-man switch.
+    async fn inheritance_status_inner(
+        &self,
+        name: &str,
+    ) -> Result<InheritanceStatus, InheritanceError> {
+        let record = self
+            .database
+            .get_inheritance(name)
+            .await
+            .expect("db failed")
+            .ok_or(InheritanceError::NotConfigured)?;
+        Ok(InheritanceStatus {
+            recovery_address: record.recovery,
+            inactivity_secs: record.inactivity_secs,
+            last_activity: record.last_activity,
+            deadline: record.last_activity.saturating_add(record.inactivity_secs),
+            sweeps: record.sweeps.iter().map(|tx| tx.hash_nosigs()).collect(),
+            triggered: record.triggered,
+        })
+    }
+
+    /// Gets an escrow by its ID.
+    async fn escrow_record(&self, id: &str) -> Result<EscrowRecord, EscrowError> {
+        self.database
+            .get_escrow(id)
+            .await
+            .expect("db failed")
+            .ok_or_else(|| EscrowError::NotFound(id.to_owned()))
+    }
+
+    /// Starts collecting signatures for a transaction paying out a funded escrow to either the seller or the buyer.
+    async fn propose_escrow_settlement(
+        &self,
+        id: &str,
+        payee: EscrowRole,
+    ) -> Result<Escrow, EscrowError> {
+        let record = self.escrow_record(id).await?;
+        let (coin, value) = match (
+            record.escrow.status,
+            record.escrow.coin,
+            record.escrow.value,
+        ) {
+            (
+                EscrowStatus::Funded | EscrowStatus::Releasing | EscrowStatus::Refunding,
+                Some(coin),
+                Some(value),
+            ) => (coin, value),
+            (status, _, _) => return Err(EscrowError::WrongStatus(status)),
+        };
+        let snapshot = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| EscrowError::Network(e.to_string()))?;
+        if snapshot
+            .get_coin(coin)
+            .await
+            .map_err(|e| EscrowError::Network(e.to_string()))?
+            .is_none()
+        {
+            return Err(EscrowError::NotConfirmed);
+        }
+        let destination = Covenant::std_ed25519_pk_new(record.parties[escrow::slot(payee)]).hash();
+        let txn = escrow::settlement_tx(
+            coin,
+            value,
+            &record.covenant,
+            destination,
+            snapshot.current_header().fee_multiplier,
+        )
+        .map_err(|e| EscrowError::Prepare(e.to_string()))?;
+        let status = if payee == EscrowRole::Seller {
+            EscrowStatus::Releasing
+        } else {
+            EscrowStatus::Refunding
+        };
+        self.database
+            .update_escrow(id, status, &txn)
+            .await
+            .expect("db failed");
+        Ok(self.escrow_record(id).await?.escrow)
+    }
+
+    /// Puts a party's signature into the pending transaction of an escrow, broadcasting the transaction once enough parties have signed.
+    async fn attach_escrow_signature(
+        &self,
+        record: EscrowRecord,
+        signature: Vec<u8>,
+    ) -> Result<Escrow, EscrowError> {
+        let status = record.escrow.status;
+        let mut txn = match (status, record.escrow.transaction) {
+            (EscrowStatus::Releasing | EscrowStatus::Refunding, Some(txn)) => txn,
+            _ => return Err(EscrowError::WrongStatus(status)),
+        };
+        let txhash = txn.hash_nosigs();
+        let role = escrow::ROLES
+            .iter()
+            .copied()
+            .zip(record.parties)
+            .find(|(_, pk)| pk.verify(&txhash.0, &signature))
+            .map(|(role, _)| role)
+            .ok_or(EscrowError::BadSignature)?;
+        txn.sigs[escrow::slot(role)] = signature.into();
+
+        let status = if escrow::signed_by(record.parties, &txn).len() < escrow::THRESHOLD {
+            status
+        } else {
+            let snapshot = self
+                .latest_snapshot()
+                .await
+                .map_err(|e| EscrowError::Network(e.to_string()))?;
+            snapshot
+                .get_raw()
+                .send_tx(txn.clone())
+                .await
+                .map_err(|e| EscrowError::Network(e.to_string()))?
+                .map_err(|e| EscrowError::Network(e.to_string()))?;
+            if let Err(err) = self
+                .database
+                .journal()
+                .append(&format!("escrow:{}", record.escrow.id), &txn)
+            {
+                log::warn!("cannot journal transaction {txhash}: {:?}", err);
+            }
+            log::info!("settled escrow {} with {txhash}", record.escrow.id);
+            if status == EscrowStatus::Releasing {
+                EscrowStatus::Released
+            } else {
+                EscrowStatus::Refunded
+            }
+        };
+        self.database
+            .update_escrow(&record.escrow.id, status, &txn)
+            .await
+            .expect("db failed");
+        Ok(self.escrow_record(&record.escrow.id).await?.escrow)
+    }
+
+    /// Decodes a base32-encoded secret key given when creating a wallet, or generates a fresh one.
+    fn secret_key(secret: Option<String>) -> Result<Ed25519SK, CreateWalletError> {
+        if let Some(secret) = secret {
+            // We must reconstruct the secret key using the ed25519-dalek library
+            let secret = base32::decode(Alphabet::Crockford, &secret).ok_or_else(|| {
+                CreateWalletError::SecretKey("Failed to decode secret key".to_owned())
+            })?;
+            let secret = ed25519_dalek::SecretKey::from_bytes(&secret).map_err(|_| {
+                CreateWalletError::SecretKey("Failed to create secret key".to_owned())
+            })?;
+            let public: ed25519_dalek::PublicKey = (&secret).into();
+            let mut vv = [0u8; 64];
+            vv[0..32].copy_from_slice(&secret.to_bytes());
+            vv[32..].copy_from_slice(&public.to_bytes());
+            Ok(Ed25519SK(vv))
+        } else {
+            Ok(Ed25519SK::generate())
+        }
+    }
+
+    /// Looks up a cold-signing request of a wallet.
+    async fn signing_request(
+        &self,
+        wallet_name: &str,
+        txhash: TxHash,
+    ) -> Result<(Wallet, SigningRequest), NeedWallet<ColdSigningError>> {
+        let wallet = self
+            .get_wallet(wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let req = self
+            .database
+            .get_signing_request(wallet_name, txhash)
+            .await
+            .expect("db failed")
+            .ok_or(ColdSigningError::NoSuchRequest(txhash))?;
+        Ok((wallet, req))
+    }
+
+    /// Prepares a transaction according to a template, signing it with the given signer. The coins in `exclude` are not used, unless the template lists them as inputs.
+    async fn prepare_with_signer(
+        &self,
+        wallet_name: &str,
+        request: ExtPrepareTxArgs,
+        signing_key: Arc<dyn Signer>,
+        exclude: &BTreeSet<CoinID>,
+    ) -> Result<Transaction, NeedWallet<PrepareTxError>> {
+        let wallet = self
+            .get_wallet(wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+
+        // calculate fees, from the last header seen if offline
+        let (fee_multiplier, snapshot) = if self.config.offline {
+            let header = self
+                .database
+                .cached_header()
+                .await
+                .expect("db failed")
+                .ok_or_else(|| {
+                    PrepareTxError::Network(NetworkError::Fatal(
+                        "offline, and no block header was ever seen".into(),
+                    ))
+                })?;
+            (header.fee_multiplier, None)
+        } else {
+            let snapshot = self
+                .latest_snapshot()
+                .await
+                .map_err(|e| PrepareTxError::Network(NetworkError::Transient(e.to_string())))?;
+            (snapshot.current_header().fee_multiplier, Some(snapshot))
+        };
+        let fee_ballast = match request.fee_ballast {
+            Some(ballast) => ballast,
+            None => wallet.default_fee_ballast().await,
+        };
+        let plugin = self
+            .wallet_plugin(wallet_name)
+            .await
+            .map_err(|e| PrepareTxError::Network(NetworkError::Fatal(e.to_string())))?;
+        let sponsor = match request.fee_sponsor.as_deref() {
+            Some(name) if name == wallet_name => None,
+            Some(name) => {
+                let sponsor = self.get_wallet(name).await.ok_or_else(|| {
+                    NeedWallet::Wallet(WalletAccessError::Other(format!(
+                        "fee sponsor {name} not found"
+                    )))
+                })?;
+                let signer = self.use_signer(name).await.ok_or_else(|| {
+                    NeedWallet::Wallet(WalletAccessError::Other(format!(
+                        "fee sponsor {name} is locked"
+                    )))
+                })?;
+                let coins: BTreeSet<CoinID> = sponsor
+                    .get_coin_mapping(true, false)
+                    .await
+                    .into_keys()
+                    .collect();
+                Some((sponsor, signer, coins))
+            }
+            None => None,
+        };
+        let sponsor_signer = sponsor
+            .as_ref()
+            .map(|(_, signer, coins)| (signer.clone(), coins.clone()));
+
+        let sign = {
+            let covenants: Vec<Bytes> = request
+                .covenants
+                .iter()
+                .map(|cb| Bytes::copy_from_slice(cb))
+                .collect();
+            let kind = request.kind;
+            let data: Bytes = request.data.into();
+            move |mut tx: Transaction| {
+                tx.kind = kind;
+
+                tx.data = data.clone();
+
+                tx.covenants.extend_from_slice(&covenants);
+                if let Some((plugin, params)) = &plugin {
+                    tx = plugin.prepare_hook(tx, params)?;
+                }
+                for i in 0..tx.inputs.len() {
+                    tx = match &sponsor_signer {
+                        // inputs from the fee sponsor are signed by the sponsor
+                        Some((signer, coins)) if coins.contains(&tx.inputs[i]) => {
+                            signer.sign_tx(tx, i)?
+                        }
+                        _ => signing_key.sign_tx(tx, i)?,
+                    };
+                }
+                Ok(tx)
+            }
+        };
+        let change_address = match request.change_address.as_deref() {
+            Some(address) => Some(parse_address(address).ok_or_else(|| {
+                PrepareTxError::Network(NetworkError::Fatal(format!(
+                    "invalid change address {address}"
+                )))
+            })?),
+            None => None,
+        };
+        // coin control: leave out coins of protected denominations, and those whose labels the request rules out
+        let mut exclude = exclude.clone();
+        let exclude_denoms = match request.exclude_denoms.clone() {
+            Some(denoms) => denoms,
+            None => wallet.default_exclude_denoms().await,
+        };
+        if !exclude_denoms.is_empty() {
+            exclude.extend(
+                wallet
+                    .get_coin_mapping(true, false)
+                    .await
+                    .into_iter()
+                    .filter(|(_, data)| exclude_denoms.contains(&data.denom))
+                    .map(|(coin, _)| coin),
+            );
+        }
+        if !request.spend_labels.is_empty() || !request.avoid_labels.is_empty() {
+            let labels = wallet
+                .coin_labels()
+                .await
+                .map_err(|e| PrepareTxError::Network(NetworkError::Fatal(e.to_string())))?;
+            let no_labels = BTreeSet::new();
+            for coin in wallet.get_coin_mapping(true, false).await.into_keys() {
+                let coin_labels = labels.get(&coin).unwrap_or(&no_labels);
+                let wanted = request.spend_labels.is_empty()
+                    || request.spend_labels.iter().any(|l| coin_labels.contains(l));
+                let avoided = request.avoid_labels.iter().any(|l| coin_labels.contains(l));
+                if !wanted || avoided {
+                    exclude.insert(coin);
+                }
+            }
+        }
+        // TODO only shortfalls get the right error. We should have Wallet return a PrepareTxError.
+        let prepared_tx = wallet
+            .prepare(
+                request.inputs.clone(),
+                request.outputs.clone(),
+                fee_multiplier,
+                Arc::new(Box::new(sign)),
+                request.nobalance.clone(),
+                fee_ballast,
+                self.config.min_fee,
+                &exclude,
+                request.coin_selection,
+                snapshot,
+                sponsor.as_ref().map(|(sponsor, _, _)| sponsor),
+                change_address,
+            )
+            .await
+            .map_err(prepare_error)?;
+        if let Some(max_fee) = self.config.max_fee {
+            if prepared_tx.fee > max_fee && !request.allow_high_fee {
+                return Err(PrepareTxError::Network(NetworkError::Fatal(format!(
+                    "fee of {} MEL is above the configured maximum of {} MEL; set allow_high_fee to pay it anyway",
+                    prepared_tx.fee, max_fee
+                )))
+                .into());
+            }
+        }
+
+        Ok(prepared_tx)
+    }
+}
+
+/// Checks an approver token against the hash of a wallet's approver token.
+fn check_approver(hash: HashVal, approver_token: &str) -> Result<(), ApprovalError> {
+    if !constant_time_eq(
+        &tmelcrypt::hash_single(approver_token.as_bytes()).0,
+        &hash.0,
+    ) {
+        return Err(ApprovalError::WrongApprover);
+    }
+    Ok(())
+}
+
+/// Turns an error from [crate::database::Wallet::prepare] into a [PrepareTxError], keeping shortfalls structured.
+fn prepare_error(err: anyhow::Error) -> PrepareTxError {
+    match err.downcast_ref::<InsufficientFunds>() {
+        Some(funds) => match funds.denom.parse() {
+            Ok(denom) => {
+                log::debug!("{:#}", err);
+                PrepareTxError::InsufficientFunds(funds.missing(), denom)
+            }
+            Err(_) => PrepareTxError::Network(NetworkError::Fatal(err.to_string())),
+        },
+        None => PrepareTxError::Network(NetworkError::Fatal(err.to_string())),
+    }
+}
+
+#[async_trait]
+impl MelwalletdExtProtocol for AppState {
+    async fn password_strength(&self, password: String) -> PasswordStrength {
+        self.config.password_policy.check(&password)
+    }
+
+    async fn change_password(
+        &self,
+        wallet_name: String,
+        old_password: String,
+        new_password: String,
+    ) -> Result<PasswordStrength, NeedWallet<WeakPasswordError>> {
+        if self.totp_active(&wallet_name) {
+            return Err(NeedWallet::Wallet(WalletAccessError::Other(
+                "TOTP is enabled; use change_password_with_totp".into(),
+            )));
+        }
+        self.change_password_inner(&wallet_name, &old_password, &new_password)
+            .await
+    }
+
+    async fn rotate_key(
+        &self,
+        wallet_name: String,
+        password: String,
+    ) -> Result<KeyRotationStatus, NeedWallet<NetworkError>> {
+        if self.get_wallet(&wallet_name).await.is_none() {
+            return Err(NeedWallet::Wallet(WalletAccessError::NotFound));
+        }
+        if self
+            .get_secret_key(&wallet_name, &password)
+            .ok()
+            .flatten()
+            .is_none()
+        {
+            return Err(NeedWallet::Wallet(WalletAccessError::Locked));
+        }
+        if self
+            .database
+            .wallet_type(&wallet_name)
+            .await
+            .expect("db failed")
+            .is_some()
+        {
+            return Err(NeedWallet::Wallet(WalletAccessError::Other(
+                "only standard wallets can rotate their keys".into(),
+            )));
+        }
+        self.rotate_key(&wallet_name, &password)
+            .await
+            .map_err(|e| NetworkError::Transient(e.to_string()))?;
+        self.key_rotation_status(&wallet_name)
+            .await
+            .map_err(|e| NeedWallet::Wallet(WalletAccessError::Other(e.to_string())))?
+            .ok_or_else(|| NetworkError::Fatal("rotation finished unexpectedly".into()).into())
+    }
+
+    async fn key_rotation_status(
+        &self,
+        wallet_name: String,
+    ) -> Result<Option<KeyRotationStatus>, WalletAccessError> {
+        if self.get_wallet(&wallet_name).await.is_none() {
+            return Err(WalletAccessError::NotFound);
+        }
+        self.key_rotation_status(&wallet_name)
+            .await
+            .map_err(|e| WalletAccessError::Other(e.to_string()))
+    }
+
+    async fn track_address(
+        &self,
+        address: String,
+        label: String,
+    ) -> Result<(), InvalidAddressError> {
+        let covhash =
+            parse_address(&address).ok_or_else(|| InvalidAddressError(address.clone()))?;
+        self.database
+            .track_address(covhash, &label)
+            .await
+            .expect("db failed");
+        Ok(())
+    }
+
+    async fn untrack_address(&self, address: String) -> Result<bool, InvalidAddressError> {
+        let covhash =
+            parse_address(&address).ok_or_else(|| InvalidAddressError(address.clone()))?;
+        Ok(self
+            .database
+            .untrack_address(covhash)
+            .await
+            .expect("db failed"))
+    }
+
+    async fn tracked_addresses(&self) -> Vec<TrackedAddress> {
+        self.database.list_tracked().await.expect("db failed")
+    }
+
+    async fn prepare_tx(
+        &self,
+        wallet_name: String,
+        request: ExtPrepareTxArgs,
+    ) -> Result<PreparedTx, NeedWallet<PrepareTxError>> {
+        let signing_key = self
+            .prepare_signer(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::Locked))?;
+        let transaction = self
+            .prepare_with_signer(&wallet_name, request, signing_key, &BTreeSet::new())
+            .await?;
+        Ok(PreparedTx {
+            weight: transaction.weight(covenant_weight_from_bytes),
+            transaction,
+        })
+    }
+
+    async fn prepare_split_tx(
+        &self,
+        wallet_name: String,
+        request: ExtPrepareTxArgs,
+    ) -> Result<Vec<PreparedTx>, NeedWallet<PrepareTxError>> {
+        let signing_key = self
+            .prepare_signer(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::Locked))?;
+
+        // greedily group outputs, leaving room for change outputs and for the weight of inputs
+        let change_slots = (request
+            .outputs
+            .iter()
+            .map(|o| o.denom)
+            .chain(std::iter::once(Denom::Mel))
+            .collect::<BTreeSet<_>>()
+            .len())
+            * 2;
+        let max_outputs = MAX_TX_OUTPUTS.saturating_sub(change_slots).max(1);
+        let chunks = fees::split_outputs(&request.outputs, max_outputs, MAX_TX_WEIGHT / 2);
+
+        let mut exclude = BTreeSet::new();
+        let mut prepared = vec![];
+        for (i, outputs) in chunks.into_iter().enumerate() {
+            let chunk_request = ExtPrepareTxArgs {
+                inputs: if i == 0 {
+                    request.inputs.clone()
+                } else {
+                    vec![]
+                },
+                outputs,
+                ..request.clone()
+            };
+            let transaction = self
+                .prepare_with_signer(&wallet_name, chunk_request, signing_key.clone(), &exclude)
+                .await?;
+            exclude.extend(transaction.inputs.iter().copied());
+            prepared.push(PreparedTx {
+                weight: transaction.weight(covenant_weight_from_bytes),
+                transaction,
+            });
+        }
+        if prepared.len() > 1 {
+            log::info!(
+                "split outputs of {wallet_name} across {} transactions",
+                prepared.len()
+            );
+        }
+        Ok(prepared)
+    }
+
+    async fn import_coin(
+        &self,
+        wallet_name: String,
+        coin_id: CoinID,
+        covenant: Option<String>,
+    ) -> Result<CoinDataHeight, NeedWallet<ImportCoinError>> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let signer = self
+            .use_signer(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::Locked))?;
+        let snapshot = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| ImportCoinError::Network(e.to_string()))?;
+        let cdh = snapshot
+            .get_coin(coin_id)
+            .await
+            .map_err(|e| ImportCoinError::Network(e.to_string()))?
+            .ok_or(ImportCoinError::NotFound)?;
+
+        let covenant = match covenant {
+            Some(covenant) => hex::decode(covenant)
+                .ok()
+                .and_then(|b| Covenant::from_bytes(&b).ok())
+                .ok_or(ImportCoinError::BadCovenant)?,
+            None if cdh.coin_data.covhash == wallet.address() => signer.covenant(),
+            None => {
+                return Err(
+                    ImportCoinError::CovenantNeeded(cdh.coin_data.covhash.to_string()).into(),
+                )
+            }
+        };
+        if covenant.hash() != cdh.coin_data.covhash {
+            return Err(ImportCoinError::BadCovenant.into());
+        }
+
+        // check spendability by running the covenant against a signed transaction moving the coin to ourselves
+        let trial = Transaction {
+            kind: TxKind::Normal,
+            inputs: vec![coin_id],
+            outputs: vec![CoinData {
+                covhash: wallet.address(),
+                ..cdh.coin_data.clone()
+            }],
+            fee: CoinValue(0),
+            covenants: vec![covenant.to_bytes()],
+            data: Default::default(),
+            sigs: vec![],
+        };
+        let trial = signer
+            .sign_tx(trial, 0)
+            .map_err(|e| NeedWallet::Wallet(WalletAccessError::Other(e.to_string())))?;
+        let env = CovenantEnv {
+            parent_coinid: coin_id,
+            parent_cdh: cdh.clone(),
+            spender_index: 0,
+            last_header: snapshot.current_header(),
+        };
+        if !covenant
+            .execute(&trial, Some(env))
+            .map(|v| v.into_bool())
+            .unwrap_or(false)
+        {
+            return Err(ImportCoinError::NotSpendable.into());
+        }
+
+        wallet
+            .import_coin(coin_id, &cdh, &covenant)
+            .await
+            .expect("db failed");
+        log::info!("imported coin {coin_id} into {wallet_name}");
+        Ok(cdh)
+    }
+
+    async fn evaluate_covenant(
+        &self,
+        request: EvaluateCovenantArgs,
+    ) -> Result<CovenantEvaluation, CovenantEvalError> {
+        let covenant = hex::decode(&request.covenant)
+            .ok()
+            .and_then(|b| Covenant::from_bytes(&b).ok())
+            .ok_or(CovenantEvalError::BadCovenant)?;
+        if covenant.weight() > MAX_EVAL_WEIGHT {
+            return Err(CovenantEvalError::TooHeavy(covenant.weight()));
+        }
+        let env = match request.input_index {
+            Some(index) => {
+                let coin_id = *request
+                    .tx
+                    .inputs
+                    .get(index as usize)
+                    .ok_or(CovenantEvalError::BadInput(index))?;
+                let snapshot = self
+                    .latest_snapshot()
+                    .await
+                    .map_err(|e| CovenantEvalError::Network(e.to_string()))?;
+                let cdh = self
+                    .chain_cache
+                    .get_coin(&snapshot, coin_id)
+                    .await
+                    .map_err(|e| CovenantEvalError::Network(e.to_string()))?
+                    .ok_or_else(|| CovenantEvalError::CoinNotFound(coin_id.to_string()))?;
+                Some(CovenantEnv {
+                    parent_coinid: coin_id,
+                    parent_cdh: cdh,
+                    spender_index: index,
+                    last_header: snapshot.current_header(),
+                })
+            }
+            None => None,
+        };
+        let tx = request.tx;
+        Ok(smol::unblock(move || covenant::evaluate(&covenant, &tx, env)).await)
+    }
+
+    async fn to_display_units(
+        &self,
+        denom: String,
+        raw: CoinValue,
+    ) -> Result<String, UnitConversionError> {
+        let denom: Denom = denom
+            .parse()
+            .map_err(|_| UnitConversionError::InvalidDenom(denom))?;
+        Ok(self.config.token_registry.format_display(denom, raw))
+    }
+
+    async fn from_display_units(
+        &self,
+        denom: String,
+        display: String,
+    ) -> Result<CoinValue, UnitConversionError> {
+        let denom: Denom = denom
+            .parse()
+            .map_err(|_| UnitConversionError::InvalidDenom(denom))?;
+        self.config.token_registry.parse_display(denom, &display)
+    }
+
+    async fn wait_for_confirmation(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+        timeout_secs: u64,
+    ) -> Result<ConfirmationOutcome, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        let deadline =
+            Instant::now() + Duration::from_secs(timeout_secs.min(MAX_CONFIRMATION_WAIT_SECS));
+        loop {
+            // listen before checking, so that a sync finishing in between isn't missed
+            let synced = self.synced.listen();
+            match MelwalletdProtocol::tx_status(self, wallet_name.clone(), txhash.0).await? {
+                Some(TransactionStatus {
+                    confirmed_height: Some(height),
+                    ..
+                }) => return Ok(ConfirmationOutcome::Confirmed(height)),
+                Some(_) => (),
+                None => return Ok(ConfirmationOutcome::Lost),
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if synced.timeout(remaining).await.is_none() {
+                return Ok(ConfirmationOutcome::TimedOut);
+            }
+        }
+    }
+
+    async fn cancel(&self, handle: String) -> bool {
+        match self.in_flight.remove(&handle) {
+            Some((_, abort)) => {
+                abort.abort();
+                log::info!("cancelled RPC call {handle}");
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn create_invoice(
+        &self,
+        wallet_name: String,
+        amount: CoinValue,
+        denom: String,
+        expires_in_secs: u64,
+        webhook: Option<String>,
+    ) -> Result<Invoice, InvoiceError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(InvoiceError::WalletNotFound)?;
+        let denom: Denom = denom
+            .parse()
+            .map_err(|_| InvoiceError::InvalidDenom(denom))?;
+        if amount == CoinValue(0) {
+            return Err(InvoiceError::ZeroAmount);
+        }
+        if let Some(url) = webhook.as_ref() {
+            if !valid_webhook(url) {
+                return Err(InvoiceError::BadWebhook(url.clone()));
+            }
+        }
+        let invoice = self
+            .database
+            .create_invoice(&wallet_name, amount, denom, expires_in_secs, webhook)
+            .await
+            .expect("db failed");
+        log::info!(
+            "created invoice {} for {} {} to {wallet_name}",
+            invoice.id,
+            invoice.amount,
+            invoice.denom
+        );
+        Ok(invoice)
+    }
+
+    async fn invoice_status(&self, invoice_id: String) -> Option<Invoice> {
+        self.database
+            .get_invoice(&invoice_id)
+            .await
+            .expect("db failed")
+    }
+
+    async fn list_invoices(&self, wallet_name: String) -> Result<Vec<Invoice>, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self
+            .database
+            .list_invoices(Some(&wallet_name))
+            .await
+            .expect("db failed"))
+    }
+
+    async fn wallet_types(&self) -> Vec<String> {
+        self.plugins.kinds()
+    }
+
+    async fn create_typed_wallet(
+        &self,
+        wallet_name: String,
+        kind: String,
+        password: String,
+        secret: Option<String>,
+        params: String,
+    ) -> Result<(), CreateWalletError> {
+        let strength = self.config.password_policy.check(&password);
+        if !strength.acceptable {
+            return Err(CreateWalletError::Other(
+                WeakPasswordError(strength).to_string(),
+            ));
+        }
+        let params = hex::decode(params)
+            .map_err(|e| CreateWalletError::Other(format!("invalid parameters: {e}")))?;
+        let sk = Self::secret_key(secret)?;
+        self.create_typed_wallet(&wallet_name, &kind, params, sk, password)
+            .await
+            .map_err(|e| CreateWalletError::Other(e.to_string()))
+    }
+
+    async fn wallet_type(&self, wallet_name: String) -> Result<String, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self
+            .database
+            .wallet_type(&wallet_name)
+            .await
+            .expect("db failed")
+            .map_or_else(|| STANDARD_WALLET.to_owned(), |(kind, _)| kind))
+    }
+
+    async fn prepare_timelocked_tx(
+        &self,
+        wallet_name: String,
+        outputs: Vec<TimelockedOutput>,
+        unlock_height: BlockHeight,
+    ) -> Result<Transaction, NeedWallet<PrepareTxError>> {
+        let request = ExtPrepareTxArgs {
+            kind: TxKind::Normal,
+            inputs: vec![],
+            outputs: outputs
+                .iter()
+                .map(|output| CoinData {
+                    covhash: timelock_covenant(output.recipient, unlock_height).hash(),
+                    value: output.value,
+                    denom: output.denom,
+                    additional_data: Default::default(),
+                })
+                .collect(),
+            covenants: vec![],
+            data: vec![],
+            nobalance: vec![],
+            fee_ballast: None,
+            fee_sponsor: None,
+            spend_labels: vec![],
+            avoid_labels: vec![],
+            coin_selection: CoinSelection::Arbitrary,
+            allow_high_fee: false,
+            change_address: None,
+            exclude_denoms: None,
+        };
+        let tx = MelwalletdExtProtocol::prepare_tx(self, wallet_name.clone(), request)
+            .await?
+            .transaction;
+        // required outputs come first
+        for (index, output) in outputs.iter().enumerate() {
+            self.database
+                .insert_timelock(
+                    &wallet_name,
+                    CoinID {
+                        txhash: tx.hash_nosigs(),
+                        index: index as u8,
+                    },
+                    output.recipient,
+                    unlock_height,
+                    output.value,
+                    output.denom,
+                )
+                .await
+                .expect("db failed");
+        }
+        Ok(tx)
+    }
+
+    async fn timelocked_coins(
+        &self,
+        wallet_name: String,
+    ) -> Result<Vec<TimelockedCoin>, WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self
+            .database
+            .list_timelocks(&wallet)
+            .await
+            .expect("db failed"))
+    }
+
+    async fn configure_inheritance(
+        &self,
+        wallet_name: String,
+        password: String,
+        recovery_address: String,
+        inactivity_secs: u64,
+    ) -> Result<InheritanceStatus, NeedWallet<InheritanceError>> {
+        let recovery = parse_address(&recovery_address)
+            .ok_or(InheritanceError::InvalidAddress(recovery_address))?;
+        let (wallet, sk) = self.wallet_with_key(&wallet_name, &password).await?;
+        let sweeps = self
+            .presign_inheritance_sweeps(&wallet, recovery, &sk)
+            .await?;
+        self.database
+            .set_inheritance(&wallet_name, recovery, inactivity_secs, &sweeps)
+            .await
+            .expect("db failed");
+        log::info!("set up dead-man switch of {wallet_name} to {recovery}");
+        Ok(self.inheritance_status_inner(&wallet_name).await?)
+    }
+
+    async fn ping_inheritance(
+        &self,
+        wallet_name: String,
+        password: String,
+    ) -> Result<InheritanceStatus, NeedWallet<InheritanceError>> {
+        let (wallet, sk) = self.wallet_with_key(&wallet_name, &password).await?;
+        let record = self
+            .database
+            .get_inheritance(&wallet_name)
+            .await
+            .expect("db failed")
+            .ok_or(InheritanceError::NotConfigured)?;
+        if record.triggered.is_some() {
+            return Err(InheritanceError::AlreadyTriggered.into());
+        }
+        let sweeps = self
+            .presign_inheritance_sweeps(&wallet, record.recovery, &sk)
+            .await?;
+        self.database
+            .set_inheritance_sweeps(&wallet_name, &sweeps)
+            .await
+            .expect("db failed");
+        self.database
+            .touch_inheritance(&wallet_name)
+            .await
+            .expect("db failed");
+        Ok(self.inheritance_status_inner(&wallet_name).await?)
+    }
+
+    async fn cancel_inheritance(
+        &self,
+        wallet_name: String,
+        password: String,
+    ) -> Result<bool, WalletAccessError> {
+        self.wallet_with_key::<WalletAccessError>(&wallet_name, &password)
+            .await
+            .map_err(|e| match e {
+                NeedWallet::Wallet(e) | NeedWallet::Other(e) => e,
+            })?;
+        let existed = self
+            .database
+            .delete_inheritance(&wallet_name)
+            .await
+            .expect("db failed");
+        if existed {
+            log::info!("removed dead-man switch of {wallet_name}");
+        }
+        Ok(existed)
+    }
+
+    async fn inheritance_status(
+        &self,
+        wallet_name: String,
+    ) -> Result<Option<InheritanceStatus>, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        match self.inheritance_status_inner(&wallet_name).await {
+            Ok(status) => Ok(Some(status)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    async fn uri_handler_info(&self) -> UriHandlerInfo {
+        UriHandlerInfo {
+            scheme: URI_SCHEME.to_owned(),
+            example: URI_EXAMPLE.to_owned(),
+            rpc_url: format!("http://{}", self.config.listen),
+            port: self.config.listen.port(),
+            network: self.network,
+            auth_token_hint: (!self.config.users.is_empty())
+                .then(|| "Authorization: Bearer <token>".to_owned()),
+            allowed_origins: self.config.allowed_origins.clone(),
+        }
+    }
+
+    async fn parse_payment_uri(
+        &self,
+        wallet_name: String,
+        uri: String,
+    ) -> Result<ExtPrepareTxArgs, NeedWallet<PaymentUriError>> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let output = parse_payment_uri(&uri, &self.config.token_registry)?;
+        Ok(ExtPrepareTxArgs {
+            kind: TxKind::Normal,
+            inputs: vec![],
+            outputs: vec![output],
+            covenants: vec![],
+            data: vec![],
+            nobalance: vec![],
+            fee_ballast: None,
+            fee_sponsor: None,
+            spend_labels: vec![],
+            avoid_labels: vec![],
+            coin_selection: CoinSelection::Arbitrary,
+            allow_high_fee: false,
+            change_address: None,
+            exclude_denoms: None,
+        })
+    }
+
+    async fn create_escrow(
+        &self,
+        buyer: String,
+        seller: String,
+        arbiter: String,
+    ) -> Result<Escrow, EscrowError> {
+        let parse = |key: String| -> Result<Ed25519PK, EscrowError> {
+            key.parse().map_err(|_| EscrowError::InvalidKey(key))
+        };
+        let parties = [parse(buyer)?, parse(seller)?, parse(arbiter)?];
+        let record = self
+            .database
+            .create_escrow(parties)
+            .await
+            .expect("db failed");
+        log::info!(
+            "created escrow {} at {}",
+            record.escrow.id,
+            record.escrow.address
+        );
+        Ok(record.escrow)
+    }
+
+    async fn escrow_status(&self, escrow_id: String) -> Option<Escrow> {
+        self.database
+            .get_escrow(&escrow_id)
+            .await
+            .expect("db failed")
+            .map(|record| record.escrow)
+    }
+
+    async fn list_escrows(&self) -> Vec<Escrow> {
+        self.database.list_escrows().await.expect("db failed")
+    }
+
+    async fn fund_escrow(
+        &self,
+        escrow_id: String,
+        wallet_name: String,
+        value: CoinValue,
+    ) -> Result<Escrow, NeedWallet<EscrowError>> {
+        let record = self.escrow_record(&escrow_id).await?;
+        if record.escrow.status != EscrowStatus::Created {
+            return Err(EscrowError::WrongStatus(record.escrow.status).into());
+        }
+        let request = ExtPrepareTxArgs {
+            kind: TxKind::Normal,
+            inputs: vec![],
+            outputs: vec![CoinData {
+                covhash: record.escrow.address,
+                value,
+                denom: Denom::Mel,
+                additional_data: Default::default(),
+            }],
+            covenants: vec![],
+            data: vec![],
+            nobalance: vec![],
+            fee_ballast: None,
+            fee_sponsor: None,
+            spend_labels: vec![],
+            avoid_labels: vec![],
+            coin_selection: CoinSelection::Arbitrary,
+            allow_high_fee: false,
+            change_address: None,
+            exclude_denoms: None,
+        };
+        let tx = MelwalletdExtProtocol::prepare_tx(self, wallet_name.clone(), request)
+            .await
+            .map_err(|e| match e {
+                NeedWallet::Wallet(e) => NeedWallet::Wallet(e),
+                NeedWallet::Other(e) => EscrowError::Prepare(e.to_string()).into(),
+            })?
+            .transaction;
+        let txhash = MelwalletdProtocol::send_tx(self, wallet_name, tx)
+            .await
+            .map_err(|e| match e {
+                NeedWallet::Wallet(e) => NeedWallet::Wallet(e),
+                NeedWallet::Other(e) => EscrowError::Network(e.to_string()).into(),
+            })?;
+        // required outputs come first, so the escrowed coin is the first output
+        let coin = CoinID { txhash, index: 0 };
+        self.database
+            .fund_escrow(&escrow_id, coin, value)
+            .await
+            .expect("db failed");
+        log::info!("funded escrow {escrow_id} with {value} MEL");
+        Ok(self.escrow_record(&escrow_id).await?.escrow)
+    }
+
+    async fn release_escrow(&self, escrow_id: String) -> Result<Escrow, EscrowError> {
+        self.propose_escrow_settlement(&escrow_id, EscrowRole::Seller)
+            .await
+    }
+
+    async fn refund_escrow(&self, escrow_id: String) -> Result<Escrow, EscrowError> {
+        self.propose_escrow_settlement(&escrow_id, EscrowRole::Buyer)
+            .await
+    }
+
+    async fn sign_escrow(
+        &self,
+        escrow_id: String,
+        wallet_name: String,
+        password: String,
+    ) -> Result<Escrow, NeedWallet<EscrowError>> {
+        let record = self.escrow_record(&escrow_id).await?;
+        let sk = self
+            .get_secret_key(&wallet_name, &password)
+            .map_err(|_| NeedWallet::Wallet(WalletAccessError::Locked))?
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        if !record.parties.contains(&sk.to_public()) {
+            return Err(EscrowError::NotAParty.into());
+        }
+        let txhash = record
+            .escrow
+            .transaction
+            .as_ref()
+            .ok_or(EscrowError::WrongStatus(record.escrow.status))?
+            .hash_nosigs();
+        let signature = sk.sign(&txhash.0);
+        Ok(self.attach_escrow_signature(record, signature).await?)
+    }
+
+    async fn add_escrow_signature(
+        &self,
+        escrow_id: String,
+        signature: String,
+    ) -> Result<Escrow, EscrowError> {
+        let record = self.escrow_record(&escrow_id).await?;
+        let signature = hex::decode(signature).map_err(|_| EscrowError::BadSignature)?;
+        self.attach_escrow_signature(record, signature).await
+    }
+
+    async fn daemon_stats(&self) -> DaemonStats {
+        let (coins, database_bytes) = self.database.usage().await.expect("db failed");
+        let (chain_cache_hits, chain_cache_misses) = self.chain_cache.hit_stats();
+        DaemonStats {
+            wallets: self.database.list_wallets().await.len(),
+            coins,
+            database_bytes,
+            uptime_secs: self.started.elapsed().as_secs(),
+            rpc_calls: self
+                .rpc_calls
+                .iter()
+                .map(|kv| (kv.key().clone(), *kv.value()))
+                .collect(),
+            chain_cache_hits,
+            chain_cache_misses,
+            node: self.chain_cache.throttle().stats(),
+            repairs: self.database.repairs().to_vec(),
+        }
+    }
+
+    async fn set_default_fee_ballast(
+        &self,
+        wallet_name: String,
+        fee_ballast: usize,
+    ) -> Result<(), WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        wallet
+            .set_default_fee_ballast(fee_ballast)
+            .await
+            .map_err(|e| WalletAccessError::Other(e.to_string()))
+    }
+
+    async fn default_fee_ballast(&self, wallet_name: String) -> Result<usize, WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(wallet.default_fee_ballast().await)
+    }
+
+    async fn set_default_exclude_denoms(
+        &self,
+        wallet_name: String,
+        denoms: BTreeSet<Denom>,
+    ) -> Result<(), WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        wallet
+            .set_default_exclude_denoms(&denoms)
+            .await
+            .map_err(|e| WalletAccessError::Other(e.to_string()))
+    }
+
+    async fn default_exclude_denoms(
+        &self,
+        wallet_name: String,
+    ) -> Result<BTreeSet<Denom>, WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(wallet.default_exclude_denoms().await)
+    }
+
+    async fn set_consolidation_policy(
+        &self,
+        wallet_name: String,
+        policy: Option<ConsolidationPolicy>,
+    ) -> Result<(), WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        if policy.as_ref().is_some_and(|policy| policy.max_utxos == 0) {
+            return Err(WalletAccessError::Other("max_utxos must be nonzero".into()));
+        }
+        self.database
+            .set_consolidation_policy(&wallet_name, policy.as_ref())
+            .await
+            .expect("db failed");
+        log::info!("set consolidation policy of {wallet_name} to {:?}", policy);
+        Ok(())
+    }
+
+    async fn consolidation_policy(
+        &self,
+        wallet_name: String,
+    ) -> Result<Option<ConsolidationPolicy>, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self
+            .database
+            .consolidation_policy(&wallet_name)
+            .await
+            .expect("db failed"))
+    }
+
+    async fn transaction_cache_stats(&self) -> TransactionCacheStats {
+        self.database
+            .transaction_cache_stats()
+            .await
+            .expect("db failed")
+    }
+
+    async fn prune_transaction_cache(&self) -> u64 {
+        let removed = self
+            .database
+            .prune_transaction_cache()
+            .await
+            .expect("db failed");
+        log::info!("pruned {removed} transactions from the cache");
+        removed
+    }
+
+    async fn create_watch_only_wallet(
+        &self,
+        wallet_name: String,
+        public_key: String,
+    ) -> Result<(), CreateWalletError> {
+        let pubkey: Ed25519PK = public_key
+            .parse()
+            .map_err(|_| CreateWalletError::SecretKey("invalid public key".to_owned()))?;
+        if self.get_wallet(&wallet_name).await.is_some() {
+            return Err(CreateWalletError::WalletExists);
+        }
+        self.database
+            .create_watch_only_wallet(&wallet_name, pubkey)
+            .await
+            .map_err(|e| CreateWalletError::Other(e.to_string()))?;
+        log::info!("created watch-only wallet {wallet_name} for {pubkey}");
+        Ok(())
+    }
+
+    async fn export_public_key(
+        &self,
+        wallet_name: String,
+        password: String,
+    ) -> Result<String, WalletAccessError> {
+        if let Some(pubkey) = self
+            .database
+            .watch_only_key(&wallet_name)
+            .await
+            .expect("db failed")
+        {
+            return Ok(pubkey.to_string());
+        }
+        let sk = self
+            .get_secret_key(&wallet_name, &password)
+            .map_err(|_| WalletAccessError::Locked)?
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(sk.to_public().to_string())
+    }
+
+    async fn export_descriptor(
+        &self,
+        wallet_name: String,
+    ) -> Result<WalletDescriptor, WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        let (plugin, params) = match self
+            .wallet_plugin(&wallet_name)
+            .await
+            .map_err(|e| WalletAccessError::Other(e.to_string()))?
+        {
+            Some(plugin) => plugin,
+            None => (
+                self.plugins
+                    .get(STANDARD_WALLET)
+                    .expect("standard wallet type missing"),
+                vec![],
+            ),
+        };
+        let watch_only = self
+            .database
+            .watch_only_key(&wallet_name)
+            .await
+            .expect("db failed");
+        let key_origin = if watch_only.is_some() {
+            KeyOrigin::Cold
+        } else if self.secrets.load(&wallet_name).is_some() {
+            KeyOrigin::Local
+        } else {
+            KeyOrigin::Unknown
+        };
+        let public_key = watch_only.or_else(|| {
+            let covenant = Covenant::from_bytes(wallet.covenant()).ok()?;
+            recover_public_key(&covenant, plugin.as_ref(), &params)
+        });
+        let imported = wallet
+            .imported_covenants()
+            .await
+            .expect("db failed")
+            .into_iter()
+            .map(|(address, covenant)| DescriptorCovenant {
+                address,
+                covenant: covenant.to_vec(),
+            })
+            .collect();
+        Ok(WalletDescriptor {
+            wallet_name,
+            network: self.network,
+            kind: plugin.kind().to_owned(),
+            descriptor: public_key.map(|pk| descriptor_string(plugin.kind(), pk, &params)),
+            public_key,
+            key_origin,
+            address: wallet.address(),
+            covenant: wallet.covenant().to_vec(),
+            params,
+            imported,
+            derivation: None,
+        })
+    }
+
+    async fn backup_now(&self) -> Result<BackupInfo, BackupError> {
+        AppState::backup_now(self).await
+    }
+
+    async fn list_backups(&self) -> Result<Vec<String>, BackupError> {
+        AppState::list_backups(self).await
+    }
+
+    async fn verify_backup(&self, name: String) -> Result<BackupInfo, BackupError> {
+        AppState::verify_backup(self, &name).await
+    }
+
+    async fn restore_backup(&self, name: String) -> Result<BackupInfo, BackupError> {
+        AppState::restore_backup(self, &name).await
+    }
+
+    async fn rebuild_from_journal(
+        &self,
+        wallet_name: Option<String>,
+    ) -> Result<JournalReplay, WalletAccessError> {
+        if let Some(name) = wallet_name.as_deref() {
+            self.get_wallet(name)
+                .await
+                .ok_or(WalletAccessError::NotFound)?;
+        }
+        self.database
+            .rebuild_from_journal(wallet_name.as_deref())
+            .await
+            .map_err(|e| WalletAccessError::Other(e.to_string()))
+    }
+
+    async fn set_anomaly_policy(
+        &self,
+        wallet_name: String,
+        password: String,
+        policy: Option<AnomalyPolicy>,
+    ) -> Result<(), NeedWallet<AnomalyError>> {
+        if let Some(policy) = policy.as_ref() {
+            if policy.window_secs == 0 || policy.multiplier.is_nan() || policy.multiplier < 1.0 {
+                return Err(AnomalyError::InvalidPolicy(
+                    "window must be nonzero and multiplier at least 1".into(),
+                )
+                .into());
+            }
+            if let Some(url) = policy.alert_webhook.as_ref() {
+                if !valid_webhook(url) {
+                    return Err(AnomalyError::BadWebhook(url.clone()).into());
+                }
+            }
+        }
+        self.wallet_with_key(&wallet_name, &password).await?;
+        self.database
+            .set_anomaly_policy(&wallet_name, policy.as_ref())
+            .await
+            .expect("db failed");
+        log::info!("set anomaly policy of {wallet_name} to {:?}", policy);
+        Ok(())
+    }
+
+    async fn anomaly_policy(
+        &self,
+        wallet_name: String,
+    ) -> Result<Option<AnomalyPolicy>, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self
+            .database
+            .anomaly_policy(&wallet_name)
+            .await
+            .expect("db failed"))
+    }
+
+    async fn list_held_sends(
+        &self,
+        wallet_name: String,
+    ) -> Result<Vec<HeldSend>, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self
+            .database
+            .list_held_sends(&wallet_name)
+            .await
+            .expect("db failed"))
+    }
+
+    async fn confirm_held_send(
+        &self,
+        wallet_name: String,
+        password: String,
+        txhash: TxHash,
+    ) -> Result<TxHash, NeedWallet<AnomalyError>> {
+        let (wallet, _) = self.wallet_with_key(&wallet_name, &password).await?;
+        let held = self
+            .database
+            .get_held_send(&wallet_name, txhash)
+            .await
+            .expect("db failed")
+            .ok_or(AnomalyError::NotHeld)?;
+        if held.kind != HoldKind::Anomaly {
+            return Err(AnomalyError::NeedsApproval.into());
+        }
+        let txhash = self
+            .release_held_send(&wallet, held)
+            .await
+            .map_err(|e| AnomalyError::Broadcast(e.to_string()))?;
+        log::info!("confirmed held transaction {txhash} of {wallet_name}");
+        Ok(txhash)
+    }
+
+    async fn discard_held_send(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+    ) -> Result<bool, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        let discarded = self
+            .database
+            .delete_held_send(&wallet_name, txhash)
+            .await
+            .expect("db failed");
+        if discarded {
+            self.database
+                .remove_internal_transfer(txhash)
+                .await
+                .expect("db failed");
+        }
+        Ok(discarded)
+    }
+
+    async fn set_approval_mode(
+        &self,
+        wallet_name: String,
+        password: String,
+        enabled: bool,
+        approver_token: Option<String>,
+    ) -> Result<Option<String>, NeedWallet<ApprovalError>> {
+        let (wallet, _) = self.wallet_with_key(&wallet_name, &password).await?;
+        if let Some(hash) = wallet.approval_hash().await {
+            check_approver(hash, approver_token.as_deref().unwrap_or_default())?;
+        }
+        let token = enabled.then(|| {
+            let mut bytes = [0u8; 32];
+            getrandom::getrandom(&mut bytes).expect("no randomness");
+            hex::encode(bytes)
+        });
+        wallet
+            .set_approval_hash(token.as_ref().map(|t| tmelcrypt::hash_single(t.as_bytes())))
+            .await
+            .expect("db failed");
+        log::info!(
+            "{} approval mode of {wallet_name}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        Ok(token)
+    }
+
+    async fn approve_send(
+        &self,
+        wallet_name: String,
+        approver_token: String,
+        txhash: TxHash,
+    ) -> Result<TxHash, NeedWallet<ApprovalError>> {
+        let (wallet, held) = self
+            .pending_approval(&wallet_name, &approver_token, txhash)
+            .await?;
+        let txhash = self
+            .release_held_send(&wallet, held)
+            .await
+            .map_err(|e| ApprovalError::Broadcast(e.to_string()))?;
+        log::info!("approved transaction {txhash} of {wallet_name}");
+        Ok(txhash)
+    }
+
+    async fn reject_send(
+        &self,
+        wallet_name: String,
+        approver_token: String,
+        txhash: TxHash,
+    ) -> Result<(), NeedWallet<ApprovalError>> {
+        self.pending_approval(&wallet_name, &approver_token, txhash)
+            .await?;
+        self.database
+            .delete_held_send(&wallet_name, txhash)
+            .await
+            .expect("db failed");
+        self.database
+            .remove_internal_transfer(txhash)
+            .await
+            .expect("db failed");
+        log::info!("rejected transaction {txhash} of {wallet_name}");
+        Ok(())
+    }
+
+    async fn enable_totp(
+        &self,
+        wallet_name: String,
+        password: String,
+        send_threshold: CoinValue,
+    ) -> Result<TotpEnrollment, NeedWallet<TotpError>> {
+        self.wallet_with_key(&wallet_name, &password).await?;
+        if self.totp_active(&wallet_name) {
+            return Err(TotpError::AlreadyActive.into());
+        }
+        let totp = TotpSecret::generate(send_threshold.0);
+        let enrollment = TotpEnrollment {
+            secret: totp.base32_key(),
+            provisioning_uri: totp.provisioning_uri(&wallet_name),
+        };
+        self.secrets
+            .set_totp(&wallet_name, Some(totp))
+            .expect("cannot write secrets");
+        log::info!("started TOTP enrollment of {wallet_name}");
+        Ok(enrollment)
+    }
+
+    async fn activate_totp(
+        &self,
+        wallet_name: String,
+        totp_code: String,
+    ) -> Result<TotpStatus, NeedWallet<TotpError>> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let totp = self
+            .secrets
+            .totp(&wallet_name)
+            .ok_or(TotpError::NotEnrolled)?;
+        if totp.active {
+            return Err(TotpError::AlreadyActive.into());
+        }
+        self.secrets.check_totp(&wallet_name, &totp_code)?;
+        let totp = TotpSecret {
+            active: true,
+            ..self
+                .secrets
+                .totp(&wallet_name)
+                .expect("TOTP secret vanished")
+        };
+        let status = TotpStatus {
+            active: true,
+            send_threshold: CoinValue(totp.send_threshold),
+        };
+        self.secrets
+            .set_totp(&wallet_name, Some(totp))
+            .expect("cannot write secrets");
+        log::info!("activated TOTP protection of {wallet_name}");
+        Ok(status)
+    }
+
+    async fn disable_totp(
+        &self,
+        wallet_name: String,
+        password: String,
+        totp_code: String,
+    ) -> Result<(), NeedWallet<TotpError>> {
+        self.wallet_with_key(&wallet_name, &password).await?;
+        if self.secrets.totp(&wallet_name).is_none() {
+            return Err(TotpError::NotEnrolled.into());
+        }
+        self.check_totp_code(&wallet_name, &totp_code)?;
+        self.secrets
+            .set_totp(&wallet_name, None)
+            .expect("cannot write secrets");
+        log::info!("disabled TOTP protection of {wallet_name}");
+        Ok(())
+    }
+
+    async fn totp_status(
+        &self,
+        wallet_name: String,
+    ) -> Result<Option<TotpStatus>, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self.secrets.totp(&wallet_name).map(|totp| TotpStatus {
+            active: totp.active,
+            send_threshold: CoinValue(totp.send_threshold),
+        }))
+    }
+
+    async fn export_sk_with_totp(
+        &self,
+        wallet_name: String,
+        password: String,
+        totp_code: String,
+    ) -> Result<String, NeedWallet<TotpError>> {
+        // check the password first, so that a wrong password doesn't use up the code
+        self.wallet_with_key(&wallet_name, &password).await?;
+        self.check_totp_code(&wallet_name, &totp_code)?;
+        Ok(self
+            .export_sk_inner(&wallet_name, &password)
+            .map_err(NeedWallet::Wallet)?)
+    }
+
+    async fn change_password_with_totp(
+        &self,
+        wallet_name: String,
+        old_password: String,
+        new_password: String,
+        totp_code: String,
+    ) -> Result<PasswordStrength, NeedWallet<TotpError>> {
+        self.wallet_with_key(&wallet_name, &old_password).await?;
+        self.check_totp_code(&wallet_name, &totp_code)?;
+        self.change_password_inner(&wallet_name, &old_password, &new_password)
+            .await
+            .map_err(|e| match e {
+                NeedWallet::Wallet(e) => NeedWallet::Wallet(e),
+                NeedWallet::Other(e) => NeedWallet::Other(TotpError::WeakPassword(e)),
+            })
+    }
+
+    async fn send_tx(
+        &self,
+        wallet_name: String,
+        tx: Transaction,
+    ) -> Result<TxHash, NeedWallet<SendError>> {
+        self.send_tx_inner(&wallet_name, tx, false).await
+    }
+
+    async fn send_tx_with_totp(
+        &self,
+        wallet_name: String,
+        tx: Transaction,
+        totp_code: String,
+    ) -> Result<TxHash, NeedWallet<TotpError>> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        self.check_totp_code(&wallet_name, &totp_code)?;
+        self.send_tx_inner(&wallet_name, tx, true)
+            .await
+            .map_err(|e| match e {
+                NeedWallet::Wallet(e) => NeedWallet::Wallet(e),
+                NeedWallet::Other(e) => NeedWallet::Other(TotpError::Send(e)),
+            })
+    }
+
+    async fn minting_info(&self) -> Result<MintingInfo, NetworkError> {
+        let snapshot = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| NetworkError::Transient(e.to_string()))?;
+        let header = snapshot.current_header();
+        let pool_key = PoolKey::new(Denom::Mel, Denom::Erg);
+        let pool = snapshot
+            .get_pool(pool_key)
+            .await
+            .map_err(|e| NetworkError::Transient(e.to_string()))?
+            .filter(|pool| pool.lefts > 0 && pool.rights > 0);
+        // the implied price is lefts per right
+        let erg_per_mel = pool.map(|pool| {
+            let price = pool.lefts as f64 / pool.rights as f64;
+            if pool_key.left() == Denom::Erg {
+                price
+            } else {
+                1.0 / price
+            }
+        });
+        Ok(MintingInfo {
+            height: header.height,
+            dosc_speed: header.dosc_speed,
+            dosc_difficulty: mint::dosc_difficulty(header.dosc_speed),
+            erg_per_mel,
+            mel_per_erg: erg_per_mel.map(|rate| 1.0 / rate),
+        })
+    }
+
+    async fn estimate_mint_reward(
+        &self,
+        difficulty: u32,
+        blocks: u64,
+    ) -> Result<MintRewardEstimate, NetworkError> {
+        let header = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| NetworkError::Transient(e.to_string()))?
+            .current_header();
+        Ok(MintRewardEstimate {
+            height: header.height,
+            dosc_speed: header.dosc_speed,
+            reward_micro_dosc: mint::mint_reward(header.dosc_speed, difficulty, blocks),
+        })
+    }
+
+    async fn network_fees(&self, blocks: u64) -> Result<NetworkFees, NetworkError> {
+        let snapshot = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| NetworkError::Transient(e.to_string()))?;
+        let header = snapshot.current_header();
+        let blocks = blocks.clamp(1, MAX_FEE_HISTORY_BLOCKS);
+        let oldest = header.height.0.saturating_sub(blocks - 1);
+        let history = futures::future::try_join_all((oldest..header.height.0).map(|height| {
+            let snapshot = &snapshot;
+            async move {
+                let older = self
+                    .chain_cache
+                    .get_older(snapshot, BlockHeight(height))
+                    .await?;
+                anyhow::Ok(FeeSample {
+                    height: BlockHeight(height),
+                    fee_multiplier: older.current_header().fee_multiplier,
+                })
+            }
+        }))
+        .await
+        .map_err(|e| NetworkError::Transient(e.to_string()))?
+        .into_iter()
+        .chain(std::iter::once(FeeSample {
+            height: header.height,
+            fee_multiplier: header.fee_multiplier,
+        }))
+        .collect::<Vec<_>>();
+        let multipliers: Vec<u128> = history.iter().map(|s| s.fee_multiplier).collect();
+        let (typical_weight, typical_fee) = fees::typical_fee(header.fee_multiplier);
+        Ok(NetworkFees {
+            height: header.height,
+            fee_multiplier: header.fee_multiplier,
+            trend: fees::fee_trend(&multipliers),
+            history,
+            typical_weight,
+            typical_fee,
+        })
+    }
+
+    async fn transaction_history(
+        &self,
+        wallet_name: String,
+    ) -> Result<Vec<HistoryEntry>, WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self.history_entries(&wallet).await.expect("db failed"))
+    }
+
+    async fn tx_time(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+    ) -> Result<Option<BlockTime>, WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        let height = wallet
+            .get_transaction_history()
+            .await
+            .into_iter()
+            .find(|(hash, _)| *hash == txhash)
+            .and_then(|(_, height)| height);
+        Ok(match height {
+            Some(height) => self.database.block_time(height).await.expect("db failed"),
+            None => None,
+        })
+    }
+
+    async fn export_history_csv(&self, wallet_name: String) -> Result<String, WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        let mut csv = String::from("txhash,height,time,time_estimated\n");
+        for entry in self.history_entries(&wallet).await.expect("db failed") {
+            let height = entry.height.map(|h| h.0.to_string()).unwrap_or_default();
+            let (time, estimated) = match entry.time {
+                Some(time) => (
+                    chrono::NaiveDateTime::from_timestamp_opt(time.time as i64, 0)
+                        .map(|time| time.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+                        .unwrap_or_default(),
+                    time.estimated.to_string(),
+                ),
+                None => (String::new(), String::new()),
+            };
+            csv.push_str(&format!("{},{height},{time},{estimated}\n", entry.txhash));
+        }
+        Ok(csv)
+    }
+
+    async fn export_history(
+        &self,
+        wallet_name: String,
+        format: AccountingFormat,
+        denom: String,
+    ) -> Result<String, HistoryExportError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(HistoryExportError::WalletNotFound)?;
+        let parsed: Denom = denom
+            .parse()
+            .map_err(|_| HistoryExportError::InvalidDenom(denom.clone()))?;
+        let snapshot = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| HistoryExportError::Network(e.to_string()))?;
+        let registry = &self.config.token_registry;
+        let mut lines = vec![];
+        let mut balance = 0i128;
+        for entry in self.history_entries(&wallet).await.expect("db failed") {
+            let time = match entry.time {
+                Some(time) if entry.height.is_some() => time.time,
+                _ => continue,
+            };
+            let raw = match wallet
+                .get_transaction(entry.txhash, snapshot.clone())
+                .await
+                .map_err(|e| HistoryExportError::Network(e.to_string()))?
+            {
+                Some(raw) => raw,
+                None => continue,
+            };
+            let details = self.balance_details(&wallet, &raw).await;
+            let delta = match details.net.get(&parsed.to_string()) {
+                Some(delta) if *delta != 0 => *delta,
+                _ => continue,
+            };
+            balance += delta;
+            let payee = match details.internal {
+                Some(transfer) if details.self_originated => {
+                    format!("Transfer to {}", transfer.to_wallet)
+                }
+                Some(transfer) => format!("Transfer from {}", transfer.from_wallet),
+                None if details.self_originated => "Sent".to_owned(),
+                None => "Received".to_owned(),
+            };
+            lines.push(ActivityLine {
+                txhash: entry.txhash,
+                time,
+                amount: registry.format_signed_display(parsed, delta),
+                payee,
+            });
+        }
+        Ok(match format {
+            AccountingFormat::Qif => accounting::qif(&lines),
+            AccountingFormat::Ofx => accounting::ofx(
+                &format!("{wallet_name}:{parsed}"),
+                &lines,
+                &registry.format_signed_display(parsed, balance),
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("clock before 1970")
+                    .as_secs(),
+            ),
+        })
+    }
+
+    async fn search_transactions(&self, query: String, limit: usize) -> Vec<TransactionSearchHit> {
+        self.database
+            .search_transactions(&query, limit)
+            .await
+            .expect("db failed")
+    }
+
+    async fn list_coins(&self, wallet_name: String) -> Result<Vec<LabeledCoin>, WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        let mut labels = wallet
+            .coin_labels()
+            .await
+            .map_err(|e| WalletAccessError::Other(e.to_string()))?;
+        Ok(wallet
+            .get_coin_mapping(true, false)
+            .await
+            .into_iter()
+            .map(|(coin_id, coin_data)| LabeledCoin {
+                coin_id,
+                coin_data,
+                labels: labels.remove(&coin_id).unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn set_coin_labels(
+        &self,
+        wallet_name: String,
+        coin_id: CoinID,
+        labels: BTreeSet<String>,
+    ) -> Result<(), WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        let labels: BTreeSet<String> = labels
+            .iter()
+            .map(|label| label.trim().to_string())
+            .filter(|label| !label.is_empty())
+            .collect();
+        wallet
+            .set_coin_labels(coin_id, &labels)
+            .await
+            .map_err(|e| WalletAccessError::Other(e.to_string()))
+    }
+
+    async fn prepare_tx_verbose(
+        &self,
+        wallet_name: String,
+        request: ExtPrepareTxArgs,
+    ) -> Result<PreparedTxDetails, NeedWallet<PrepareTxError>> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let fee_ballast = match request.fee_ballast {
+            Some(ballast) => ballast,
+            None => wallet.default_fee_ballast().await,
+        };
+        let requested_inputs: BTreeSet<CoinID> = request.inputs.iter().copied().collect();
+        let requested_outputs = request.outputs.len();
+        // prepare_tx has already rejected it if it doesn't parse
+        let change_address = request
+            .change_address
+            .as_deref()
+            .and_then(parse_address)
+            .unwrap_or_else(|| wallet.address());
+        let sponsor = match request.fee_sponsor.as_deref() {
+            Some(name) if name != wallet_name => Some(name.to_owned()),
+            _ => None,
+        };
+        let transaction = MelwalletdExtProtocol::prepare_tx(self, wallet_name.clone(), request)
+            .await?
+            .transaction;
+
+        let own_coins = wallet.get_coin_mapping(true, false).await;
+        let sponsor_coins = match sponsor.as_deref() {
+            Some(name) => match self.get_wallet(name).await {
+                Some(sponsor) => sponsor.get_coin_mapping(true, false).await,
+                None => BTreeMap::new(),
+            },
+            None => BTreeMap::new(),
+        };
+        let inputs = transaction
+            .inputs
+            .iter()
+            .map(|coin_id| {
+                let (coin_data, reason) = if requested_inputs.contains(coin_id) {
+                    (own_coins.get(coin_id), InputSelection::Requested)
+                } else if let Some(data) = sponsor_coins.get(coin_id) {
+                    (Some(data), InputSelection::SponsorFee)
+                } else {
+                    (own_coins.get(coin_id), InputSelection::Balancing)
+                };
+                SelectedInput {
+                    coin_id: *coin_id,
+                    coin_data: coin_data.cloned(),
+                    reason,
+                }
+            })
+            .collect();
+        let change = transaction
+            .outputs
+            .iter()
+            .enumerate()
+            .skip(requested_outputs)
+            .map(|(i, output)| (i as u8, output.clone()))
+            .collect();
+
+        let fee_multiplier = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| PrepareTxError::Network(NetworkError::Transient(e.to_string())))?
+            .current_header()
+            .fee_multiplier;
+        let fee = FeeBreakdown {
+            fee_multiplier,
+            weight: transaction.weight(covenant_weight_from_bytes),
+            fee_ballast,
+            base_fee: transaction.base_fee(
+                fee_multiplier,
+                fee_ballast as _,
+                covenant_weight_from_bytes,
+            ),
+            fee: transaction.fee,
+            paid_by: sponsor.unwrap_or(wallet_name),
+        };
+        Ok(PreparedTxDetails {
+            txhash: transaction.hash_nosigs(),
+            transaction,
+            inputs,
+            change,
+            change_address,
+            fee,
+        })
+    }
+
+    async fn recoverable_wallets(&self) -> Vec<String> {
+        AppState::recoverable_wallets(self).await
+    }
+
+    async fn recover_wallets_from_secrets(
+        &self,
+        passwords: BTreeMap<String, String>,
+    ) -> BTreeMap<String, String> {
+        let mut recovered = BTreeMap::new();
+        for name in AppState::recoverable_wallets(self).await {
+            let password = passwords.get(&name).map(|p| p.as_str()).unwrap_or_default();
+            match self.recover_from_secret(&name, password).await {
+                Ok(Some(address)) => {
+                    recovered.insert(name, address.to_string());
+                }
+                Ok(None) => log::warn!("cannot recover wallet {name}: wrong or missing password"),
+                Err(err) => log::warn!("cannot recover wallet {name}: {:?}", err),
+            }
+        }
+        recovered
+    }
+
+    async fn address_forms(&self, address: String) -> Result<AddressForms, InvalidAddressError> {
+        let parsed = parse_address(&address).ok_or(InvalidAddressError(address))?;
+        Ok(address_forms(parsed))
+    }
+
+    async fn capabilities(&self) -> Capabilities {
+        capabilities::capabilities(&self.config)
+    }
+
+    async fn export_sync_snapshot(
+        &self,
+        wallet_name: String,
+        password: String,
+    ) -> Result<String, NeedWallet<SyncSnapshotError>> {
+        let (wallet, sk) = self.wallet_with_key(&wallet_name, &password).await?;
+        let (height, coins) = wallet
+            .synced_coins()
+            .await
+            .expect("db failed")
+            .ok_or(SyncSnapshotError::NotSynced)?;
+        log::info!(
+            "exporting sync snapshot of {wallet_name} with {} coins at height {height}",
+            coins.len()
+        );
+        Ok(SyncSnapshot::new(wallet.address(), height, coins, &sk).encode())
+    }
+
+    async fn import_sync_snapshot(
+        &self,
+        wallet_name: String,
+        snapshot: String,
+    ) -> Result<BlockHeight, NeedWallet<SyncSnapshotError>> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let snapshot = SyncSnapshot::decode(&snapshot).ok_or(SyncSnapshotError::Malformed)?;
+        if snapshot.address != wallet.address() {
+            return Err(SyncSnapshotError::WrongWallet(snapshot.address.to_string()).into());
+        }
+        let pubkey = self
+            .wallet_public_key(&wallet_name, &wallet)
+            .await
+            .expect("db failed")
+            .ok_or(SyncSnapshotError::UnknownKey)?;
+        if !snapshot.verify(pubkey) {
+            return Err(SyncSnapshotError::BadSignature.into());
+        }
+        if let Some((synced, _)) = wallet.synced_coins().await.expect("db failed") {
+            if synced >= snapshot.height {
+                return Err(SyncSnapshotError::Stale {
+                    snapshot: snapshot.height,
+                    synced,
+                }
+                .into());
+            }
+        }
+        let tip = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| SyncSnapshotError::Network(e.to_string()))?
+            .current_header()
+            .height;
+        if snapshot.height > tip {
+            return Err(SyncSnapshotError::FromTheFuture(snapshot.height).into());
+        }
+        log::info!(
+            "importing sync snapshot of {wallet_name} with {} coins at height {}",
+            snapshot.coins.len(),
+            snapshot.height
+        );
+        wallet
+            .restore_synced_coins(snapshot.height, snapshot.coins)
+            .await
+            .expect("db failed");
+        Ok(snapshot.height)
+    }
+
+    async fn export_watch_package(
+        &self,
+        wallet_name: String,
+        password: String,
+    ) -> Result<String, NeedWallet<WatchPackageError>> {
+        let (wallet, sk) = self.wallet_with_key(&wallet_name, &password).await?;
+        let (height, coins, spends) = wallet
+            .synced_history()
+            .await
+            .expect("db failed")
+            .ok_or(WatchPackageError::NotSynced)?;
+        let covenant = Covenant::from_bytes(wallet.covenant()).expect("malformed covenant in db");
+        let package = WatchPackage::new(&covenant, height, coins, spends, &sk);
+        if !package.verify() {
+            return Err(WatchPackageError::UnsupportedCovenant.into());
+        }
+        log::info!(
+            "exporting watch-only package of {wallet_name} with {} coins at height {height}",
+            package.coins.len()
+        );
+        Ok(package.encode())
+    }
+
+    async fn import_watch_package(
+        &self,
+        wallet_name: String,
+        package: String,
+    ) -> Result<BlockHeight, WatchPackageError> {
+        let package = WatchPackage::decode(&package).ok_or(WatchPackageError::Malformed)?;
+        if !package.verify() {
+            return Err(WatchPackageError::BadSignature);
+        }
+        if self.get_wallet(&wallet_name).await.is_some() {
+            return Err(WatchPackageError::WalletExists);
+        }
+        let tip = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| WatchPackageError::Network(e.to_string()))?
+            .current_header()
+            .height;
+        if package.height > tip {
+            return Err(WatchPackageError::FromTheFuture(package.height));
+        }
+        let covenant = package.covenant().ok_or(WatchPackageError::Malformed)?;
+        self.database
+            .create_imported_wallet(&wallet_name, covenant, package.height)
+            .await
+            .map_err(|e| WatchPackageError::Other(e.to_string()))?;
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .expect("wallet just created");
+        log::info!(
+            "importing watch-only package as {wallet_name} with {} coins at height {}",
+            package.coins.len(),
+            package.height
+        );
+        wallet
+            .restore_history(package.height, package.coins, package.spends)
+            .await
+            .expect("db failed");
+        Ok(package.height)
+    }
+
+    async fn propose_trade(
+        &self,
+        wallet_name: String,
+        terms: TradeTerms,
+    ) -> Result<TradeOffer, NeedWallet<TradeError>> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let parsed = trade::Terms::parse(&terms)?;
+        let coins = wallet
+            .get_coin_mapping(true, false)
+            .await
+            .into_iter()
+            .filter(|(_, data)| data.covhash == wallet.address())
+            .collect();
+        let tx = trade::propose(&parsed, wallet.address(), wallet.covenant(), &coins)?;
+        let trade = self
+            .database
+            .create_trade(&wallet_name, &terms)
+            .await
+            .expect("db failed");
+        log::info!("{wallet_name} proposed trade {}", trade.id);
+        Ok(TradeOffer {
+            id: trade.id,
+            proposer: wallet.address().to_string(),
+            terms,
+            tx,
+        })
+    }
+
+    async fn accept_trade(
+        &self,
+        wallet_name: String,
+        offer: TradeOffer,
+    ) -> Result<Transaction, NeedWallet<TradeError>> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let terms = trade::Terms::parse(&offer.terms)?;
+        if terms.counterparty != wallet.address() {
+            return Err(TradeError::WrongWallet(offer.terms.counterparty).into());
+        }
+        let signer = self
+            .use_signer(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::Locked))?;
+        let fee_multiplier = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| TradeError::Network(e.to_string()))?
+            .current_header()
+            .fee_multiplier;
+        let coins = wallet
+            .get_coin_mapping(true, false)
+            .await
+            .into_iter()
+            .filter(|(_, data)| data.covhash == wallet.address())
+            .collect();
+        let tx = trade::accept(
+            offer.tx,
+            &terms,
+            wallet.covenant(),
+            &coins,
+            fee_multiplier,
+            self.config.min_fee,
+            signer.as_ref(),
+        )?;
+        log::info!("{wallet_name} accepted trade {}", offer.id);
+        Ok(tx)
+    }
+
+    async fn complete_trade(
+        &self,
+        trade_id: String,
+        tx: Transaction,
+    ) -> Result<TxHash, NeedWallet<TradeError>> {
+        let trade = self
+            .database
+            .get_trade(&trade_id)
+            .await
+            .expect("db failed")
+            .ok_or(TradeError::NotFound)?;
+        if trade.status == TradeStatus::Completed {
+            return Err(TradeError::AlreadyCompleted.into());
+        }
+        let wallet = self
+            .get_wallet(&trade.wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let signer = self
+            .use_signer(&trade.wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::Locked))?;
+        let terms = trade::Terms::parse(&trade.terms)?;
+        let coins = wallet
+            .get_coin_mapping(true, false)
+            .await
+            .into_iter()
+            .filter(|(_, data)| data.covhash == wallet.address())
+            .collect();
+        let tx = trade::complete(tx, &terms, wallet.address(), &coins, signer.as_ref())?;
+        let txhash = self
+            .send_tx_inner(&trade.wallet_name, tx, false)
+            .await
+            .map_err(|e| match e {
+                NeedWallet::Wallet(e) => NeedWallet::Wallet(e),
+                NeedWallet::Other(e) => TradeError::Network(e.to_string()).into(),
+            })?;
+        self.database
+            .complete_trade(&trade_id, txhash)
+            .await
+            .expect("db failed");
+        log::info!("completed trade {trade_id} with {txhash}");
+        Ok(txhash)
+    }
+
+    async fn list_trades(&self, wallet_name: String) -> Result<Vec<Trade>, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self
+            .database
+            .list_trades(&wallet_name)
+            .await
+            .expect("db failed"))
+    }
+
+    async fn build_data_output(
+        &self,
+        address: String,
+        payload: DataPayload,
+        denom: String,
+        value: CoinValue,
+    ) -> Result<CoinData, DataPayloadError> {
+        let covhash = parse_address(&address).ok_or(DataPayloadError::InvalidAddress(address))?;
+        let denom: Denom = denom
+            .parse()
+            .map_err(|_| DataPayloadError::InvalidDenom(denom))?;
+        let additional_data = payload::encode(&payload);
+        if additional_data.len() > MAX_PAYLOAD_BYTES {
+            return Err(DataPayloadError::TooLarge(additional_data.len()));
+        }
+        Ok(CoinData {
+            covhash,
+            value,
+            denom,
+            additional_data: additional_data.into(),
+        })
+    }
+
+    async fn data_coins(
+        &self,
+        wallet_name: String,
+        template: Option<PayloadTemplate>,
+    ) -> Result<Vec<DataCoin>, WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(wallet.data_coins(template).await.expect("db failed"))
+    }
+
+    async fn prove_payment(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+        output_index: u8,
+    ) -> Result<String, PaymentProofError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(PaymentProofError::WalletNotFound)?;
+        let height = wallet
+            .get_transaction_history()
+            .await
+            .into_iter()
+            .find(|(h, _)| *h == txhash)
+            .and_then(|(_, height)| height)
+            .ok_or(PaymentProofError::NotConfirmed)?;
+        let network = |e: &dyn std::fmt::Display| PaymentProofError::Network(e.to_string());
+        let snapshot = self.latest_snapshot().await.map_err(|e| network(&e))?;
+        let anchor = snapshot.current_header();
+        let block = snapshot.get_older(height).await.map_err(|e| network(&e))?;
+        let tx = block
+            .get_transaction(txhash)
+            .await
+            .map_err(|e| network(&e))?
+            .ok_or(PaymentProofError::NotConfirmed)?;
+        let (_, tx_branch) = snapshot
+            .get_raw()
+            .get_smt_branch(height, Substate::Transactions, PaymentProof::tx_key(&tx))
+            .await
+            .map_err(|e| network(&e))?
+            .ok_or_else(|| network(&"node has no proof of the transaction"))?;
+        let history_branch = if anchor.height > height {
+            let (_, branch) = snapshot
+                .get_raw()
+                .get_smt_branch(
+                    anchor.height,
+                    Substate::History,
+                    PaymentProof::history_key(height),
+                )
+                .await
+                .map_err(|e| network(&e))?
+                .ok_or_else(|| network(&"node has no proof of the block"))?;
+            Some(branch)
+        } else {
+            None
+        };
+        let proof = PaymentProof::new(
+            tx,
+            output_index,
+            block.current_header(),
+            tx_branch,
+            anchor,
+            history_branch,
+        );
+        proof.verify().map_err(PaymentProofError::BadProof)?;
+        log::info!("proved output {output_index} of {txhash} for {wallet_name}");
+        Ok(proof.encode())
+    }
+
+    async fn verify_payment_proof(
+        &self,
+        proof: String,
+        trusted: Option<TrustedHeader>,
+    ) -> Result<VerifiedPayment, PaymentProofError> {
+        let proof = PaymentProof::decode(&proof).ok_or(PaymentProofError::Malformed)?;
+        let anchor_hash = proof.anchor.hash();
+        match trusted {
+            Some(trusted) => {
+                if trusted.height != proof.anchor.height || trusted.header_hash != anchor_hash {
+                    return Err(PaymentProofError::UntrustedAnchor);
+                }
+            }
+            None => {
+                if proof.anchor.network != self.network {
+                    return Err(PaymentProofError::UntrustedAnchor);
+                }
+                let snapshot = self
+                    .latest_snapshot()
+                    .await
+                    .map_err(|e| PaymentProofError::Network(e.to_string()))?;
+                if proof.anchor.height > snapshot.current_header().height {
+                    return Err(PaymentProofError::UntrustedAnchor);
+                }
+                let known = snapshot
+                    .get_older(proof.anchor.height)
+                    .await
+                    .map_err(|e| PaymentProofError::Network(e.to_string()))?
+                    .current_header();
+                if known.hash() != anchor_hash {
+                    return Err(PaymentProofError::UntrustedAnchor);
+                }
+            }
+        }
+        let output = proof.verify().map_err(PaymentProofError::BadProof)?.clone();
+        Ok(VerifiedPayment {
+            txhash: proof.tx.hash_nosigs(),
+            height: proof.header.height,
+            output_index: proof.output_index,
+            output,
+            anchor_height: proof.anchor.height,
+            anchor_hash,
+        })
+    }
+
+    async fn set_power_mode(&self, mode: PowerMode) {
+        log::info!("power mode set to {:?}", mode);
+        self.power.set_mode(mode);
+    }
+
+    async fn power_status(&self) -> PowerStatus {
+        self.power.status()
+    }
+
+    async fn verify_wallets(
+        &self,
+        repair: bool,
+    ) -> Result<Vec<WalletMismatch>, VerifyWalletsError> {
+        reconcile::verify_wallets(
+            &self.database,
+            &self.secrets,
+            &self.other_network_wallets(),
+            repair,
+        )
+        .await
+        .map_err(|e| VerifyWalletsError::Other(e.to_string()))
+    }
+
+    async fn build_info(&self) -> BuildInfo {
+        build_info::build_info()
+    }
+
+    async fn secrets_status(&self) -> SecretsStatus {
+        SecretsStatus {
+            protected: self.secrets.is_protected(),
+            sealed: self.secrets.is_sealed(),
+        }
+    }
+
+    async fn unseal_secrets(&self, passphrase: String) -> Result<(), MasterPassphraseError> {
+        self.secrets.unseal(&passphrase)?;
+        // declared wallets with keys are skipped at startup while sealed
+        provision_wallets(&self.database, &self.secrets, &self.config.wallets)
+            .await
+            .map_err(|e| {
+                MasterPassphraseError::Other(format!(
+                    "unsealed, but cannot provision declared wallets: {e:#}"
+                ))
+            })
+    }
+
+    async fn reload_secrets(&self) -> Result<SecretsStatus, MasterPassphraseError> {
+        let count = self
+            .secrets
+            .reload()
+            .map_err(|e| MasterPassphraseError::Other(e.to_string()))?;
+        log::info!("reloaded the secret store, which holds {count} secrets");
+        Ok(self.secrets_status().await)
+    }
+
+    async fn set_master_passphrase(
+        &self,
+        current: Option<String>,
+        new: Option<String>,
+    ) -> Result<(), MasterPassphraseError> {
+        self.secrets
+            .set_master_passphrase(current.as_deref(), new.as_deref())?;
+        if new.is_some() {
+            log::info!("set the master passphrase of the secret store");
+        } else {
+            log::warn!("removed the master passphrase of the secret store");
+        }
+        Ok(())
+    }
+
+    async fn set_signing_limit(
+        &self,
+        wallet_name: String,
+        password: String,
+        limit: Option<SigningLimit>,
+    ) -> Result<(), NeedWallet<SigningLimitError>> {
+        if let Some(limit) = limit.as_ref() {
+            if limit.max_signatures == 0 || limit.window_secs == 0 {
+                return Err(SigningLimitError::InvalidLimit(
+                    "max_signatures and window_secs must be nonzero".into(),
+                )
+                .into());
+            }
+            if let Some(url) = limit.alert_webhook.as_ref() {
+                if !valid_webhook(url) {
+                    return Err(SigningLimitError::BadWebhook(url.clone()).into());
+                }
+            }
+        }
+        self.wallet_with_key(&wallet_name, &password).await?;
+        self.database
+            .set_signing_limit(&wallet_name, limit.as_ref())
+            .await
+            .expect("db failed");
+        self.signing.lift_lockout(&wallet_name);
+        log::info!("set signing limit of {wallet_name} to {:?}", limit);
+        Ok(())
+    }
+
+    async fn signing_activity(
+        &self,
+        wallet_name: String,
+    ) -> Result<SigningActivity, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self.signing_status(&wallet_name).await.expect("db failed"))
+    }
+
+    async fn tx_balance_details(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+    ) -> Result<Option<TxBalanceDetails>, WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        let snapshot = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| WalletAccessError::Other(e.to_string()))?;
+        let raw = wallet
+            .get_transaction(txhash, snapshot)
+            .await
+            .map_err(|e| WalletAccessError::Other(e.to_string()))?;
+        Ok(match raw {
+            Some(raw) => Some(self.balance_details(&wallet, &raw).await),
+            None => None,
+        })
+    }
+
+    async fn network_diagnostics(&self) -> NetworkDiagnostics {
+        self.nodes.diagnostics()
+    }
+
+    async fn wallet_sync_summary(
+        &self,
+        wallet_name: String,
+    ) -> Result<WalletSyncSummary, WalletAccessError> {
+        let summary = MelwalletdProtocol::wallet_summary(self, wallet_name.clone()).await?;
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        let last_sync_height = wallet.sync_height().await.expect("db failed");
+        // fall back to the last header seen, so that an unreachable node doesn't hide how far behind the wallet is
+        let latest_height = match self.latest_snapshot().await {
+            Ok(snapshot) => Some(snapshot.current_header().height),
+            Err(_) => self
+                .database
+                .cached_header()
+                .await
+                .expect("db failed")
+                .map(|header| header.height),
+        };
+        let is_stale = match (last_sync_height, latest_height) {
+            (Some(synced), Some(latest)) => latest.0 > synced.0 + STALE_AFTER_BLOCKS,
+            _ => true,
+        };
+        Ok(WalletSyncSummary {
+            summary,
+            last_sync_height,
+            latest_height,
+            is_stale,
+            display_preferences: self
+                .database
+                .display_preferences(&wallet_name)
+                .await
+                .expect("db failed"),
+            key_status: self.key_status(&wallet_name).await.expect("db failed"),
+        })
+    }
+
+    async fn sync_errors(&self, wallet_name: String) -> Result<Vec<SyncError>, WalletAccessError> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(wallet.sync_errors().await.expect("db failed"))
+    }
+
+    async fn preview_coin_selection(
+        &self,
+        wallet_name: String,
+        outputs: Vec<CoinData>,
+        strategy: Option<CoinSelection>,
+    ) -> Result<Vec<CoinSelectionPreview>, NeedWallet<PrepareTxError>> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let own_coins = wallet.get_coin_mapping(true, false).await;
+        let strategies = match strategy {
+            Some(strategy) => vec![strategy],
+            None => vec![
+                CoinSelection::Arbitrary,
+                CoinSelection::LargestFirst,
+                CoinSelection::SmallestFirst,
+            ],
+        };
+        // placeholder signatures weigh the same whatever the key, so any key will do
+        let signer = Arc::new(PlaceholderSigner(Ed25519SK::generate().to_public()));
+        let mut previews = vec![];
+        for strategy in strategies {
+            let request = ExtPrepareTxArgs {
+                kind: TxKind::Normal,
+                inputs: vec![],
+                outputs: outputs.clone(),
+                covenants: vec![],
+                data: vec![],
+                nobalance: vec![],
+                fee_ballast: None,
+                fee_sponsor: None,
+                spend_labels: vec![],
+                avoid_labels: vec![],
+                coin_selection: strategy,
+                allow_high_fee: true,
+                change_address: None,
+                exclude_denoms: None,
+            };
+            let tx = self
+                .prepare_with_signer(&wallet_name, request, signer.clone(), &BTreeSet::new())
+                .await?;
+            previews.push(CoinSelectionPreview {
+                strategy,
+                inputs: tx
+                    .inputs
+                    .iter()
+                    .map(|coin_id| SelectedInput {
+                        coin_id: *coin_id,
+                        coin_data: own_coins.get(coin_id).cloned(),
+                        reason: InputSelection::Balancing,
+                    })
+                    .collect(),
+                change: tx.outputs[outputs.len()..].to_vec(),
+                fee: tx.fee,
+                weight: tx.weight(covenant_weight_from_bytes),
+            });
+        }
+        Ok(previews)
+    }
+
+    async fn purge_expired(
+        &self,
+        wallet_name: String,
+    ) -> Result<PendingPurge, NeedWallet<NetworkError>> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let height = self.latest_header().await?.height;
+        let purge = wallet.purge_expired(height).await.expect("db failed");
+        if !purge.purged.is_empty() {
+            log::info!(
+                "purged {} expired pending transactions of {wallet_name}",
+                purge.purged.len()
+            );
+        }
+        Ok(purge)
+    }
+
+    async fn is_mine(
+        &self,
+        wallet_name: Option<String>,
+        address: String,
+    ) -> Result<AddressOwnership, NeedWallet<InvalidAddressError>> {
+        let parsed = parse_address(&address).ok_or(InvalidAddressError(address))?;
+        let names = match wallet_name {
+            Some(name) => {
+                self.get_wallet(&name)
+                    .await
+                    .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+                vec![name]
+            }
+            None => self.database.list_wallets().await,
+        };
+        let mut owners = vec![];
+        for name in names {
+            // wallets deleted since they were listed are skipped
+            let wallet = match self.get_wallet(&name).await {
+                Some(wallet) => wallet,
+                None => continue,
+            };
+            let rotation = self.database.get_rotation(&name).await.expect("db failed");
+            let kind = if wallet.address() == parsed {
+                Some(OwnershipKind::Wallet)
+            } else if rotation.is_some_and(|rotation| rotation.covhash == parsed) {
+                Some(OwnershipKind::RotationTarget)
+            } else if wallet
+                .imported_covenants()
+                .await
+                .expect("db failed")
+                .contains_key(&parsed)
+            {
+                Some(OwnershipKind::Imported)
+            } else {
+                None
+            };
+            if let Some(kind) = kind {
+                owners.push(AddressOwner { wallet: name, kind });
+            }
+        }
+        Ok(AddressOwnership {
+            address: parsed,
+            is_mine: !owners.is_empty(),
+            owners,
+        })
+    }
+
+    async fn tail_logs(&self, level: Option<LogLevel>, limit: usize) -> Vec<LogRecord> {
+        LOG_BUFFER.tail(level.unwrap_or(LogLevel::Trace), limit)
+    }
+
+    async fn follow_logs(
+        &self,
+        after: Option<u64>,
+        level: Option<LogLevel>,
+        timeout_secs: u64,
+    ) -> Vec<LogRecord> {
+        let level = level.unwrap_or(LogLevel::Trace);
+        let deadline = Instant::now() + Duration::from_secs(timeout_secs.min(MAX_LOG_WAIT_SECS));
+        loop {
+            // listen before checking, so that a record logged in between isn't missed
+            let appended = LOG_BUFFER.listen();
+            let records = LOG_BUFFER.since(after, level);
+            if !records.is_empty() {
+                return records;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if appended.timeout(remaining).await.is_none() {
+                return vec![];
+            }
+        }
+    }
+
+    async fn clone_wallet(
+        &self,
+        source: String,
+        password: String,
+        dest: String,
+        target_network: NetID,
+    ) -> Result<String, NeedWallet<CloneWalletError>> {
+        if target_network == self.config.network {
+            return Err(CloneWalletError::SameNetwork(target_network).into());
+        }
+        let (wallet, sk) = self.wallet_with_key(&source, &password).await?;
+        if self.secrets.load(&dest).is_some() {
+            return Err(CloneWalletError::DestinationExists(dest).into());
+        }
+        let other = |e: anyhow::Error| CloneWalletError::Other(e.to_string());
+        // the target network's daemon may be running, so its database is left as it is
+        let target = Database::open_other(
+            self.config.db_path_for(target_network),
+            self.config.split_wallet_files,
+        )
+        .await
+        .map_err(|e| CloneWalletError::Other(format!("{e:#}")))?;
+        if target.get_wallet(&dest).await.is_some() {
+            return Err(CloneWalletError::DestinationExists(dest).into());
+        }
+        let covenant = Covenant::from_bytes(wallet.covenant())
+            .map_err(|e| CloneWalletError::Other(e.to_string()))?;
+        target.create_wallet(&dest, covenant).await.map_err(other)?;
+        if let Some((kind, params)) = self.database.wallet_type(&source).await.map_err(other)? {
+            target
+                .set_wallet_type(&dest, &kind, &params)
+                .await
+                .map_err(other)?;
+        }
+        if let Err(err) = self.secrets.store(
+            dest.clone(),
+            PersistentSecret::PasswordEncrypted(EncryptedSK::new(sk, &password)),
+        ) {
+            target.remove_wallet(&dest).await.map_err(other)?;
+            return Err(other(err).into());
+        }
+        log::info!("cloned wallet {source} into {dest} on {target_network:?}");
+        Ok(wallet.address().to_string())
+    }
+
+    async fn collect_diagnostics(&self, redact: bool, log_limit: usize) -> DiagnosticsBundle {
+        AppState::collect_diagnostics(self, redact, log_limit).await
+    }
+
+    async fn set_alert_thresholds(
+        &self,
+        wallet_name: String,
+        password: String,
+        thresholds: Option<AlertThresholds>,
+    ) -> Result<(), NeedWallet<AlertError>> {
+        if let Some(thresholds) = thresholds.as_ref() {
+            if thresholds.min_balance.is_none() && thresholds.max_outgoing.is_none() {
+                return Err(AlertError::InvalidThresholds(
+                    "set min_balance, max_outgoing or both".into(),
+                )
+                .into());
+            }
+            if let Some(url) = thresholds.alert_webhook.as_ref() {
+                if !valid_webhook(url) {
+                    return Err(AlertError::BadWebhook(url.clone()).into());
+                }
+            }
+        }
+        let (wallet, _) = self.wallet_with_key(&wallet_name, &password).await?;
+        let already_made = wallet.confirmed_spenders().await.expect("db failed");
+        self.database
+            .set_alert_thresholds(&wallet_name, thresholds.as_ref(), &already_made)
+            .await
+            .expect("db failed");
+        log::info!("set alert thresholds of {wallet_name} to {:?}", thresholds);
+        Ok(())
+    }
+
+    async fn alert_thresholds(
+        &self,
+        wallet_name: String,
+    ) -> Result<Option<AlertThresholds>, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self
+            .database
+            .alert_thresholds(&wallet_name)
+            .await
+            .expect("db failed")
+            .map(|(thresholds, _)| thresholds))
+    }
+
+    async fn wallet_alerts(
+        &self,
+        wallet_name: String,
+        limit: usize,
+    ) -> Result<Vec<WalletAlert>, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self
+            .database
+            .wallet_alerts(&wallet_name, limit)
+            .await
+            .expect("db failed"))
+    }
+
+    async fn list_pending(&self, filter: PendingFilter) -> PendingPage {
+        let names = match filter.wallet.as_ref() {
+            Some(name) => vec![name.clone()],
+            None => self.database.list_wallets().await,
+        };
+        let mut pending = vec![];
+        for name in names {
+            if let Some(wallet) = self.get_wallet(&name).await {
+                pending.extend(wallet.pending_transactions().await.expect("db failed"));
+            }
+        }
+        pending.retain(|tx: &PendingTransaction| {
+            filter
+                .expires_before
+                .is_none_or(|height| tx.expires < height)
+                && filter
+                    .min_age_secs
+                    .is_none_or(|min| tx.age_secs.is_some_and(|age| age >= min))
+                && filter
+                    .min_retransmits
+                    .is_none_or(|min| tx.retransmits >= min)
+        });
+        // transactions of unknown age were sent before any others
+        pending.sort_by_key(|tx| (tx.age_secs.map(Reverse), tx.txhash));
+        PendingPage {
+            total: pending.len(),
+            pending: pending
+                .into_iter()
+                .skip(filter.offset)
+                .take(filter.limit.unwrap_or(usize::MAX))
+                .collect(),
+        }
+    }
+
+    async fn internal_transfer(
+        &self,
+        from_wallet: String,
+        to_wallet: String,
+        value: CoinValue,
+        denom: Denom,
+    ) -> Result<TxHash, NeedWallet<InternalTransferError>> {
+        if from_wallet == to_wallet {
+            return Err(InternalTransferError::SameWallet.into());
+        }
+        let destination = self
+            .get_wallet(&to_wallet)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let request = ExtPrepareTxArgs {
+            kind: TxKind::Normal,
+            inputs: vec![],
+            outputs: vec![CoinData {
+                covhash: destination.address(),
+                value,
+                denom,
+                additional_data: Default::default(),
+            }],
+            covenants: vec![],
+            data: vec![],
+            nobalance: vec![],
+            fee_ballast: None,
+            fee_sponsor: None,
+            spend_labels: vec![],
+            avoid_labels: vec![],
+            coin_selection: CoinSelection::Arbitrary,
+            allow_high_fee: false,
+            change_address: None,
+            exclude_denoms: None,
+        };
+        let tx = MelwalletdExtProtocol::prepare_tx(self, from_wallet.clone(), request)
+            .await
+            .map_err(|e| match e {
+                NeedWallet::Wallet(e) => NeedWallet::Wallet(e),
+                NeedWallet::Other(e) => InternalTransferError::Prepare(e.to_string()).into(),
+            })?
+            .transaction;
+        // recorded before sending, so that a transfer held back by the sender's policies is still marked once released; forgotten if it is not sent at all
+        let prepared = tx.hash_nosigs();
+        self.database
+            .record_internal_transfer(
+                prepared,
+                &InternalTransfer {
+                    from_wallet: from_wallet.clone(),
+                    to_wallet: to_wallet.clone(),
+                },
+            )
+            .await
+            .expect("db failed");
+        let txhash = match self.send_tx_inner(&from_wallet, tx, false).await {
+            Ok(txhash) => txhash,
+            Err(err) => {
+                if !matches!(err, NeedWallet::Other(SendError::Held { .. })) {
+                    self.database
+                        .remove_internal_transfer(prepared)
+                        .await
+                        .expect("db failed");
+                }
+                return Err(match err {
+                    NeedWallet::Wallet(e) => NeedWallet::Wallet(e),
+                    NeedWallet::Other(e) => {
+                        InternalTransferError::Network(NetworkError::from(e).to_string()).into()
+                    }
+                });
+            }
+        };
+        log::info!("transferred {value} {denom} from {from_wallet} to {to_wallet} in {txhash}");
+        Ok(txhash)
+    }
+
+    async fn place_swap_order(
+        &self,
+        wallet_name: String,
+        from: String,
+        to: String,
+        amount: CoinValue,
+        limit_price: f64,
+        expiry: BlockHeight,
+    ) -> Result<SwapOrder, SwapOrderError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(SwapOrderError::WalletNotFound)?;
+        let from_denom: Denom = from
+            .parse()
+            .map_err(|_| SwapOrderError::InvalidDenom(from.clone()))?;
+        let to_denom: Denom = to
+            .parse()
+            .map_err(|_| SwapOrderError::InvalidDenom(to.clone()))?;
+        if from_denom == to_denom {
+            return Err(SwapOrderError::SameDenom);
+        }
+        if amount == CoinValue(0) {
+            return Err(SwapOrderError::ZeroAmount);
+        }
+        if !(limit_price.is_finite() && limit_price > 0.0) {
+            return Err(SwapOrderError::InvalidPrice(limit_price));
+        }
+        let snapshot = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| SwapOrderError::Network(e.to_string()))?;
+        if expiry <= snapshot.current_header().height {
+            return Err(SwapOrderError::AlreadyExpired(expiry));
+        }
+        snapshot
+            .get_pool(PoolKey::new(from_denom, to_denom))
+            .await
+            .map_err(|e| SwapOrderError::Network(e.to_string()))?
+            .ok_or(SwapOrderError::NoPool(from, to))?;
+        let order = self
+            .database
+            .create_swap_order(
+                &wallet_name,
+                from_denom,
+                to_denom,
+                amount,
+                limit_price,
+                expiry,
+            )
+            .await
+            .expect("db failed");
+        log::info!(
+            "placed swap order {} of {wallet_name}: {} {} for {} at {limit_price}",
+            order.id,
+            order.amount,
+            order.from,
+            order.to
+        );
+        Ok(order)
+    }
+
+    async fn list_swap_orders(&self, wallet_name: Option<String>) -> Vec<SwapOrder> {
+        self.database
+            .list_swap_orders(wallet_name.as_deref())
+            .await
+            .expect("db failed")
+    }
+
+    async fn cancel_swap_order(&self, order_id: String) -> Result<SwapOrder, SwapOrderError> {
+        let order = self
+            .database
+            .get_swap_order(&order_id)
+            .await
+            .expect("db failed")
+            .ok_or(SwapOrderError::OrderNotFound)?;
+        if !matches!(order.status, SwapOrderStatus::Open | SwapOrderStatus::Held) {
+            return Err(SwapOrderError::NotOpen(order.status));
+        }
+        self.database
+            .update_swap_order(&order_id, SwapOrderStatus::Cancelled, None)
+            .await
+            .expect("db failed");
+        log::info!("cancelled swap order {order_id} of {}", order.wallet_name);
+        Ok(SwapOrder {
+            status: SwapOrderStatus::Cancelled,
+            ..order
+        })
+    }
+
+    async fn create_dca_job(
+        &self,
+        wallet_name: String,
+        from: String,
+        to: String,
+        amount: CoinValue,
+        interval_secs: u64,
+        max_slippage: f64,
+    ) -> Result<DcaJob, DcaError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(DcaError::WalletNotFound)?;
+        let from_denom: Denom = from
+            .parse()
+            .map_err(|_| DcaError::InvalidDenom(from.clone()))?;
+        let to_denom: Denom = to.parse().map_err(|_| DcaError::InvalidDenom(to.clone()))?;
+        if from_denom == to_denom {
+            return Err(DcaError::SameDenom);
+        }
+        if amount == CoinValue(0) {
+            return Err(DcaError::ZeroAmount);
+        }
+        if interval_secs == 0 {
+            return Err(DcaError::ZeroInterval);
+        }
+        if !(0.0..1.0).contains(&max_slippage) {
+            return Err(DcaError::InvalidSlippage(max_slippage));
+        }
+        self.latest_snapshot()
+            .await
+            .map_err(|e| DcaError::Network(e.to_string()))?
+            .get_pool(PoolKey::new(from_denom, to_denom))
+            .await
+            .map_err(|e| DcaError::Network(e.to_string()))?
+            .ok_or(DcaError::NoPool(from, to))?;
+        let job = self
+            .database
+            .create_dca_job(
+                &wallet_name,
+                from_denom,
+                to_denom,
+                amount,
+                interval_secs,
+                max_slippage,
+            )
+            .await
+            .expect("db failed");
+        log::info!(
+            "created DCA job {} of {wallet_name}: {} {} for {} every {interval_secs}s",
+            job.id,
+            job.amount,
+            job.from,
+            job.to
+        );
+        Ok(job)
+    }
+
+    async fn list_dca_jobs(&self, wallet_name: Option<String>) -> Vec<DcaJob> {
+        self.database
+            .list_dca_jobs(wallet_name.as_deref())
+            .await
+            .expect("db failed")
+    }
+
+    async fn cancel_dca_job(&self, job_id: String) -> Result<DcaJob, DcaError> {
+        let job = self
+            .database
+            .get_dca_job(&job_id)
+            .await
+            .expect("db failed")
+            .ok_or(DcaError::JobNotFound)?;
+        if !job.active {
+            return Err(DcaError::Inactive);
+        }
+        self.database
+            .cancel_dca_job(&job_id)
+            .await
+            .expect("db failed");
+        log::info!("cancelled DCA job {job_id} of {}", job.wallet_name);
+        Ok(DcaJob {
+            active: false,
+            ..job
+        })
+    }
+
+    async fn dca_runs(&self, job_id: String, limit: usize) -> Result<Vec<DcaRun>, DcaError> {
+        self.database
+            .get_dca_job(&job_id)
+            .await
+            .expect("db failed")
+            .ok_or(DcaError::JobNotFound)?;
+        Ok(self
+            .database
+            .dca_runs(&job_id, limit)
+            .await
+            .expect("db failed"))
+    }
+
+    async fn set_display_preferences(
+        &self,
+        wallet_name: String,
+        preferences: DisplayPreferences,
+    ) -> Result<(), NeedWallet<DisplayPreferencesError>> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        if let Some(bad) = preferences
+            .hidden_tokens
+            .iter()
+            .find(|denom| denom.parse::<Denom>().is_err())
+        {
+            return Err(DisplayPreferencesError::InvalidDenom(bad.clone()).into());
+        }
+        self.database
+            .set_display_preferences(&wallet_name, &preferences)
+            .await
+            .expect("db failed");
+        Ok(())
+    }
+
+    async fn display_preferences(
+        &self,
+        wallet_name: String,
+    ) -> Result<DisplayPreferences, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self
+            .database
+            .display_preferences(&wallet_name)
+            .await
+            .expect("db failed"))
+    }
+
+    async fn prepare_unsigned_tx(
+        &self,
+        wallet_name: String,
+        request: ExtPrepareTxArgs,
+    ) -> Result<SigningRequest, NeedWallet<PrepareTxError>> {
+        let pubkey = self
+            .database
+            .watch_only_key(&wallet_name)
+            .await
+            .expect("db failed")
+            .ok_or_else(|| {
+                NeedWallet::Wallet(WalletAccessError::Other(
+                    ColdSigningError::NotWatchOnly.to_string(),
+                ))
+            })?;
+        // sign with placeholders, so that the fee accounts for the real signatures
+        let mut tx = self
+            .prepare_with_signer(
+                &wallet_name,
+                request,
+                Arc::new(PlaceholderSigner(pubkey)),
+                &BTreeSet::new(),
+            )
+            .await?;
+        tx.sigs.clear();
+        let req = self
+            .database
+            .insert_signing_request(&wallet_name, &tx)
+            .await
+            .expect("db failed");
+        log::info!(
+            "prepared unsigned transaction {} for {wallet_name}",
+            req.txhash
+        );
+        Ok(req)
+    }
+
+    async fn prepare_emergency_sweep(
+        &self,
+        wallet_name: String,
+        destination: String,
+    ) -> Result<Vec<SigningRequest>, NeedWallet<ColdSigningError>> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let destination = parse_address(&destination)
+            .ok_or_else(|| ColdSigningError::InvalidAddress(destination.clone()))?;
+        let pubkey = self
+            .wallet_public_key(&wallet_name, &wallet)
+            .await
+            .expect("db failed")
+            .ok_or(ColdSigningError::UnknownKey)?;
+        let snapshot = self
+            .latest_snapshot()
+            .await
+            .map_err(|e| ColdSigningError::Network(e.to_string()))?;
+        let fee_multiplier = snapshot.current_header().fee_multiplier * SWEEP_FEE_HEADROOM;
+        let sweeps = prepare_sweeps(
+            &wallet,
+            destination,
+            fee_multiplier,
+            &PlaceholderSigner(pubkey),
+        )
+        .await
+        .map_err(|e| ColdSigningError::CannotSweep(e.to_string()))?;
+        if sweeps.is_empty() {
+            return Err(ColdSigningError::CannotSweep("no spendable coins".into()).into());
+        }
+        let mut requests = vec![];
+        for mut tx in sweeps {
+            tx.sigs.clear();
+            let req = self
+                .database
+                .insert_signing_request(&wallet_name, &tx)
+                .await
+                .expect("db failed");
+            log::warn!(
+                "prepared emergency sweep {} of {wallet_name} to {destination}",
+                req.txhash
+            );
+            requests.push(req);
+        }
+        Ok(requests)
+    }
+
+    async fn export_signing_bundle(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+    ) -> Result<SigningBundle, NeedWallet<ColdSigningError>> {
+        let (wallet, req) = self.signing_request(&wallet_name, txhash).await?;
+        if !matches!(
+            req.status,
+            SigningStatus::Prepared | SigningStatus::Exported
+        ) {
+            return Err(ColdSigningError::WrongStatus(req.status).into());
+        }
+        let mut spent_coins = BTreeMap::new();
+        for input in req.transaction.inputs.iter() {
+            if let Some(data) = wallet.get_one_coin(*input).await {
+                spent_coins.insert(input.to_string(), data);
+            }
+        }
+        self.database
+            .update_signing_request(txhash, SigningStatus::Exported, None)
+            .await
+            .expect("db failed");
+        Ok(SigningBundle {
+            address: wallet.address(),
+            transaction: req.transaction,
+            spent_coins,
+        })
+    }
+
+    async fn sign_bundle(
+        &self,
+        wallet_name: String,
+        bundle: SigningBundle,
+    ) -> Result<Transaction, NeedWallet<ColdSigningError>> {
+        let wallet = self
+            .get_wallet(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::NotFound))?;
+        let signer = self
+            .use_signer(&wallet_name)
+            .await
+            .ok_or(NeedWallet::Wallet(WalletAccessError::Locked))?;
+        if bundle.address != wallet.address() {
+            return Err(ColdSigningError::WrongWallet(bundle.address.to_string()).into());
+        }
+        let mut tx = bundle.transaction;
+        for i in 0..tx.inputs.len() {
+            tx = signer
+                .sign_tx(tx, i)
+                .map_err(|e| NeedWallet::Wallet(WalletAccessError::Other(e.to_string())))?;
+        }
+        log::info!("signed bundle {} with {wallet_name}", tx.hash_nosigs());
+        Ok(tx)
+    }
+
+    async fn import_signed_tx(
+        &self,
+        wallet_name: String,
+        tx: Transaction,
+    ) -> Result<SigningRequest, NeedWallet<ColdSigningError>> {
+        let (wallet, mut req) = self.signing_request(&wallet_name, tx.hash_nosigs()).await?;
+        if req.status == SigningStatus::Sent {
+            return Err(ColdSigningError::WrongStatus(req.status).into());
+        }
+        let pubkey = self
+            .wallet_public_key(&wallet_name, &wallet)
+            .await
+            .expect("db failed")
+            .ok_or(ColdSigningError::UnknownKey)?;
+        verify_signatures(pubkey, &tx).map_err(ColdSigningError::BadSignature)?;
+        self.database
+            .update_signing_request(req.txhash, SigningStatus::Signed, Some(&tx))
+            .await
+            .expect("db failed");
+        req.status = SigningStatus::Signed;
+        req.transaction = tx;
+        Ok(req)
+    }
+
+    async fn broadcast_signed_tx(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+    ) -> Result<TxHash, NeedWallet<ColdSigningError>> {
+        let (_, req) = self.signing_request(&wallet_name, txhash).await?;
+        if req.status != SigningStatus::Signed {
+            return Err(ColdSigningError::WrongStatus(req.status).into());
+        }
+        let txhash = MelwalletdProtocol::send_tx(self, wallet_name, req.transaction)
+            .await
+            .map_err(|e| match e {
+                NeedWallet::Wallet(e) => NeedWallet::Wallet(e),
+                NeedWallet::Other(e) => ColdSigningError::Network(e.to_string()).into(),
+            })?;
+        self.database
+            .update_signing_request(txhash, SigningStatus::Sent, None)
+            .await
+            .expect("db failed");
+        Ok(txhash)
+    }
+
+    async fn submit_signatures(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+        sigs: Vec<String>,
+    ) -> Result<TxHash, NeedWallet<ColdSigningError>> {
+        let (_, req) = self.signing_request(&wallet_name, txhash).await?;
+        if req.status == SigningStatus::Sent {
+            return Err(ColdSigningError::WrongStatus(req.status).into());
+        }
+        if sigs.len() != req.transaction.inputs.len() {
+            return Err(ColdSigningError::BadSignature(
+                sigs.len().min(req.transaction.inputs.len()),
+            )
+            .into());
+        }
+        let mut tx = req.transaction;
+        tx.sigs = sigs
+            .iter()
+            .enumerate()
+            .map(|(i, sig)| {
+                hex::decode(sig.trim())
+                    .map(Bytes::from)
+                    .map_err(|_| ColdSigningError::BadSignature(i))
+            })
+            .collect::<Result<_, _>>()?;
+        self.import_signed_tx(wallet_name.clone(), tx).await?;
+        self.broadcast_signed_tx(wallet_name, txhash).await
+    }
+
+    async fn signing_requests(
+        &self,
+        wallet_name: String,
+    ) -> Result<Vec<SigningRequest>, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self
+            .database
+            .list_signing_requests(&wallet_name)
+            .await
+            .expect("db failed"))
+    }
+
+    async fn discard_signing_request(
+        &self,
+        wallet_name: String,
+        txhash: TxHash,
+    ) -> Result<bool, WalletAccessError> {
+        self.get_wallet(&wallet_name)
+            .await
+            .ok_or(WalletAccessError::NotFound)?;
+        Ok(self
+            .database
+            .delete_signing_request(&wallet_name, txhash)
+            .await
+            .expect("db failed"))
+    }
+
+    async fn encode_tx(&self, tx: Transaction) -> String {
+        hex::encode(tx.stdcode())
+    }
+
+    async fn decode_tx(&self, hex: String) -> Result<Transaction, TxDecodeError> {
+        let bytes = hex::decode(hex.trim()).map_err(|e| TxDecodeError(e.to_string()))?;
+        stdcode::deserialize(&bytes).map_err(|e| TxDecodeError(e.to_string()))
+    }
+}
+
+/// Starts the RPC tide route
+/// Methods that can be called while the secret store is sealed.
+const SEALED_METHODS: &[&str] = &[
+    "secrets_status",
+    "unseal_secrets",
+    "reload_secrets",
+    "capabilities",
+    "build_info",
+];
+
+/// Methods that expose the whole daemon's log, which mixes every user's wallets, and so can't be called in the namespaces of a multi-user daemon.
+const ROOT_METHODS: &[&str] = &["tail_logs", "follow_logs"];
+
+pub fn route_rpc(app: &mut Server<AppState>) {
+    // the unversioned root predates protocol versions, and serves the first one
+    app.at("").post(serve_rpc);
+    app.at(capabilities::ENDPOINT).post(serve_rpc);
+}
+
+/// Answers calls of both the upstream protocol and its extensions.
+pub fn rpc_service(
+    state: AppState,
+) -> OrService<MelwalletdExtService<AppState>, MelwalletdService<AppState>> {
+    OrService::new(
+        MelwalletdExtService(state.clone()),
+        MelwalletdService(state),
+    )
+}
+
+async fn serve_rpc(mut r: Request<AppState>) -> tide::Result<Body> {
+    let service = r
+        .state()
+        .for_request(r.header("authorization").map(|h| h.as_str()))
+        .ok_or_else(|| {
+            tide::Error::from_str(StatusCode::Unauthorized, "missing or unknown token")
+        })?;
+    let request_body: nanorpc::JrpcRequest = r.body_json().await?;
+    let method = request_body.method.clone();
+    let id = request_body.id.clone();
+    if service.secrets.is_sealed() && !SEALED_METHODS.contains(&method.as_str()) {
+        return Body::from_json(&rpc_error(
+            id,
+            -32000,
+            "secrets are sealed; call unseal_secrets with the master passphrase".into(),
+        ));
+    }
+    if service.user.is_some() && ROOT_METHODS.contains(&method.as_str()) {
+        return Body::from_json(&rpc_error(
+            id,
+            -32000,
+            format!("{method} is not available to users of a multi-user daemon"),
+        ));
+    }
+    if !POWER_METHODS.contains(&method.as_str()) {
+        service.power.interactive();
+    }
+    let rpc_calls = service.rpc_calls.clone();
+    let in_flight = service.in_flight.clone();
+    let timeout = service.config.rpc_timeout(&method);
+    let service = rpc_service(service);
+    // unregisters the cancel handle, if any, once the call is over
+    let mut _registered = None;
+    let call = match r.header(CANCEL_HANDLE_HEADER) {
+        Some(_) if SENDING_METHODS.contains(&method.as_str()) => {
+            return Err(tide::Error::from_str(
+                StatusCode::BadRequest,
+                format!("{method} sends transactions, so it cannot be cancelled"),
+            ))
+        }
+        Some(handle) => {
+            let handle = handle.as_str().to_owned();
+            let (abort, registration) = AbortHandle::new_pair();
+            match in_flight.entry(handle.clone()) {
+                Entry::Occupied(_) => {
+                    return Err(tide::Error::from_str(
+                        StatusCode::Conflict,
+                        "cancel handle already in use",
+                    ))
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(abort);
+                }
+            }
+            _registered = Some(InFlight(&in_flight, handle));
+            Abortable::new(service.respond_raw(request_body), registration).boxed()
+        }
+        None => service
+            .respond_raw(request_body)
+            .map(Ok::<_, Aborted>)
+            .boxed(),
+    };
+    let cancelled = |Aborted| rpc_error(id.clone(), CANCELLED_ERROR_CODE, "cancelled".into());
+    let rpc_res = match timeout {
+        Some(timeout) => match call.timeout(timeout).await {
+            Some(res) => res.unwrap_or_else(cancelled),
+            None => {
+                log::warn!("RPC call {method} timed out after {:?}", timeout);
+                rpc_error(
+                    id.clone(),
+                    TIMEOUT_ERROR_CODE,
+                    format!("timed out after {} seconds", timeout.as_secs()),
+                )
+            }
+        },
+        None => call.await.unwrap_or_else(cancelled),
+    };
+    // only count methods that exist, so that bogus calls can't grow the table
+    if !matches!(&rpc_res.error, Some(err) if err.code == -32601) {
+        *rpc_calls.entry(method).or_default() += 1;
+    }
+    Body::from_json(&rpc_res)
+}
+
+/// A response to an RPC call that failed before or instead of getting to the method.
+fn rpc_error(id: JrpcId, code: i64, message: String) -> JrpcResponse {
+    JrpcResponse {
+        jsonrpc: "2.0".into(),
+        result: None,
+        error: Some(JrpcError {
+            code,
+            message,
+            data: serde_json::Value::Null,
+        }),
+        id,
+    }
+}
+
+/// Unregisters a cancellable RPC call once it's done, however it ends.
+struct InFlight<'a>(&'a DashMap<String, AbortHandle>, String);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.remove(&self.1);
+    }
 }
@@ -0,0 +1,1947 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::SocketAddr,
+};
+
+use melstructs::{
+    Address, BlockHeight, CoinData, CoinID, CoinValue, Denom, NetID, Transaction, TxHash, TxKind,
+};
+use melwalletd_prot::types::WalletSummary;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tmelcrypt::{Ed25519PK, HashVal};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Returned from [crate::protocol::ext::MelwalletdExtProtocol::password_strength], estimating how hard a password is to guess.
+pub struct PasswordStrength {
+    /// zxcvbn strength score, from 0 (trivially guessable) to 4 (very hard to guess)
+    pub score: u8,
+    /// Base-10 logarithm of the estimated number of guesses needed to crack the password
+    pub guesses_log10: f64,
+    /// Length of the password, in characters
+    pub length: usize,
+    /// Whether the password satisfies the daemon's password policy
+    pub acceptable: bool,
+    /// Explanation of what makes the password weak, if anything
+    pub warning: Option<String>,
+    /// Suggestions for a stronger password
+    pub suggestions: Vec<String>,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+#[error("password does not satisfy the password policy (score {}, length {})", .0.score, .0.length)]
+/// Indicates that a new password was rejected by the password policy.
+pub struct WeakPasswordError(pub PasswordStrength);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// The status of an in-progress key rotation, returned from [crate::protocol::ext::MelwalletdExtProtocol::key_rotation_status].
+pub struct KeyRotationStatus {
+    /// Address the wallet will have once the rotation finishes. JSON-serialized as the standard `t.....` address format.
+    #[serde(with = "stdcode::asstr")]
+    pub new_address: Address,
+    /// All sweep transactions sent so far
+    pub sweeps: Vec<TxHash>,
+    /// Sweep transactions that have not confirmed yet
+    pub pending_sweeps: Vec<TxHash>,
+    /// Number of confirmed coins still at the old address
+    pub remaining_coins: usize,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+#[error("invalid address: {0}")]
+/// Indicates that a string could not be parsed as an address.
+pub struct InvalidAddressError(pub String);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// An address tracked in observe-only mode, returned from [crate::protocol::ext::MelwalletdExtProtocol::tracked_addresses].
+pub struct TrackedAddress {
+    /// The tracked address. JSON-serialized as the standard `t.....` address format.
+    #[serde(with = "stdcode::asstr")]
+    pub address: Address,
+    /// Free-form label given when tracking the address
+    pub label: String,
+    /// Number of unspent coins at the address
+    pub coin_count: u64,
+    /// Balance of the address. Keys are the standard string representation of a [melstructs::Denom].
+    pub balances: BTreeMap<String, CoinValue>,
+    /// Height at which the balance was last refreshed, or `null` if it hasn't been yet.
+    pub last_height: Option<BlockHeight>,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+#[error("cannot decode transaction: {0}")]
+/// Indicates that a hex string is not a validly encoded transaction.
+pub struct TxDecodeError(pub String);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Arguments passed to [crate::protocol::ext::MelwalletdExtProtocol::prepare_tx]. A superset of [melwalletd_prot::types::PrepareTxArgs], with the same JSON representation for the fields they share.
+pub struct PrepareTxArgs {
+    #[serde(default = "txkind_normal")]
+    /// "Kind" of the transaction. Optional in JSON, defaulting to [TxKind::Normal].
+    pub kind: TxKind,
+    /// **Additional** inputs of the transaction. See [melwalletd_prot::types::PrepareTxArgs::inputs]. Optional in JSON, defaulting to an empty list.
+    #[serde(default)]
+    pub inputs: Vec<CoinID>,
+    /// **Required** outputs of the transaction. More outputs may be added after these as "change" outputs.
+    pub outputs: Vec<CoinData>,
+    /// **Additional** covenants that must be included in the transaction. Optional in JSON, defaulting to an empty list.
+    #[serde(default, with = "stdcode::hexvec")]
+    pub covenants: Vec<Vec<u8>>,
+    /// The "data" field of the transaction. Optional and hex-encoded in JSON, defaulting to an empty string.
+    #[serde(default, with = "stdcode::hex")]
+    pub data: Vec<u8>,
+    /// Denominations that should not be balanced. Optional in JSON, defaulting to an empty list.
+    #[serde(default)]
+    pub nobalance: Vec<Denom>,
+    /// Pretend like the transaction has this many more bytes when calculating the correct fee level. Optional in JSON, defaulting to the wallet's default fee ballast.
+    #[serde(default)]
+    pub fee_ballast: Option<usize>,
+    /// Name of another wallet that pays the fee, out of its own MEL, and gets the MEL change of paying it. It must be unlocked. The outputs are still funded by this wallet. Optional in JSON, defaulting to this wallet paying its own fee.
+    #[serde(default)]
+    pub fee_sponsor: Option<String>,
+    /// If not empty, only coins carrying at least one of these labels are picked as inputs. Optional in JSON, defaulting to an empty list.
+    #[serde(default)]
+    pub spend_labels: Vec<String>,
+    /// Coins carrying any of these labels are never picked as inputs. Optional in JSON, defaulting to an empty list.
+    #[serde(default)]
+    pub avoid_labels: Vec<String>,
+    /// Order in which the wallet's coins are picked as inputs. Optional in JSON, defaulting to [CoinSelection::Arbitrary].
+    #[serde(default)]
+    pub coin_selection: CoinSelection,
+    /// Prepare the transaction even if its fee is above the daemon's configured maximum. Optional in JSON, defaulting to false.
+    #[serde(default)]
+    pub allow_high_fee: bool,
+    /// Address, in any encoding [crate::protocol::ext::MelwalletdExtProtocol::address_forms] accepts, that change outputs go to, such as a cold wallet's when draining a hot wallet. Addresses don't say which network they are for, so only their checksum can be checked. Optional in JSON, defaulting to this wallet's own address.
+    #[serde(default)]
+    pub change_address: Option<String>,
+    /// Denominations whose coins are never picked as inputs, even to balance the outputs, unless listed in `inputs`. Unlike `nobalance`, the transaction still has to balance these denominations, so it can only pay them out of `inputs`. Optional in JSON, defaulting to the wallet's default set by [crate::protocol::ext::MelwalletdExtProtocol::set_default_exclude_denoms].
+    #[serde(default)]
+    pub exclude_denoms: Option<BTreeSet<Denom>>,
+}
+
+fn txkind_normal() -> TxKind {
+    TxKind::Normal
+}
+
+impl From<melwalletd_prot::types::PrepareTxArgs> for PrepareTxArgs {
+    fn from(args: melwalletd_prot::types::PrepareTxArgs) -> Self {
+        Self {
+            kind: args.kind,
+            inputs: args.inputs,
+            outputs: args.outputs,
+            covenants: args.covenants,
+            data: args.data,
+            nobalance: args.nobalance,
+            // old clients always send a ballast, and almost always send zero without meaning it
+            fee_ballast: Some(args.fee_ballast).filter(|ballast| *ballast > 0),
+            fee_sponsor: None,
+            spend_labels: vec![],
+            avoid_labels: vec![],
+            coin_selection: CoinSelection::Arbitrary,
+            allow_high_fee: false,
+            change_address: None,
+            exclude_denoms: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Statistics about the transaction cache shared by all wallets, returned from [crate::protocol::ext::MelwalletdExtProtocol::transaction_cache_stats].
+pub struct TransactionCacheStats {
+    /// Number of cached transactions
+    pub transactions: u64,
+    /// Total size of the cached transactions, in bytes
+    pub bytes: u64,
+    /// Number of cached transactions that no wallet refers to any more. These are removed by pruning.
+    pub unreferenced: u64,
+    /// Number of cached transactions referred to by each wallet. A transaction between two wallets counts towards both.
+    pub per_wallet: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// Where a transaction prepared by a watch-only wallet is in the cold-signing workflow.
+pub enum SigningStatus {
+    /// Prepared, but not yet handed to the cold wallet
+    Prepared,
+    /// Exported as a [SigningBundle] for the cold wallet to sign
+    Exported,
+    /// Signatures from the cold wallet have been imported; ready to broadcast
+    Signed,
+    /// Broadcast to the network. From here on, the transaction is tracked like any other.
+    Sent,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A transaction going through the cold-signing workflow of a watch-only wallet.
+pub struct SigningRequest {
+    /// Hash of the transaction, not covering signatures. Identifies the request.
+    pub txhash: TxHash,
+    /// Current step of the workflow
+    pub status: SigningStatus,
+    /// The transaction: unsigned until signatures are imported, signed afterwards
+    pub transaction: Transaction,
+    /// For each input, the hash its signature must sign
+    pub sighashes: Vec<TxHash>,
+    /// UNIX timestamp at which the transaction was prepared
+    pub created: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Everything a cold wallet needs to sign a transaction prepared by its watch-only counterpart.
+pub struct SigningBundle {
+    /// Address whose key must sign the transaction. JSON-serialized as the standard `t.....` address format.
+    #[serde(with = "stdcode::asstr")]
+    pub address: Address,
+    /// The unsigned transaction
+    pub transaction: Transaction,
+    /// The coins spent by the transaction, so that the cold wallet can show what it is signing without network access
+    pub spent_coins: BTreeMap<String, CoinData>,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors in the cold-signing workflow.
+pub enum ColdSigningError {
+    #[error("wallet is not watch-only")]
+    NotWatchOnly,
+    #[error("bundle is for address {0}, not this wallet")]
+    WrongWallet(String),
+    #[error("no signing request for transaction {0}")]
+    NoSuchRequest(TxHash),
+    #[error("signing request is {0:?}, which does not allow this")]
+    WrongStatus(SigningStatus),
+    #[error("invalid signature for input {0}")]
+    BadSignature(usize),
+    #[error("network error: {0}")]
+    Network(String),
+    #[error("the wallet's public key is unknown, so signatures cannot be checked")]
+    UnknownKey,
+    #[error("invalid address: {0}")]
+    InvalidAddress(String),
+    #[error("cannot sweep the wallet: {0}")]
+    CannotSweep(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A prepared transaction, along with its weight, returned from [crate::protocol::ext::MelwalletdExtProtocol::prepare_tx] and [crate::protocol::ext::MelwalletdExtProtocol::prepare_split_tx].
+pub struct PreparedTx {
+    pub transaction: Transaction,
+    /// Weight of the transaction, which determines its fee. Never more than [crate::database::MAX_TX_WEIGHT].
+    pub weight: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Local operational statistics, returned from [crate::protocol::ext::MelwalletdExtProtocol::daemon_stats].
+pub struct DaemonStats {
+    /// Number of wallets
+    pub wallets: usize,
+    /// Number of coins known across all wallets, spent or not
+    pub coins: u64,
+    /// Total size of the database files, in bytes, not counting write-ahead logs
+    pub database_bytes: u64,
+    /// Seconds since the daemon started
+    pub uptime_secs: u64,
+    /// Number of RPC calls served since the daemon started, by method
+    pub rpc_calls: BTreeMap<String, u64>,
+    /// Number of chain lookups during sync that were answered from the cache shared by all wallets
+    pub chain_cache_hits: u64,
+    /// Number of chain lookups during sync that went to the node
+    pub chain_cache_misses: u64,
+    /// Statistics about requests to the node
+    pub node: NodeStats,
+    /// Corrupt database files that were rebuilt when the daemon started. Coins are resynced from scratch after a repair.
+    pub repairs: Vec<DatabaseRepair>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Aggregate statistics about requests made to the node while syncing, for tuning.
+pub struct NodeStats {
+    /// Number of requests made, including retries
+    pub requests: u64,
+    /// Number of requests that failed
+    pub failures: u64,
+    /// Number of requests retried after a transient error
+    pub retries: u64,
+    /// Moving average of the latency of successful requests, in milliseconds
+    pub latency_ms: Option<u64>,
+    /// Current limit on requests made at once, which adapts to the node's latency and error rate
+    pub concurrency: usize,
+    /// Number of requests currently in flight
+    pub in_flight: usize,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when importing a coin into a wallet.
+pub enum ImportCoinError {
+    #[error("coin not found; it may not exist, or may already be spent")]
+    NotFound,
+    #[error("coin is at {0}, which is not the wallet's address, so its covenant must be given")]
+    CovenantNeeded(String),
+    #[error("covenant is malformed, or does not match the coin's address")]
+    BadCovenant,
+    #[error("the wallet cannot spend this coin")]
+    NotSpendable,
+    #[error("network error: {0}")]
+    Network(String),
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when converting between raw values and display units.
+pub enum UnitConversionError {
+    #[error("invalid denomination: {0}")]
+    InvalidDenom(String),
+    #[error("invalid amount: {0}")]
+    InvalidAmount(String),
+    #[error("amount has more than {0} decimal places")]
+    TooPrecise(u8),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+/// How waiting for a transaction to confirm ended, returned from [crate::protocol::ext::MelwalletdExtProtocol::wait_for_confirmation].
+pub enum ConfirmationOutcome {
+    /// The transaction confirmed at this height
+    Confirmed(BlockHeight),
+    /// The wallet no longer knows of the transaction: it was never sent, or it expired without confirming
+    Lost,
+    /// The transaction is still pending
+    TimedOut,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// Whether an invoice has been paid.
+pub enum InvoiceStatus {
+    /// Not (fully) paid yet
+    Open,
+    /// Payments carrying the invoice's memo add up to at least its amount
+    Paid,
+    /// Expired before being fully paid
+    Expired,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A request for payment to a wallet, returned from [crate::protocol::ext::MelwalletdExtProtocol::create_invoice].
+pub struct Invoice {
+    /// Unique identifier of the invoice
+    pub id: String,
+    pub wallet_name: String,
+    /// Address to pay. JSON-serialized as the standard `t.....` address format.
+    #[serde(with = "stdcode::asstr")]
+    pub address: Address,
+    /// Amount due, in raw units of `denom`
+    pub amount: CoinValue,
+    /// Standard string representation of the [Denom] due
+    pub denom: String,
+    /// Hex-encoded `additional_data` that payments must carry to count towards this invoice
+    pub memo: String,
+    /// URL that is sent an HTTP POST with this invoice as JSON once it's paid
+    pub webhook: Option<String>,
+    pub status: InvoiceStatus,
+    /// Total of the confirmed payments so far
+    pub received: CoinValue,
+    /// Height at which the invoice was fully paid
+    pub paid_height: Option<BlockHeight>,
+    /// UNIX timestamp of creation
+    pub created: u64,
+    /// UNIX timestamp after which the invoice can no longer be paid
+    pub expires: u64,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when creating an invoice.
+pub enum InvoiceError {
+    #[error("wallet not found")]
+    WalletNotFound,
+    #[error("invalid denomination: {0}")]
+    InvalidDenom(String),
+    #[error("amount must be positive")]
+    ZeroAmount,
+    #[error("webhook must be an http:// URL, not {0}")]
+    BadWebhook(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// A party to an escrow.
+pub enum EscrowRole {
+    Buyer,
+    Seller,
+    Arbiter,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// The state of an escrow.
+pub enum EscrowStatus {
+    /// Waiting for the buyer to fund it
+    Created,
+    /// Holds the buyer's funds
+    Funded,
+    /// Collecting signatures for a transaction paying the seller
+    Releasing,
+    /// Collecting signatures for a transaction paying the buyer back
+    Refunding,
+    /// The seller has been paid
+    Released,
+    /// The buyer has been paid back
+    Refunded,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A 2-of-3 escrow between a buyer, a seller and an arbiter, returned from [crate::protocol::ext::MelwalletdExtProtocol::create_escrow]. Any two of the parties can together release the funds to the seller, or refund them to the buyer.
+pub struct Escrow {
+    /// Unique identifier of the escrow
+    pub id: String,
+    /// Address holding the escrowed funds. JSON-serialized as the standard `t.....` address format.
+    #[serde(with = "stdcode::asstr")]
+    pub address: Address,
+    /// Hex-encoded public key of the buyer, who funds the escrow
+    pub buyer: String,
+    /// Hex-encoded public key of the seller, who is paid on release
+    pub seller: String,
+    /// Hex-encoded public key of the arbiter, who settles disputes
+    pub arbiter: String,
+    pub status: EscrowStatus,
+    /// The escrowed coin, once funded
+    pub coin: Option<CoinID>,
+    /// Amount of MEL escrowed, once funded
+    pub value: Option<CoinValue>,
+    /// The release or refund transaction, with the signatures collected so far
+    pub transaction: Option<Transaction>,
+    /// Parties who have validly signed the release or refund transaction
+    pub signed_by: Vec<EscrowRole>,
+    /// UNIX timestamp of creation
+    pub created: u64,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors in the escrow workflow.
+pub enum EscrowError {
+    #[error("no escrow {0}")]
+    NotFound(String),
+    #[error("invalid public key {0}")]
+    InvalidKey(String),
+    #[error("escrow is {0:?}, which does not allow this")]
+    WrongStatus(EscrowStatus),
+    #[error("funding transaction has not confirmed yet")]
+    NotConfirmed,
+    #[error("key is not a party to the escrow")]
+    NotAParty,
+    #[error("signature is not valid for any party")]
+    BadSignature,
+    #[error("cannot prepare transaction: {0}")]
+    Prepare(String),
+    #[error("network error: {0}")]
+    Network(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// An output of a transaction prepared by [crate::protocol::ext::MelwalletdExtProtocol::prepare_timelocked_tx], which its recipient can only spend from the unlock height onwards.
+pub struct TimelockedOutput {
+    /// Public key of the recipient. JSON-serialized as hex.
+    pub recipient: Ed25519PK,
+    pub value: CoinValue,
+    pub denom: Denom,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// The state of a timelocked coin.
+pub enum TimelockStatus {
+    /// The transaction creating the coin has not confirmed (or has not been sent)
+    Unconfirmed,
+    /// Confirmed, but not spendable until the unlock height
+    Locked,
+    /// Spendable by the recipient
+    Mature,
+    /// Spent by the recipient
+    Spent,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// Whether a timelocked coin is coming into or going out of a wallet.
+pub enum TimelockDirection {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A coin locked until some height, returned from [crate::protocol::ext::MelwalletdExtProtocol::timelocked_coins].
+pub struct TimelockedCoin {
+    pub coin_id: CoinID,
+    pub direction: TimelockDirection,
+    /// Name of the wallet that prepared the transaction creating the coin
+    pub sender: String,
+    /// Public key of the recipient. JSON-serialized as hex.
+    pub recipient: Ed25519PK,
+    /// Address of the coin. JSON-serialized as the standard `t.....` address format.
+    #[serde(with = "stdcode::asstr")]
+    pub address: Address,
+    /// First height at which the coin can be spent
+    pub unlock_height: BlockHeight,
+    pub value: CoinValue,
+    /// Standard string representation of the [Denom] of the coin
+    pub denom: String,
+    pub status: TimelockStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// The dead-man switch of a wallet, returned from [crate::protocol::ext::MelwalletdExtProtocol::inheritance_status].
+pub struct InheritanceStatus {
+    /// Address that the wallet is swept to if the switch goes off. JSON-serialized as the standard `t.....` address format.
+    #[serde(with = "stdcode::asstr")]
+    pub recovery_address: Address,
+    /// How long the wallet may go without being unlocked or pinged, in seconds
+    pub inactivity_secs: u64,
+    /// UNIX timestamp at which the wallet was last unlocked or pinged
+    pub last_activity: u64,
+    /// UNIX timestamp at which the switch goes off, unless the wallet is unlocked or pinged before then
+    pub deadline: u64,
+    /// Pre-signed sweep transactions, broadcast when the switch goes off
+    pub sweeps: Vec<TxHash>,
+    /// UNIX timestamp at which the switch went off, if it has
+    pub triggered: Option<u64>,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when configuring the dead-man switch of a wallet.
+pub enum InheritanceError {
+    #[error("invalid recovery address: {0}")]
+    InvalidAddress(String),
+    #[error("wallet has no dead-man switch")]
+    NotConfigured,
+    #[error("dead-man switch has already gone off")]
+    AlreadyTriggered,
+    #[error("cannot prepare sweep: {0}")]
+    Sweep(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Everything needed to register the daemon as the handler of payment URIs, returned from [crate::protocol::ext::MelwalletdExtProtocol::uri_handler_info].
+pub struct UriHandlerInfo {
+    /// URI scheme of payment links, without the colon
+    pub scheme: String,
+    /// Example of a payment link, showing the recognized parameters
+    pub example: String,
+    /// URL at which the daemon serves JSON-RPC requests
+    pub rpc_url: String,
+    /// Port the daemon listens on
+    pub port: u16,
+    pub network: NetID,
+    /// How clients authenticate to the daemon, or `null` if they don't need to. Unless it has several users, the daemon relies on only listening locally, and on its list of allowed origins for browsers.
+    pub auth_token_hint: Option<String>,
+    /// Origins that browsers may make requests from
+    pub allowed_origins: Vec<String>,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when parsing a payment URI.
+pub enum PaymentUriError {
+    #[error("not a payment URI: {0}")]
+    Malformed(String),
+    #[error("invalid address: {0}")]
+    InvalidAddress(String),
+    #[error("payment URI has no amount")]
+    MissingAmount,
+    #[error(transparent)]
+    InvalidAmount(#[from] UnitConversionError),
+    #[error("invalid data: {0}")]
+    InvalidData(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// Where the secret key of a wallet lives.
+pub enum KeyOrigin {
+    /// The key is kept, encrypted, by this daemon
+    Local,
+    /// The wallet is watch-only; the key is kept in a cold wallet elsewhere
+    Cold,
+    /// The daemon holds no key for the wallet
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// An imported covenant, as part of a [WalletDescriptor].
+pub struct DescriptorCovenant {
+    /// JSON-serialized as the standard `t.....` address format.
+    #[serde(with = "stdcode::asstr")]
+    pub address: Address,
+    /// Hex-encoded covenant
+    #[serde(with = "stdcode::hex")]
+    pub covenant: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Everything other tooling needs to reconstruct a watch-only view of a wallet, returned from [crate::protocol::ext::MelwalletdExtProtocol::export_descriptor].
+pub struct WalletDescriptor {
+    pub wallet_name: String,
+    pub network: NetID,
+    /// Wallet type, such as `standard`
+    pub kind: String,
+    /// Compact descriptor of the form `<kind>(<pubkey>[,<params>])`, or `null` if the public key cannot be recovered from the covenant
+    pub descriptor: Option<String>,
+    /// Public key of the wallet, if it can be recovered. JSON-serialized as hex.
+    pub public_key: Option<Ed25519PK>,
+    pub key_origin: KeyOrigin,
+    /// JSON-serialized as the standard `t.....` address format.
+    #[serde(with = "stdcode::asstr")]
+    pub address: Address,
+    /// Hex-encoded covenant guarding the wallet's coins
+    #[serde(with = "stdcode::hex")]
+    pub covenant: Vec<u8>,
+    /// Hex-encoded wallet type parameters
+    #[serde(with = "stdcode::hex")]
+    pub params: Vec<u8>,
+    /// Covenants imported into the wallet, whose coins the wallet also spends
+    pub imported: Vec<DescriptorCovenant>,
+    /// Key derivation path, for wallets derived from a seed. Always `null` for now, as every wallet holds a single key.
+    pub derivation: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Describes an encrypted backup kept in object storage.
+pub struct BackupInfo {
+    /// Name of the backup object
+    pub name: String,
+    /// UNIX timestamp of when the backup was taken
+    pub created: u64,
+    /// Size of the encrypted backup, in bytes
+    pub size: u64,
+    pub network: NetID,
+    /// Wallets contained in the backup
+    pub wallets: Vec<String>,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when backing up or restoring.
+pub enum BackupError {
+    #[error("backups are not configured")]
+    NotConfigured,
+    #[error("backup storage failed: {0}")]
+    Storage(String),
+    #[error("backup is corrupt, or was encrypted with another passphrase: {0}")]
+    Corrupt(String),
+    #[error("backup is of network {0:?}")]
+    WrongNetwork(NetID),
+    #[error("cannot take backup: {0}")]
+    Other(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+/// Outcome of [crate::protocol::ext::MelwalletdExtProtocol::rebuild_from_journal].
+pub struct JournalReplay {
+    /// Number of transactions in the journal
+    pub entries: usize,
+    /// Transactions put back into the transaction cache
+    pub restored: usize,
+    /// Transactions that were still cached
+    pub already_known: usize,
+    /// Transactions of wallets that don't exist, such as escrow settlements
+    pub skipped: usize,
+    /// Journal lines that could not be read, such as one torn by a crash
+    pub corrupt: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A corrupt database file that was rebuilt at startup.
+pub struct DatabaseRepair {
+    /// The repaired file
+    pub file: String,
+    /// Where the corrupt file was kept, for forensics
+    pub corrupt_file: String,
+    /// UNIX timestamp of the repair
+    pub time: u64,
+    /// Problems found by the integrity check
+    pub problems: Vec<String>,
+    /// Tables whose readable rows were carried over
+    pub salvaged: Vec<String>,
+    /// Number of unreadable rows left behind, for each salvaged table that had any
+    #[serde(default)]
+    pub skipped_rows: BTreeMap<String, u64>,
+    /// Tables that could not be read at all, or could not be read past some row; rows read before that were carried over
+    pub lost: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// When a wallet's outgoing transactions count as anomalous. Anomalous transactions are held until confirmed with the wallet's password, rather than broadcast. See [crate::protocol::ext::MelwalletdExtProtocol::set_anomaly_policy].
+pub struct AnomalyPolicy {
+    /// Length of the rolling window that activity is measured over, in seconds
+    #[serde(default = "default_anomaly_window")]
+    pub window_secs: u64,
+    /// How far back the baseline of normal activity goes, in seconds
+    #[serde(default = "default_anomaly_baseline")]
+    pub baseline_secs: u64,
+    /// How many times the busiest window of the baseline the current window's send count or volume of any token may reach
+    #[serde(default = "default_anomaly_multiplier")]
+    pub multiplier: f64,
+    /// Sends needed in the baseline period before anything is considered anomalous
+    #[serde(default = "default_anomaly_min_sends")]
+    pub min_baseline_sends: usize,
+    /// HTTP URL that alerts about held transactions are POSTed to, as a JSON [HeldSend]
+    #[serde(default)]
+    pub alert_webhook: Option<String>,
+}
+
+fn default_anomaly_window() -> u64 {
+    3600
+}
+
+fn default_anomaly_baseline() -> u64 {
+    30 * 86400
+}
+
+fn default_anomaly_multiplier() -> f64 {
+    3.0
+}
+
+fn default_anomaly_min_sends() -> usize {
+    5
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// Why a transaction was held back, which decides how it may be released.
+pub enum HoldKind {
+    /// Tripped the wallet's anomaly policy; released with the wallet's password
+    Anomaly,
+    /// Sent by a wallet in approval mode; released with the wallet's approver token
+    Approval,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A transaction that was held back instead of being broadcast.
+pub struct HeldSend {
+    /// Hash of the transaction, not covering signatures
+    pub txhash: TxHash,
+    /// Wallet that tried to send the transaction
+    pub wallet_name: String,
+    /// What has to happen before the transaction is broadcast
+    pub kind: HoldKind,
+    /// Why the transaction was held
+    pub reason: String,
+    /// The signed transaction
+    pub transaction: Transaction,
+    /// UNIX timestamp at which the transaction was held
+    pub created: u64,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when sending a transaction, as returned from [crate::protocol::ext::MelwalletdExtProtocol::send_tx].
+pub enum SendError {
+    #[error("sending more than {0} micromel, or any other token, needs a TOTP code; use send_tx_with_totp")]
+    NeedsTotp(u128),
+    /// The transaction was not sent, but held back; it is released with approve_send or confirm_held_send, depending on `kind`
+    #[error("transaction {txhash} is held: {reason}")]
+    Held {
+        txhash: TxHash,
+        kind: HoldKind,
+        reason: String,
+    },
+    #[error(transparent)]
+    Network(melwalletd_prot::types::NetworkError),
+}
+
+impl From<SendError> for melwalletd_prot::types::NetworkError {
+    fn from(err: SendError) -> Self {
+        match err {
+            SendError::Network(err) => err,
+            err => Self::Fatal(err.to_string()),
+        }
+    }
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when configuring anomaly detection or handling held transactions.
+pub enum AnomalyError {
+    #[error("invalid anomaly policy: {0}")]
+    InvalidPolicy(String),
+    #[error("webhook must be an http:// URL, not {0}")]
+    BadWebhook(String),
+    #[error("no such held transaction")]
+    NotHeld,
+    #[error("transaction awaits approval, and can only be released with approve_send")]
+    NeedsApproval,
+    #[error("cannot broadcast transaction: {0}")]
+    Broadcast(String),
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when approving or rejecting the spends of a wallet in approval mode.
+pub enum ApprovalError {
+    #[error("wallet is not in approval mode")]
+    NotEnabled,
+    #[error("wrong approver token")]
+    WrongApprover,
+    #[error("no such transaction awaiting approval")]
+    NotPending,
+    #[error("cannot broadcast transaction: {0}")]
+    Broadcast(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A fresh TOTP secret of a wallet, returned from [crate::protocol::ext::MelwalletdExtProtocol::enable_totp]. It becomes active once a code from it is passed to [crate::protocol::ext::MelwalletdExtProtocol::activate_totp].
+pub struct TotpEnrollment {
+    /// The shared secret, in base32, for entering into an authenticator app by hand
+    pub secret: String,
+    /// `otpauth://` URI of the secret, for showing as a QR code
+    pub provisioning_uri: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// The TOTP protection of a wallet.
+pub struct TotpStatus {
+    /// Whether codes are required. False while enrollment awaits activation.
+    pub active: bool,
+    /// Sends of more than this many micromel to other addresses need a code, as do sends of any other token
+    pub send_threshold: CoinValue,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when managing TOTP protection, or calling methods that need a TOTP code.
+pub enum TotpError {
+    #[error("wallet has no TOTP secret")]
+    NotEnrolled,
+    #[error("TOTP is already active; disable it first")]
+    AlreadyActive,
+    #[error("wrong or already used TOTP code")]
+    WrongCode,
+    #[error("too many wrong TOTP codes; try again in {0} seconds")]
+    LockedOut(u64),
+    #[error(transparent)]
+    WeakPassword(WeakPasswordError),
+    #[error(transparent)]
+    Send(SendError),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Melmint economics at the latest block, as returned by [crate::protocol::ext::MelwalletdExtProtocol::minting_info].
+pub struct MintingInfo {
+    /// Height of the block the figures are taken from
+    pub height: BlockHeight,
+    /// DOSC speed, in hashes per block, recorded in the block header
+    pub dosc_speed: u128,
+    /// Base-2 logarithm of the number of sequential hashes making up one DOSC at the current DOSC speed
+    pub dosc_difficulty: f64,
+    /// ERG bought by one MEL at the ERG/MEL pool's current price, if the pool exists
+    pub erg_per_mel: Option<f64>,
+    /// MEL bought by one ERG at the ERG/MEL pool's current price, if the pool exists
+    pub mel_per_erg: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Estimated Melmint reward for a proof, as returned by [crate::protocol::ext::MelwalletdExtProtocol::estimate_mint_reward].
+pub struct MintRewardEstimate {
+    /// Height of the block whose DOSC speed the estimate uses
+    pub height: BlockHeight,
+    /// DOSC speed, in hashes per block, the estimate uses
+    pub dosc_speed: u128,
+    /// Reward in micro-DOSC. The ERG actually minted is this amount adjusted by the network's DOSC-to-ERG conversion.
+    pub reward_micro_dosc: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A transaction found by [crate::protocol::ext::MelwalletdExtProtocol::search_transactions].
+pub struct TransactionSearchHit {
+    /// Wallet whose history the transaction is in
+    pub wallet_name: String,
+    pub txhash: TxHash,
+    /// Excerpt of the transaction's searchable text, with matches in square brackets
+    pub snippet: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// An unspent coin of a wallet, with its labels, as returned by [crate::protocol::ext::MelwalletdExtProtocol::list_coins].
+pub struct LabeledCoin {
+    pub coin_id: CoinID,
+    pub coin_data: CoinData,
+    /// Labels given to the coin with [crate::protocol::ext::MelwalletdExtProtocol::set_coin_labels]
+    pub labels: BTreeSet<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// Why a coin was picked as an input of a prepared transaction.
+pub enum InputSelection {
+    /// Listed as an input in the request
+    Requested,
+    /// Picked from the wallet to cover the outputs (and, unless sponsored, the fee) in the coin's denomination
+    Balancing,
+    /// Picked from the fee sponsor's wallet to pay the fee
+    SponsorFee,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// An input of a prepared transaction, in [PreparedTxDetails].
+pub struct SelectedInput {
+    pub coin_id: CoinID,
+    /// The coin, if it belongs to a wallet of this daemon
+    pub coin_data: Option<CoinData>,
+    pub reason: InputSelection,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// How the fee of a prepared transaction was arrived at, in [PreparedTxDetails].
+pub struct FeeBreakdown {
+    /// Fee multiplier of the block the transaction was prepared against
+    pub fee_multiplier: u128,
+    /// Weight of the signed transaction
+    pub weight: u128,
+    /// Extra bytes the fee was calculated for, on top of the transaction's own
+    pub fee_ballast: usize,
+    /// Smallest fee the network accepts for the transaction, including the ballast
+    pub base_fee: CoinValue,
+    /// The fee actually paid. Preparation aims slightly above the base fee, in case the fee multiplier rises before the transaction confirms.
+    pub fee: CoinValue,
+    /// Wallet that paid the fee
+    pub paid_by: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A prepared transaction along with how it was put together, returned from [crate::protocol::ext::MelwalletdExtProtocol::prepare_tx_verbose].
+pub struct PreparedTxDetails {
+    pub transaction: Transaction,
+    pub txhash: TxHash,
+    /// Every input, in order, and why it was picked
+    pub inputs: Vec<SelectedInput>,
+    /// Change outputs added after the requested outputs, keyed by output index
+    pub change: BTreeMap<u8, CoinData>,
+    /// Address the change went to. JSON-serialized as the standard `t.....` address format.
+    #[serde(with = "stdcode::asstr")]
+    pub change_address: Address,
+    pub fee: FeeBreakdown,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// An address in every encoding melwalletd understands, returned from [crate::protocol::ext::MelwalletdExtProtocol::address_forms]. Compare addresses by their canonical form.
+pub struct AddressForms {
+    /// The standard `t...` form, in lowercase. This is the form melwalletd itself returns everywhere.
+    pub canonical: String,
+    /// The standard form in uppercase, split into dash-separated groups for reading aloud or copying by hand
+    pub display: String,
+    /// The covenant hash as 64 hex digits
+    pub hex: String,
+    /// BIP-173 bech32 encoding with the `mel` prefix
+    pub bech32: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// What a daemon supports, returned from [crate::protocol::ext::MelwalletdExtProtocol::capabilities].
+pub struct Capabilities {
+    /// Version of the JSON-RPC protocol. Incompatible changes get a new version, served at a new endpoint alongside the old one.
+    pub protocol_version: u32,
+    /// Version of melwalletd itself
+    pub daemon_version: String,
+    /// Path of the endpoint serving this protocol version
+    pub endpoint: String,
+    /// Names of every supported method
+    pub methods: BTreeSet<String>,
+    /// Optional features enabled in this daemon's configuration, such as `backup` or `mdns`
+    pub features: BTreeSet<String>,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when exporting or importing a wallet's sync snapshot.
+pub enum SyncSnapshotError {
+    #[error("wallet has not synced yet")]
+    NotSynced,
+    #[error("snapshot is malformed")]
+    Malformed,
+    #[error("snapshot is for address {0}, not this wallet")]
+    WrongWallet(String),
+    #[error("the wallet's public key is unknown, so the snapshot cannot be checked")]
+    UnknownKey,
+    #[error("snapshot is not signed by this wallet's key")]
+    BadSignature,
+    #[error("snapshot is as of height {snapshot}, but the wallet is already synced to {synced}")]
+    Stale {
+        snapshot: BlockHeight,
+        synced: BlockHeight,
+    },
+    #[error("snapshot is as of height {0}, which the network has not reached")]
+    FromTheFuture(BlockHeight),
+    #[error("network error: {0}")]
+    Network(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Exactly how this daemon was built, returned from [crate::protocol::ext::MelwalletdExtProtocol::build_info]. Worth including in bug reports.
+pub struct BuildInfo {
+    pub daemon_version: String,
+    /// Commit built from, suffixed with `-dirty` if the tree had uncommitted changes. None if unknown.
+    pub git_commit: Option<String>,
+    /// Unix timestamp of the build, which reproducible builds pin through `SOURCE_DATE_EPOCH`
+    pub build_timestamp: Option<u64>,
+    pub rustc_version: Option<String>,
+    /// Target triple, such as `x86_64-unknown-linux-musl`
+    pub target: String,
+    /// Cargo profile, either `debug` or `release`
+    pub profile: String,
+    /// Cargo features enabled at build time
+    pub features: BTreeSet<String>,
+    /// Versions of the crates that decide how transactions and blocks are validated, by crate name
+    pub consensus_crates: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Whether the secret store is protected by a master passphrase, returned from [crate::protocol::ext::MelwalletdExtProtocol::secrets_status].
+pub struct SecretsStatus {
+    /// Whether a master passphrase is set
+    pub protected: bool,
+    /// Whether the daemon is waiting for the master passphrase. While sealed, only a few methods, such as [crate::protocol::ext::MelwalletdExtProtocol::unseal_secrets], can be called.
+    pub sealed: bool,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when unsealing the secret store or changing its master passphrase.
+pub enum MasterPassphraseError {
+    #[error("no master passphrase is set")]
+    NotSet,
+    #[error("wrong master passphrase")]
+    WrongPassphrase,
+    #[error("{0}")]
+    Other(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A cap on how often an unlocked wallet may sign. See [crate::protocol::ext::MelwalletdExtProtocol::set_signing_limit].
+pub struct SigningLimit {
+    /// Most signatures allowed in any window
+    pub max_signatures: u32,
+    /// Length of the rolling window that signatures are counted over, in seconds
+    pub window_secs: u64,
+    /// How long the wallet stays locked after exceeding the limit, in seconds
+    #[serde(default = "default_signing_lockout")]
+    pub lockout_secs: u64,
+    /// HTTP URL that an alarm is POSTed to when the limit is exceeded, as a JSON [SigningActivity]
+    #[serde(default)]
+    pub alert_webhook: Option<String>,
+}
+
+fn default_signing_lockout() -> u64 {
+    3600
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// How much a wallet has signed lately, against its signing limit.
+pub struct SigningActivity {
+    /// Signatures made in the limit's current window. Always zero for wallets without a limit.
+    pub signatures: usize,
+    pub limit: Option<SigningLimit>,
+    /// UNIX timestamp until which the wallet cannot be unlocked, if it exceeded its limit
+    pub locked_out_until: Option<u64>,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when setting the signing limit of a wallet.
+pub enum SigningLimitError {
+    #[error("invalid signing limit: {0}")]
+    InvalidLimit(String),
+    #[error("webhook must be an http:// URL, not {0}")]
+    BadWebhook(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// How a transaction moved the balance of a wallet. Keys are the standard string representation of a [Denom].
+pub struct TxBalanceDetails {
+    /// Whether the wallet sent the transaction
+    pub self_originated: bool,
+    pub kind: TxKind,
+    /// Value of the outputs the wallet paid for, which is all of them if it sent the transaction. Excludes the fee.
+    pub outgoing: BTreeMap<String, u128>,
+    /// Value of the outputs that went to the wallet, including change
+    pub incoming: BTreeMap<String, u128>,
+    /// Fee the wallet paid, which is zero unless it sent the transaction
+    pub fee: CoinValue,
+    /// Net change of the wallet's balance, fee included
+    pub net: BTreeMap<String, i128>,
+    /// Set if the transaction moved funds between two wallets of this daemon, by [crate::protocol::ext::MelwalletdExtProtocol::internal_transfer]
+    #[serde(default)]
+    pub internal: Option<InternalTransfer>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// How a full node answered a probe.
+pub struct NodeProbe {
+    pub addr: SocketAddr,
+    /// Time the node took to report its latest state, in milliseconds, if it answered
+    pub latency_ms: Option<u64>,
+    /// Height of the node's latest block, if it answered
+    pub height: Option<BlockHeight>,
+    /// Why the node didn't answer, if it didn't
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Which full node the daemon is connected to, and how the alternatives compare.
+pub struct NetworkDiagnostics {
+    pub current_node: SocketAddr,
+    /// Whether the current node was picked by probing the bootstrap nodes at startup, rather than configured
+    pub auto_selected: bool,
+    /// Node that did best in the latest probes: the fastest of those caught up with the highest. If auto-selecting, the daemon switches to it on restart.
+    pub best_node: Option<SocketAddr>,
+    /// Latest probes of the bootstrap nodes and the configured node. Empty if nothing was probed yet.
+    pub probes: Vec<NodeProbe>,
+    /// UNIX timestamp of the latest probes
+    pub probed_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// The summary of a wallet, along with how up to date it is.
+pub struct WalletSyncSummary {
+    pub summary: WalletSummary,
+    /// Height the wallet's coins were last synced to, or None if it never synced
+    pub last_sync_height: Option<BlockHeight>,
+    /// Height of the latest block, from the node or, if it can't be reached, the last block seen
+    pub latest_height: Option<BlockHeight>,
+    /// Whether the wallet's balances may be outdated
+    pub is_stale: bool,
+    /// How frontends should show the wallet's balances
+    #[serde(default)]
+    pub display_preferences: DisplayPreferences,
+    /// Whether the wallet can sign, and what it takes to make it. `summary.locked` is true for every wallet that is not [KeyStatus::Unlocked].
+    #[serde(default)]
+    pub key_status: KeyStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+/// Whether a wallet can sign transactions, so that frontends offer the right way to make it.
+pub enum KeyStatus {
+    /// The daemon holds the key, decrypted, and signs with it
+    Unlocked,
+    /// The daemon holds the key, encrypted; unlocking it takes the password
+    #[default]
+    Locked,
+    /// The key is kept in a cold wallet, which signs the bundles exported by [crate::protocol::ext::MelwalletdExtProtocol::export_signing_bundle]
+    ExternalSigner,
+    /// The daemon holds no key for the wallet, which can only watch its coins
+    WatchOnly,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+/// Order in which a wallet's coins are picked to fund a transaction, after any inputs it must spend.
+pub enum CoinSelection {
+    /// By coin ID, which is effectively random
+    #[default]
+    Arbitrary,
+    /// Largest coins first, which spends the fewest coins and so pays the lowest fee
+    LargestFirst,
+    /// Smallest coins first, which consolidates small coins at the cost of a higher fee
+    SmallestFirst,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// The coins a selection strategy would pick for a transaction, returned from [crate::protocol::ext::MelwalletdExtProtocol::preview_coin_selection].
+pub struct CoinSelectionPreview {
+    pub strategy: CoinSelection,
+    /// Every input, in order
+    pub inputs: Vec<SelectedInput>,
+    /// Change outputs that would be added after the requested outputs
+    pub change: Vec<CoinData>,
+    pub fee: CoinValue,
+    /// Weight of the signed transaction
+    pub weight: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// What [crate::protocol::ext::MelwalletdExtProtocol::purge_expired] cleaned up.
+pub struct PendingPurge {
+    /// Height the transactions expired before
+    pub height: BlockHeight,
+    /// Expired pending transactions that were forgotten
+    pub purged: Vec<TxHash>,
+    /// Coins spent by the forgotten transactions, which can be spent again
+    pub released_coins: usize,
+    /// Pending transactions of the wallet that have not yet expired
+    pub still_pending: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// How an address belongs to a wallet.
+pub enum OwnershipKind {
+    /// It is the wallet's own address
+    Wallet,
+    /// It is the new address of the wallet's in-progress key rotation
+    RotationTarget,
+    /// It guards coins imported into the wallet, such as an escrow or timelock
+    Imported,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A wallet that an address belongs to, in [AddressOwnership].
+pub struct AddressOwner {
+    pub wallet: String,
+    pub kind: OwnershipKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Which wallets of this daemon an address belongs to, returned from [crate::protocol::ext::MelwalletdExtProtocol::is_mine].
+pub struct AddressOwnership {
+    /// JSON-serialized as the standard `t.....` address format.
+    #[serde(with = "stdcode::asstr")]
+    pub address: Address,
+    /// Whether any of the wallets checked owns the address
+    pub is_mine: bool,
+    pub owners: Vec<AddressOwner>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+/// Severity of a log record, most severe first.
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A record logged by the daemon, returned from [crate::protocol::ext::MelwalletdExtProtocol::tail_logs].
+pub struct LogRecord {
+    /// Sequence number, increasing by one with every record logged since the daemon started
+    pub seq: u64,
+    /// UNIX timestamp, in milliseconds
+    pub time: u64,
+    pub level: LogLevel,
+    /// Module that logged the record
+    pub target: String,
+    pub message: String,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when cloning a wallet into another network's database.
+pub enum CloneWalletError {
+    #[error("the wallet is already on network {0:?}")]
+    SameNetwork(NetID),
+    #[error("a wallet named {0} already exists")]
+    DestinationExists(String),
+    #[error("cannot clone wallet: {0}")]
+    Other(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A pending transaction of a wallet, in [WalletDiagnostics].
+pub struct PendingDiagnostics {
+    /// Left out if the bundle is redacted
+    pub txhash: Option<TxHash>,
+    /// Height the transaction expires at, if it hasn't confirmed by then
+    pub expires: BlockHeight,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// The state of a wallet, in [DiagnosticsBundle].
+pub struct WalletDiagnostics {
+    /// The wallet's name, or an alias such as `wallet-1` if the bundle is redacted
+    pub name: String,
+    /// Left out if the bundle is redacted
+    pub address: Option<String>,
+    /// Height the wallet last synced to, or `null` if it never has
+    pub sync_height: Option<BlockHeight>,
+    pub pending: Vec<PendingDiagnostics>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Everything needed to diagnose a problem with the daemon, for attaching to a bug report. Returned from [crate::protocol::ext::MelwalletdExtProtocol::collect_diagnostics].
+pub struct DiagnosticsBundle {
+    /// UNIX timestamp of when the bundle was put together
+    pub generated_at: u64,
+    /// Whether wallet names, addresses and transaction hashes were redacted
+    pub redacted: bool,
+    pub build: BuildInfo,
+    pub network: NetID,
+    pub offline: bool,
+    pub uptime_secs: u64,
+    /// Height of the latest block, or `null` if the node can't be reached
+    pub latest_height: Option<BlockHeight>,
+    pub node: NetworkDiagnostics,
+    pub wallets: Vec<WalletDiagnostics>,
+    /// The latest log records, oldest first
+    pub logs: Vec<LogRecord>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Thresholds that raise an alert for a wallet when crossed. See [crate::protocol::ext::MelwalletdExtProtocol::set_alert_thresholds].
+pub struct AlertThresholds {
+    /// Alert when the wallet's MEL balance drops below this, in micromel
+    #[serde(default)]
+    pub min_balance: Option<CoinValue>,
+    /// Alert when a confirmed transaction sends more MEL than this out of the wallet, in micromel
+    #[serde(default)]
+    pub max_outgoing: Option<CoinValue>,
+    /// HTTP URL that alerts are POSTed to, as a JSON [WalletAlert]
+    #[serde(default)]
+    pub alert_webhook: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+/// Which threshold a [WalletAlert] is about.
+pub enum AlertKind {
+    /// The MEL balance dropped below the threshold
+    LowBalance {
+        balance: CoinValue,
+        threshold: CoinValue,
+    },
+    /// A confirmed transaction sent more MEL out of the wallet than the threshold
+    LargeOutgoing {
+        txhash: TxHash,
+        amount: CoinValue,
+        threshold: CoinValue,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// An alert raised for a wallet when it crossed one of its [AlertThresholds].
+pub struct WalletAlert {
+    pub wallet: String,
+    /// Height the wallet had synced to when the alert was raised
+    pub height: BlockHeight,
+    /// UNIX timestamp of when the alert was raised
+    pub time: u64,
+    pub kind: AlertKind,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when setting the alert thresholds of a wallet.
+pub enum AlertError {
+    #[error("invalid alert thresholds: {0}")]
+    InvalidThresholds(String),
+    #[error("webhook must be an http:// URL, not {0}")]
+    BadWebhook(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+/// Which pending transactions [crate::protocol::ext::MelwalletdExtProtocol::list_pending] lists. Every field is optional; the default lists everything.
+pub struct PendingFilter {
+    /// Only list this wallet's pending transactions
+    #[serde(default)]
+    pub wallet: Option<String>,
+    /// Only list transactions that expire before this height
+    #[serde(default)]
+    pub expires_before: Option<BlockHeight>,
+    /// Only list transactions sent at least this many seconds ago
+    #[serde(default)]
+    pub min_age_secs: Option<u64>,
+    /// Only list transactions retransmitted at least this many times
+    #[serde(default)]
+    pub min_retransmits: Option<u64>,
+    /// How many matching transactions to skip
+    #[serde(default)]
+    pub offset: usize,
+    /// Most transactions to list
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A transaction sent by the daemon that hasn't confirmed yet.
+pub struct PendingTransaction {
+    /// Wallet whose coins the transaction spends or pays
+    pub wallet: String,
+    pub txhash: TxHash,
+    /// Height the transaction expires at, if it hasn't confirmed by then
+    pub expires: BlockHeight,
+    /// How many times the transaction was sent to the network again since it was first sent
+    pub retransmits: u64,
+    /// Seconds since the transaction was sent, or `null` if it was sent by an older melwalletd that didn't record that
+    pub age_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A page of pending transactions, returned from [crate::protocol::ext::MelwalletdExtProtocol::list_pending].
+pub struct PendingPage {
+    /// How many pending transactions match the filter, across all pages
+    pub total: usize,
+    /// The matching transactions on this page, oldest first
+    pub pending: Vec<PendingTransaction>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+/// The wallets that a transaction made by [crate::protocol::ext::MelwalletdExtProtocol::internal_transfer] moved funds between.
+pub struct InternalTransfer {
+    pub from_wallet: String,
+    pub to_wallet: String,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when transferring funds between two wallets of the daemon.
+pub enum InternalTransferError {
+    #[error("cannot transfer from a wallet to itself")]
+    SameWallet,
+    #[error("cannot prepare transfer: {0}")]
+    Prepare(String),
+    #[error("cannot send transfer: {0}")]
+    Network(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// Where a [SwapOrder] stands.
+pub enum SwapOrderStatus {
+    /// Waiting for the pool's price to reach the limit
+    Open,
+    /// The swap was sent
+    Executed,
+    /// The expiry height passed before the price reached the limit
+    Expired,
+    /// Cancelled with [crate::protocol::ext::MelwalletdExtProtocol::cancel_swap_order]
+    Cancelled,
+    /// The swap was not sent, since the wallet's approval mode or anomaly policy held it back, or it needs a TOTP code. A held transaction is the order's txhash, and is sent once approved.
+    Held,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// An order to swap one token for another on melswap once the price is good enough, returned from [crate::protocol::ext::MelwalletdExtProtocol::place_swap_order].
+pub struct SwapOrder {
+    /// Unique identifier of the order
+    pub id: String,
+    pub wallet_name: String,
+    /// Standard string representation of the [Denom] sold
+    pub from: String,
+    /// Standard string representation of the [Denom] bought
+    pub to: String,
+    /// Amount sold, in raw units of `from`
+    pub amount: CoinValue,
+    /// Least amount of `to` that each raw unit of `from` must fetch, slippage included
+    pub limit_price: f64,
+    /// Height after which the order is no longer executed
+    pub expiry: BlockHeight,
+    pub status: SwapOrderStatus,
+    /// The swap transaction, once executed
+    pub txhash: Option<TxHash>,
+    /// UNIX timestamp of when the order was placed
+    pub created: u64,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when placing or cancelling a swap order.
+pub enum SwapOrderError {
+    #[error("wallet not found")]
+    WalletNotFound,
+    #[error("order not found")]
+    OrderNotFound,
+    #[error("invalid denomination {0}")]
+    InvalidDenom(String),
+    #[error("cannot swap a token for itself")]
+    SameDenom,
+    #[error("amount must be nonzero")]
+    ZeroAmount,
+    #[error("limit price must be positive, not {0}")]
+    InvalidPrice(f64),
+    #[error("expiry height {0} has already passed")]
+    AlreadyExpired(BlockHeight),
+    #[error("no melswap pool trades {0} for {1}")]
+    NoPool(String, String),
+    #[error("order is {0:?}, not open")]
+    NotOpen(SwapOrderStatus),
+    #[error("network error: {0}")]
+    Network(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A recurring swap of a fixed amount of one token for another on melswap, returned from [crate::protocol::ext::MelwalletdExtProtocol::create_dca_job].
+pub struct DcaJob {
+    /// Unique identifier of the job
+    pub id: String,
+    pub wallet_name: String,
+    /// Standard string representation of the [Denom] sold
+    pub from: String,
+    /// Standard string representation of the [Denom] bought
+    pub to: String,
+    /// Amount sold every run, in raw units of `from`
+    pub amount: CoinValue,
+    /// Seconds between runs
+    pub interval_secs: u64,
+    /// Largest fraction below the pool's spot price, pool fee included, that a run may swap at; runs that would swap at a worse price are skipped
+    pub max_slippage: f64,
+    /// UNIX timestamp of the next run
+    pub next_run: u64,
+    /// Whether the job still runs, which it does until cancelled
+    pub active: bool,
+    /// UNIX timestamp of creation
+    pub created: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// One run of a [DcaJob].
+pub struct DcaRun {
+    pub job_id: String,
+    /// UNIX timestamp of the run
+    pub time: u64,
+    /// Height of the latest block at the time of the run
+    pub height: BlockHeight,
+    /// The swap transaction, if one was sent or held back
+    pub txhash: Option<TxHash>,
+    /// Amount of the bought token the swap was expected to fetch when sent
+    pub output: Option<CoinValue>,
+    /// Why no swap was sent, if none was
+    pub error: Option<String>,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when creating or cancelling a DCA job.
+pub enum DcaError {
+    #[error("wallet not found")]
+    WalletNotFound,
+    #[error("job not found")]
+    JobNotFound,
+    #[error("invalid denomination {0}")]
+    InvalidDenom(String),
+    #[error("cannot swap a token for itself")]
+    SameDenom,
+    #[error("amount must be nonzero")]
+    ZeroAmount,
+    #[error("interval must be nonzero")]
+    ZeroInterval,
+    #[error("slippage bound must be between 0 and 1, not {0}")]
+    InvalidSlippage(f64),
+    #[error("no melswap pool trades {0} for {1}")]
+    NoPool(String, String),
+    #[error("job was already cancelled")]
+    Inactive,
+    #[error("network error: {0}")]
+    Network(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+/// Unit that frontends show amounts in.
+pub enum DisplayUnit {
+    /// Display units, as given by [crate::protocol::ext::MelwalletdExtProtocol::to_display_units], such as MEL
+    #[default]
+    Display,
+    /// Raw units, such as micromel
+    Raw,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+/// How frontends should show a wallet's balances, so that every frontend of the same user shows them alike. The daemon only stores these; it applies none of them itself.
+pub struct DisplayPreferences {
+    /// Unit to show amounts in. Optional in JSON, defaulting to [DisplayUnit::Display].
+    #[serde(default)]
+    pub unit: DisplayUnit,
+    /// Leave out tokens the wallet has none of. Optional in JSON, defaulting to false.
+    #[serde(default)]
+    pub hide_zero_balances: bool,
+    /// Standard string representations of the [Denom]s never to show. Optional in JSON, defaulting to an empty list.
+    #[serde(default)]
+    pub hidden_tokens: BTreeSet<String>,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when setting the display preferences of a wallet.
+pub enum DisplayPreferencesError {
+    #[error("invalid denomination {0}")]
+    InvalidDenom(String),
+}
+
+#[derive(Error, Debug, Serialize, Deserialize, Clone)]
+#[error("not enough {denom}: {required} needed, but only {available} available (fee estimate: {fee_estimate} MEL)")]
+/// Why a wallet cannot fund a transaction. Preparing returns it as [melwalletd_prot::types::PrepareTxError::InsufficientFunds], with how much is missing.
+pub struct InsufficientFunds {
+    /// Standard string representation of the [Denom] there is not enough of
+    pub denom: String,
+    /// Total needed, including the fee if the denomination is MEL and the wallet pays the fee
+    pub required: CoinValue,
+    /// Amount the wallet can spend
+    pub available: CoinValue,
+    /// Fee of the cheapest transaction tried
+    pub fee_estimate: CoinValue,
+}
+
+impl InsufficientFunds {
+    /// How much more is needed.
+    pub fn missing(&self) -> CoinValue {
+        CoinValue(self.required.0.saturating_sub(self.available.0))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// Which way the fee multiplier is heading, in [NetworkFees].
+pub enum FeeTrend {
+    Rising,
+    Steady,
+    Falling,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Fee multiplier of a block, in [NetworkFees].
+pub struct FeeSample {
+    pub height: BlockHeight,
+    pub fee_multiplier: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Current fee conditions on the network, returned from [crate::protocol::ext::MelwalletdExtProtocol::network_fees].
+pub struct NetworkFees {
+    /// Height of the latest block
+    pub height: BlockHeight,
+    /// Fee multiplier of the latest block, which the network raises when blocks are full and lowers when they are not
+    pub fee_multiplier: u128,
+    /// Fee multipliers of the blocks looked at, oldest first, ending with the latest block
+    pub history: Vec<FeeSample>,
+    /// Which way the fee multiplier is heading over those blocks
+    pub trend: FeeTrend,
+    /// Weight of a typical payment, spending one coin of a standard wallet into a payment and change
+    pub typical_weight: u128,
+    /// Smallest fee the network accepts for a typical payment at the current fee multiplier. Prepared transactions pay slightly more, in case the multiplier rises before they confirm.
+    pub typical_fee: CoinValue,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// When the daemon consolidates the coins of a wallet on its own. See [crate::protocol::ext::MelwalletdExtProtocol::set_consolidation_policy].
+pub struct ConsolidationPolicy {
+    /// Most unspent coins the wallet should hold
+    pub max_utxos: usize,
+    /// Highest fee multiplier at which consolidating is worth it, so that it only happens while the network is quiet
+    pub max_fee_multiplier: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+/// When a block was made. Block headers carry no time, so this is when the daemon first saw the block while syncing or, for blocks it didn't see being made, an estimate from the closest blocks it did see.
+pub struct BlockTime {
+    /// UNIX timestamp of the block
+    pub time: u64,
+    /// Whether the time is estimated rather than seen
+    pub estimated: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A transaction in a wallet's history, as returned by [crate::protocol::ext::MelwalletdExtProtocol::transaction_history].
+pub struct HistoryEntry {
+    pub txhash: TxHash,
+    /// Height of the block confirming the transaction, or None if it's still pending
+    pub height: Option<BlockHeight>,
+    /// Time of the block confirming the transaction, if known
+    pub time: Option<BlockTime>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A covenant to run against a transaction with [crate::protocol::ext::MelwalletdExtProtocol::evaluate_covenant].
+pub struct EvaluateCovenantArgs {
+    /// Hex-encoded covenant
+    pub covenant: String,
+    pub tx: Transaction,
+    /// Index of the input of `tx` spending a coin locked by the covenant. If given, the coin is looked up on the network, and the covenant sees it and the latest block header as its environment, as it would on chain; otherwise it only sees the transaction.
+    #[serde(default)]
+    pub input_index: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// The outcome of running a covenant with [crate::protocol::ext::MelwalletdExtProtocol::evaluate_covenant].
+pub struct CovenantEvaluation {
+    /// Whether the covenant lets the transaction spend the coin
+    pub passed: bool,
+    /// The value the covenant left on top of the stack, or None if it failed before finishing
+    pub result: Option<String>,
+    /// Weight of the covenant, which is what it adds to the fee of spending transactions
+    pub weight: u128,
+    /// The covenant's instructions
+    pub ops: Vec<String>,
+    /// The instructions run, in order, as melvm reports them
+    pub trace: Vec<String>,
+    /// Whether the trace was cut short at [crate::protocol::ext::MAX_TRACE_STEPS] steps
+    pub trace_truncated: bool,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when evaluating a covenant.
+pub enum CovenantEvalError {
+    #[error("covenant is malformed")]
+    BadCovenant,
+    #[error("covenant weighs {0}, more than can be evaluated")]
+    TooHeavy(u128),
+    #[error("transaction has no input {0}")]
+    BadInput(u8),
+    #[error("coin {0} not found; it may not exist, or may already be spent")]
+    CoinNotFound(String),
+    #[error("network error: {0}")]
+    Network(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A failure to sync a wallet, as returned by [crate::protocol::ext::MelwalletdExtProtocol::sync_errors].
+pub struct SyncError {
+    /// UNIX timestamp of the failure
+    pub time: u64,
+    /// Height of the block the wallet was being synced to
+    pub height: BlockHeight,
+    pub message: String,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when exporting or importing a watch-only package of a wallet.
+pub enum WatchPackageError {
+    #[error("wallet has not synced yet")]
+    NotSynced,
+    #[error("the wallet's covenant is not made from its key, so the package could not be checked by whoever imports it")]
+    UnsupportedCovenant,
+    #[error("package is malformed")]
+    Malformed,
+    #[error("package is not signed by a key its covenant is made from")]
+    BadSignature,
+    #[error("a wallet with this name already exists")]
+    WalletExists,
+    #[error("package is as of height {0}, which the network has not reached")]
+    FromTheFuture(BlockHeight),
+    #[error("network error: {0}")]
+    Network(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+/// What the proposer of a peer-to-peer trade gives and wants in return, for [crate::protocol::ext::MelwalletdExtProtocol::propose_trade].
+pub struct TradeTerms {
+    /// Standard string representation of the [Denom] the proposer gives
+    pub give_denom: String,
+    /// Amount given, in raw units of `give_denom`
+    pub give_amount: CoinValue,
+    /// Standard string representation of the [Denom] the proposer wants
+    pub want_denom: String,
+    /// Amount wanted, in raw units of `want_denom`
+    pub want_amount: CoinValue,
+    /// Address of the counterparty, who gets what the proposer gives
+    pub counterparty: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeStatus {
+    /// Waiting for the counterparty's half of the transaction
+    Proposed,
+    /// Signed by both parties and sent
+    Completed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A peer-to-peer trade proposed by a wallet of this daemon.
+pub struct Trade {
+    /// Unique identifier of the trade
+    pub id: String,
+    pub wallet_name: String,
+    pub terms: TradeTerms,
+    pub status: TradeStatus,
+    /// The trade transaction, once sent
+    pub txhash: Option<TxHash>,
+    /// UNIX timestamp of when the trade was proposed
+    pub created: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A proposed trade, as handed to the counterparty for [crate::protocol::ext::MelwalletdExtProtocol::accept_trade].
+pub struct TradeOffer {
+    /// Identifier of the trade on the proposer's daemon
+    pub id: String,
+    /// Address of the proposer, who gets what the counterparty gives
+    pub proposer: String,
+    pub terms: TradeTerms,
+    /// The proposer's half of the transaction: its inputs and both parties' payments, unsigned
+    pub tx: Transaction,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when proposing, accepting or completing a peer-to-peer trade.
+pub enum TradeError {
+    #[error("trade not found")]
+    NotFound,
+    #[error("invalid denomination {0}")]
+    InvalidDenom(String),
+    #[error("cannot trade a token for itself")]
+    SameDenom,
+    #[error("amounts must be nonzero")]
+    ZeroAmount,
+    #[error("invalid address {0}")]
+    InvalidAddress(String),
+    #[error("the offer is for {0}, not this wallet")]
+    WrongWallet(String),
+    #[error("the transaction does not match the terms: {0}")]
+    TermsMismatch(String),
+    #[error("not enough {denom}: {needed} needed but {available} available")]
+    InsufficientFunds {
+        denom: String,
+        needed: CoinValue,
+        available: CoinValue,
+    },
+    #[error("trade is already completed")]
+    AlreadyCompleted,
+    #[error("network error: {0}")]
+    Network(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "template", rename_all = "snake_case")]
+/// A structured payload in a coin's `additional_data`, built from one of the daemon's templates with [crate::protocol::ext::MelwalletdExtProtocol::build_data_output].
+pub enum DataPayload {
+    /// One item of a collection of non-fungible tokens
+    Nft {
+        collection: String,
+        token_id: u64,
+        /// Where the item's content or metadata lives
+        uri: Option<String>,
+    },
+    /// An entry of a key-value registry, such as a name service
+    Registry {
+        registry: String,
+        key: String,
+        value: String,
+    },
+}
+
+impl DataPayload {
+    /// The template the payload was built from.
+    pub fn template(&self) -> PayloadTemplate {
+        match self {
+            DataPayload::Nft { .. } => PayloadTemplate::Nft,
+            DataPayload::Registry { .. } => PayloadTemplate::Registry,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// The kinds of [DataPayload].
+pub enum PayloadTemplate {
+    Nft,
+    Registry,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A coin of a wallet carrying a [DataPayload], as returned by [crate::protocol::ext::MelwalletdExtProtocol::data_coins].
+pub struct DataCoin {
+    pub coin_id: CoinID,
+    pub coin_data: CoinData,
+    pub payload: DataPayload,
+    /// Height the coin was confirmed at
+    pub height: Option<BlockHeight>,
+    /// Whether the coin has been spent
+    pub spent: bool,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when building a coin carrying a [DataPayload].
+pub enum DataPayloadError {
+    #[error("invalid address {0}")]
+    InvalidAddress(String),
+    #[error("invalid denomination {0}")]
+    InvalidDenom(String),
+    #[error("payload is {0} bytes, more than the maximum of {max}", max = crate::protocol::ext::MAX_PAYLOAD_BYTES)]
+    TooLarge(usize),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// File formats of accounting software that [crate::protocol::ext::MelwalletdExtProtocol::export_history] can produce.
+pub enum AccountingFormat {
+    /// Open Financial Exchange 2.2, as imported by GnuCash, Quicken and most banking software
+    Ofx,
+    /// Quicken Interchange Format
+    Qif,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when exporting a wallet's history for accounting software.
+pub enum HistoryExportError {
+    #[error("wallet not found")]
+    WalletNotFound,
+    #[error("invalid denomination {0}")]
+    InvalidDenom(String),
+    #[error("network error: {0}")]
+    Network(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+/// A block header known from a trusted source, for [crate::protocol::ext::MelwalletdExtProtocol::verify_payment_proof].
+pub struct TrustedHeader {
+    pub height: BlockHeight,
+    pub header_hash: HashVal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A payment shown by a valid payment proof, as returned by [crate::protocol::ext::MelwalletdExtProtocol::verify_payment_proof].
+pub struct VerifiedPayment {
+    pub txhash: TxHash,
+    /// Height of the block confirming the payment
+    pub height: BlockHeight,
+    pub output_index: u8,
+    /// The output the proof is about, including its recipient, value and denomination
+    pub output: CoinData,
+    /// Height of the header the proof is anchored to
+    pub anchor_height: BlockHeight,
+    pub anchor_hash: HashVal,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when producing or verifying a payment proof.
+pub enum PaymentProofError {
+    #[error("wallet not found")]
+    WalletNotFound,
+    #[error("transaction is not confirmed in the wallet's history")]
+    NotConfirmed,
+    #[error("malformed proof")]
+    Malformed,
+    #[error("invalid proof: {0}")]
+    BadProof(String),
+    #[error("the proof is anchored to a header that isn't trusted")]
+    UntrustedAnchor,
+    #[error("network error: {0}")]
+    Network(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+/// Whether the daemon saves power, as set with [crate::protocol::ext::MelwalletdExtProtocol::set_power_mode].
+pub enum PowerMode {
+    /// Save power while running on battery
+    #[default]
+    Auto,
+    /// Always save power
+    LowPower,
+    /// Never save power
+    Full,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Whether the daemon is saving power, and why, as returned by [crate::protocol::ext::MelwalletdExtProtocol::power_status].
+pub struct PowerStatus {
+    pub mode: PowerMode,
+    /// Whether the machine runs on battery, or None if it has no battery or it can't be told
+    pub on_battery: Option<bool>,
+    /// Whether low-power mode is in effect right now. It is lifted for a while after every RPC call.
+    pub low_power: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// How a wallet's database record and the secret store disagree.
+pub enum WalletMismatchKind {
+    /// The wallet is in the database, but has no secret key, isn't watch-only and has never received a coin, as if its creation failed halfway
+    MissingSecret,
+    /// The secret store has a key for a wallet that isn't in the database
+    OrphanSecret,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A wallet whose database record and secret key disagree, as found by [crate::protocol::ext::MelwalletdExtProtocol::verify_wallets].
+pub struct WalletMismatch {
+    pub name: String,
+    pub kind: WalletMismatchKind,
+    /// Whether the mismatch was repaired
+    pub repaired: bool,
+}
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+/// Errors when checking wallets against the secret store.
+pub enum VerifyWalletsError {
+    #[error("{0}")]
+    Other(String),
+}
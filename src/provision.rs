@@ -0,0 +1,242 @@
+use std::{collections::BTreeSet, path::PathBuf};
+
+use anyhow::Context;
+use melvm::Covenant;
+use serde::{Deserialize, Serialize};
+use tmelcrypt::Ed25519PK;
+
+use crate::{
+    address::parse_address,
+    database::Database,
+    secrets::{PersistentSecret, SecretStore},
+};
+
+/// A wallet declared in the config file, which the daemon makes sure exists at startup.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct WalletDecl {
+    pub name: String,
+    #[serde(flatten)]
+    pub source: WalletSource,
+    /// Address the wallet must have, in any accepted encoding. Startup fails if it doesn't, which catches a key file swapped for another.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+/// Where a declared wallet's key comes from.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum WalletSource {
+    /// A watch-only wallet, given the public key its address is made from, since an address alone doesn't say which key can spend from it
+    WatchOnly { watch_only: String },
+    /// A wallet whose password-encrypted key is in a file, as found under `secret` in a wallet's file in the secrets directory. The password, which becomes the wallet's password, is read from the environment variable `password_env`.
+    KeyFile {
+        key_file: PathBuf,
+        password_env: String,
+    },
+}
+
+/// What a declared wallet should look like once created.
+enum Provisioned {
+    WatchOnly(Ed25519PK),
+    Keyed(PersistentSecret),
+}
+
+impl WalletDecl {
+    /// Loads the wallet's key, returning it along with the wallet's covenant.
+    fn load(&self) -> anyhow::Result<(Provisioned, Covenant)> {
+        match &self.source {
+            WalletSource::WatchOnly { watch_only } => {
+                let pubkey: Ed25519PK = watch_only
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("malformed public key {watch_only:?}"))?;
+                Ok((
+                    Provisioned::WatchOnly(pubkey),
+                    Covenant::std_ed25519_pk_new(pubkey),
+                ))
+            }
+            WalletSource::KeyFile {
+                key_file,
+                password_env,
+            } => {
+                let secret: PersistentSecret = serde_json::from_slice(
+                    &std::fs::read(key_file)
+                        .with_context(|| format!("cannot read {key_file:?}"))?,
+                )
+                .with_context(|| format!("malformed key file {key_file:?}"))?;
+                let encrypted = match &secret {
+                    PersistentSecret::PasswordEncrypted(encrypted) => encrypted,
+                    PersistentSecret::Plaintext(_) => {
+                        anyhow::bail!("key file {key_file:?} holds an unencrypted key")
+                    }
+                };
+                let password = std::env::var(password_env)
+                    .with_context(|| format!("environment variable {password_env} is not set"))?;
+                let sk = encrypted.decrypt(&password).with_context(|| {
+                    format!("wrong password for {key_file:?} in {password_env}")
+                })?;
+                Ok((
+                    Provisioned::Keyed(secret),
+                    Covenant::std_ed25519_pk_new(sk.to_public()),
+                ))
+            }
+        }
+    }
+}
+
+/// Makes sure every declared wallet exists, creating those that don't. A wallet that already exists must have the declared address; if it lacks its declared key, the key is stored again. Fails on the first wallet that can't be provisioned, so that a deployment never runs with wallets other than those declared.
+///
+/// While the secret store is sealed, wallets with keys are skipped, since their keys can't be stored; they are provisioned once the store is unsealed.
+pub async fn provision_wallets(
+    database: &Database,
+    secrets: &SecretStore,
+    wallets: &[WalletDecl],
+) -> anyhow::Result<()> {
+    let mut seen = BTreeSet::new();
+    for decl in wallets {
+        let name = decl.name.as_str();
+        anyhow::ensure!(seen.insert(name), "wallet {name} is declared twice");
+        let (provisioned, covenant) = decl
+            .load()
+            .with_context(|| format!("cannot provision wallet {name}"))?;
+        let address = covenant.hash();
+        if let Some(declared) = decl.address.as_deref() {
+            let declared = parse_address(declared)
+                .with_context(|| format!("malformed address {declared:?} of wallet {name}"))?;
+            anyhow::ensure!(
+                declared == address,
+                "wallet {name} is declared with address {declared}, but its key gives {address}"
+            );
+        }
+        if secrets.is_sealed() && matches!(provisioned, Provisioned::Keyed(_)) {
+            log::warn!("provisioning wallet {name} once the secret store is unsealed");
+            continue;
+        }
+        if let Some(existing) = database.get_wallet(name).await {
+            anyhow::ensure!(
+                existing.address() == address,
+                "wallet {name} already exists with address {}, not {address}",
+                existing.address()
+            );
+            if let Provisioned::Keyed(secret) = provisioned {
+                if secrets.load(name).is_none() {
+                    secrets.store(name.to_owned(), secret)?;
+                    log::warn!("stored the missing key of declared wallet {name}");
+                }
+            }
+            continue;
+        }
+        match provisioned {
+            Provisioned::WatchOnly(pubkey) => {
+                database.create_watch_only_wallet(name, pubkey).await?
+            }
+            Provisioned::Keyed(secret) => {
+                database.create_wallet(name, covenant).await?;
+                if let Err(err) = secrets.store(name.to_owned(), secret) {
+                    database.remove_wallet(name).await?;
+                    return Err(err);
+                }
+            }
+        }
+        log::info!("provisioned declared wallet {name} at {address}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tmelcrypt::Ed25519SK;
+
+    use crate::secrets::EncryptedSK;
+
+    use super::*;
+
+    #[test]
+    fn parse_declarations() {
+        let wallets: Vec<WalletDecl> = serde_yaml::from_str(
+            r#"
+- name: cold
+  watch_only: "8c6a27d9a2ac9c6bd3c3f6e2d0d9c3f8b1f1e5e4f5e2c5d9a3a2c1b4e5f6a7b8"
+  address: t1v3x2y4wqm0qy2g0a2e8ffd7g6xz1ha9p5dh4wq6a8v1tmz7xjgyg
+- name: hot
+  key_file: /etc/melwalletd/hot.json
+  password_env: HOT_PASSWORD
+"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            &wallets[0].source,
+            WalletSource::WatchOnly { watch_only } if watch_only.starts_with("8c6a")
+        ));
+        assert!(wallets[0].address.is_some());
+        assert!(matches!(
+            &wallets[1].source,
+            WalletSource::KeyFile { password_env, .. } if password_env == "HOT_PASSWORD"
+        ));
+        assert!(wallets[1].address.is_none());
+
+        let missing_password: Result<Vec<WalletDecl>, _> =
+            serde_yaml::from_str("- name: hot\n  key_file: hot.json\n");
+        assert!(missing_password.is_err());
+    }
+
+    #[test]
+    fn keyed_wallets_wait_for_unsealing() {
+        smol::block_on(async {
+            let dir =
+                std::env::temp_dir().join(format!("melwalletd-provision-{}", fastrand::u64(..)));
+            std::fs::create_dir_all(&dir).unwrap();
+            let sk = Ed25519SK::generate();
+            let key_file = dir.join("hot.json");
+            std::fs::write(
+                &key_file,
+                serde_json::to_vec(&PersistentSecret::PasswordEncrypted(EncryptedSK::new(
+                    sk, "hunter2",
+                )))
+                .unwrap(),
+            )
+            .unwrap();
+            let password_env = format!("MELWALLETD_TEST_PASSWORD_{}", fastrand::u64(..));
+            std::env::set_var(&password_env, "hunter2");
+            let wallets = vec![
+                WalletDecl {
+                    name: "cold".into(),
+                    source: WalletSource::WatchOnly {
+                        watch_only: Ed25519SK::generate().to_public().to_string(),
+                    },
+                    address: None,
+                },
+                WalletDecl {
+                    name: "hot".into(),
+                    source: WalletSource::KeyFile {
+                        key_file,
+                        password_env,
+                    },
+                    address: None,
+                },
+            ];
+
+            let secrets_path = dir.join("secrets.json");
+            SecretStore::open(&secrets_path)
+                .unwrap()
+                .set_master_passphrase(None, Some("master"))
+                .unwrap();
+            let secrets = SecretStore::open(&secrets_path).unwrap();
+            assert!(secrets.is_sealed());
+            let database = Database::open(dir.join("wallets.db"), false, 0)
+                .await
+                .unwrap();
+            provision_wallets(&database, &secrets, &wallets)
+                .await
+                .unwrap();
+            assert_eq!(database.list_wallets().await, vec!["cold".to_string()]);
+
+            secrets.unseal("master").unwrap();
+            provision_wallets(&database, &secrets, &wallets)
+                .await
+                .unwrap();
+            assert!(database.get_wallet("hot").await.is_some());
+            assert!(secrets.load("hot").is_some());
+            let _ = std::fs::remove_dir_all(dir);
+        })
+    }
+}
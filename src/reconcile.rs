@@ -0,0 +1,218 @@
+use std::path::Path;
+
+use crate::{
+    database::Database,
+    protocol::types::{WalletMismatch, WalletMismatchKind},
+    secrets::SecretStore,
+};
+
+/// Pairs up wallet records with stored secrets, returning the records without a secret and the secrets without a record, each sorted by name.
+fn unpaired(wallets: &[String], secrets: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut without_secret: Vec<String> = wallets
+        .iter()
+        .filter(|name| !secrets.contains(name))
+        .cloned()
+        .collect();
+    let mut without_record: Vec<String> = secrets
+        .iter()
+        .filter(|name| !wallets.contains(name))
+        .cloned()
+        .collect();
+    without_secret.sort();
+    without_record.sort();
+    (without_secret, without_record)
+}
+
+/// Names of the wallets in the databases of every other network in a wallet directory, whose main database file for the current network is `db_path`. Every network shares the secret store, so these wallets' secrets are not orphans.
+pub fn other_network_wallets(wallet_dir: &Path, db_path: &Path) -> Vec<String> {
+    let entries = match std::fs::read_dir(wallet_dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut names = vec![];
+    for path in entries.flatten().map(|entry| entry.path()) {
+        let is_db = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.ends_with("-wallets.db"))
+            .unwrap_or(false);
+        if !is_db || path == db_path {
+            continue;
+        }
+        match Database::wallet_names_in(&path) {
+            Some(wallets) => names.extend(wallets),
+            None => log::warn!("cannot list the wallets in {:?}", path),
+        }
+    }
+    names
+}
+
+/// Names of wallets whose secret key is in the secret store, but which are missing from the database, for example because the database was lost. Wallets of other networks, listed in `elsewhere`, are not missing.
+pub async fn recoverable_wallets(
+    database: &Database,
+    secrets: &SecretStore,
+    elsewhere: &[String],
+) -> Vec<String> {
+    let existing = database.list_wallets().await;
+    secrets
+        .wallet_names()
+        .into_iter()
+        .filter(|name| !existing.contains(name) && !elsewhere.contains(name))
+        .collect()
+}
+
+/// Compares the wallets in the database with the secrets in the secret store, returning every wallet where the two disagree. Watch-only wallets, including those imported from watch packages, legitimately have no secret and are skipped; a wallet without a secret only counts as a mismatch if it has never received a coin, which is what a wallet whose creation failed halfway looks like.
+///
+/// With `repair`, such wallets are removed from the database. Secrets without a wallet are never removed, since they may be the only copy of a key; they can be turned back into wallets with [crate::protocol::ext::MelwalletdExtProtocol::recover_wallets_from_secrets]. Secrets of wallets of other networks, listed in `elsewhere`, have a wallet.
+pub async fn verify_wallets(
+    database: &Database,
+    secrets: &SecretStore,
+    elsewhere: &[String],
+    repair: bool,
+) -> anyhow::Result<Vec<WalletMismatch>> {
+    anyhow::ensure!(
+        !secrets.is_sealed(),
+        "secrets are sealed until unlocked with the master passphrase"
+    );
+    let (without_secret, without_record) =
+        unpaired(&database.list_wallets().await, &secrets.wallet_names());
+    let mut mismatches = vec![];
+    for name in without_secret {
+        if database.watch_only_key(&name).await?.is_some()
+            || database.is_imported_wallet(&name).await?
+        {
+            continue;
+        }
+        let wallet = match database.get_wallet(&name).await {
+            Some(wallet) => wallet,
+            None => continue,
+        };
+        if wallet.has_coins().await? {
+            continue;
+        }
+        let repaired = if repair {
+            database.remove_wallet(&name).await?;
+            log::warn!("removed wallet {name}, which has no secret key and no coins");
+            true
+        } else {
+            false
+        };
+        mismatches.push(WalletMismatch {
+            name,
+            kind: WalletMismatchKind::MissingSecret,
+            repaired,
+        });
+    }
+    for name in without_record {
+        if elsewhere.contains(&name) {
+            continue;
+        }
+        mismatches.push(WalletMismatch {
+            name,
+            kind: WalletMismatchKind::OrphanSecret,
+            repaired: false,
+        });
+    }
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use melstructs::BlockHeight;
+    use melvm::Covenant;
+    use tmelcrypt::Ed25519SK;
+
+    use super::*;
+    use crate::secrets::PersistentSecret;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn pairs_records_and_secrets() {
+        let (without_secret, without_record) = unpaired(
+            &names(&["main", "half", "cold"]),
+            &names(&["orphan", "main"]),
+        );
+        assert_eq!(without_secret, names(&["cold", "half"]));
+        assert_eq!(without_record, names(&["orphan"]));
+        assert_eq!(
+            unpaired(&names(&["main"]), &names(&["main"])),
+            (vec![], vec![])
+        );
+    }
+
+    #[test]
+    fn imported_wallets_are_not_failed_creations() {
+        smol::block_on(async {
+            let dir =
+                std::env::temp_dir().join(format!("melwalletd-reconcile-{}", fastrand::u64(..)));
+            std::fs::create_dir_all(&dir).unwrap();
+            let database = Database::open(dir.join("wallets.db"), false, 0)
+                .await
+                .unwrap();
+            let secrets = SecretStore::open(&dir.join("secrets.json")).unwrap();
+            let covenant = || Covenant::std_ed25519_pk_new(Ed25519SK::generate().to_public());
+            database
+                .create_imported_wallet("watched", covenant(), BlockHeight(100))
+                .await
+                .unwrap();
+            database.create_wallet("half", covenant()).await.unwrap();
+
+            let mismatches = verify_wallets(&database, &secrets, &[], true)
+                .await
+                .unwrap();
+            assert_eq!(mismatches.len(), 1);
+            assert_eq!(mismatches[0].name, "half");
+            assert!(mismatches[0].repaired);
+            assert_eq!(database.list_wallets().await, names(&["watched"]));
+            let _ = std::fs::remove_dir_all(dir);
+        })
+    }
+
+    #[test]
+    fn secrets_are_shared_across_networks() {
+        smol::block_on(async {
+            let dir =
+                std::env::temp_dir().join(format!("melwalletd-reconcile-{}", fastrand::u64(..)));
+            std::fs::create_dir_all(&dir).unwrap();
+            let secrets = SecretStore::open(&dir.join("secrets.json")).unwrap();
+            let mainnet_path = dir.join("mainnet-wallets.db");
+            let mainnet = Database::open(&mainnet_path, false, 0).await.unwrap();
+            let testnet = Database::open(dir.join("testnet-wallets.db"), false, 0)
+                .await
+                .unwrap();
+            for (database, name) in [(&mainnet, "main"), (&testnet, "test")] {
+                let sk = Ed25519SK::generate();
+                database
+                    .create_wallet(name, Covenant::std_ed25519_pk_new(sk.to_public()))
+                    .await
+                    .unwrap();
+                secrets
+                    .store(name.into(), PersistentSecret::Plaintext(sk))
+                    .unwrap();
+            }
+            secrets
+                .store(
+                    "lost".into(),
+                    PersistentSecret::Plaintext(Ed25519SK::generate()),
+                )
+                .unwrap();
+
+            // the testnet wallet's secret is neither recoverable nor an orphan on mainnet
+            let elsewhere = other_network_wallets(&dir, &mainnet_path);
+            assert_eq!(elsewhere, names(&["test"]));
+            assert_eq!(
+                recoverable_wallets(&mainnet, &secrets, &elsewhere).await,
+                names(&["lost"])
+            );
+            let mismatches = verify_wallets(&mainnet, &secrets, &elsewhere, false)
+                .await
+                .unwrap();
+            let orphans: Vec<&str> = mismatches.iter().map(|m| m.name.as_str()).collect();
+            assert_eq!(orphans, vec!["lost"]);
+            let _ = std::fs::remove_dir_all(dir);
+        })
+    }
+}
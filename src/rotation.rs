@@ -0,0 +1,380 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use dashmap::DashMap;
+use melstructs::{Address, BlockHeight, CoinData, CoinID, Denom, Transaction};
+use tmelcrypt::Ed25519SK;
+
+use crate::{
+    database::{Database, Wallet},
+    protocol::types::KeyRotationStatus,
+    secrets::{EncryptedSK, PersistentSecret, SecretStore},
+    signer::Signer,
+    state::AppState,
+};
+
+/// Maximum number of coins moved by a single sweep transaction.
+const MAX_SWEEP_INPUTS: usize = 100;
+
+/// Name under which the secret of a retired covenant is kept, so that coins that still arrive at the old address remain recoverable.
+pub fn retired_secret_name(name: &str, covhash: Address) -> String {
+    format!("{name}#retired-{covhash}")
+}
+
+/// Name under which the secret of a rotation's new covenant is kept until the rotation finishes, so that the swept coins are as recoverable as the wallet's own.
+pub fn pending_secret_name(name: &str, covhash: Address) -> String {
+    format!("{name}#rotating-{covhash}")
+}
+
+impl AppState {
+    /// Starts, or resumes, rotating the key of a wallet. A fresh key is generated the first time; afterwards, every call sweeps whatever coins are still left at the old address to the new one.
+    pub async fn rotate_key(&self, name: &str, pwd: &str) -> anyhow::Result<()> {
+        let old_sk = self
+            .get_secret_key(name, pwd)?
+            .context("wallet has no secret key")?;
+        let wallet = self.get_wallet(name).await.context("no such wallet")?;
+        if self.database.get_rotation(name).await?.is_none() {
+            let new_sk = Ed25519SK::generate();
+            // protect the new key the same way as the old one
+            let secret = match self.secrets.load(name) {
+                Some(PersistentSecret::Plaintext(_)) => PersistentSecret::Plaintext(new_sk),
+                _ => PersistentSecret::PasswordEncrypted(EncryptedSK::new(new_sk, pwd)),
+            };
+            // the key goes into the secret store before anything is swept to it
+            self.secrets
+                .store(pending_secret_name(name, new_sk.covenant().hash()), secret)?;
+            self.database
+                .start_rotation(name, new_sk.covenant())
+                .await?;
+            log::info!(
+                "rotating key of {name} from {} to {}",
+                wallet.address(),
+                new_sk.covenant().hash()
+            );
+        }
+        let rotation = self
+            .database
+            .get_rotation(name)
+            .await?
+            .context("rotation disappeared")?;
+
+        let snapshot = self.latest_snapshot().await?;
+        let fee_multiplier = snapshot.current_header().fee_multiplier;
+        for tx in prepare_sweeps(&wallet, rotation.covhash, fee_multiplier, &old_sk).await? {
+            snapshot
+                .get_raw()
+                .send_tx(tx.clone())
+                .await?
+                .map_err(|e| anyhow::anyhow!("sweep rejected: {e}"))?;
+            wallet
+                .commit_sent(
+                    tx.clone(),
+                    snapshot.current_header().height + BlockHeight(10),
+                )
+                .await?;
+            self.database
+                .add_rotation_sweep(name, tx.hash_nosigs())
+                .await?;
+            log::info!("sent rotation sweep {} for {name}", tx.hash_nosigs());
+        }
+        Ok(())
+    }
+
+    /// Returns the status of the in-progress key rotation of a wallet, if any.
+    pub async fn key_rotation_status(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<Option<KeyRotationStatus>> {
+        let wallet = self.get_wallet(name).await.context("no such wallet")?;
+        let rotation = if let Some(rotation) = self.database.get_rotation(name).await? {
+            rotation
+        } else {
+            return Ok(None);
+        };
+        let mut pending_sweeps = vec![];
+        for &txhash in rotation.sweeps.iter() {
+            if wallet.is_pending(txhash).await {
+                pending_sweeps.push(txhash);
+            }
+        }
+        Ok(Some(KeyRotationStatus {
+            new_address: rotation.covhash,
+            sweeps: rotation.sweeps,
+            pending_sweeps,
+            remaining_coins: wallet.get_coin_mapping(true, true).await.len(),
+        }))
+    }
+}
+
+/// Prepares transactions sweeping every confirmed, unspent coin of a wallet (including imported ones) to a destination, signed with the wallet's key.
+pub async fn prepare_sweeps(
+    wallet: &Wallet,
+    destination: Address,
+    fee_multiplier: u128,
+    signer: &dyn Signer,
+) -> anyhow::Result<Vec<Transaction>> {
+    let coins = wallet.get_coin_mapping(true, false).await;
+    let imported = wallet.imported_covenants().await?;
+    let covhashes: BTreeMap<CoinID, Address> =
+        coins.iter().map(|(id, data)| (*id, data.covhash)).collect();
+    let sign = move |mut tx: Transaction| {
+        // imported coins need their own covenants
+        let used: BTreeSet<Address> = tx
+            .inputs
+            .iter()
+            .filter_map(|i| covhashes.get(i).copied())
+            .collect();
+        for covhash in used {
+            if let Some(covenant) = imported.get(&covhash) {
+                tx.covenants.push(covenant.clone());
+            }
+        }
+        for i in 0..tx.inputs.len() {
+            tx = signer.sign_tx(tx, i)?;
+        }
+        Ok(tx)
+    };
+    sweep_batches(coins.into_iter().collect())
+        .into_iter()
+        .map(|batch| wallet.prepare_sweep(&batch, destination, fee_multiplier, &sign))
+        .collect()
+}
+
+/// Splits coins into batches small enough for one sweep transaction each, making sure that every batch has a MEL coin to pay its fee with. Coins that don't fit, for want of MEL coins to start more batches with, are left for a later sweep.
+fn sweep_batches(coins: Vec<(CoinID, CoinData)>) -> Vec<Vec<(CoinID, CoinData)>> {
+    let (mel, others): (Vec<_>, Vec<_>) = coins
+        .into_iter()
+        .partition(|(_, data)| data.denom == Denom::Mel);
+    let batch_count = (mel.len() + others.len())
+        .div_ceil(MAX_SWEEP_INPUTS)
+        .min(mel.len());
+    let mut mel = mel.into_iter();
+    let mut batches: Vec<Vec<_>> = mel
+        .by_ref()
+        .take(batch_count)
+        .map(|coin| vec![coin])
+        .collect();
+    let mut rest = mel.chain(others);
+    'fill: for batch in batches.iter_mut() {
+        while batch.len() < MAX_SWEEP_INPUTS {
+            match rest.next() {
+                Some(coin) => batch.push(coin),
+                None => break 'fill,
+            }
+        }
+    }
+    batches
+}
+
+/// Finishes every key rotation whose sweeps have all confirmed, leaving nothing at the old address. Called from the confirmation loop; does nothing while the secret store is sealed.
+pub async fn finish_rotations(
+    database: &Database,
+    secrets: &SecretStore,
+    unlocked_signers: &DashMap<String, Arc<dyn Signer>>,
+) -> anyhow::Result<()> {
+    if secrets.is_sealed() {
+        return Ok(());
+    }
+    for (name, covhash, secret) in database.legacy_rotation_secrets().await? {
+        secrets.store(pending_secret_name(&name, covhash), secret)?;
+        database.delete_legacy_rotation_secret(&name).await?;
+        log::info!("moved the pending rotation key of {name} into the secret store");
+    }
+    for name in database.list_rotations().await? {
+        let (wallet, rotation) = match (
+            database.get_wallet(&name).await,
+            database.get_rotation(&name).await?,
+        ) {
+            (Some(wallet), Some(rotation)) => (wallet, rotation),
+            _ => continue,
+        };
+        let mut in_flight = false;
+        for &txhash in rotation.sweeps.iter() {
+            in_flight |= wallet.is_pending(txhash).await;
+        }
+        if in_flight || !wallet.get_coin_mapping(true, true).await.is_empty() {
+            continue;
+        }
+        let pending = pending_secret_name(&name, rotation.covhash);
+        let new_secret = match secrets.load(&pending) {
+            Some(secret) => secret,
+            None => {
+                log::warn!("the new key of {name}'s rotation is missing from the secret store");
+                continue;
+            }
+        };
+        // keep the old secret around, then switch over the wallet; retrying after a crash in between must not retire the new secret in place of the old one
+        let retired = retired_secret_name(&name, wallet.address());
+        if secrets.load(&retired).is_none() {
+            if let Some(old_secret) = secrets.load(&name).filter(|old| *old != new_secret) {
+                secrets.store(retired, old_secret)?;
+            }
+        }
+        secrets.store(name.clone(), new_secret)?;
+        database.finish_rotation(&name).await?;
+        secrets.remove(&pending)?;
+        unlocked_signers.remove(&name);
+        log::info!(
+            "finished rotating key of {name}; its address is now {}",
+            rotation.covhash
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use melvm::Covenant;
+
+    use crate::reconcile::{recoverable_wallets, verify_wallets};
+
+    use super::*;
+
+    fn coins(denom: Denom, count: usize) -> Vec<(CoinID, CoinData)> {
+        (0..count)
+            .map(|_| {
+                (
+                    CoinID {
+                        txhash: tmelcrypt::HashVal::random().into(),
+                        index: 0,
+                    },
+                    CoinData {
+                        covhash: Address::coin_destroy(),
+                        value: 1000.into(),
+                        denom,
+                        additional_data: Default::default(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sweep_batch_sizes() {
+        let check = |batches: &[Vec<(CoinID, CoinData)>]| {
+            for batch in batches {
+                assert!(batch.len() <= MAX_SWEEP_INPUTS);
+                assert!(batch.iter().any(|(_, data)| data.denom == Denom::Mel));
+            }
+            batches.iter().map(|batch| batch.len()).sum::<usize>()
+        };
+        let mut few_mel = coins(Denom::Mel, 2);
+        few_mel.extend(coins(Denom::Sym, 500));
+        let batches = sweep_batches(few_mel);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(check(&batches), 2 * MAX_SWEEP_INPUTS);
+
+        let mut plenty = coins(Denom::Mel, 150);
+        plenty.extend(coins(Denom::Sym, 101));
+        let batches = sweep_batches(plenty);
+        assert_eq!(batches.len(), 3);
+        assert_eq!(check(&batches), 251);
+
+        assert_eq!(sweep_batches(coins(Denom::Mel, 100)).len(), 1);
+        assert!(sweep_batches(coins(Denom::Sym, 10)).is_empty());
+    }
+
+    #[test]
+    fn retired_keys_are_not_wallets() {
+        smol::block_on(async {
+            let dir =
+                std::env::temp_dir().join(format!("melwalletd-rotation-{}", fastrand::u64(..)));
+            std::fs::create_dir_all(&dir).unwrap();
+            let database = Database::open(dir.join("wallets.db"), false, 0)
+                .await
+                .unwrap();
+            let secrets = SecretStore::open(&dir.join("secrets.json")).unwrap();
+            let old_sk = Ed25519SK::generate();
+            database
+                .create_wallet("alice", Covenant::std_ed25519_pk_new(old_sk.to_public()))
+                .await
+                .unwrap();
+            secrets
+                .store("alice".into(), PersistentSecret::Plaintext(old_sk))
+                .unwrap();
+
+            let new_sk = Ed25519SK::generate();
+            let pending = pending_secret_name("alice", new_sk.covenant().hash());
+            secrets
+                .store(pending.clone(), PersistentSecret::Plaintext(new_sk))
+                .unwrap();
+            database
+                .start_rotation("alice", new_sk.covenant())
+                .await
+                .unwrap();
+            // nothing to sweep, so the rotation finishes right away
+            finish_rotations(&database, &secrets, &DashMap::new())
+                .await
+                .unwrap();
+            let retired = retired_secret_name("alice", old_sk.covenant().hash());
+            assert!(secrets.names().contains(&retired));
+            assert!(!secrets.names().contains(&pending));
+            assert!(matches!(
+                secrets.load("alice"),
+                Some(PersistentSecret::Plaintext(sk)) if sk == new_sk
+            ));
+
+            assert!(recoverable_wallets(&database, &secrets, &[])
+                .await
+                .is_empty());
+            assert!(verify_wallets(&database, &secrets, &[], false)
+                .await
+                .unwrap()
+                .is_empty());
+            let _ = std::fs::remove_dir_all(dir);
+        })
+    }
+
+    #[test]
+    fn resumed_rotation_keeps_old_key() {
+        smol::block_on(async {
+            let dir =
+                std::env::temp_dir().join(format!("melwalletd-rotation-{}", fastrand::u64(..)));
+            std::fs::create_dir_all(&dir).unwrap();
+            let database = Database::open(dir.join("wallets.db"), false, 0)
+                .await
+                .unwrap();
+            let secrets = SecretStore::open(&dir.join("secrets.json")).unwrap();
+            let old_sk = Ed25519SK::generate();
+            let new_sk = Ed25519SK::generate();
+            database
+                .create_wallet("alice", Covenant::std_ed25519_pk_new(old_sk.to_public()))
+                .await
+                .unwrap();
+            database
+                .start_rotation("alice", new_sk.covenant())
+                .await
+                .unwrap();
+            let pending = pending_secret_name("alice", new_sk.covenant().hash());
+            let retired = retired_secret_name("alice", old_sk.covenant().hash());
+            // as if the daemon crashed after swapping in the new key, but before finishing the rotation in the database
+            secrets
+                .store(pending.clone(), PersistentSecret::Plaintext(new_sk))
+                .unwrap();
+            secrets
+                .store(retired.clone(), PersistentSecret::Plaintext(old_sk))
+                .unwrap();
+            secrets
+                .store("alice".into(), PersistentSecret::Plaintext(new_sk))
+                .unwrap();
+
+            finish_rotations(&database, &secrets, &DashMap::new())
+                .await
+                .unwrap();
+            assert!(matches!(
+                secrets.load(&retired),
+                Some(PersistentSecret::Plaintext(sk)) if sk == old_sk
+            ));
+            assert!(matches!(
+                secrets.load("alice"),
+                Some(PersistentSecret::Plaintext(sk)) if sk == new_sk
+            ));
+            assert!(!secrets.names().contains(&pending));
+            assert!(database.get_rotation("alice").await.unwrap().is_none());
+            let _ = std::fs::remove_dir_all(dir);
+        })
+    }
+}
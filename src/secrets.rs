@@ -1,46 +1,634 @@
-use std::{collections::BTreeMap, path::Path};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
 
-use acidjson::AcidJson;
+use anyhow::Context;
+use atomicwrites::{AtomicFile, OverwriteBehavior};
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 use tmelcrypt::Ed25519SK;
 
-/// Represents a whole directory of persistent secrets, some of which may be unlocked
+use crate::{
+    protocol::types::{MasterPassphraseError, TotpError},
+    totp::{TotpFailures, TotpSecret},
+};
+
+/// Name of the file, within the secrets directory, holding the data key wrapped under the master passphrase. Not a valid entry file name.
+pub const MASTER_KEY_FILE: &str = "master.key";
+
+/// Represents a whole directory of persistent secrets, some of which may be unlocked.
+///
+/// Each wallet's secrets live in their own file, which is replaced atomically whenever they change, so that writing one wallet's secrets never puts another's at risk.
+///
+/// If a master passphrase is set, every file is encrypted with a random data key, which is itself stored encrypted under the passphrase. The store then starts out sealed: until it is unsealed with the passphrase, it holds no secrets and refuses to store any.
 pub struct SecretStore {
-    /// Maps wallet name to secret.
-    secrets: AcidJson<BTreeMap<String, PersistentSecret>>,
+    dir: PathBuf,
+    /// The single secrets file of older versions, moved into the directory when found
+    old_file: PathBuf,
+    inner: RwLock<StoreState>,
+    /// Wrong TOTP codes given for each wallet. Held while checking a code, so that codes are checked one at a time.
+    totp_failures: Mutex<BTreeMap<String, TotpFailures>>,
+}
+
+#[derive(Default)]
+struct StoreState {
+    entries: BTreeMap<String, SecretEntry>,
+    /// The wrapped data key, if a master passphrase is set
+    master: Option<MasterKeyFile>,
+    /// The data key, once unsealed
+    key: Option<Vec<u8>>,
+    /// Encrypted entries, kept until the store is unsealed
+    sealed: BTreeMap<String, Vec<u8>>,
+}
+
+impl StoreState {
+    fn is_sealed(&self) -> bool {
+        self.master.is_some() && self.key.is_none()
+    }
+}
+
+/// Format of the single secrets file used by older versions, which is still the format of [SecretStore::export]: a map from wallet name to secret, plus, once any wallet has enrolled in TOTP, the TOTP secrets of wallets under `$totp`, which is not a valid wallet name.
+#[derive(Serialize, Deserialize, Default)]
+struct SecretFile {
+    #[serde(rename = "$totp", default, skip_serializing_if = "BTreeMap::is_empty")]
+    totp: BTreeMap<String, TotpSecret>,
+    #[serde(flatten)]
+    secrets: BTreeMap<String, PersistentSecret>,
+}
+
+/// Contents of the file holding one wallet's secrets.
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct SecretEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secret: Option<PersistentSecret>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    totp: Option<TotpSecret>,
+}
+
+/// A wallet's secrets file, either as is or encrypted with the data key.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum StoredEntry {
+    Sealed {
+        #[serde(with = "stdcode::hex")]
+        sealed: Vec<u8>,
+    },
+    Open(SecretEntry),
+}
+
+impl StoredEntry {
+    fn parse(contents: &[u8]) -> anyhow::Result<Self> {
+        // untagged enums can't be deserialized with arbitrary-precision numbers, so tell the two apart by hand
+        let value: serde_json::Value = serde_json::from_slice(contents)?;
+        match value.get("sealed").and_then(|s| s.as_str()) {
+            Some(sealed) => Ok(Self::Sealed {
+                sealed: hex::decode(sealed)?,
+            }),
+            None => Ok(Self::Open(serde_json::from_value(value)?)),
+        }
+    }
+}
+
+/// The data key, encrypted under the master passphrase in the same way as [EncryptedSK].
+#[derive(Serialize, Deserialize, Clone)]
+struct MasterKeyFile {
+    #[serde(with = "stdcode::hex")]
+    argon2id_salt: Vec<u8>,
+    argon2id_mem_cost: u32,
+    argon2id_time_cost: u32,
+    #[serde(with = "stdcode::hex")]
+    cp20p1350_ciphertext: Vec<u8>,
+}
+
+impl MasterKeyFile {
+    fn new(data_key: &[u8], passphrase: &str) -> Self {
+        let mut salt = [0u8; 16];
+        getrandom::getrandom(&mut salt).unwrap();
+        let wrapping_key = argon2id_key(passphrase, &salt, MEM_COST, TIME_COST);
+        let aead = crypto_api_chachapoly::ChachaPolyIetf::aead_cipher();
+        let mut output_buf = vec![0u8; data_key.len() + 16];
+        aead.seal_to(&mut output_buf, data_key, &[], &wrapping_key, &[0; 12])
+            .expect("seal failed");
+        Self {
+            argon2id_salt: salt.to_vec(),
+            argon2id_mem_cost: MEM_COST,
+            argon2id_time_cost: TIME_COST,
+            cp20p1350_ciphertext: output_buf,
+        }
+    }
+
+    /// Decrypts the data key, or returns None if the passphrase is wrong.
+    fn data_key(&self, passphrase: &str) -> Option<Vec<u8>> {
+        let wrapping_key = argon2id_key(
+            passphrase,
+            &self.argon2id_salt,
+            self.argon2id_mem_cost,
+            self.argon2id_time_cost,
+        );
+        let aead = crypto_api_chachapoly::ChachaPolyIetf::aead_cipher();
+        let mut output = vec![0u8; self.cp20p1350_ciphertext.len()];
+        let len = aead
+            .open_to(
+                &mut output,
+                &self.cp20p1350_ciphertext,
+                &[],
+                &wrapping_key,
+                &[0; 12],
+            )
+            .ok()?;
+        output.truncate(len);
+        Some(output)
+    }
+}
+
+/// Encrypts with the data key. Unlike keys derived from passwords, the data key encrypts many times, so every encryption gets a random nonce, which is prepended to the ciphertext.
+fn seal(key: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut nonce = [0u8; 12];
+    getrandom::getrandom(&mut nonce).unwrap();
+    let aead = crypto_api_chachapoly::ChachaPolyIetf::aead_cipher();
+    let mut output = vec![0u8; 12 + plaintext.len() + 16];
+    aead.seal_to(&mut output[12..], plaintext, &[], key, &nonce)
+        .expect("seal failed");
+    output[..12].copy_from_slice(&nonce);
+    output
+}
+
+fn open_sealed(key: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < 12 {
+        return None;
+    }
+    let aead = crypto_api_chachapoly::ChachaPolyIetf::aead_cipher();
+    let mut output = vec![0u8; sealed.len() - 12];
+    let len = aead
+        .open_to(&mut output, &sealed[12..], &[], key, &sealed[..12])
+        .ok()?;
+    output.truncate(len);
+    Some(output)
+}
+
+/// Directory holding the per-wallet files of the secret store at the given path.
+pub fn entry_dir(path: &Path) -> PathBuf {
+    path.with_extension("d")
+}
+
+/// Whether a name can be a wallet's. Names starting with `$` or containing `#` are reserved for other secrets, such as retired keys.
+pub fn is_wallet_name(name: &str) -> bool {
+    !name.starts_with('$') && !name.contains('#')
+}
+
+/// File name for a wallet's secrets. Names that aren't safe as file names are hex-encoded.
+fn entry_file_name(name: &str) -> String {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        format!("{name}.json")
+    } else {
+        format!("~{}.json", hex::encode(name))
+    }
+}
+
+/// Inverse of [entry_file_name]. Returns None for files that aren't wallet secrets.
+fn entry_name(file_name: &str) -> Option<String> {
+    let stem = file_name.strip_suffix(".json")?;
+    match stem.strip_prefix('~') {
+        Some(encoded) => String::from_utf8(hex::decode(encoded).ok()?).ok(),
+        None => Some(stem.to_owned()),
+    }
 }
 
 impl SecretStore {
-    /// Opens or creates a secretstore from a given filename.
+    /// Opens or creates a secretstore from a given filename. The per-wallet files live in a directory next to it; secrets still in the file itself, written by an older version or restored from a backup, are moved into the directory, and the file is then overwritten and deleted.
     pub fn open(path: &Path) -> anyhow::Result<Self> {
-        // if not exists, create
-        if std::fs::read(path).is_err() {
-            std::fs::write(path, "{}")?;
+        let dir = entry_dir(path);
+        std::fs::create_dir_all(&dir).context("cannot create secrets directory")?;
+        let state = read_state(&dir)?;
+        let store = Self {
+            dir,
+            old_file: path.to_owned(),
+            inner: RwLock::new(state),
+            totp_failures: Default::default(),
+        };
+        store.shred_leftover()?;
+        if store.is_sealed() {
+            if store.old_file.exists() {
+                log::warn!("secrets in {path:?} will be moved once the secrets are unsealed");
+            }
+        } else {
+            store.migrate_old_file()?;
         }
-        Ok(Self {
-            secrets: AcidJson::open(path)?,
-        })
+        Ok(store)
+    }
+
+    /// Re-reads the secrets from disk, so that files edited or restored while the daemon runs take effect, including an old-style secrets file, which is moved into the directory as on startup. Returns the number of wallets with a stored secret.
+    ///
+    /// Encrypted files stay readable if they are still encrypted with the data key the store was unsealed with; otherwise, such as when the master key file was restored from a backup, the store becomes sealed again. Wallets already unlocked keep their signers until locked.
+    pub fn reload(&self) -> anyhow::Result<usize> {
+        let mut fresh = read_state(&self.dir)?;
+        let mut state = self.inner.write();
+        if let (Some(key), true) = (state.key.clone(), fresh.master.is_some()) {
+            let opened: Option<BTreeMap<String, SecretEntry>> = fresh
+                .sealed
+                .iter()
+                .map(|(name, sealed)| {
+                    let entry = serde_json::from_slice(&open_sealed(&key, sealed)?).ok()?;
+                    Some((name.clone(), entry))
+                })
+                .collect();
+            match opened {
+                Some(opened) => {
+                    fresh.entries.extend(opened);
+                    fresh.sealed.clear();
+                    fresh.key = Some(key);
+                }
+                None => log::warn!("reloaded secrets are encrypted with another key; sealing"),
+            }
+        }
+        *state = fresh;
+        let sealed = state.is_sealed();
+        drop(state);
+        if !sealed {
+            self.migrate_old_file()?;
+        }
+        log::info!("reloaded secrets from {:?}", self.dir);
+        Ok(self.names().len())
+    }
+
+    /// Overwrites and deletes the plaintext copy of an old-style secrets file that earlier versions kept, with a `.migrated` suffix, after moving its secrets into the directory.
+    fn shred_leftover(&self) -> anyhow::Result<()> {
+        let leftover = with_suffix(&self.old_file, ".migrated");
+        if leftover.exists() {
+            shred_file(&leftover)?;
+            log::info!("deleted the leftover copy of old secrets at {:?}", leftover);
+        }
+        Ok(())
+    }
+
+    /// Moves the contents of an old-style secrets file, if there is one, into per-wallet files, overwriting whatever is there, then overwrites and deletes the old file.
+    fn migrate_old_file(&self) -> anyhow::Result<()> {
+        let contents = match std::fs::read(&self.old_file) {
+            Ok(contents) => contents,
+            Err(_) => return Ok(()),
+        };
+        let migrated = file_entries(&contents)?;
+        let mut state = self.inner.write();
+        for (name, entry) in migrated {
+            write_entry(&self.dir, &name, &entry, state.key.as_deref())?;
+            state.entries.insert(name, entry);
+        }
+        drop(state);
+        shred_file(&self.old_file)?;
+        log::info!("moved secrets into {:?}", self.dir);
+        Ok(())
+    }
+
+    /// Changes a wallet's secrets, writing them out before the change becomes visible. Fails if the store is sealed.
+    fn update<T>(&self, name: &str, f: impl FnOnce(&mut SecretEntry) -> T) -> anyhow::Result<T> {
+        let mut state = self.inner.write();
+        anyhow::ensure!(
+            !state.is_sealed(),
+            "secrets are sealed until unlocked with the master passphrase"
+        );
+        let mut entry = state.entries.get(name).cloned().unwrap_or_default();
+        let res = f(&mut entry);
+        write_entry(&self.dir, name, &entry, state.key.as_deref())?;
+        state.entries.insert(name.to_owned(), entry);
+        Ok(res)
     }
 
     /// Stores a new PersistentSecret into the SecretStore.
-    pub fn store(&self, name: String, secret: PersistentSecret) {
-        self.secrets.write().insert(name, secret);
+    pub fn store(&self, name: String, secret: PersistentSecret) -> anyhow::Result<()> {
+        self.update(&name, |entry| entry.secret = Some(secret))
+    }
+
+    /// Removes a PersistentSecret from the SecretStore, keeping the name's other secrets, such as its TOTP secret.
+    pub fn remove(&self, name: &str) -> anyhow::Result<()> {
+        self.update(name, |entry| entry.secret = None)
     }
 
     /// Obtains a PersistentSecret from the SecretStore.
     pub fn load(&self, name: &str) -> Option<PersistentSecret> {
-        self.secrets.read().get(name).cloned()
+        self.inner.read().entries.get(name)?.secret.clone()
+    }
+
+    /// Names of every stored secret, including those that aren't wallets', such as retired keys.
+    pub fn names(&self) -> Vec<String> {
+        self.inner
+            .read()
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.secret.is_some())
+            .map(|(name, _)| name.clone())
+            .collect()
     }
+
+    /// Names of all wallets with a stored secret.
+    pub fn wallet_names(&self) -> Vec<String> {
+        self.names()
+            .into_iter()
+            .filter(|name| is_wallet_name(name))
+            .collect()
+    }
+
+    /// Gets the TOTP secret of a wallet, if it has one.
+    pub fn totp(&self, name: &str) -> Option<TotpSecret> {
+        self.inner.read().entries.get(name)?.totp.clone()
+    }
+
+    /// Sets or, given None, removes the TOTP secret of a wallet.
+    pub fn set_totp(&self, name: &str, totp: Option<TotpSecret>) -> anyhow::Result<()> {
+        self.update(name, |entry| entry.totp = totp)
+    }
+
+    /// Checks a TOTP code of a wallet, using it up if valid so that it cannot be replayed. After a few wrong codes in a row, checking the wallet's codes is refused for a while, whether or not the code is right; wrong codes write nothing to disk.
+    pub fn check_totp(&self, name: &str, code: &str) -> Result<(), TotpError> {
+        let mut failures = self.totp_failures.lock();
+        let wallet_failures = failures.entry(name.to_owned()).or_default();
+        if let Some(secs) = wallet_failures.locked_for() {
+            return Err(TotpError::LockedOut(secs));
+        }
+        let totp = self.totp(name).ok_or(TotpError::NotEnrolled)?;
+        let step = match totp.verify(code) {
+            Some(step) => step,
+            None => {
+                wallet_failures.record();
+                log::warn!("wrong TOTP code for {name}");
+                return Err(TotpError::WrongCode);
+            }
+        };
+        failures.remove(name);
+        self.update(name, |entry| {
+            if let Some(totp) = entry.totp.as_mut() {
+                totp.last_step = step;
+            }
+        })
+        .map_err(|err| {
+            log::warn!("cannot use up TOTP code of {name}: {:?}", err);
+            TotpError::WrongCode
+        })
+    }
+
+    /// Whether a master passphrase is set.
+    pub fn is_protected(&self) -> bool {
+        self.inner.read().master.is_some()
+    }
+
+    /// Whether the store is waiting for its master passphrase.
+    pub fn is_sealed(&self) -> bool {
+        self.inner.read().is_sealed()
+    }
+
+    /// Unseals the store with its master passphrase, decrypting every secret into memory. Does nothing if the store isn't sealed.
+    pub fn unseal(&self, passphrase: &str) -> Result<(), MasterPassphraseError> {
+        let mut state = self.inner.write();
+        if !state.is_sealed() {
+            return Ok(());
+        }
+        let master = state.master.clone().ok_or(MasterPassphraseError::NotSet)?;
+        let key = master
+            .data_key(passphrase)
+            .ok_or(MasterPassphraseError::WrongPassphrase)?;
+        let mut entries = BTreeMap::new();
+        for (name, sealed) in state.sealed.iter() {
+            let entry = open_sealed(&key, sealed)
+                .and_then(|plain| serde_json::from_slice(&plain).ok())
+                .ok_or_else(|| {
+                    MasterPassphraseError::Other(format!("cannot decrypt the secrets of {name}"))
+                })?;
+            entries.insert(name.clone(), entry);
+        }
+        state.entries.extend(entries);
+        state.sealed.clear();
+        state.key = Some(key);
+        drop(state);
+        log::info!("unsealed secrets in {:?}", self.dir);
+        self.migrate_old_file()
+            .map_err(|e| MasterPassphraseError::Other(e.to_string()))
+    }
+
+    /// Sets, changes or, given None, removes the master passphrase. If one is already set, `current` must match it.
+    pub fn set_master_passphrase(
+        &self,
+        current: Option<&str>,
+        new: Option<&str>,
+    ) -> Result<(), MasterPassphraseError> {
+        let master = self.inner.read().master.clone();
+        if let Some(master) = master {
+            let current = current.ok_or(MasterPassphraseError::WrongPassphrase)?;
+            if self.is_sealed() {
+                self.unseal(current)?;
+            } else if master.data_key(current).is_none() {
+                return Err(MasterPassphraseError::WrongPassphrase);
+            }
+        }
+        let res = (|| {
+            self.shred_leftover()?;
+            let mut state = self.inner.write();
+            let master_path = self.dir.join(MASTER_KEY_FILE);
+            match new {
+                Some(new) => {
+                    let key = match state.key.clone() {
+                        Some(key) => key,
+                        None => {
+                            let mut key = vec![0u8; 32];
+                            getrandom::getrandom(&mut key)?;
+                            key
+                        }
+                    };
+                    // the wrapped key goes first, so that encrypted files are never left without it
+                    let master = MasterKeyFile::new(&key, new);
+                    write_atomically(&master_path, &serde_json::to_vec_pretty(&master)?)?;
+                    if state.key.is_none() {
+                        for (name, entry) in state.entries.iter() {
+                            write_entry(&self.dir, name, entry, Some(&key))?;
+                        }
+                    }
+                    state.master = Some(master);
+                    state.key = Some(key);
+                }
+                None => {
+                    // files are decrypted first, so that they are never left without the key
+                    for (name, entry) in state.entries.iter() {
+                        write_entry(&self.dir, name, entry, None)?;
+                    }
+                    if master_path.exists() {
+                        std::fs::remove_file(&master_path)?;
+                    }
+                    state.master = None;
+                    state.key = None;
+                }
+            }
+            anyhow::Ok(())
+        })();
+        res.map_err(|e| MasterPassphraseError::Other(e.to_string()))
+    }
+
+    /// Serializes every secret, in the format of the single secrets file used by older versions. Returns None if the store is sealed.
+    pub fn export(&self) -> Option<Vec<u8>> {
+        let state = self.inner.read();
+        if state.is_sealed() {
+            return None;
+        }
+        let mut file = SecretFile::default();
+        for (name, entry) in state.entries.iter() {
+            if let Some(secret) = entry.secret.clone() {
+                file.secrets.insert(name.clone(), secret);
+            }
+            if let Some(totp) = entry.totp.clone() {
+                file.totp.insert(name.clone(), totp);
+            }
+        }
+        Some(serde_json::to_vec(&file).expect("cannot serialize secrets"))
+    }
+
+    /// Writes secrets in the format of [SecretStore::export] out as a new secrets directory, protected like this store: if a master passphrase is set, the directory gets the same master key file, and every file is encrypted under it. Fails if the store is sealed.
+    pub fn write_restored(&self, exported: &[u8], dir: &Path) -> anyhow::Result<()> {
+        let entries = file_entries(exported)?;
+        let state = self.inner.read();
+        anyhow::ensure!(
+            !state.is_sealed(),
+            "secrets are sealed until unlocked with the master passphrase"
+        );
+        std::fs::create_dir_all(dir)?;
+        if let Some(master) = state.master.as_ref() {
+            write_atomically(
+                &dir.join(MASTER_KEY_FILE),
+                &serde_json::to_vec_pretty(master)?,
+            )?;
+        }
+        for (name, entry) in entries {
+            write_entry(dir, &name, &entry, state.key.as_deref())?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a secrets file in the format of [SecretStore::export] into per-wallet entries.
+fn file_entries(contents: &[u8]) -> anyhow::Result<BTreeMap<String, SecretEntry>> {
+    let file: SecretFile = serde_json::from_slice(contents).context("malformed secrets file")?;
+    let mut entries: BTreeMap<String, SecretEntry> = BTreeMap::new();
+    for (name, secret) in file.secrets {
+        entries.entry(name).or_default().secret = Some(secret);
+    }
+    for (name, totp) in file.totp {
+        entries.entry(name).or_default().totp = Some(totp);
+    }
+    Ok(entries)
+}
+
+/// Atomically replaces the file holding a wallet's secrets in a secrets directory, encrypting it if given a data key, and removing it if there is nothing left to hold.
+fn write_entry(
+    dir: &Path,
+    name: &str,
+    entry: &SecretEntry,
+    key: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    let path = dir.join(entry_file_name(name));
+    if entry.secret.is_none() && entry.totp.is_none() {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    let stored = match key {
+        Some(key) => StoredEntry::Sealed {
+            sealed: seal(key, &serde_json::to_vec(entry)?),
+        },
+        None => StoredEntry::Open(entry.clone()),
+    };
+    write_atomically(&path, &serde_json::to_vec_pretty(&stored)?)
+}
+
+/// Reads the secrets directory, leaving encrypted files sealed.
+fn read_state(dir: &Path) -> anyhow::Result<StoreState> {
+    let mut state = StoreState::default();
+    let master_path = dir.join(MASTER_KEY_FILE);
+    if master_path.exists() {
+        state.master = Some(
+            serde_json::from_slice(&std::fs::read(&master_path)?)
+                .context("malformed master key file")?,
+        );
+    }
+    for file in std::fs::read_dir(dir)? {
+        let file = file?;
+        if !file.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(name) = file.file_name().to_str().and_then(entry_name) {
+            let entry = StoredEntry::parse(&std::fs::read(file.path())?)
+                .with_context(|| format!("malformed secrets file {:?}", file.path()))?;
+            match entry {
+                StoredEntry::Open(entry) => {
+                    state.entries.insert(name, entry);
+                }
+                StoredEntry::Sealed { sealed } => {
+                    anyhow::ensure!(
+                        state.master.is_some(),
+                        "secrets file {:?} is encrypted, but there is no master key file",
+                        file.path()
+                    );
+                    state.sealed.insert(name, sealed);
+                }
+            }
+        }
+    }
+    Ok(state)
+}
+
+/// Overwrites a file with zeros before deleting it, so that secrets it held don't linger in its old blocks. This is best effort: filesystems that copy on write, and SSDs, may keep the old blocks anyway.
+fn shred_file(path: &Path) -> anyhow::Result<()> {
+    let len = std::fs::metadata(path)?.len();
+    let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+    std::io::copy(&mut std::io::repeat(0).take(len), &mut file)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+fn write_atomically(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    AtomicFile::new(path, OverwriteBehavior::AllowOverwrite).write(|f| f.write_all(contents))?;
+    Ok(())
 }
 
 /// A persistent signing secret (right now, either a plaintext secret key or a password-protected secret key)
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum PersistentSecret {
     Plaintext(Ed25519SK),
     PasswordEncrypted(EncryptedSK),
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Memory cost, in KiB, of deriving a key from a password.
+pub const MEM_COST: u32 = 32 * 1024;
+/// Number of passes made when deriving a key from a password.
+pub const TIME_COST: u32 = 10;
+
+/// Derives a 32-byte encryption key from a password with argon2id.
+pub fn argon2id_key(pwd: &str, salt: &[u8], mem_cost: u32, time_cost: u32) -> Vec<u8> {
+    let cfg = argon2::Config {
+        ad: &[],
+        hash_length: 32, // always enough
+        lanes: 1,
+        mem_cost,
+        secret: &[],
+        thread_mode: argon2::ThreadMode::Sequential,
+        time_cost,
+        variant: argon2::Variant::Argon2id,
+        version: argon2::Version::Version13,
+    };
+    argon2::hash_raw(pwd.as_bytes(), salt, &cfg).expect("argon2id invocation failed")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct EncryptedSK {
     #[serde(with = "stdcode::hex")]
     argon2id_salt: Vec<u8>,
@@ -55,21 +643,7 @@ impl EncryptedSK {
     pub fn new(sk: Ed25519SK, pwd: &str) -> Self {
         let mut salt = [0u8; 16];
         getrandom::getrandom(&mut salt).unwrap();
-        const MEM_COST: u32 = 32 * 1024;
-        const TIME_COST: u32 = 10;
-        let cfg = argon2::Config {
-            ad: &[],
-            hash_length: 32, // always enough
-            lanes: 1,
-            mem_cost: MEM_COST,
-            secret: &[],
-            thread_mode: argon2::ThreadMode::Sequential,
-            time_cost: TIME_COST,
-            variant: argon2::Variant::Argon2id,
-            version: argon2::Version::Version13,
-        };
-        let encryption_key =
-            argon2::hash_raw(pwd.as_bytes(), &salt, &cfg).expect("argon2id invocation failed");
+        let encryption_key = argon2id_key(pwd, &salt, MEM_COST, TIME_COST);
         // now we use this secret key to encrypt the secret key
         let aead = crypto_api_chachapoly::ChachaPolyIetf::aead_cipher();
         let mut output_buf = vec![0u8; sk.0.len() + 16];
@@ -85,19 +659,12 @@ impl EncryptedSK {
 
     /// Decrypts to an ed25519 secret key.
     pub fn decrypt(&self, pwd: &str) -> Option<Ed25519SK> {
-        let cfg = argon2::Config {
-            ad: &[],
-            hash_length: 32, // always enough
-            lanes: 1,
-            mem_cost: self.argon2id_mem_cost,
-            secret: &[],
-            thread_mode: argon2::ThreadMode::Sequential,
-            time_cost: self.argon2id_time_cost,
-            variant: argon2::Variant::Argon2id,
-            version: argon2::Version::Version13,
-        };
-        let encryption_key = argon2::hash_raw(pwd.as_bytes(), &self.argon2id_salt, &cfg)
-            .expect("argon2id invocation failed");
+        let encryption_key = argon2id_key(
+            pwd,
+            &self.argon2id_salt,
+            self.argon2id_mem_cost,
+            self.argon2id_time_cost,
+        );
         let aead = crypto_api_chachapoly::ChachaPolyIetf::aead_cipher();
         let mut output = [0u8; 64];
         aead.open_to(
@@ -123,4 +690,128 @@ mod tests {
         assert!(encrypted.decrypt("hello world").is_some());
         assert!(encrypted.decrypt("hello worldr").is_none())
     }
+
+    #[test]
+    fn totp_alongside_keys() {
+        let path =
+            std::env::temp_dir().join(format!("melwalletd-secrets-{}.json", fastrand::u64(..)));
+        let store = SecretStore::open(&path).unwrap();
+        store
+            .store(
+                "alice".into(),
+                PersistentSecret::Plaintext(Ed25519SK::generate()),
+            )
+            .unwrap();
+        // without TOTP, the export keeps the old format
+        let plain: BTreeMap<String, PersistentSecret> =
+            serde_json::from_slice(&store.export().unwrap()).unwrap();
+        assert_eq!(plain.len(), 1);
+
+        store
+            .set_totp("alice", Some(TotpSecret::generate(0)))
+            .unwrap();
+        drop(store);
+        let store = SecretStore::open(&path).unwrap();
+        assert!(store.load("alice").is_some());
+        assert!(store.totp("alice").is_some());
+        assert!(store.load("$totp").is_none());
+        let _ = std::fs::remove_dir_all(entry_dir(&path));
+    }
+
+    #[test]
+    fn migrates_single_file() {
+        let path =
+            std::env::temp_dir().join(format!("melwalletd-secrets-{}.json", fastrand::u64(..)));
+        let old = SecretFile {
+            totp: std::iter::once(("bob".to_string(), TotpSecret::generate(0))).collect(),
+            secrets: ["alice", "bob#retired-x"]
+                .iter()
+                .map(|name| {
+                    (
+                        name.to_string(),
+                        PersistentSecret::Plaintext(Ed25519SK::generate()),
+                    )
+                })
+                .collect(),
+        };
+        std::fs::write(&path, serde_json::to_vec(&old).unwrap()).unwrap();
+        // left behind by an earlier version's migration
+        let leftover = with_suffix(&path, ".migrated");
+        std::fs::write(&leftover, serde_json::to_vec(&old).unwrap()).unwrap();
+        let store = SecretStore::open(&path).unwrap();
+        assert!(!path.exists());
+        assert!(!leftover.exists());
+        drop(store);
+
+        let store = SecretStore::open(&path).unwrap();
+        assert_eq!(store.names(), vec!["alice", "bob#retired-x"]);
+        assert_eq!(store.wallet_names(), vec!["alice"]);
+        assert!(store.totp("bob").is_some());
+        assert_eq!(
+            entry_name(&entry_file_name("bob#retired-x")).unwrap(),
+            "bob#retired-x"
+        );
+        store.set_totp("bob", None).unwrap();
+        assert_eq!(std::fs::read_dir(entry_dir(&path)).unwrap().count(), 2);
+
+        assert!(!with_suffix(&path, ".migrated").exists());
+        let _ = std::fs::remove_dir_all(entry_dir(&path));
+    }
+
+    #[test]
+    fn reloads_restored_file() {
+        let path =
+            std::env::temp_dir().join(format!("melwalletd-secrets-{}.json", fastrand::u64(..)));
+        let store = SecretStore::open(&path).unwrap();
+        assert!(store.load("alice").is_none());
+        // restored from a backup while the daemon runs
+        let old: BTreeMap<String, PersistentSecret> = std::iter::once((
+            "alice".to_string(),
+            PersistentSecret::Plaintext(Ed25519SK::generate()),
+        ))
+        .collect();
+        std::fs::write(&path, serde_json::to_vec(&old).unwrap()).unwrap();
+        assert_eq!(store.reload().unwrap(), 1);
+        assert!(store.load("alice").is_some());
+        assert!(!path.exists());
+
+        assert!(!with_suffix(&path, ".migrated").exists());
+        let _ = std::fs::remove_dir_all(entry_dir(&path));
+    }
+
+    #[test]
+    fn master_passphrase() {
+        let path =
+            std::env::temp_dir().join(format!("melwalletd-secrets-{}.json", fastrand::u64(..)));
+        let store = SecretStore::open(&path).unwrap();
+        let sk = Ed25519SK::generate();
+        store
+            .store("alice".into(), PersistentSecret::Plaintext(sk))
+            .unwrap();
+        store.set_master_passphrase(None, Some("hunter2")).unwrap();
+        let on_disk = std::fs::read_to_string(entry_dir(&path).join("alice.json")).unwrap();
+        assert!(!on_disk.contains(&hex::encode(&sk.0[..32])));
+        drop(store);
+
+        let store = SecretStore::open(&path).unwrap();
+        assert!(store.is_sealed());
+        assert!(store.load("alice").is_none());
+        assert!(store.export().is_none());
+        assert!(store
+            .store("bob".into(), PersistentSecret::Plaintext(sk))
+            .is_err());
+        assert!(matches!(
+            store.unseal("hunter3"),
+            Err(MasterPassphraseError::WrongPassphrase)
+        ));
+        store.unseal("hunter2").unwrap();
+        assert!(store.load("alice").is_some());
+
+        store.set_master_passphrase(Some("hunter2"), None).unwrap();
+        drop(store);
+        let store = SecretStore::open(&path).unwrap();
+        assert!(!store.is_protected());
+        assert!(store.load("alice").is_some());
+        let _ = std::fs::remove_dir_all(entry_dir(&path));
+    }
 }
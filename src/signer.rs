@@ -1,9 +1,9 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, sync::Arc};
 
 use lru::LruCache;
 use melstructs::{Transaction, TxHash};
 use melvm::Covenant;
-use tmelcrypt::Ed25519SK;
+use tmelcrypt::{Ed25519PK, Ed25519SK};
 
 /// This trait is implemented by anything "secret key-like" that can sign a transaction. This includes secret keys, password-encumbered secret keys,
 pub trait Signer: Send + Sync + 'static {
@@ -38,3 +38,59 @@ impl Signer for Ed25519SK {
         Covenant::std_ed25519_pk_new(self.to_public())
     }
 }
+
+/// Stands in for a key that lives elsewhere, such as on an offline machine. "Signs" with all-zero signatures of the right length, so that a transaction can be prepared with the correct fee and then signed for real.
+pub struct PlaceholderSigner(pub Ed25519PK);
+
+impl Signer for PlaceholderSigner {
+    fn sign_tx(&self, mut txn: Transaction, input_idx: usize) -> anyhow::Result<Transaction> {
+        while txn.sigs.len() <= input_idx {
+            txn.sigs.push(Default::default());
+        }
+        txn.sigs[input_idx] = vec![0u8; 64].into();
+        Ok(txn)
+    }
+
+    fn covenant(&self) -> Covenant {
+        Covenant::std_ed25519_pk_new(self.0)
+    }
+}
+
+/// Stands in for a wallet's own signer while preparing a transaction that may only be signed once it is sent, so that it can't be broadcast around the checks sending makes. Leaves the same placeholder signatures as [PlaceholderSigner], which [is_placeholder] recognizes.
+pub struct DeferredSigner(pub Arc<dyn Signer>);
+
+impl Signer for DeferredSigner {
+    fn sign_tx(&self, mut txn: Transaction, input_idx: usize) -> anyhow::Result<Transaction> {
+        while txn.sigs.len() <= input_idx {
+            txn.sigs.push(Default::default());
+        }
+        txn.sigs[input_idx] = vec![0u8; 64].into();
+        Ok(txn)
+    }
+
+    fn covenant(&self) -> Covenant {
+        self.0.covenant()
+    }
+}
+
+/// Whether a signature is a placeholder left by [PlaceholderSigner] or [DeferredSigner].
+pub fn is_placeholder(sig: &[u8]) -> bool {
+    sig.len() == 64 && sig.iter().all(|&b| b == 0)
+}
+
+/// The hash that the signature of each input of a transaction must sign. Standard covenants check every input's signature against the hash of the transaction without signatures, so the hashes are all the same.
+pub fn sighashes(txn: &Transaction) -> Vec<TxHash> {
+    vec![txn.hash_nosigs(); txn.inputs.len()]
+}
+
+/// Checks that every input of a transaction carries a valid signature by the given key. Returns the index of the first input that doesn't.
+pub fn verify_signatures(pubkey: Ed25519PK, txn: &Transaction) -> Result<(), usize> {
+    let h = txn.hash_nosigs();
+    for i in 0..txn.inputs.len() {
+        match txn.sigs.get(i) {
+            Some(sig) if pubkey.verify(&h.0, sig) => (),
+            _ => return Err(i),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::DashMap;
+
+use crate::{
+    invoice::fire_webhook,
+    protocol::types::{SigningActivity, SigningLimit},
+    signer::Signer,
+    state::AppState,
+};
+
+/// Recent signing operations of every wallet, for enforcing [SigningLimit]s. Kept in memory only, so restarting the daemon resets it.
+#[derive(Default)]
+pub struct SigningTracker {
+    /// UNIX timestamps of recent signing operations, by wallet, oldest first
+    recent: DashMap<String, VecDeque<u64>>,
+    /// UNIX timestamps until which wallets that exceeded their limits may not be unlocked
+    lockouts: DashMap<String, u64>,
+}
+
+impl SigningTracker {
+    /// Records a signing operation of a wallet at `now`. Returns whether it takes the wallet over its limit.
+    pub fn record(&self, name: &str, limit: &SigningLimit, now: u64) -> bool {
+        let mut recent = self.recent.entry(name.to_owned()).or_default();
+        recent.push_back(now);
+        prune(&mut recent, limit.window_secs, now);
+        // only enough history to tell whether the limit is exceeded is worth keeping
+        while recent.len() > limit.max_signatures as usize + 1 {
+            recent.pop_front();
+        }
+        recent.len() > limit.max_signatures as usize
+    }
+
+    /// Signing operations of a wallet in the `window_secs` up to `now`.
+    pub fn count(&self, name: &str, window_secs: u64, now: u64) -> usize {
+        match self.recent.get_mut(name) {
+            Some(mut recent) => {
+                prune(&mut recent, window_secs, now);
+                recent.len()
+            }
+            None => 0,
+        }
+    }
+
+    /// Stops a wallet from being unlocked until the given UNIX timestamp, forgetting its signing history.
+    pub fn lock_out(&self, name: &str, until: u64) {
+        self.recent.remove(name);
+        self.lockouts.insert(name.to_owned(), until);
+    }
+
+    /// Lifts any lockout of a wallet.
+    pub fn lift_lockout(&self, name: &str) {
+        self.lockouts.remove(name);
+    }
+
+    /// When the lockout of a wallet ends, if it is locked out at `now`.
+    pub fn locked_out_until(&self, name: &str, now: u64) -> Option<u64> {
+        let until = *self.lockouts.get(name)?;
+        if until > now {
+            Some(until)
+        } else {
+            self.lockouts.remove(name);
+            None
+        }
+    }
+}
+
+fn prune(recent: &mut VecDeque<u64>, window_secs: u64, now: u64) {
+    let start = now.saturating_sub(window_secs);
+    while recent.front().is_some_and(|&time| time <= start) {
+        recent.pop_front();
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock before 1970")
+        .as_secs()
+}
+
+impl AppState {
+    /// Obtains the signer of a wallet in order to sign something, counting the signature against the wallet's signing limit. A signature over the limit relocks the wallet for the limit's lockout period, fires the limit's alarm webhook, and yields None, as if the wallet were locked.
+    pub async fn use_signer(&self, name: &str) -> Option<Arc<dyn Signer>> {
+        let signer = self.get_signer(name)?;
+        let limit = match self.database.signing_limit(name).await.expect("db failed") {
+            Some(limit) => limit,
+            None => return Some(signer),
+        };
+        let now = unix_now();
+        if !self.signing.record(name, &limit, now) {
+            return Some(signer);
+        }
+        self.unlocked_signers.remove(name);
+        let until = now + limit.lockout_secs;
+        self.signing.lock_out(name, until);
+        log::warn!(
+            "{name} signed more than {} times in {} seconds; relocked until {until}",
+            limit.max_signatures,
+            limit.window_secs
+        );
+        if let Some(url) = limit.alert_webhook.clone() {
+            let activity = SigningActivity {
+                signatures: limit.max_signatures as usize + 1,
+                limit: Some(limit),
+                locked_out_until: Some(until),
+            };
+            let label = format!("signing limit of {name}");
+            smolscale::spawn(fire_webhook(url, label, activity)).detach();
+        }
+        None
+    }
+
+    /// When the signing lockout of a wallet ends, if it is locked out.
+    pub fn signing_lockout(&self, name: &str) -> Option<u64> {
+        self.signing.locked_out_until(name, unix_now())
+    }
+
+    /// The recent signing activity of a wallet, against its limit.
+    pub async fn signing_status(&self, name: &str) -> anyhow::Result<SigningActivity> {
+        let limit = self.database.signing_limit(name).await?;
+        let now = unix_now();
+        Ok(SigningActivity {
+            signatures: limit
+                .as_ref()
+                .map(|limit| self.signing.count(name, limit.window_secs, now))
+                .unwrap_or_default(),
+            limit,
+            locked_out_until: self.signing.locked_out_until(name, now),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enforces_limits() {
+        let limit = SigningLimit {
+            max_signatures: 3,
+            window_secs: 60,
+            lockout_secs: 600,
+            alert_webhook: None,
+        };
+        let tracker = SigningTracker::default();
+        let now = 1_000_000;
+        for i in 0..3 {
+            assert!(!tracker.record("hot", &limit, now + i));
+        }
+        assert!(tracker.record("hot", &limit, now + 10));
+        assert!(!tracker.record("other", &limit, now + 10));
+
+        // signatures age out of the window
+        let tracker = SigningTracker::default();
+        for i in 0..3 {
+            tracker.record("hot", &limit, now + i * 30);
+        }
+        assert_eq!(tracker.count("hot", 60, now + 60), 2);
+        assert!(!tracker.record("hot", &limit, now + 70));
+
+        tracker.lock_out("hot", now + 600);
+        assert_eq!(tracker.count("hot", 60, now + 70), 0);
+        assert_eq!(tracker.locked_out_until("hot", now + 599), Some(now + 600));
+        assert_eq!(tracker.locked_out_until("hot", now + 600), None);
+    }
+}
@@ -1,21 +1,48 @@
-use std::{collections::BTreeMap, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
+    alerts::check_alerts,
+    backup::{backup_driver, backup_task, BackupDriver},
+    chain_cache::ChainCache,
+    cli::Config,
     database::{Database, Wallet},
+    inheritance::check_inheritance,
+    invoice::check_invoices,
+    node_select::NodeSelection,
+    plugin::{PluginRegistry, WalletPlugin, STANDARD_WALLET},
+    power::PowerState,
+    reconcile,
+    rotation::finish_rotations,
     secrets::{EncryptedSK, PersistentSecret, SecretStore},
     signer::Signer,
+    signing_limit::SigningTracker,
+    users::UserConfig,
 };
 
 use anyhow::Context;
 use dashmap::DashMap;
-use futures::StreamExt;
-use melprot::Client;
-use melstructs::{Denom, NetID};
+use event_listener::Event;
+use futures::{future::AbortHandle, StreamExt};
+use melprot::{Client, ClientError, Snapshot};
+use melstructs::{Address, BlockHeight, Denom, NetID};
 use melvm::Covenant;
 use melwalletd_prot::types::WalletSummary;
+use smol::future::FutureExt;
 use smol_timeout::TimeoutExt;
 use tmelcrypt::Ed25519SK;
 
+/// How long a snapshot fetched for an RPC call is reused. Well under the block time, so that a reused snapshot is rarely a block behind.
+const SNAPSHOT_TTL: Duration = Duration::from_secs(3);
+
+/// How many times sending a transaction is retried, with a fresh snapshot, when the node can't be reached.
+pub const SEND_RETRIES: u32 = 3;
+/// Wait before the first retry of sending a transaction, doubling with every further retry.
+pub const SEND_RETRY_DELAY: Duration = Duration::from_millis(500);
+
 /// Encapsulates all the state and logic needed for the wallet daemon.
 #[derive(Clone)]
 pub struct AppState {
@@ -24,7 +51,36 @@ pub struct AppState {
     pub _client: Client,
     pub unlocked_signers: Arc<DashMap<String, Arc<dyn Signer>>>,
     pub secrets: Arc<SecretStore>,
-    pub _confirm_task: Arc<smol::Task<()>>,
+    /// Syncs wallets with the network, unless offline
+    pub _confirm_task: Option<Arc<smol::Task<()>>>,
+    pub config: Arc<Config>,
+    /// When the daemon started
+    pub started: Instant,
+    /// Number of RPC calls served, by method
+    pub rpc_calls: Arc<DashMap<String, u64>>,
+    /// Notified every time the confirmation loop finishes syncing wallets
+    pub synced: Arc<Event>,
+    /// Chain data fetched while syncing, shared by all wallets
+    pub chain_cache: ChainCache,
+    /// Registered wallet types
+    pub plugins: PluginRegistry,
+    /// Where backups are kept, if backups are configured
+    pub backups: Option<Arc<dyn BackupDriver>>,
+    pub _backup_task: Option<Arc<smol::Task<()>>>,
+    /// Namespaces of the users of a multi-user daemon. Only set on the daemon's own state, which then serves nothing itself.
+    pub users: Option<Arc<Vec<(UserConfig, AppState)>>>,
+    /// Name of the user whose namespace this is, in a multi-user daemon
+    pub user: Option<String>,
+    /// Recent signing activity, checked against wallets' signing limits
+    pub signing: Arc<SigningTracker>,
+    /// The full node in use, and how the alternatives compare
+    pub nodes: Arc<NodeSelection>,
+    /// The latest snapshot fetched for an RPC call, and when it was fetched
+    pub snapshot_cache: Arc<smol::lock::Mutex<Option<(Instant, Snapshot)>>>,
+    /// RPC calls in flight that can be cancelled, by their handles
+    pub in_flight: Arc<DashMap<String, AbortHandle>>,
+    /// Whether to save power, which the background loops follow
+    pub power: PowerState,
     // pub trusted_height: TrustedHeight,
 }
 
@@ -33,18 +89,60 @@ impl AppState {
         database: Database,
         network: NetID,
         secrets: SecretStore,
-        _addr: SocketAddr,
+        nodes: Arc<NodeSelection>,
         _client: Client,
+        config: Arc<Config>,
     ) -> Self {
-        let _confirm_task = smolscale::spawn(confirm_task(database.clone(), _client.clone()));
+        let database = Arc::new(database);
+        let secrets = Arc::new(secrets);
+        let unlocked_signers: Arc<DashMap<String, Arc<dyn Signer>>> = Default::default();
+        let synced = Arc::new(Event::new());
+        let chain_cache = ChainCache::default();
+        let power = PowerState::default();
+        let _confirm_task = (!config.offline).then(|| {
+            Arc::new(smolscale::spawn(confirm_task(
+                database.clone(),
+                _client.clone(),
+                secrets.clone(),
+                unlocked_signers.clone(),
+                synced.clone(),
+                chain_cache.clone(),
+                power.clone(),
+                config.paranoid,
+            )))
+        });
+        let backups = config.backup.as_ref().map(backup_driver);
+        let _backup_task = backups.clone().map(|driver| {
+            Arc::new(smolscale::spawn(backup_task(
+                database.clone(),
+                secrets.clone(),
+                driver,
+                config.clone(),
+            )))
+        });
 
         Self {
-            database: database.into(),
+            database,
             network,
             _client,
-            unlocked_signers: Default::default(),
-            secrets: secrets.into(),
-            _confirm_task: _confirm_task.into(),
+            unlocked_signers,
+            secrets,
+            _confirm_task,
+            config,
+            started: Instant::now(),
+            rpc_calls: Default::default(),
+            synced,
+            chain_cache,
+            plugins: Default::default(),
+            backups,
+            _backup_task,
+            users: None,
+            user: None,
+            signing: Default::default(),
+            nodes,
+            snapshot_cache: Default::default(),
+            power,
+            in_flight: Default::default(),
         }
     }
 }
@@ -54,11 +152,31 @@ impl AppState {
         self._client.clone()
     }
 
+    /// Obtains a snapshot of the latest block, reusing one fetched less than [SNAPSHOT_TTL] ago. Concurrent callers wait for a single fetch, so that bursts of RPC calls cost one round-trip to the node.
+    pub async fn latest_snapshot(&self) -> Result<Snapshot, ClientError> {
+        let mut cached = self.snapshot_cache.lock().await;
+        if let Some((fetched, snapshot)) = cached.as_ref() {
+            if fetched.elapsed() < SNAPSHOT_TTL {
+                return Ok(snapshot.clone());
+            }
+        }
+        let snapshot = self.client().latest_snapshot().await?;
+        *cached = Some((Instant::now(), snapshot.clone()));
+        Ok(snapshot)
+    }
+
+    /// Obtains a snapshot of the latest block straight from the node, replacing the one [AppState::latest_snapshot] reuses, which may belong to a connection that has since failed.
+    pub async fn fresh_snapshot(&self) -> Result<Snapshot, ClientError> {
+        let mut cached = self.snapshot_cache.lock().await;
+        *cached = None;
+        let snapshot = self.client().latest_snapshot().await?;
+        *cached = Some((Instant::now(), snapshot.clone()));
+        Ok(snapshot)
+    }
+
     pub fn get_network(&self) -> NetID {
         self.network
     }
-    /// Creates a new appstate, given a network server `addr`.
-
     /// Returns a summary of wallets.
     pub async fn list_wallets(&self) -> BTreeMap<String, WalletSummary> {
         let mlist = self.database.list_wallets().await;
@@ -86,21 +204,54 @@ impl AppState {
     }
 
     /// Unlocks a particular wallet. Returns None if unlocking failed.
-    pub fn unlock(&self, name: &str, pwd: String) -> Option<()> {
+    pub async fn unlock(&self, name: &str, pwd: String) -> Option<()> {
+        if let Some(until) = self.signing_lockout(name) {
+            log::warn!("not unlocking {name}, which exceeded its signing limit, until {until}");
+            return None;
+        }
         let enc = self.secrets.load(name)?;
-        match enc {
-            PersistentSecret::Plaintext(sec) => {
-                self.unlocked_signers.insert(name.to_owned(), Arc::new(sec));
-            }
-            PersistentSecret::PasswordEncrypted(enc) => {
-                let decrypted = enc.decrypt(&pwd)?;
-                self.unlocked_signers
-                    .insert(name.to_owned(), Arc::new(decrypted));
+        let sk = match enc {
+            PersistentSecret::Plaintext(sec) => sec,
+            PersistentSecret::PasswordEncrypted(enc) => enc.decrypt(&pwd)?,
+        };
+        let signer: Arc<dyn Signer> = match self.wallet_plugin(name).await {
+            Ok(None) => Arc::new(sk),
+            Ok(Some((plugin, params))) => match plugin.signer(sk, &params) {
+                Ok(signer) => signer,
+                Err(err) => {
+                    log::warn!("cannot make signer for wallet {name}: {:?}", err);
+                    return None;
+                }
+            },
+            Err(err) => {
+                log::warn!("cannot unlock wallet {name}: {:?}", err);
+                return None;
             }
+        };
+        self.unlocked_signers.insert(name.to_owned(), signer);
+        if let Err(err) = self.database.touch_inheritance(name).await {
+            log::warn!("cannot restart dead-man switch of {name}: {:?}", err);
         }
         Some(())
     }
 
+    /// Gets the plugin-provided type of a wallet and its parameters, or None for an ordinary wallet.
+    pub async fn wallet_plugin(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<Option<(Arc<dyn WalletPlugin>, Vec<u8>)>> {
+        match self.database.wallet_type(name).await? {
+            None => Ok(None),
+            Some((kind, params)) => {
+                let plugin = self
+                    .plugins
+                    .get(&kind)
+                    .with_context(|| format!("wallet type {kind} is not registered"))?;
+                Ok(Some((plugin, params)))
+            }
+        }
+    }
+
     /// Dumps a particular private key. Use carefully!
     pub fn get_secret_key(&self, name: &str, pwd: &str) -> anyhow::Result<Option<Ed25519SK>> {
         let maybe_enc = self.secrets.load(name);
@@ -132,59 +283,268 @@ impl AppState {
         key: Ed25519SK,
         pwd: String,
     ) -> anyhow::Result<()> {
-        let covenant = Covenant::std_ed25519_pk_new(key.to_public());
+        self.create_typed_wallet(name, STANDARD_WALLET, vec![], key, pwd)
+            .await
+    }
+
+    /// Creates a wallet of a registered type, with type-specific parameters.
+    pub async fn create_typed_wallet(
+        &self,
+        name: &str,
+        kind: &str,
+        params: Vec<u8>,
+        key: Ed25519SK,
+        pwd: String,
+    ) -> anyhow::Result<()> {
+        let plugin = self
+            .plugins
+            .get(kind)
+            .with_context(|| format!("unknown wallet type {kind}"))?;
+        let covenant = plugin.covenant(key.to_public(), &params)?;
         self.database.create_wallet(name, covenant).await?;
-        self.secrets.store(
+        if kind != STANDARD_WALLET {
+            self.database.set_wallet_type(name, kind, &params).await?;
+        }
+        if let Err(err) = self.secrets.store(
             name.to_owned(),
             PersistentSecret::PasswordEncrypted(EncryptedSK::new(key, &pwd)),
-        );
+        ) {
+            // don't leave behind a wallet that can never sign
+            self.database.remove_wallet(name).await?;
+            return Err(err);
+        }
         log::info!("created wallet with name {}", name);
         Ok(())
     }
+
+    /// Names of wallets whose secret key is in the secret store, but which are missing from the database, for example because the database was lost.
+    pub async fn recoverable_wallets(&self) -> Vec<String> {
+        let elsewhere = self.other_network_wallets();
+        reconcile::recoverable_wallets(&self.database, &self.secrets, &elsewhere).await
+    }
+
+    /// Names of the wallets of other networks, which share the secret store with this one.
+    pub fn other_network_wallets(&self) -> Vec<String> {
+        reconcile::other_network_wallets(&self.config.wallet_dir, &self.config.db_path())
+    }
+
+    /// Recreates the database record of a wallet missing from the database, from its secret key in the secret store, as a standard wallet. The wallet is then fully rescanned by the next sync. Returns the wallet's address, or None if there is no secret or the password is wrong.
+    pub async fn recover_from_secret(
+        &self,
+        name: &str,
+        pwd: &str,
+    ) -> anyhow::Result<Option<Address>> {
+        let sk = match self.secrets.load(name) {
+            Some(PersistentSecret::Plaintext(sk)) => sk,
+            Some(PersistentSecret::PasswordEncrypted(enc)) => match enc.decrypt(pwd) {
+                Some(sk) => sk,
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        let covenant = Covenant::std_ed25519_pk_new(sk.to_public());
+        let address = covenant.hash();
+        self.database.create_wallet(name, covenant).await?;
+        log::info!("recovered wallet {name} at {address} from the secret store");
+        Ok(Some(address))
+    }
+
+    /// Re-encrypts a wallet's secret key under a new password. Returns None if the wallet has no secret, or if the old password is wrong.
+    pub fn change_password(&self, name: &str, old_pwd: &str, new_pwd: &str) -> Option<()> {
+        let sk = match self.secrets.load(name)? {
+            PersistentSecret::Plaintext(sk) => sk,
+            PersistentSecret::PasswordEncrypted(enc) => enc.decrypt(old_pwd)?,
+        };
+        self.secrets
+            .store(
+                name.to_owned(),
+                PersistentSecret::PasswordEncrypted(EncryptedSK::new(sk, new_pwd)),
+            )
+            .expect("cannot write secrets");
+        log::info!("changed password of wallet {}", name);
+        Some(())
+    }
+}
+
+/// How often the confirmation loop checks whether a new block has arrived.
+const BLOCK_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Longest the confirmation loop waits between syncs, even if no new block arrives.
+const MAX_SYNC_INTERVAL: Duration = Duration::from_secs(15);
+/// How often the confirmation loop checks for new blocks in low-power mode.
+const LOW_POWER_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Longest the confirmation loop waits between syncs in low-power mode.
+const LOW_POWER_SYNC_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Waits until the network has a block above `height`, or [MAX_SYNC_INTERVAL] has passed. Only block summaries are polled, so new blocks are synced within seconds at little cost. In low-power mode, polls and syncs are much further apart, but an RPC call ending low-power mode cuts the wait short.
+async fn wait_for_block(client: &Client, height: BlockHeight, power: &PowerState) {
+    let (poll_interval, sync_interval) = if power.low_power() {
+        (LOW_POWER_POLL_INTERVAL, LOW_POWER_SYNC_INTERVAL)
+    } else {
+        (BLOCK_POLL_INTERVAL, MAX_SYNC_INTERVAL)
+    };
+    let deadline = Instant::now() + sync_interval;
+    while Instant::now() < deadline {
+        let woken = power.woken.listen();
+        let timer = async {
+            smol::Timer::after(poll_interval).await;
+            false
+        };
+        if timer
+            .or(async {
+                woken.await;
+                true
+            })
+            .await
+        {
+            return;
+        }
+        match client.latest_snapshot().await {
+            Ok(snap) if snap.current_header().height > height => return,
+            Ok(_) => (),
+            Err(err) => log::debug!("failed to poll for new blocks: {:?}", err),
+        }
+    }
 }
 
 // task that periodically pulls random coins to try to confirm
-pub async fn confirm_task(database: Database, client: Client) {
-    let mut pacer = smol::Timer::interval(Duration::from_millis(15000));
-    // let sent = Arc::new(Mutex::new(HashMap::new()));
+#[allow(clippy::too_many_arguments)]
+pub async fn confirm_task(
+    database: Arc<Database>,
+    client: Client,
+    secrets: Arc<SecretStore>,
+    unlocked_signers: Arc<DashMap<String, Arc<dyn Signer>>>,
+    synced: Arc<Event>,
+    chain_cache: ChainCache,
+    power: PowerState,
+    paranoid: bool,
+) {
+    let mut synced_height = BlockHeight(0);
+    let mut was_low_power = false;
     loop {
+        let low_power = power.low_power();
+        if low_power != was_low_power {
+            log::info!(
+                "{} low-power mode",
+                if low_power { "entering" } else { "leaving" }
+            );
+            if let Err(err) = database.set_low_power(low_power).await {
+                log::warn!("failed to resize database pool: {:?}", err);
+            }
+            was_low_power = low_power;
+        }
         let possible_wallets = database.list_wallets().await;
         log::trace!("-- confirm loop sees {} wallets --", possible_wallets.len());
         match client.latest_snapshot().await {
             Ok(snap) => {
+                synced_height = snap.current_header().height;
+                if let Err(err) = database.cache_header(snap.current_header()).await {
+                    log::warn!("failed to cache block header: {:?}", err);
+                }
                 futures::stream::iter(possible_wallets)
                     .map(|wname| {
                         let database = &database;
                         let snap = &snap;
+                        let chain_cache = &chain_cache;
                         async move {
                             if let Some(wallet) = database.get_wallet(&wname).await {
                                 let r = wallet
-                                    .network_sync(snap.clone())
+                                    .network_sync(snap.clone(), chain_cache, paranoid)
                                     .timeout(Duration::from_secs(120))
                                     .await;
+                                let mut failures = vec![];
                                 match r {
-                                    None => log::warn!("sync {} timed out", wname),
+                                    None => {
+                                        log::warn!("sync {} timed out", wname);
+                                        failures.push("sync timed out".to_owned());
+                                    }
                                     Some(Err(err)) => {
-                                        log::warn!("sync {} failed: {:?}", wname, err)
+                                        log::warn!("sync {} failed: {:?}", wname, err);
+                                        failures.push(format!("sync failed: {:#}", err));
                                     }
                                     _ => (),
                                 }
+                                // indexing can wait until there's power to spare
+                                if !low_power {
+                                    if let Err(err) = wallet.index_history(snap.clone()).await {
+                                        log::warn!(
+                                            "indexing transactions of {} failed: {:?}",
+                                            wname,
+                                            err
+                                        )
+                                    }
+                                    if let Err(err) = wallet.index_data_coins().await {
+                                        log::warn!(
+                                            "indexing data coins of {} failed: {:?}",
+                                            wname,
+                                            err
+                                        )
+                                    }
+                                }
+                                if let Err(err) = wallet.sync_imported(snap.clone()).await {
+                                    log::warn!(
+                                        "sync of imported coins of {} failed: {:?}",
+                                        wname,
+                                        err
+                                    );
+                                    failures
+                                        .push(format!("sync of imported coins failed: {:#}", err));
+                                }
+                                for message in failures {
+                                    if let Err(err) = wallet
+                                        .record_sync_error(snap.current_header().height, &message)
+                                        .await
+                                    {
+                                        log::warn!("failed to record sync error: {:?}", err);
+                                    }
+                                }
                             }
                         }
                     })
                     .buffer_unordered(6)
                     .count()
                     .await;
+                synced.notify(usize::MAX);
+
+                if let Err(err) = database
+                    .record_block_time(snap.current_header().height)
+                    .await
+                {
+                    log::warn!("failed to record block time: {:?}", err);
+                }
+
+                if let Err(err) = database.sync_tracked(snap.clone()).await {
+                    log::warn!("failed to sync tracked addresses: {:?}", err);
+                }
+
+                if let Err(err) = database.sync_timelocks(snap.clone()).await {
+                    log::warn!("failed to sync timelocked coins: {:?}", err);
+                }
 
                 let _ = database
-                    .retransmit_pending(snap)
+                    .retransmit_pending(snap.clone())
                     .timeout(Duration::from_secs(10))
                     .await;
+
+                if let Err(err) = check_invoices(&database).await {
+                    log::warn!("failed to check invoices: {:?}", err);
+                }
+
+                if let Err(err) = check_alerts(&database, snap.current_header().height).await {
+                    log::warn!("failed to check wallet alerts: {:?}", err);
+                }
+
+                if let Err(err) = check_inheritance(&database, &snap, &unlocked_signers).await {
+                    log::warn!("failed to check dead-man switches: {:?}", err);
+                }
+
+                if let Err(err) = finish_rotations(&database, &secrets, &unlocked_signers).await {
+                    log::warn!("failed to finish key rotations: {:?}", err);
+                }
             }
             Err(err) => {
                 log::warn!("failed to snap: {:?}", err);
             }
         }
-        (&mut pacer).await;
+        wait_for_block(&client, synced_height, &power).await;
     }
 }
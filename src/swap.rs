@@ -0,0 +1,320 @@
+use std::{
+    collections::BTreeSet,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use melprot::Snapshot;
+use melstructs::{CoinData, CoinValue, Denom, PoolKey, PoolState, Transaction, TxHash, TxKind};
+use melwalletd_prot::types::NeedWallet;
+
+use crate::{
+    database::Wallet,
+    protocol::types::{CoinSelection, DcaRun, SendError, SwapOrder, SwapOrderStatus},
+    signer::Signer,
+    state::AppState,
+};
+
+/// How much of the other token swapping `amount` of `from` into a pool fetches.
+fn swap_output(pool: PoolState, key: PoolKey, from: Denom, amount: u128) -> u128 {
+    let mut pool = pool;
+    if key.left() == from {
+        pool.swap_many(amount, 0).1
+    } else {
+        pool.swap_many(0, amount).0
+    }
+}
+
+/// Prepares and signs a transaction swapping `amount` of `from` from a wallet into the pool `key`, paying the result back to the wallet.
+async fn prepare_swap(
+    wallet: &Wallet,
+    snapshot: &Snapshot,
+    signer: Arc<dyn Signer>,
+    key: PoolKey,
+    from: Denom,
+    amount: CoinValue,
+) -> anyhow::Result<Transaction> {
+    let sign = move |mut tx: Transaction| {
+        tx.kind = TxKind::Swap;
+        tx.data = key.to_bytes();
+        for i in 0..tx.inputs.len() {
+            tx = signer.sign_tx(tx, i)?;
+        }
+        Ok(tx)
+    };
+    // the first output is the one that gets swapped
+    wallet
+        .prepare(
+            vec![],
+            vec![CoinData {
+                covhash: wallet.address(),
+                value: amount,
+                denom: from,
+                additional_data: Default::default(),
+            }],
+            snapshot.current_header().fee_multiplier,
+            Arc::new(Box::new(sign)),
+            vec![],
+            wallet.default_fee_ballast().await,
+            CoinValue(0),
+            &BTreeSet::new(),
+            CoinSelection::Arbitrary,
+            Some(snapshot.clone()),
+            None,
+            None,
+        )
+        .await
+}
+
+/// How far below the spot price, as a fraction of it, swapping `amount` of `from` into a pool fetches, counting the pool's fee.
+fn slippage(pool: PoolState, key: PoolKey, from: Denom, amount: u128) -> f64 {
+    let (ours, theirs) = if key.left() == from {
+        (pool.lefts, pool.rights)
+    } else {
+        (pool.rights, pool.lefts)
+    };
+    let at_spot = amount as f64 * theirs as f64 / ours as f64;
+    1.0 - swap_output(pool, key, from, amount) as f64 / at_spot
+}
+
+/// Whether a swap fetching `output` for `order.amount` meets the order's limit price.
+fn limit_reached(order: &SwapOrder, output: u128) -> bool {
+    output as f64 >= order.amount.0 as f64 * order.limit_price
+}
+
+/// What became of a swap sent by [AppState::send_guarded_swap].
+enum SwapOutcome {
+    /// The node accepted the swap transaction
+    Sent(TxHash),
+    /// The swap was held back, with the held transaction, if there is one, and why
+    Held(Option<TxHash>, String),
+}
+
+impl AppState {
+    /// Swaps `amount` of `from` from a wallet into the pool `key`, signing with [AppState::use_signer] and sending with [AppState::send_tx_inner], so that the wallet's signing limit, approval mode, anomaly policy and TOTP threshold apply just as they do to sends over RPC. Fails if the wallet is locked.
+    async fn send_guarded_swap(
+        &self,
+        wallet_name: &str,
+        snapshot: &Snapshot,
+        key: PoolKey,
+        from: Denom,
+        amount: CoinValue,
+    ) -> anyhow::Result<SwapOutcome> {
+        let wallet = self
+            .database
+            .get_wallet(wallet_name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("the wallet is gone"))?;
+        let signer = self
+            .use_signer(wallet_name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("the wallet is locked"))?;
+        let tx = prepare_swap(&wallet, snapshot, signer, key, from, amount).await?;
+        match self.send_tx_inner(wallet_name, tx, false).await {
+            Ok(txhash) => Ok(SwapOutcome::Sent(txhash)),
+            Err(NeedWallet::Other(SendError::Held { txhash, reason, .. })) => {
+                Ok(SwapOutcome::Held(Some(txhash), reason))
+            }
+            Err(NeedWallet::Other(err @ SendError::NeedsTotp(_))) => {
+                Ok(SwapOutcome::Held(None, err.to_string()))
+            }
+            Err(err) => anyhow::bail!("{err}"),
+        }
+    }
+
+    /// Executes swap orders, runs DCA jobs and, unless saving power, consolidates coins after every sync, until the daemon stops.
+    pub async fn sending_loop(self) {
+        loop {
+            self.synced.listen().await;
+            let snapshot = match self.latest_snapshot().await {
+                Ok(snapshot) => snapshot,
+                Err(err) => {
+                    log::warn!(
+                        "failed to snap for swap orders and consolidation: {:?}",
+                        err
+                    );
+                    continue;
+                }
+            };
+            if let Err(err) = self.check_swap_orders(&snapshot).await {
+                log::warn!("failed to check swap orders: {:?}", err);
+            }
+            if let Err(err) = self.run_dca_jobs(&snapshot).await {
+                log::warn!("failed to run DCA jobs: {:?}", err);
+            }
+            if !self.power.low_power() {
+                if let Err(err) = self.check_consolidation(&snapshot).await {
+                    log::warn!("failed to consolidate coins: {:?}", err);
+                }
+            }
+        }
+    }
+
+    /// Executes every open swap order whose limit price the pool now meets, and expires those past their expiry height.
+    ///
+    /// Orders of locked wallets stay open until the price is right while the wallet is unlocked. Orders whose swap is held back, or needs a TOTP code, become [SwapOrderStatus::Held] rather than being retried.
+    async fn check_swap_orders(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        let database = &self.database;
+        let height = snapshot.current_header().height;
+        for order in database.list_swap_orders(None).await? {
+            if height > order.expiry {
+                database
+                    .update_swap_order(&order.id, SwapOrderStatus::Expired, None)
+                    .await?;
+                log::info!("swap order {} of {} expired", order.id, order.wallet_name);
+                continue;
+            }
+            let (from, to): (Denom, Denom) = (order.from.parse()?, order.to.parse()?);
+            let key = PoolKey::new(from, to);
+            let pool = match snapshot.get_pool(key).await? {
+                Some(pool) => pool,
+                None => continue,
+            };
+            if !limit_reached(&order, swap_output(pool, key, from, order.amount.0)) {
+                continue;
+            }
+            if !self.unlocked_signers.contains_key(&order.wallet_name) {
+                log::debug!(
+                    "swap order {} reached its limit, but {} is locked",
+                    order.id,
+                    order.wallet_name
+                );
+                continue;
+            }
+            match self
+                .send_guarded_swap(&order.wallet_name, snapshot, key, from, order.amount)
+                .await
+            {
+                Ok(SwapOutcome::Sent(txhash)) => {
+                    database
+                        .update_swap_order(&order.id, SwapOrderStatus::Executed, Some(txhash))
+                        .await?;
+                    log::info!(
+                        "executed swap order {} of {} in {txhash}",
+                        order.id,
+                        order.wallet_name
+                    );
+                }
+                Ok(SwapOutcome::Held(txhash, reason)) => {
+                    database
+                        .update_swap_order(&order.id, SwapOrderStatus::Held, txhash)
+                        .await?;
+                    log::info!(
+                        "swap order {} of {} is held: {reason}",
+                        order.id,
+                        order.wallet_name
+                    );
+                }
+                Err(err) => log::warn!("cannot execute swap order {}: {:?}", order.id, err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs every active DCA job that is due, recording each run and scheduling the next one.
+    ///
+    /// A run is skipped, and recorded with the reason, if the wallet is locked or the swap would exceed the job's slippage bound. A run whose swap is held back is recorded with the held transaction and why. Runs missed while the daemon was down are not made up for.
+    async fn run_dca_jobs(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        let database = &self.database;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        for job in database.list_dca_jobs(None).await? {
+            if job.next_run > now {
+                continue;
+            }
+            if database.get_wallet(&job.wallet_name).await.is_none() {
+                continue;
+            }
+            let mut run = DcaRun {
+                job_id: job.id.clone(),
+                time: now,
+                height: snapshot.current_header().height,
+                txhash: None,
+                output: None,
+                error: None,
+            };
+            let result = async {
+                let (from, to): (Denom, Denom) = (job.from.parse()?, job.to.parse()?);
+                let key = PoolKey::new(from, to);
+                let pool = snapshot
+                    .get_pool(key)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("the pool is gone"))?;
+                let slippage = slippage(pool, key, from, job.amount.0);
+                anyhow::ensure!(
+                    slippage <= job.max_slippage,
+                    "slippage of {slippage:.4} exceeds the bound of {}",
+                    job.max_slippage
+                );
+                anyhow::ensure!(
+                    self.unlocked_signers.contains_key(&job.wallet_name),
+                    "the wallet is locked"
+                );
+                run.output = Some(CoinValue(swap_output(pool, key, from, job.amount.0)));
+                self.send_guarded_swap(&job.wallet_name, snapshot, key, from, job.amount)
+                    .await
+            }
+            .await;
+            match result {
+                Ok(SwapOutcome::Sent(txhash)) => {
+                    run.txhash = Some(txhash);
+                    log::info!("ran DCA job {} of {} in {txhash}", job.id, job.wallet_name);
+                }
+                Ok(SwapOutcome::Held(txhash, reason)) => {
+                    run.output = None;
+                    run.txhash = txhash;
+                    log::info!("run of DCA job {} is held: {reason}", job.id);
+                    run.error = Some(format!("held back: {reason}"));
+                }
+                Err(err) => {
+                    run.output = None;
+                    log::warn!("skipped run of DCA job {}: {:#}", job.id, err);
+                    run.error = Some(format!("{:#}", err));
+                }
+            }
+            database
+                .record_dca_run(&run, now.saturating_add(job.interval_secs))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use melstructs::BlockHeight;
+
+    use super::*;
+
+    #[test]
+    fn executes_at_limit() {
+        let key = PoolKey::new(Denom::Mel, Denom::Sym);
+        let pool = PoolState {
+            lefts: 1_000_000_000,
+            rights: 2_000_000_000,
+            price_accum: 0,
+            liqs: 0,
+        };
+        let from = key.left();
+        let output = swap_output(pool, key, from, 1000);
+        // about two of the right token per left token, minus the fee
+        assert!(output > 1980 && output < 2000);
+        let mut order = SwapOrder {
+            id: "order".into(),
+            wallet_name: "wallet".into(),
+            from: from.to_string(),
+            to: key.right().to_string(),
+            amount: CoinValue(1000),
+            limit_price: 1.9,
+            expiry: BlockHeight(100),
+            status: SwapOrderStatus::Open,
+            txhash: None,
+            created: 0,
+        };
+        assert!(limit_reached(&order, output));
+        order.limit_price = 2.0;
+        assert!(!limit_reached(&order, output));
+        // the pool fee alone is half a percent
+        let slippage = slippage(pool, key, from, 1000);
+        assert!(slippage > 0.005 && slippage < 0.006);
+    }
+}
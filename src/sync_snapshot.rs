@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+use base32::Alphabet;
+use melstructs::{Address, BlockHeight, CoinDataHeight, CoinID};
+use serde::{Deserialize, Serialize};
+use tmelcrypt::{Ed25519PK, Ed25519SK, HashVal};
+
+/// Key that snapshot contents are hashed under before signing, so that a snapshot signature can never pass for a transaction signature.
+const SIGNING_DOMAIN: &[u8] = b"melwalletd-sync-snapshot";
+
+/// A wallet's unspent coins as of the height it was synced to, signed by the wallet's key. Importing one on another machine lets the wallet sync onwards from that height instead of from scratch.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SyncSnapshot {
+    pub address: Address,
+    pub height: BlockHeight,
+    pub coins: BTreeMap<CoinID, CoinDataHeight>,
+    signature: Vec<u8>,
+}
+
+impl SyncSnapshot {
+    /// Signs a snapshot of the given coins.
+    pub fn new(
+        address: Address,
+        height: BlockHeight,
+        coins: BTreeMap<CoinID, CoinDataHeight>,
+        key: &Ed25519SK,
+    ) -> Self {
+        let mut snapshot = Self {
+            address,
+            height,
+            coins,
+            signature: vec![],
+        };
+        snapshot.signature = key.sign(&snapshot.signed_hash().0);
+        snapshot
+    }
+
+    fn signed_hash(&self) -> HashVal {
+        let contents = stdcode::serialize(&(self.address, self.height, &self.coins))
+            .expect("cannot serialize snapshot");
+        tmelcrypt::hash_keyed(SIGNING_DOMAIN, contents)
+    }
+
+    /// Checks that the snapshot was signed by the given key, and that its coins are all at its address and confirmed no later than its height.
+    pub fn verify(&self, pubkey: Ed25519PK) -> bool {
+        pubkey.verify(&self.signed_hash().0, &self.signature)
+            && self
+                .coins
+                .values()
+                .all(|cdh| cdh.coin_data.covhash == self.address && cdh.height <= self.height)
+    }
+
+    /// Encodes the snapshot as a compact string.
+    pub fn encode(&self) -> String {
+        let bytes = stdcode::serialize(self).expect("cannot serialize snapshot");
+        base32::encode(Alphabet::Crockford, &bytes)
+    }
+
+    /// Decodes a snapshot encoded with [SyncSnapshot::encode]. Does not check the signature.
+    pub fn decode(s: &str) -> Option<Self> {
+        let bytes = base32::decode(Alphabet::Crockford, s.trim())?;
+        stdcode::deserialize(&bytes).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use melstructs::{CoinData, CoinValue, Denom};
+
+    use super::*;
+
+    #[test]
+    fn signs_and_round_trips() {
+        let key = Ed25519SK::generate();
+        let address: Address = tmelcrypt::hash_single(b"wallet").into();
+        let coin = CoinID::new(tmelcrypt::hash_single(b"tx").into(), 0);
+        let cdh = CoinDataHeight {
+            coin_data: CoinData {
+                covhash: address,
+                value: CoinValue(1000),
+                denom: Denom::Mel,
+                additional_data: Default::default(),
+            },
+            height: BlockHeight(5),
+        };
+        let snapshot = SyncSnapshot::new(
+            address,
+            BlockHeight(10),
+            std::iter::once((coin, cdh)).collect(),
+            &key,
+        );
+        let decoded = SyncSnapshot::decode(&snapshot.encode()).unwrap();
+        assert_eq!(decoded, snapshot);
+        assert!(decoded.verify(key.to_public()));
+        assert!(!decoded.verify(Ed25519SK::generate().to_public()));
+
+        // tampering with the height breaks the signature
+        let mut tampered = decoded;
+        tampered.height = BlockHeight(20);
+        assert!(!tampered.verify(key.to_public()));
+        assert_eq!(SyncSnapshot::decode("not a snapshot"), None);
+    }
+}
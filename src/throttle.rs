@@ -0,0 +1,148 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use event_listener::Event;
+use futures::Future;
+use melprot::ClientError;
+use parking_lot::Mutex;
+
+use crate::protocol::types::NodeStats;
+
+/// Most requests made to the node at once.
+pub const MAX_CONCURRENCY: usize = 16;
+/// Requests made to the node at once, before anything is known about it.
+const INITIAL_CONCURRENCY: usize = 8;
+/// Latency above which the node is considered overloaded.
+const TARGET_LATENCY: Duration = Duration::from_secs(1);
+/// Times a request is attempted before giving up.
+const MAX_ATTEMPTS: u32 = 4;
+/// Delay before the first retry. Later retries wait exponentially longer.
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Limits the requests made to the node at once, shared by everything that syncs. The limit adapts to how the node copes: it creeps up while requests are fast, backs off when they get slow, and halves on errors.
+#[derive(Clone)]
+pub struct NodeThrottle {
+    state: Arc<Mutex<ThrottleState>>,
+    freed: Arc<Event>,
+}
+
+struct ThrottleState {
+    concurrency: usize,
+    in_flight: usize,
+    /// Moving average of latency, in seconds
+    latency: Option<f64>,
+    requests: u64,
+    failures: u64,
+    retries: u64,
+}
+
+impl Default for NodeThrottle {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ThrottleState {
+                concurrency: INITIAL_CONCURRENCY,
+                in_flight: 0,
+                latency: None,
+                requests: 0,
+                failures: 0,
+                retries: 0,
+            })),
+            freed: Default::default(),
+        }
+    }
+}
+
+impl NodeThrottle {
+    /// Makes a request once there is room for it, retrying with jittered backoff if it fails with a network error. Other errors are returned immediately.
+    pub async fn call<T, F: Future<Output = Result<T, ClientError>>>(
+        &self,
+        request: impl Fn() -> F,
+    ) -> Result<T, ClientError> {
+        let mut attempt = 0;
+        loop {
+            let res = {
+                let _slot = self.acquire().await;
+                let start = Instant::now();
+                let res = request().await;
+                self.record(start.elapsed(), res.is_ok());
+                res
+            };
+            match res {
+                Err(ClientError::NetworkError(err)) if attempt + 1 < MAX_ATTEMPTS => {
+                    log::debug!("retrying node request after error: {:?}", err);
+                    let delay = RETRY_DELAY * 2u32.pow(attempt);
+                    smol::Timer::after(delay.mul_f64(0.5 + fastrand::f64())).await;
+                    self.state.lock().retries += 1;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Returns aggregate statistics about requests to the node.
+    pub fn stats(&self) -> NodeStats {
+        let state = self.state.lock();
+        NodeStats {
+            requests: state.requests,
+            failures: state.failures,
+            retries: state.retries,
+            latency_ms: state.latency.map(|l| (l * 1000.0) as u64),
+            concurrency: state.concurrency,
+            in_flight: state.in_flight,
+        }
+    }
+
+    async fn acquire(&self) -> Slot<'_> {
+        loop {
+            let listener = {
+                let mut state = self.state.lock();
+                if state.in_flight < state.concurrency {
+                    state.in_flight += 1;
+                    return Slot(self);
+                }
+                self.freed.listen()
+            };
+            // the slot may have been freed between giving up the lock and listening
+            {
+                let state = self.state.lock();
+                if state.in_flight < state.concurrency {
+                    continue;
+                }
+            }
+            listener.await;
+        }
+    }
+
+    fn record(&self, latency: Duration, success: bool) {
+        let mut state = self.state.lock();
+        state.requests += 1;
+        if success {
+            let latency = latency.as_secs_f64();
+            let average = state
+                .latency
+                .map_or(latency, |avg| avg * 0.8 + latency * 0.2);
+            state.latency = Some(average);
+            if average > TARGET_LATENCY.as_secs_f64() {
+                state.concurrency = state.concurrency.saturating_sub(1).max(1);
+            } else {
+                state.concurrency = (state.concurrency + 1).min(MAX_CONCURRENCY);
+            }
+        } else {
+            state.failures += 1;
+            state.concurrency = (state.concurrency / 2).max(1);
+        }
+    }
+}
+
+/// A request slot, given back when dropped (even if the request is cancelled).
+struct Slot<'a>(&'a NodeThrottle);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().in_flight -= 1;
+        self.0.freed.notify(usize::MAX);
+    }
+}
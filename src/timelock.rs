@@ -0,0 +1,23 @@
+use melstructs::BlockHeight;
+use melvm::{opcode::OpCode, Covenant};
+use tmelcrypt::Ed25519PK;
+
+/// Heap address where melvm puts the latest block header.
+const HADDR_LAST_HEADER: u16 = 10;
+/// Index of the height in a header.
+const HEADER_HEIGHT: u32 = 2;
+
+/// Returns a covenant that lets a key spend a coin, like [Covenant::std_ed25519_pk_new], but only from a given height onwards.
+pub fn timelock_covenant(pk: Ed25519PK, unlock_height: BlockHeight) -> Covenant {
+    let mut ops = vec![
+        // height > unlock_height - 1
+        OpCode::PushI(unlock_height.0.saturating_sub(1).into()),
+        OpCode::PushI(HEADER_HEIGHT.into()),
+        OpCode::LoadImm(HADDR_LAST_HEADER),
+        OpCode::VRef,
+        OpCode::Gt,
+    ];
+    ops.extend(Covenant::std_ed25519_pk_new(pk).to_ops());
+    ops.push(OpCode::And);
+    Covenant::from_ops(&ops)
+}
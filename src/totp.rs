@@ -0,0 +1,223 @@
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base32::Alphabet;
+use melstructs::Denom;
+use serde::{Deserialize, Serialize};
+
+/// Length of a TOTP time step, in seconds.
+const STEP_SECS: u64 = 30;
+/// Digits in a TOTP code.
+const DIGITS: u32 = 6;
+/// Time steps before and after the current one whose codes are also accepted, to allow for clock drift.
+const DRIFT_STEPS: u64 = 1;
+/// Wrong codes in a row that a wallet is allowed before checking its codes is refused for a while.
+const FREE_FAILURES: u32 = 3;
+/// How long checking codes is refused after the first wrong code beyond [FREE_FAILURES]. Every further wrong code doubles it, up to [MAX_LOCKOUT_SECS].
+const BASE_LOCKOUT_SECS: u64 = 30;
+/// Longest that checking codes is ever refused for.
+const MAX_LOCKOUT_SECS: u64 = 24 * 60 * 60;
+
+/// The TOTP secret of a wallet, kept in the secret store next to the wallet's key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TotpSecret {
+    /// Shared HMAC-SHA1 key
+    #[serde(with = "stdcode::hex")]
+    pub key: Vec<u8>,
+    /// Whether enrollment was confirmed with a valid code. Until then, nothing needs a code.
+    pub active: bool,
+    /// Sends of more than this many micromel to other addresses need a code, as do sends of any other token
+    pub send_threshold: u128,
+    /// Last time step whose code was accepted. Codes are single-use, so codes of this step and earlier are refused.
+    #[serde(default)]
+    pub last_step: u64,
+}
+
+impl TotpSecret {
+    /// Generates a fresh, not yet active secret.
+    pub fn generate(send_threshold: u128) -> Self {
+        let mut key = vec![0u8; 20];
+        getrandom::getrandom(&mut key).expect("no randomness");
+        Self {
+            key,
+            active: false,
+            send_threshold,
+            last_step: 0,
+        }
+    }
+
+    /// Whether sending a transaction that pays out `outgoing`, by denomination, to other addresses needs a code. The threshold is in MEL, so sending any other token always needs one.
+    pub fn send_needs_code(&self, outgoing: &BTreeMap<Denom, u128>) -> bool {
+        outgoing.iter().any(|(&denom, &value)| {
+            if denom == Denom::Mel {
+                value > self.send_threshold
+            } else {
+                value > 0
+            }
+        })
+    }
+
+    /// The key in base32, as authenticator apps expect it.
+    pub fn base32_key(&self) -> String {
+        base32::encode(Alphabet::RFC4648 { padding: false }, &self.key)
+    }
+
+    /// An `otpauth://` URI that authenticator apps can import, usually from a QR code.
+    pub fn provisioning_uri(&self, wallet_name: &str) -> String {
+        let label: String =
+            url::form_urlencoded::byte_serialize(format!("melwalletd:{wallet_name}").as_bytes())
+                .collect();
+        format!(
+            "otpauth://totp/{label}?secret={}&issuer=melwalletd&algorithm=SHA1&digits={DIGITS}&period={STEP_SECS}",
+            self.base32_key()
+        )
+    }
+
+    /// Checks a code against the current time, returning the time step it belongs to. Codes of steps that were already used are refused.
+    pub fn verify(&self, code: &str) -> Option<u64> {
+        self.verify_at(code, unix_time())
+    }
+
+    fn verify_at(&self, code: &str, now: u64) -> Option<u64> {
+        let code = code.trim();
+        if code.len() != DIGITS as usize {
+            return None;
+        }
+        let code: u32 = code.parse().ok()?;
+        let current = now / STEP_SECS;
+        (current.saturating_sub(DRIFT_STEPS)..=current + DRIFT_STEPS)
+            .filter(|&step| step > self.last_step)
+            .find(|&step| hotp(&self.key, step) == code)
+    }
+}
+
+/// The wrong codes given for a wallet in a row. Once there are more than [FREE_FAILURES], checking the wallet's codes is refused for exponentially growing periods, so that codes cannot be guessed. Only kept in memory.
+#[derive(Default, Debug, Clone)]
+pub struct TotpFailures {
+    count: u32,
+    locked_until: u64,
+}
+
+impl TotpFailures {
+    /// Seconds until codes may be checked again, if checking them is refused now.
+    pub fn locked_for(&self) -> Option<u64> {
+        self.locked_for_at(unix_time())
+    }
+
+    fn locked_for_at(&self, now: u64) -> Option<u64> {
+        (self.locked_until > now).then(|| self.locked_until - now)
+    }
+
+    /// Records a wrong code.
+    pub fn record(&mut self) {
+        self.record_at(unix_time())
+    }
+
+    fn record_at(&mut self, now: u64) {
+        self.count = self.count.saturating_add(1);
+        if self.count > FREE_FAILURES {
+            let doublings = (self.count - FREE_FAILURES - 1).min(32);
+            self.locked_until = now + (BASE_LOCKOUT_SECS << doublings).min(MAX_LOCKOUT_SECS);
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock before 1970")
+        .as_secs()
+}
+
+/// The HOTP code (RFC 4226) of a counter.
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mac = hmac_sha1(key, &counter.to_be_bytes());
+    let offset = (mac[19] & 0xf) as usize;
+    let binary = u32::from_be_bytes([
+        mac[offset],
+        mac[offset + 1],
+        mac[offset + 2],
+        mac[offset + 3],
+    ]) & 0x7fff_ffff;
+    binary % 10u32.pow(DIGITS)
+}
+
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    const BLOCK: usize = 64;
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..20].copy_from_slice(&sha1_smol::Sha1::from(key).digest().bytes());
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = sha1_smol::Sha1::new();
+    inner.update(&block.map(|b| b ^ 0x36));
+    inner.update(message);
+    let mut outer = sha1_smol::Sha1::new();
+    outer.update(&block.map(|b| b ^ 0x5c));
+    outer.update(&inner.digest().bytes());
+    outer.digest().bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc6238_vectors() {
+        let secret = TotpSecret {
+            key: b"12345678901234567890".to_vec(),
+            active: true,
+            send_threshold: 0,
+            last_step: 0,
+        };
+        // the RFC's 8-digit codes, truncated to 6 digits
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (2000000000, "279037"),
+        ] {
+            assert_eq!(secret.verify_at(code, time), Some(time / STEP_SECS));
+        }
+        assert!(secret.verify_at("287082", 59 + 3 * STEP_SECS).is_none());
+        let used = TotpSecret {
+            last_step: 1,
+            ..secret
+        };
+        assert!(used.verify_at("287082", 59).is_none());
+    }
+
+    #[test]
+    fn threshold_covers_every_denom() {
+        let secret = TotpSecret {
+            send_threshold: 1_000_000,
+            ..TotpSecret::generate(0)
+        };
+        let outgoing = |denom: Denom, value: u128| std::iter::once((denom, value)).collect();
+        assert!(!secret.send_needs_code(&outgoing(Denom::Mel, 1_000_000)));
+        assert!(secret.send_needs_code(&outgoing(Denom::Mel, 1_000_001)));
+        // a large send of another token can't slip under the MEL threshold
+        assert!(secret.send_needs_code(&outgoing(Denom::Sym, 1_000_000_000_000)));
+        assert!(!secret.send_needs_code(&BTreeMap::new()));
+    }
+
+    #[test]
+    fn lockout_grows_exponentially() {
+        let mut failures = TotpFailures::default();
+        for _ in 0..FREE_FAILURES {
+            failures.record_at(1000);
+            assert_eq!(failures.locked_for_at(1000), None);
+        }
+        failures.record_at(1000);
+        assert_eq!(failures.locked_for_at(1000), Some(BASE_LOCKOUT_SECS));
+        assert_eq!(failures.locked_for_at(1000 + BASE_LOCKOUT_SECS), None);
+        failures.record_at(2000);
+        assert_eq!(failures.locked_for_at(2000), Some(2 * BASE_LOCKOUT_SECS));
+        for _ in 0..100 {
+            failures.record_at(3000);
+        }
+        assert_eq!(failures.locked_for_at(3000), Some(MAX_LOCKOUT_SECS));
+    }
+}
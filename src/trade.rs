@@ -0,0 +1,331 @@
+use std::collections::BTreeMap;
+
+use melstructs::{Address, CoinData, CoinID, CoinValue, Denom, Transaction, TxKind};
+use melvm::covenant_weight_from_bytes;
+
+use crate::{
+    address::parse_address,
+    protocol::types::{TradeError, TradeTerms},
+    signer::Signer,
+};
+
+/// [TradeTerms], parsed.
+#[derive(Clone, Copy, Debug)]
+pub struct Terms {
+    pub give: (Denom, CoinValue),
+    pub want: (Denom, CoinValue),
+    pub counterparty: Address,
+}
+
+impl Terms {
+    /// Parses and checks the terms of a trade.
+    pub fn parse(terms: &TradeTerms) -> Result<Self, TradeError> {
+        let denom = |s: &String| -> Result<Denom, TradeError> {
+            match s.parse() {
+                Ok(Denom::NewCustom) | Err(_) => Err(TradeError::InvalidDenom(s.clone())),
+                Ok(denom) => Ok(denom),
+            }
+        };
+        let give = (denom(&terms.give_denom)?, terms.give_amount);
+        let want = (denom(&terms.want_denom)?, terms.want_amount);
+        if give.0 == want.0 {
+            return Err(TradeError::SameDenom);
+        }
+        if give.1 == CoinValue(0) || want.1 == CoinValue(0) {
+            return Err(TradeError::ZeroAmount);
+        }
+        let counterparty = parse_address(&terms.counterparty)
+            .ok_or_else(|| TradeError::InvalidAddress(terms.counterparty.clone()))?;
+        Ok(Self {
+            give,
+            want,
+            counterparty,
+        })
+    }
+}
+
+/// How much of each denomination `address` gains (positive) or loses (negative) through a transaction, counting as its inputs those among `coins`. Denominations that come out even are left out.
+pub fn net_flows(
+    tx: &Transaction,
+    address: Address,
+    coins: &BTreeMap<CoinID, CoinData>,
+) -> BTreeMap<Denom, i128> {
+    let mut flows = BTreeMap::new();
+    for output in tx.outputs.iter().filter(|o| o.covhash == address) {
+        *flows.entry(output.denom).or_default() += output.value.0 as i128;
+    }
+    for data in tx.inputs.iter().filter_map(|i| coins.get(i)) {
+        *flows.entry(data.denom).or_default() -= data.value.0 as i128;
+    }
+    flows.retain(|_, v| *v != 0);
+    flows
+}
+
+/// The flows a trade party expects, in the form returned by [net_flows].
+fn expected_flows(entries: &[(Denom, i128)]) -> BTreeMap<Denom, i128> {
+    let mut flows = BTreeMap::new();
+    for (denom, value) in entries {
+        *flows.entry(*denom).or_default() += value;
+    }
+    flows.retain(|_, v| *v != 0);
+    flows
+}
+
+/// Checks that `address` gains and loses exactly what it expects through a transaction.
+fn check_flows(
+    tx: &Transaction,
+    address: Address,
+    coins: &BTreeMap<CoinID, CoinData>,
+    expected: &[(Denom, i128)],
+) -> Result<(), TradeError> {
+    let actual = net_flows(tx, address, coins);
+    let expected = expected_flows(expected);
+    if actual != expected {
+        return Err(TradeError::TermsMismatch(format!(
+            "expected flows {expected:?}, got {actual:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Picks coins of `denom` worth at least `needed`, largest first. Returns them with their total.
+fn select_coins(
+    coins: &BTreeMap<CoinID, CoinData>,
+    denom: Denom,
+    needed: CoinValue,
+) -> Result<(Vec<CoinID>, CoinValue), TradeError> {
+    let mut candidates: Vec<_> = coins.iter().filter(|(_, d)| d.denom == denom).collect();
+    candidates.sort_by_key(|(_, d)| std::cmp::Reverse(d.value));
+    let mut picked = vec![];
+    let mut total = CoinValue(0);
+    for (id, data) in candidates {
+        if total >= needed {
+            break;
+        }
+        picked.push(*id);
+        total += data.value;
+    }
+    if total < needed {
+        return Err(TradeError::InsufficientFunds {
+            denom: denom.to_string(),
+            needed,
+            available: total,
+        });
+    }
+    Ok((picked, total))
+}
+
+fn output(covhash: Address, denom: Denom, value: CoinValue) -> CoinData {
+    CoinData {
+        covhash,
+        value,
+        denom,
+        additional_data: Default::default(),
+    }
+}
+
+/// Builds the proposer's half of a trade: its inputs covering what it gives, both parties' payments and its change, unsigned and with no fee, which the counterparty pays.
+pub fn propose(
+    terms: &Terms,
+    proposer: Address,
+    covenant: &[u8],
+    coins: &BTreeMap<CoinID, CoinData>,
+) -> Result<Transaction, TradeError> {
+    let (give_denom, give_amount) = terms.give;
+    let (want_denom, want_amount) = terms.want;
+    let (inputs, total) = select_coins(coins, give_denom, give_amount)?;
+    let mut outputs = vec![
+        output(terms.counterparty, give_denom, give_amount),
+        output(proposer, want_denom, want_amount),
+    ];
+    if total > give_amount {
+        outputs.push(output(proposer, give_denom, total - give_amount));
+    }
+    Ok(Transaction {
+        kind: TxKind::Normal,
+        inputs,
+        outputs,
+        fee: CoinValue(0),
+        covenants: vec![covenant.to_vec().into()],
+        data: vec![].into(),
+        sigs: vec![],
+    })
+}
+
+/// Completes the proposer's half of a trade with the counterparty's inputs, change and fee, then signs the counterparty's inputs. The proposer's signatures are left as placeholders of the right length, so the fee covers them.
+///
+/// Before adding anything, checks that the offered transaction gives the counterparty exactly what the terms say and takes nothing from it; after, that it loses only what the terms say, plus the fee.
+pub fn accept(
+    offer: Transaction,
+    terms: &Terms,
+    covenant: &[u8],
+    coins: &BTreeMap<CoinID, CoinData>,
+    fee_multiplier: u128,
+    min_fee: CoinValue,
+    signer: &dyn Signer,
+) -> Result<Transaction, TradeError> {
+    let me = terms.counterparty;
+    let (give_denom, give_amount) = terms.give;
+    let (want_denom, want_amount) = terms.want;
+    check_flows(&offer, me, coins, &[(give_denom, give_amount.0 as i128)])?;
+
+    let mut fee = min_fee;
+    let mut settled = None;
+    // the fee depends on the size of the transaction, which depends on the coins picked to pay it, so we iterate a few times
+    for _ in 0..5 {
+        let mut tx = offer.clone();
+        let mut needed = BTreeMap::new();
+        *needed.entry(want_denom).or_insert(CoinValue(0)) += want_amount;
+        *needed.entry(Denom::Mel).or_insert(CoinValue(0)) += fee;
+        for (denom, needed) in needed {
+            if needed == CoinValue(0) {
+                continue;
+            }
+            let (inputs, total) = select_coins(coins, denom, needed)?;
+            tx.inputs.extend(inputs);
+            if total > needed {
+                tx.outputs.push(output(me, denom, total - needed));
+            }
+        }
+        if !tx.covenants.iter().any(|c| c.as_ref() == covenant) {
+            tx.covenants.push(covenant.to_vec().into());
+        }
+        tx.fee = fee;
+        tx.sigs = vec![vec![0u8; 64].into(); tx.inputs.len()];
+        let base_fee = tx.base_fee(fee_multiplier, 0, covenant_weight_from_bytes);
+        if tx.fee >= base_fee {
+            settled = Some(tx);
+            break;
+        }
+        fee = (base_fee * 21 / 20).max(min_fee);
+    }
+    let mut tx =
+        settled.ok_or_else(|| TradeError::Other("could not settle on a trade fee".into()))?;
+
+    check_flows(
+        &tx,
+        me,
+        coins,
+        &[
+            (give_denom, give_amount.0 as i128),
+            (want_denom, -(want_amount.0 as i128)),
+            (Denom::Mel, -(tx.fee.0 as i128)),
+        ],
+    )?;
+    for i in 0..tx.inputs.len() {
+        if coins.contains_key(&tx.inputs[i]) {
+            tx = signer
+                .sign_tx(tx, i)
+                .map_err(|e| TradeError::Other(e.to_string()))?;
+        }
+    }
+    Ok(tx)
+}
+
+/// Signs the proposer's inputs of a trade transaction completed by the counterparty, after checking that the proposer gains and loses exactly what the terms say.
+pub fn complete(
+    mut tx: Transaction,
+    terms: &Terms,
+    proposer: Address,
+    coins: &BTreeMap<CoinID, CoinData>,
+    signer: &dyn Signer,
+) -> Result<Transaction, TradeError> {
+    let (give_denom, give_amount) = terms.give;
+    let (want_denom, want_amount) = terms.want;
+    check_flows(
+        &tx,
+        proposer,
+        coins,
+        &[
+            (give_denom, -(give_amount.0 as i128)),
+            (want_denom, want_amount.0 as i128),
+        ],
+    )?;
+    for i in 0..tx.inputs.len() {
+        if coins.contains_key(&tx.inputs[i]) {
+            tx = signer
+                .sign_tx(tx, i)
+                .map_err(|e| TradeError::Other(e.to_string()))?;
+        }
+    }
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use melstructs::CoinValue;
+    use tmelcrypt::{Ed25519SK, HashVal};
+
+    use super::*;
+    use crate::signer::PlaceholderSigner;
+
+    fn coins(owner: Address, seed: u8, values: &[(u128, Denom)]) -> BTreeMap<CoinID, CoinData> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, (value, denom))| {
+                (
+                    CoinID {
+                        txhash: HashVal([seed; 32]).into(),
+                        index: i as u8,
+                    },
+                    output(owner, *denom, CoinValue(*value)),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trade_roundtrip() {
+        let alice = Address(HashVal([1; 32]));
+        let bob = Address(HashVal([2; 32]));
+        let alice_coins = coins(alice, 1, &[(700, Denom::Sym), (50, Denom::Mel)]);
+        let bob_coins = coins(bob, 2, &[(300, Denom::Mel), (400, Denom::Mel)]);
+        let terms = Terms {
+            give: (Denom::Sym, CoinValue(500)),
+            want: (Denom::Mel, CoinValue(350)),
+            counterparty: bob,
+        };
+        let signer = PlaceholderSigner(Ed25519SK::generate().to_public());
+
+        let offer = propose(&terms, alice, b"alice", &alice_coins).unwrap();
+        assert_eq!(offer.inputs.len(), 1);
+        let tx = accept(
+            offer.clone(),
+            &terms,
+            b"bob",
+            &bob_coins,
+            1 << 10,
+            CoinValue(0),
+            &signer,
+        )
+        .unwrap();
+        assert!(tx.fee > CoinValue(0));
+        assert!(tx.fee >= tx.base_fee(1 << 10, 0, covenant_weight_from_bytes));
+        assert_eq!(tx.sigs.len(), tx.inputs.len());
+        assert_eq!(tx.covenants.len(), 2);
+        complete(tx.clone(), &terms, alice, &alice_coins, &signer).unwrap();
+
+        // giving or getting other than agreed is caught by both parties
+        let mut stingy = offer;
+        stingy.outputs[0].value = CoinValue(400);
+        assert!(matches!(
+            accept(
+                stingy,
+                &terms,
+                b"bob",
+                &bob_coins,
+                1 << 10,
+                CoinValue(0),
+                &signer
+            ),
+            Err(TradeError::TermsMismatch(_))
+        ));
+        let mut short = tx;
+        short.outputs[1].value = CoinValue(340);
+        assert!(matches!(
+            complete(short, &terms, alice, &alice_coins, &signer),
+            Err(TradeError::TermsMismatch(_))
+        ));
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use melstructs::{CoinValue, Denom};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::types::UnitConversionError;
+
+/// Number of decimal places of a token whose decimals aren't otherwise known. MEL, SYM and ERG all count in millionths.
+pub const DEFAULT_DECIMALS: u8 = 6;
+
+/// Known tokens and how many decimal places their display units have. Values on the blockchain are always integers of the smallest unit; one display unit is `10^decimals` of those.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TokenRegistry {
+    /// Decimal places of tokens that don't use the default, keyed by the standard string representation of their [Denom].
+    #[serde(default)]
+    pub decimals: BTreeMap<String, u8>,
+}
+
+impl TokenRegistry {
+    /// Number of decimal places of a token's display units.
+    pub fn decimals(&self, denom: Denom) -> u8 {
+        self.decimals
+            .get(&denom.to_string())
+            .copied()
+            .unwrap_or(DEFAULT_DECIMALS)
+    }
+
+    /// Formats a raw value in display units, without losing precision. Trailing zeros in the fraction are dropped.
+    pub fn format_display(&self, denom: Denom, raw: CoinValue) -> String {
+        let decimals = self.decimals(denom) as u32;
+        let scale = 10u128.pow(decimals);
+        let whole = raw.0 / scale;
+        let frac = raw.0 % scale;
+        if frac == 0 {
+            return whole.to_string();
+        }
+        let frac = format!("{:0width$}", frac, width = decimals as usize);
+        format!("{whole}.{}", frac.trim_end_matches('0'))
+    }
+
+    /// Formats a signed change of a raw value in display units, with a leading `-` if negative.
+    pub fn format_signed_display(&self, denom: Denom, delta: i128) -> String {
+        let formatted = self.format_display(denom, CoinValue(delta.unsigned_abs()));
+        if delta < 0 {
+            format!("-{formatted}")
+        } else {
+            formatted
+        }
+    }
+
+    /// Parses a value in display units into a raw value. Fails rather than rounding if the value has more decimal places than the token does.
+    pub fn parse_display(
+        &self,
+        denom: Denom,
+        display: &str,
+    ) -> Result<CoinValue, UnitConversionError> {
+        let decimals = self.decimals(denom) as usize;
+        let invalid = || UnitConversionError::InvalidAmount(display.to_owned());
+        let (whole, frac) = display
+            .trim()
+            .split_once('.')
+            .unwrap_or((display.trim(), ""));
+        if whole.is_empty() && frac.is_empty()
+            || !whole
+                .chars()
+                .chain(frac.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let frac = frac.trim_end_matches('0');
+        if frac.len() > decimals {
+            return Err(UnitConversionError::TooPrecise(decimals as u8));
+        }
+        let whole: u128 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| invalid())?
+        };
+        let frac: u128 = format!("{:0<width$}", frac, width = decimals)
+            .parse()
+            .unwrap_or_default();
+        whole
+            .checked_mul(10u128.pow(decimals as u32))
+            .and_then(|w| w.checked_add(frac))
+            .map(CoinValue)
+            .ok_or_else(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let registry = TokenRegistry::default();
+        for (display, raw) in [
+            ("1.5", 1_500_000),
+            ("0.000001", 1),
+            ("42", 42_000_000),
+            ("0", 0),
+        ] {
+            assert_eq!(
+                registry.parse_display(Denom::Mel, display).unwrap(),
+                CoinValue(raw)
+            );
+            assert_eq!(registry.format_display(Denom::Mel, CoinValue(raw)), display);
+        }
+        assert_eq!(
+            registry.parse_display(Denom::Mel, ".25").unwrap(),
+            CoinValue(250_000)
+        );
+        assert!(registry.parse_display(Denom::Mel, "0.0000001").is_err());
+        assert!(registry.parse_display(Denom::Mel, "-1").is_err());
+        assert!(registry.parse_display(Denom::Mel, ".").is_err());
+        assert_eq!(
+            registry.format_signed_display(Denom::Mel, -2_500_000),
+            "-2.5"
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{cli::Config, state::AppState};
+
+/// A user of a multi-user daemon. Each user gets an isolated namespace of wallets, with its own database and secret store, and authenticates with a bearer token.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UserConfig {
+    /// Name of the user, which is also the name of their directory
+    pub name: String,
+    /// Token the user authenticates with, sent as `Authorization: Bearer <token>`. Keep the config file private!
+    pub token: String,
+}
+
+impl UserConfig {
+    /// Checks that the user's name can safely be used as a directory name, and that their token isn't trivially guessable.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.name.is_empty()
+                && self
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "user name {:?} may only contain letters, digits, dashes and underscores",
+            self.name
+        );
+        anyhow::ensure!(
+            self.token.len() >= 16,
+            "token of user {} is too short",
+            self.name
+        );
+        Ok(())
+    }
+
+    /// Validates every configured user, and checks that no two share a name, and so a directory, or a token, which would let one user into the other's namespace.
+    pub fn validate_all(users: &[UserConfig]) -> anyhow::Result<()> {
+        for (i, user) in users.iter().enumerate() {
+            user.validate()?;
+            for earlier in users[..i].iter() {
+                anyhow::ensure!(
+                    earlier.name != user.name,
+                    "user {} is configured twice",
+                    user.name
+                );
+                anyhow::ensure!(
+                    !constant_time_eq(earlier.token.as_bytes(), user.token.as_bytes()),
+                    "users {} and {} have the same token",
+                    earlier.name,
+                    user.name
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Configuration of a user's namespace: the same daemon settings, but with the user's own wallet directory and backup location, and none of the declared wallets.
+    pub fn for_user(&self, user: &UserConfig) -> Config {
+        let mut config = self.clone();
+        config.wallet_dir = self.wallet_dir.join("users").join(&user.name);
+        if let Some(backup) = config.backup.as_mut() {
+            backup.s3.prefix = format!("{}{}/", backup.s3.prefix, user.name);
+        }
+        config.wallets = vec![];
+        config.users = vec![];
+        config
+    }
+}
+
+impl AppState {
+    /// Gets the state serving a request, given its `Authorization` header. Without users configured, that's this state; otherwise, it's the namespace of the user whose token was given, if any.
+    pub fn for_request(&self, authorization: Option<&str>) -> Option<AppState> {
+        let users = match self.users.as_ref() {
+            None => return Some(self.clone()),
+            Some(users) => users,
+        };
+        let token = authorization?.strip_prefix("Bearer ")?.trim();
+        users
+            .iter()
+            .find(|(user, _)| constant_time_eq(user.token.as_bytes(), token.as_bytes()))
+            .map(|(_, state)| state.clone())
+    }
+}
+
+/// Compares two secrets in time that depends only on their lengths, so that timing a comparison reveals nothing of where they differ.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(name: &str, token: &str) -> UserConfig {
+        UserConfig {
+            name: name.into(),
+            token: token.into(),
+        }
+    }
+
+    #[test]
+    fn rejects_shared_tokens() {
+        let alice = user("alice", "0123456789abcdef");
+        let bob = user("bob", "fedcba9876543210");
+        UserConfig::validate_all(&[alice.clone(), bob]).unwrap();
+        assert!(UserConfig::validate_all(&[alice.clone(), user("bob", &alice.token)]).is_err());
+        assert!(
+            UserConfig::validate_all(&[alice.clone(), user("alice", "fedcba9876543210")]).is_err()
+        );
+        assert!(UserConfig::validate_all(&[user("../etc", "0123456789abcdef")]).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use melstructs::{CoinDataHeight, CoinID, Denom, Header, Transaction, TxKind};
+use melvm::{Covenant, CovenantEnv};
+
+/// Checks that the input `coinid` of a transaction, spending the coin `parent`, passes the coin's covenant, executed just as the network would with `last_header` being the header of the block before the transaction's.
+pub fn verify_spend(
+    tx: &Transaction,
+    coinid: CoinID,
+    parent: CoinDataHeight,
+    last_header: Header,
+) -> anyhow::Result<()> {
+    let spender_index = tx
+        .inputs
+        .iter()
+        .position(|input| *input == coinid)
+        .ok_or_else(|| anyhow::anyhow!("{} does not spend {coinid}", tx.hash_nosigs()))?;
+    let covenant = tx
+        .covenants
+        .iter()
+        .filter_map(|bytes| Covenant::from_bytes(bytes).ok())
+        .find(|covenant| covenant.hash() == parent.coin_data.covhash)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} lacks the covenant of {}",
+                tx.hash_nosigs(),
+                parent.coin_data.covhash
+            )
+        })?;
+    let env = CovenantEnv {
+        parent_coinid: coinid,
+        parent_cdh: parent,
+        spender_index: spender_index as u8,
+        last_header,
+    };
+    anyhow::ensure!(
+        covenant
+            .execute(tx, Some(env))
+            .is_some_and(|value| value.into_bool()),
+        "{} fails the covenant of {coinid}",
+        tx.hash_nosigs()
+    );
+    Ok(())
+}
+
+/// Checks that a coin is really an output of the transaction that created it. The outputs of normal transactions must match exactly; those of other kinds, such as swaps, can change denomination and value when applied, so only their recipient is checked.
+pub fn verify_received(
+    tx: &Transaction,
+    coinid: CoinID,
+    coin: &CoinDataHeight,
+) -> anyhow::Result<()> {
+    let txhash = tx.hash_nosigs();
+    anyhow::ensure!(
+        txhash == coinid.txhash,
+        "got transaction {txhash} rather than {}",
+        coinid.txhash
+    );
+    let output = tx
+        .outputs
+        .get(coinid.index as usize)
+        .ok_or_else(|| anyhow::anyhow!("{txhash} has no output {}", coinid.index))?;
+    let matches = if tx.kind == TxKind::Normal {
+        let denom = if output.denom == Denom::NewCustom {
+            Denom::Custom(txhash)
+        } else {
+            output.denom
+        };
+        output.covhash == coin.coin_data.covhash
+            && output.value == coin.coin_data.value
+            && denom == coin.coin_data.denom
+            && output.additional_data == coin.coin_data.additional_data
+    } else {
+        output.covhash == coin.coin_data.covhash
+    };
+    anyhow::ensure!(matches, "{coinid} does not match its transaction's output");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use melstructs::{BlockHeight, CoinData, CoinValue, NetID};
+    use tmelcrypt::{Ed25519SK, HashVal};
+
+    use super::*;
+    use crate::signer::Signer;
+
+    fn header() -> Header {
+        Header {
+            network: NetID::Testnet,
+            previous: HashVal::default(),
+            height: BlockHeight(9),
+            history_hash: HashVal::default(),
+            coins_hash: HashVal::default(),
+            transactions_hash: HashVal::default(),
+            fee_pool: CoinValue(0),
+            fee_multiplier: 1,
+            dosc_speed: 1,
+            pools_hash: HashVal::default(),
+            stakes_hash: HashVal::default(),
+        }
+    }
+
+    #[test]
+    fn checks_signatures_and_outputs() {
+        let sk = Ed25519SK::generate();
+        let covenant = Covenant::std_ed25519_pk_new(sk.to_public());
+        let coinid = CoinID {
+            txhash: tmelcrypt::hash_single(b"parent").into(),
+            index: 0,
+        };
+        let parent = CoinDataHeight {
+            coin_data: CoinData {
+                covhash: covenant.hash(),
+                value: CoinValue(1000),
+                denom: Denom::Mel,
+                additional_data: Default::default(),
+            },
+            height: BlockHeight(5),
+        };
+        let mut tx = Transaction {
+            kind: TxKind::Normal,
+            inputs: vec![coinid],
+            outputs: vec![parent.coin_data.clone()],
+            fee: CoinValue(0),
+            covenants: vec![covenant.to_bytes()],
+            data: Default::default(),
+            sigs: vec![],
+        };
+        let unsigned = tx.clone();
+        tx = sk.sign_tx(tx, 0).unwrap();
+        assert!(verify_spend(&tx, coinid, parent.clone(), header()).is_ok());
+        assert!(verify_spend(&unsigned, coinid, parent.clone(), header()).is_err());
+
+        let received = CoinID {
+            txhash: tx.hash_nosigs(),
+            index: 0,
+        };
+        let mut coin = CoinDataHeight {
+            coin_data: parent.coin_data.clone(),
+            height: BlockHeight(10),
+        };
+        assert!(verify_received(&tx, received, &coin).is_ok());
+        coin.coin_data.value = CoinValue(2000);
+        assert!(verify_received(&tx, received, &coin).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+
+use base32::Alphabet;
+use melstructs::{BlockHeight, CoinDataHeight, CoinID, TxHash};
+use melvm::{opcode::OpCode, Covenant};
+use serde::{Deserialize, Serialize};
+use tmelcrypt::{Ed25519PK, Ed25519SK, HashVal};
+
+/// Key that package contents are hashed under before signing, so that a package signature can never pass for a transaction or sync snapshot signature.
+const SIGNING_DOMAIN: &[u8] = b"melwalletd-watch-package";
+
+/// Everything needed to follow a wallet without being able to spend from it: its covenant, and its history as of the height it synced to, signed by the wallet's key. Importing one on another daemon creates a wallet that shows the same balances and history, and syncs onwards from there.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WatchPackage {
+    pub covenant: Vec<u8>,
+    pub height: BlockHeight,
+    /// Every confirmed coin the wallet has held, spent or not
+    pub coins: BTreeMap<CoinID, CoinDataHeight>,
+    /// The transactions that spent the spent coins
+    pub spends: BTreeMap<CoinID, TxHash>,
+    pub public_key: Ed25519PK,
+    signature: Vec<u8>,
+}
+
+impl WatchPackage {
+    /// Signs a package of the given history.
+    pub fn new(
+        covenant: &Covenant,
+        height: BlockHeight,
+        coins: BTreeMap<CoinID, CoinDataHeight>,
+        spends: BTreeMap<CoinID, TxHash>,
+        key: &Ed25519SK,
+    ) -> Self {
+        let mut package = Self {
+            covenant: covenant.to_bytes().to_vec(),
+            height,
+            coins,
+            spends,
+            public_key: key.to_public(),
+            signature: vec![],
+        };
+        package.signature = key.sign(&package.signed_hash().0);
+        package
+    }
+
+    fn signed_hash(&self) -> HashVal {
+        let contents = stdcode::serialize(&(
+            &self.covenant,
+            self.height,
+            &self.coins,
+            &self.spends,
+            self.public_key,
+        ))
+        .expect("cannot serialize package");
+        tmelcrypt::hash_keyed(SIGNING_DOMAIN, contents)
+    }
+
+    /// Parses the package's covenant.
+    pub fn covenant(&self) -> Option<Covenant> {
+        Covenant::from_bytes(&self.covenant).ok()
+    }
+
+    /// Checks that the package was signed by a key its covenant is made from, and that its coins are all at its address and confirmed no later than its height.
+    pub fn verify(&self) -> bool {
+        let covenant = match self.covenant() {
+            Some(covenant) => covenant,
+            None => return false,
+        };
+        let address = covenant.hash();
+        let committed = covenant
+            .to_ops()
+            .into_iter()
+            .any(|op| matches!(op, OpCode::PushB(bytes) if bytes == self.public_key.0));
+        committed
+            && self
+                .public_key
+                .verify(&self.signed_hash().0, &self.signature)
+            && self
+                .coins
+                .values()
+                .all(|cdh| cdh.coin_data.covhash == address && cdh.height <= self.height)
+            && self.spends.keys().all(|coin| self.coins.contains_key(coin))
+    }
+
+    /// Encodes the package as a compact string.
+    pub fn encode(&self) -> String {
+        let bytes = stdcode::serialize(self).expect("cannot serialize package");
+        base32::encode(Alphabet::Crockford, &bytes)
+    }
+
+    /// Decodes a package encoded with [WatchPackage::encode]. Does not check the signature.
+    pub fn decode(s: &str) -> Option<Self> {
+        let bytes = base32::decode(Alphabet::Crockford, s.trim())?;
+        stdcode::deserialize(&bytes).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use melstructs::{CoinData, CoinValue, Denom};
+
+    use super::*;
+
+    #[test]
+    fn signs_and_round_trips() {
+        let key = Ed25519SK::generate();
+        let covenant = Covenant::std_ed25519_pk_new(key.to_public());
+        let coin = CoinID::new(tmelcrypt::hash_single(b"tx").into(), 0);
+        let cdh = CoinDataHeight {
+            coin_data: CoinData {
+                covhash: covenant.hash(),
+                value: CoinValue(1000),
+                denom: Denom::Mel,
+                additional_data: Default::default(),
+            },
+            height: BlockHeight(5),
+        };
+        let spender: TxHash = tmelcrypt::hash_single(b"spender").into();
+        let package = WatchPackage::new(
+            &covenant,
+            BlockHeight(10),
+            std::iter::once((coin, cdh)).collect(),
+            std::iter::once((coin, spender)).collect(),
+            &key,
+        );
+        let decoded = WatchPackage::decode(&package.encode()).unwrap();
+        assert_eq!(decoded, package);
+        assert!(decoded.verify());
+        assert_eq!(decoded.covenant(), Some(covenant.clone()));
+
+        // a key the covenant isn't made from can't vouch for it
+        let other = Ed25519SK::generate();
+        let forged = WatchPackage::new(
+            &covenant,
+            BlockHeight(10),
+            decoded.coins.clone(),
+            decoded.spends.clone(),
+            &other,
+        );
+        assert!(!forged.verify());
+
+        // tampering with the history breaks the signature
+        let mut tampered = decoded;
+        tampered.spends.clear();
+        assert!(!tampered.verify());
+        assert_eq!(WatchPackage::decode("not a package"), None);
+    }
+}